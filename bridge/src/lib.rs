@@ -219,8 +219,15 @@ pub struct tt_bridge_api_t {
         ) -> Option<usize>,
     >,
     pub input_close: Option<unsafe fn(_: *mut libc::c_void, _: rust_input_handle_t) -> i32>,
+    pub shell_escape: Option<unsafe fn(_: *mut libc::c_void, _: *const i8, _: usize) -> i32>,
 }
 
+/// Outcomes of a `\write18` request, as reported by the `shell_escape` bridge
+/// function. Nonnegative return values are the exit status of the command.
+pub const SHELL_ESCAPE_DISABLED: i32 = -1;
+pub const SHELL_ESCAPE_NOT_ALLOWED: i32 = -2;
+pub const SHELL_ESCAPE_FAILED: i32 = -3;
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq)]
 pub enum TTHistory {
@@ -426,6 +433,19 @@ pub unsafe fn ttstub_input_read_rust_style(
     )
 }
 
+/// Run a shell command on behalf of `\write18`. See the `SHELL_ESCAPE_*`
+/// constants for the meaning of negative return values.
+pub unsafe fn ttstub_shell_escape(cmd: &str) -> i32 {
+    match (*tectonic_global_bridge).shell_escape {
+        Some(f) => f(
+            (*tectonic_global_bridge).context,
+            cmd.as_ptr() as *const i8,
+            cmd.len(),
+        ),
+        None => SHELL_ESCAPE_DISABLED,
+    }
+}
+
 pub trait ReadByte {
     fn read_byte(&mut self) -> Option<u8>;
 }
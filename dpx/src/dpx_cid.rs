@@ -78,6 +78,7 @@ pub(crate) struct cid_opt {
     pub(crate) cff_charsets: *mut libc::c_void,
 }
 use super::dpx_fontmap::fontmap_opt;
+use super::dpx_pdfa::pdfa_violation;
 /*
  * Unicode and PDF Standard Character Collections.
  *
@@ -717,6 +718,9 @@ pub(crate) unsafe fn CIDFont_cache_close() {
             info!("(CID");
         }
         CIDFont_dofont(font);
+        if CIDFont_is_BaseFont(font) || CIDFont_get_embedding(font) == 0 {
+            pdfa_violation(&format!("Font \"{}\" is not embedded.", font.fontname));
+        }
         CIDFont_flush(font);
         if __verbose != 0 {
             info!(")");
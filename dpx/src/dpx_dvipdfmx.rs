@@ -39,11 +39,12 @@ use super::dpx_pdfdoc::pdf_doc_mut;
 use super::dpx_pdfdoc::{pdf_doc_set_creator, pdf_doc_set_verbose};
 use super::dpx_pdffont::{
     pdf_font_reset_unique_tag_state, pdf_font_set_deterministic_unique_tags, pdf_font_set_dpi,
+    pdf_font_set_source_date_epoch,
 };
 use super::dpx_tt_aux::tt_aux_set_verbose;
 use crate::bridge::DisplayExt;
 use crate::dpx_pdfparse::parse_unsigned;
-use crate::info;
+use crate::{info, warn};
 use std::ffi::CStr;
 use std::ptr;
 
@@ -54,6 +55,7 @@ use super::dpx_error::shut_up;
 use super::dpx_fontmap::{
     pdf_close_fontmaps, pdf_fontmap_set_verbose, pdf_init_fontmaps, pdf_load_fontmap_file,
 };
+use super::dpx_pdfa::{pdf_get_pdfa, pdf_set_pdfa, pdfa_check_conformance};
 use super::dpx_pdfencrypt::{pdf_enc_compute_id_string, pdf_enc_set_passwd, pdf_enc_set_verbose};
 use super::dpx_pdfobj::{
     pdf_files_close, pdf_files_init, pdf_get_version, pdf_obj_reset_global_state,
@@ -70,14 +72,47 @@ use std::borrow::Cow;
 
 pub struct XdvipdfmxConfig {
     pub paperspec: Cow<'static, str>,
+    /// Pages to output; an empty list means all of them.
+    pub page_ranges: Vec<PageRange>,
+    /// If set, encrypt the output document.
+    pub encryption: Option<EncryptionConfig>,
+    /// If set, the creation date of the document and the seed of its ID, in
+    /// seconds since the epoch. This takes precedence over
+    /// `SOURCE_DATE_EPOCH`.
+    pub source_date_epoch: Option<u64>,
+    /// If set, make the output conform to this PDF/A level, and fail if the
+    /// document uses something that the standard forbids.
+    pub pdfa: Option<PdfAConformance>,
 }
 
-pub(crate) type PageRange = page_range;
-#[derive(Copy, Clone)]
+/// The PDF/A levels that we can produce. Both ask only that the appearance
+/// of the document be preserved; PDF/A-3b also allows arbitrary attached
+/// files.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PdfAConformance {
+    A2b,
+    A3b,
+}
+
+/// Settings for PDF encryption. These may still be overridden by a
+/// `pdf:encrypt` special in the document.
+pub struct EncryptionConfig {
+    /// Key length in bits: 128 selects AES-128, 256 selects AES-256.
+    pub key_bits: i32,
+    /// The PDF permission flags (the `P` entry of the encryption dictionary).
+    pub permission: i32,
+    pub owner_password: String,
+    pub user_password: String,
+}
+
+pub type PageRange = page_range;
+/// Zero-based, inclusive page range. A negative `last` counts back from
+/// the end of the document, so that `-1` is the last page.
+#[derive(Copy, Clone, Debug, PartialEq)]
 #[repr(C)]
-pub(crate) struct page_range {
-    pub(crate) first: i32,
-    pub(crate) last: i32,
+pub struct page_range {
+    pub first: i32,
+    pub last: i32,
 }
 
 pub(crate) static mut is_xdv: i32 = 0;
@@ -197,6 +232,36 @@ unsafe fn select_pages(pagespec: *const i8, page_ranges: &mut Vec<PageRange>) {
     }
 }
 
+/* Check that every page range selects at least one page of the document. */
+unsafe fn check_page_ranges(page_ranges: &[PageRange]) -> Result<(), String> {
+    let npages = dvi_npages() as i32;
+    for range in page_ranges {
+        let last = if range.last < 0 {
+            range.last + npages
+        } else {
+            range.last
+        };
+        if range.first.min(last) >= npages || last < 0 {
+            return Err(format!(
+                "page range {}-{} is outside the document, which has {} page(s)",
+                range.first + 1,
+                last + 1,
+                npages
+            ));
+        }
+    }
+    Ok(())
+}
+
+/* Copy a password into a NUL-terminated buffer, truncating it if needed. */
+fn copy_password(src: &str, dst: &mut [i8]) {
+    let n = src.len().min(dst.len() - 1);
+    for (d, s) in dst.iter_mut().zip(src.as_bytes()[..n].iter()) {
+        *d = *s as i8;
+    }
+    dst[n] = 0;
+}
+
 unsafe fn do_dvi_pages(mut page_ranges: Vec<PageRange>) {
     spc_exec_at_begin_document().ok();
     let mut page_width = paper_width;
@@ -317,6 +382,10 @@ pub unsafe fn dvipdfmx_main(
     }
     pdf_set_compression(if compress as i32 != 0 { 9 } else { 0 });
     pdf_font_set_deterministic_unique_tags(if deterministic_tags as i32 != 0 { 1 } else { 0 });
+    pdf_font_set_source_date_epoch(dpx_config.source_date_epoch);
+    pdf_set_pdfa(dpx_config.pdfa);
+    /* PDF/A needs every font embedded, whatever its license says. */
+    always_embed = dpx_config.pdfa.is_some() as i32;
     pdf_init_fontmaps();
     /* We used to read the config file here. It synthesized command-line
      * arguments, so we emulate the default TeXLive config file by copying those
@@ -325,6 +394,7 @@ pub unsafe fn dvipdfmx_main(
     select_paper(&dpx_config.paperspec);
     annot_grow = 0 as f64;
     bookmark_open = 0;
+    do_encryption = 0;
     key_bits = 40;
     permission = 0x3c;
     font_dpi = 600;
@@ -335,6 +405,8 @@ pub unsafe fn dvipdfmx_main(
     pdf_load_fontmap_file("ckx.map", '+' as i32).ok();
     if !pagespec.is_null() {
         select_pages(pagespec, &mut page_ranges);
+    } else {
+        page_ranges.extend_from_slice(&dpx_config.page_ranges);
     }
     if page_ranges.is_empty() {
         page_ranges.push(PageRange { first: 0, last: -1 });
@@ -359,11 +431,23 @@ pub unsafe fn dvipdfmx_main(
     let mut ver_minor: i32 = 0;
     let mut owner_pw: [i8; 127] = [0; 127];
     let mut user_pw: [i8; 127] = [0; 127];
+    if let Some(enc) = &dpx_config.encryption {
+        do_encryption = 1;
+        key_bits = enc.key_bits;
+        permission = enc.permission;
+        copy_password(&enc.owner_password, &mut owner_pw);
+        copy_password(&enc.user_password, &mut user_pw);
+    }
     /* Dependency between DVI and PDF side is rather complicated... */
     let dvi2pts = dvi_init(dvi_filename, mag);
     if dvi2pts == 0.0f64 {
         panic!("dvi_init() failed!");
     }
+    if let Err(msg) = check_page_ranges(&page_ranges) {
+        dvi_close();
+        bridge::error_buf = msg;
+        return 99;
+    }
     pdf_doc_set_creator(dvi_comment());
     dvi_scan_specials(
         0,
@@ -383,10 +467,22 @@ pub unsafe fn dvipdfmx_main(
     if ver_minor >= 3 && ver_minor <= 7 {
         pdf_set_version(ver_minor as u32);
     }
+    if do_encryption != 0 && pdf_get_pdfa().is_some() {
+        warn!("PDF/A forbids encryption; the output will not be encrypted.");
+        do_encryption = 0;
+    }
     if do_encryption != 0 {
         if !(key_bits >= 40 && key_bits <= 128 && key_bits % 8 == 0) && key_bits != 256 {
             panic!("Invalid encryption key length specified: {}", key_bits);
         } else {
+            if key_bits == 256 && pdf_get_version() < 7_u32 {
+                /* AES-256 is only defined for PDF 1.7 (Extension Level 3) and later. */
+                pdf_set_version(7_u32);
+            }
+            if key_bits == 128 && pdf_get_version() < 5_u32 {
+                /* AES-128 needs PDF 1.5; below that it would quietly fall back to RC4. */
+                pdf_set_version(5_u32);
+            }
             if key_bits > 40 && pdf_get_version() < 4_u32 {
                 panic!("Chosen key length requires at least PDF 1.4. Use \"-V 4\" to change.");
             }
@@ -444,6 +540,10 @@ pub unsafe fn dvipdfmx_main(
     pdf_doc_mut().close_document(); /* pdf_font may depend on fontmap. */
     pdf_close_fontmaps();
     dvi_close();
+    if let Err(msg) = pdfa_check_conformance() {
+        bridge::error_buf = msg;
+        return 99;
+    }
     info!("\n");
     0
 }
@@ -0,0 +1,470 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! GIF images. Only the first frame of an animation is used. It is drawn on
+//! the image's logical screen and stored with an Indexed color space; a
+//! transparent color becomes a color-key mask, as for PNG palette images.
+
+use crate::dpx_imageprobe::image_data_size;
+use crate::dpx_pdfobj::{pdf_stream, pdf_string, IntoObj, PushObj, STREAM_COMPRESS};
+use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
+use crate::warn;
+
+use std::io::{Read, Seek, SeekFrom};
+
+/// LZW codes are at most 12 bits long.
+const MAX_LZW_CODES: usize = 4096;
+
+pub fn check_for_gif<R: Read + Seek>(handle: &mut R) -> bool {
+    let mut sigbytes: [u8; 6] = [0; 6];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    handle.read_exact(&mut sigbytes[..]).is_ok()
+        && (&sigbytes == b"GIF87a" || &sigbytes == b"GIF89a")
+}
+
+pub fn gif_get_bbox<R: Read + Seek>(handle: &mut R) -> Result<(u32, u32, f64, f64), ()> {
+    let data = read_file(handle)?;
+    let mut reader = Reader {
+        data: &data,
+        pos: 6,
+    };
+    let width = reader.u16()? as u32;
+    let height = reader.u16()? as u32;
+    /* GIF files have no resolution. */
+    Ok((width, height, 1., 1.))
+}
+
+pub(crate) unsafe fn gif_include_image<R: Read + Seek>(
+    ximage: &mut pdf_ximage,
+    handle: &mut R,
+) -> Result<(), ()> {
+    let data = read_file(handle)?;
+    let gif = read_gif(&data)?;
+    let mut info = ximage_info::init();
+    info.width = gif.width as i32;
+    info.height = gif.height as i32;
+    info.bits_per_component = 8;
+    info.num_components = 1;
+    if info.width <= 0 || info.height <= 0 {
+        warn!(
+            "Invalid GIF file: width={}, height={}",
+            info.width, info.height,
+        );
+        return Err(());
+    }
+    let mut stream = pdf_stream::new(STREAM_COMPRESS);
+    let stream_dict = stream.get_dict_mut();
+    let mut colorspace = vec![];
+    colorspace.push_obj("Indexed");
+    colorspace.push_obj("DeviceRGB");
+    colorspace.push_obj((gif.palette.len() / 3 - 1) as f64);
+    colorspace.push_obj(pdf_string::new(&gif.palette));
+    stream_dict.set("ColorSpace", colorspace.into_obj());
+    if let Some(index) = gif.transparent {
+        let mut colorkeys = vec![];
+        colorkeys.push_obj(index as f64);
+        colorkeys.push_obj(index as f64);
+        stream_dict.set("Mask", colorkeys.into_obj());
+    }
+    stream.add_slice(&gif.pixels);
+    ximage.set_image(&info, stream.into_obj());
+    Ok(())
+}
+
+/// The first frame of a GIF, drawn on its logical screen.
+struct GifImage {
+    width: u32,
+    height: u32,
+    /// The color table, as RGB triples.
+    palette: Vec<u8>,
+    transparent: Option<u8>,
+    /// One palette index per pixel, row by row from the top.
+    pixels: Vec<u8>,
+}
+
+fn read_file<R: Read + Seek>(handle: &mut R) -> Result<Vec<u8>, ()> {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("Reading GIF file failed...");
+        return Err(());
+    }
+    Ok(data)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ()> {
+        if self.data.len().saturating_sub(self.pos) < len {
+            warn!("GIF: Unexpected end of file.");
+            return Err(());
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ()> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ()> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    /// Read a sequence of data sub-blocks, up to the empty block that ends
+    /// it.
+    fn sub_blocks(&mut self) -> Result<Vec<u8>, ()> {
+        let mut data = Vec::new();
+        loop {
+            let len = self.u8()? as usize;
+            if len == 0 {
+                return Ok(data);
+            }
+            data.extend_from_slice(self.bytes(len)?);
+        }
+    }
+
+    /// Read a color table, if `flags` says that there is one.
+    fn color_table(&mut self, flags: u8) -> Result<Option<Vec<u8>>, ()> {
+        if flags & 0x80 == 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.bytes(3 << ((flags & 7) + 1))?.to_vec()))
+    }
+}
+
+fn read_gif(data: &[u8]) -> Result<GifImage, ()> {
+    let mut reader = Reader { data, pos: 6 };
+    let width = reader.u16()? as u32;
+    let height = reader.u16()? as u32;
+    let size = match image_data_size(width, height, 8) {
+        Some(size) => size,
+        None => {
+            warn!("GIF: Unsupported image size {}x{}.", width, height);
+            return Err(());
+        }
+    };
+    let flags = reader.u8()?;
+    let background = reader.u8()?;
+    /* pixel aspect ratio */
+    reader.u8()?;
+    let mut palette = reader.color_table(flags)?;
+    let mut transparent = None;
+    loop {
+        match reader.u8()? {
+            0x21 => {
+                /* Extension; only the Graphic Control Extension matters. */
+                let label = reader.u8()?;
+                let block = reader.sub_blocks()?;
+                if label == 0xf9 && block.len() >= 4 && block[0] & 1 != 0 {
+                    transparent = Some(block[3]);
+                }
+            }
+            0x2c => {
+                let left = reader.u16()? as u32;
+                let top = reader.u16()? as u32;
+                let frame_width = reader.u16()? as u32;
+                let frame_height = reader.u16()? as u32;
+                let flags = reader.u8()?;
+                if let Some(local) = reader.color_table(flags)? {
+                    palette = Some(local);
+                }
+                let palette = match palette {
+                    Some(p) => p,
+                    None => {
+                        warn!("GIF: Image has no color table.");
+                        return Err(());
+                    }
+                };
+                let frame_size = match image_data_size(frame_width, frame_height, 8) {
+                    Some(size) => size,
+                    None => {
+                        warn!(
+                            "GIF: Unsupported frame size {}x{}.",
+                            frame_width, frame_height
+                        );
+                        return Err(());
+                    }
+                };
+                let min_code_size = reader.u8()?;
+                let lzw_data = reader.sub_blocks()?;
+                let frame = lzw_decode(&lzw_data, min_code_size, frame_size)?;
+                /* Whatever the frame doesn't cover is transparent, or failing
+                 * that the background color. */
+                let mut pixels = vec![transparent.unwrap_or(background); size];
+                let rows = frame_rows(frame_height, flags & 0x40 != 0);
+                for (i, y) in rows.into_iter().enumerate() {
+                    let y = top + y;
+                    if y >= height {
+                        continue;
+                    }
+                    for x in 0..frame_width.min(width.saturating_sub(left)) {
+                        if let Some(&index) = frame.get(i * frame_width as usize + x as usize) {
+                            pixels[y as usize * width as usize + (left + x) as usize] = index;
+                        }
+                    }
+                }
+                return Ok(GifImage {
+                    width,
+                    height,
+                    palette,
+                    transparent,
+                    pixels,
+                });
+            }
+            0x3b => {
+                warn!("GIF: No image found.");
+                return Err(());
+            }
+            b => {
+                warn!("GIF: Unknown block type 0x{:02x}.", b);
+                return Err(());
+            }
+        }
+    }
+}
+
+/// The rows of a frame in the order in which they are stored.
+fn frame_rows(height: u32, interlaced: bool) -> Vec<u32> {
+    if !interlaced {
+        return (0..height).collect();
+    }
+    [(0, 8), (4, 8), (2, 4), (1, 2)]
+        .iter()
+        .flat_map(|&(start, step)| (start..height).step_by(step))
+        .collect()
+}
+
+/// Decode GIF's variant of LZW, producing at most `max_len` bytes. Data that
+/// ends early gives a short result, which leaves the rest of the frame
+/// blank, as is customary.
+fn lzw_decode(data: &[u8], min_code_size: u8, max_len: usize) -> Result<Vec<u8>, ()> {
+    if !(2..=8).contains(&min_code_size) {
+        warn!("GIF: Invalid LZW code size {}.", min_code_size);
+        return Err(());
+    }
+    let clear = 1_usize << min_code_size;
+    let end = clear + 1;
+    let mut prefix = vec![0_usize; MAX_LZW_CODES];
+    let mut suffix = vec![0_u8; MAX_LZW_CODES];
+    let mut code_size = min_code_size as u32 + 1;
+    let mut next_code = end + 1;
+    let mut prev: Option<usize> = None;
+    /* No code stands for more bytes than there are codes in the data, or in
+     * the table, which bounds what the data can yield. */
+    let num_codes = data.len().saturating_mul(8) / (min_code_size as usize + 1);
+    let max_yield = num_codes.saturating_mul((num_codes + 1).min(MAX_LZW_CODES));
+    let mut out = Vec::with_capacity(max_len.min(max_yield));
+    let mut string = Vec::new();
+    let mut bits: u32 = 0;
+    let mut nbits: u32 = 0;
+    let mut bytes = data.iter();
+
+    while out.len() < max_len {
+        while nbits < code_size {
+            match bytes.next() {
+                Some(&b) => {
+                    bits |= (b as u32) << nbits;
+                    nbits += 8;
+                }
+                None => return Ok(out),
+            }
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        nbits -= code_size;
+
+        if code == clear {
+            code_size = min_code_size as u32 + 1;
+            next_code = end + 1;
+            prev = None;
+            continue;
+        }
+        if code == end {
+            break;
+        }
+        let prev_code = match prev {
+            Some(p) => p,
+            None => {
+                if code > clear {
+                    warn!("GIF: Invalid LZW code.");
+                    return Err(());
+                }
+                out.push(code as u8);
+                prev = Some(code);
+                continue;
+            }
+        };
+        /* A code that isn't in the table yet stands for the previous string
+         * followed by its own first byte. */
+        let mut c = if code < next_code {
+            code
+        } else if code == next_code {
+            prev_code
+        } else {
+            warn!("GIF: Invalid LZW code.");
+            return Err(());
+        };
+        string.clear();
+        while c > end {
+            string.push(suffix[c]);
+            c = prefix[c];
+        }
+        let first = c as u8;
+        string.push(first);
+        if code == next_code {
+            string.insert(0, first);
+        }
+        out.extend(string.iter().rev());
+        if next_code < MAX_LZW_CODES {
+            prefix[next_code] = prev_code;
+            suffix[next_code] = first;
+            next_code += 1;
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        prev = Some(code);
+    }
+    out.truncate(max_len);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Encode `data` as GIF does. If `reset` is set, the code table is
+    /// cleared whenever it fills up; otherwise it is kept as it is.
+    fn lzw_encode(data: &[u8], min_code_size: u8, reset: bool) -> Vec<u8> {
+        let clear = 1_usize << min_code_size;
+        let end = clear + 1;
+        let mut out = Vec::new();
+        let mut bits: u32 = 0;
+        let mut nbits: u32 = 0;
+        let mut emit = |code: usize, next_code: usize| {
+            /* The decoder adds a table entry for a code when it reads the
+             * next one, so it is one entry behind us. */
+            let mut code_size = min_code_size as u32 + 1;
+            while (1 << code_size) < next_code && code_size < 12 {
+                code_size += 1;
+            }
+            bits |= (code as u32) << nbits;
+            nbits += code_size;
+            while nbits >= 8 {
+                out.push(bits as u8);
+                bits >>= 8;
+                nbits -= 8;
+            }
+        };
+        let mut table = HashMap::new();
+        let mut next_code = end + 1;
+        emit(clear, next_code);
+        let mut current = data[0] as usize;
+        for &b in &data[1..] {
+            if let Some(&code) = table.get(&(current, b)) {
+                current = code;
+                continue;
+            }
+            emit(current, next_code);
+            if next_code < MAX_LZW_CODES {
+                table.insert((current, b), next_code);
+                next_code += 1;
+            }
+            if next_code == MAX_LZW_CODES && reset {
+                emit(clear, next_code);
+                table.clear();
+                next_code = end + 1;
+            }
+            current = b as usize;
+        }
+        emit(current, next_code);
+        emit(end, next_code + 1);
+        if nbits > 0 {
+            out.push(bits as u8);
+        }
+        out
+    }
+
+    /// Data that fills the code table many times over.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut x: u32 = 1;
+        (0..len)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8 & 0x0f
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let data = [
+            0, 1, 0, 1, 0, 1, 0, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 2, 0, 1, 2, 0, 1, 2,
+            0, 1, 2, 3,
+        ];
+        let encoded = lzw_encode(&data, 2, true);
+        assert_eq!(lzw_decode(&encoded, 2, data.len()).unwrap(), &data[..]);
+
+        /* Only `max_len` bytes are wanted. */
+        assert_eq!(lzw_decode(&encoded, 2, 5).unwrap(), &data[..5]);
+    }
+
+    #[test]
+    fn lzw_full_table() {
+        let data = noise(100_000);
+        for &reset in &[true, false] {
+            let encoded = lzw_encode(&data, 4, reset);
+            assert_eq!(
+                lzw_decode(&encoded, 4, data.len()).unwrap(),
+                data,
+                "reset: {}",
+                reset
+            );
+        }
+    }
+
+    #[test]
+    fn lzw_truncated() {
+        let data = noise(10_000);
+        let encoded = lzw_encode(&data, 4, true);
+        let decoded = lzw_decode(&encoded[..encoded.len() / 2], 4, data.len()).unwrap();
+        assert!(!decoded.is_empty());
+        assert!(decoded.len() < data.len());
+        assert_eq!(decoded, &data[..decoded.len()]);
+
+        assert_eq!(lzw_decode(&[], 4, data.len()).unwrap(), b"");
+    }
+
+    #[test]
+    fn lzw_invalid() {
+        /* Warnings go to the engine's output, which unit tests don't have. */
+        unsafe { crate::dpx_error::shut_up(2) };
+
+        /* Clear, 1, and then 7, which is beyond the end of the table. */
+        assert!(lzw_decode(&[0xcc, 0x01], 2, 10).is_err());
+        assert!(lzw_decode(&[0x00], 1, 10).is_err());
+        assert!(lzw_decode(&[0x00], 9, 10).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Working out what kind of image a file holds, and how big it is. This is
+//! shared by xdvipdfmx, which embeds the images, and by XeTeX, which needs
+//! their sizes for `\XeTeXpicfile`.
+
+use crate::bridge::InFile;
+use crate::strstartswith;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::dpx_bmpimage::{bmp_get_bbox, check_for_bmp};
+use super::dpx_gifimage::{check_for_gif, gif_get_bbox};
+use super::dpx_jp2image::{check_for_jp2, jp2_get_bbox};
+use super::dpx_jpegimage::{check_for_jpeg, jpeg_get_bbox};
+use super::dpx_mfileio::{tt_mfgets, work_buffer};
+use super::dpx_pdfobj::check_for_pdf;
+use super::dpx_pngimage::{check_for_png, png_get_bbox};
+use super::dpx_svgimage::{check_for_svg, svg_get_size};
+use super::dpx_tiffimage::{check_for_tiff, tiff_get_bbox};
+
+/// The largest width or height, in pixels, of a bitmap image that we decode.
+const MAX_IMAGE_DIMENSION: u32 = 1 << 18;
+
+/// The largest number of bytes that we decode a bitmap image into.
+const MAX_IMAGE_BYTES: usize = 1 << 30;
+
+/// The number of bytes needed for the pixels of a bitmap image, with each row
+/// padded to a whole byte. The dimensions come from the image file, so this
+/// returns `None` if the image is empty or unreasonably big.
+pub(crate) fn image_data_size(width: u32, height: u32, bits_per_pixel: u32) -> Option<usize> {
+    if width == 0 || height == 0 || width > MAX_IMAGE_DIMENSION || height > MAX_IMAGE_DIMENSION {
+        return None;
+    }
+    let rowbytes = (width as usize)
+        .checked_mul(bits_per_pixel as usize)?
+        .checked_add(7)?
+        / 8;
+    rowbytes
+        .checked_mul(height as usize)
+        .filter(|&size| size <= MAX_IMAGE_BYTES)
+}
+
+/// The kinds of image file that we know about.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ImageType {
+    Unknown,
+    Pdf,
+    Jpeg,
+    Png,
+    Eps,
+    Bmp,
+    Jp2,
+    Gif,
+    Tiff,
+    Svg,
+}
+
+unsafe fn check_for_ps<R: Read + Seek>(handle: &mut R) -> i32 {
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    tt_mfgets(work_buffer.as_mut_ptr(), 1024, handle);
+    if !strstartswith(
+        work_buffer.as_mut_ptr(),
+        b"%!\x00" as *const u8 as *const i8,
+    )
+    .is_null()
+    {
+        return 1;
+    }
+    0
+}
+
+/// Work out the type of an image from the signature at the start of its
+/// file. The file is left positioned at its start.
+pub unsafe fn probe_image_type<R: Read + Seek>(handle: &mut R) -> ImageType {
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    /* Original check order: jpeg, jp2, png, bmp, pdf, ps */
+    let format = if check_for_jpeg(handle) != 0 {
+        ImageType::Jpeg
+    } else if check_for_jp2(handle) != 0 {
+        ImageType::Jp2
+    } else if check_for_png(handle) != 0 {
+        ImageType::Png
+    } else if check_for_gif(handle) {
+        ImageType::Gif
+    } else if check_for_tiff(handle) {
+        ImageType::Tiff
+    } else if check_for_bmp(handle) {
+        ImageType::Bmp
+    } else if check_for_svg(handle) {
+        ImageType::Svg
+    } else if check_for_pdf(handle) {
+        ImageType::Pdf
+    } else if check_for_ps(handle) != 0 {
+        ImageType::Eps
+    } else {
+        ImageType::Unknown
+    };
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    format
+}
+
+/// Get the width and height in pixels of a bitmap image of the given type,
+/// and its horizontal and vertical densities, which are 72 divided by its
+/// resolution in dots per inch.
+pub unsafe fn image_get_bbox(
+    handle: &mut InFile,
+    format: ImageType,
+) -> Result<(u32, u32, f64, f64), ()> {
+    match format {
+        ImageType::Jpeg => jpeg_get_bbox(handle),
+        ImageType::Jp2 => jp2_get_bbox(handle),
+        ImageType::Png => png_get_bbox(handle),
+        ImageType::Gif => gif_get_bbox(handle),
+        ImageType::Tiff => tiff_get_bbox(handle),
+        ImageType::Bmp => bmp_get_bbox(handle),
+        ImageType::Svg | ImageType::Pdf | ImageType::Eps | ImageType::Unknown => Err(()),
+    }
+}
+
+/// Get the natural width and height of an image in big points: the pixel
+/// size scaled by the resolution for bitmaps, and the viewport size for SVG.
+pub unsafe fn image_get_size(handle: &mut InFile, format: ImageType) -> Result<(f64, f64), ()> {
+    match format {
+        ImageType::Svg => svg_get_size(handle),
+        _ => {
+            let (width, height, xdensity, ydensity) = image_get_bbox(handle, format)?;
+            Ok((width as f64 * xdensity, height as f64 * ydensity))
+        }
+    }
+}
@@ -270,6 +270,10 @@ unsafe fn scan_file<R: Read + Seek>(info: &mut ximage_info, smask: *mut i32, fp:
         len = read_box_hdr(fp, &mut lbox, &mut tbox);
         if lbox == 0_u32 {
             lbox = size as u32
+        } else if lbox as usize > size || lbox < len {
+            warn!("JPEG2000: Box length in JP2 file is out of range.");
+            error = -1;
+            break;
         }
         match tbox {
             1785737832 => {
@@ -303,8 +307,7 @@ unsafe fn scan_file<R: Read + Seek>(info: &mut ximage_info, smask: *mut i32, fp:
     error
 }
 
-#[allow(unused)]
-pub(crate) unsafe fn check_for_jp2<R: Read + Seek>(fp: &mut R) -> i32 {
+pub unsafe fn check_for_jp2<R: Read + Seek>(fp: &mut R) -> i32 {
     let mut lbox: u32 = 0;
     let mut tbox: u32 = 0;
     fp.seek(SeekFrom::Start(0)).unwrap();
@@ -323,7 +326,6 @@ pub(crate) unsafe fn check_for_jp2<R: Read + Seek>(fp: &mut R) -> i32 {
     1
 }
 
-#[allow(unused)]
 pub(crate) unsafe fn jp2_include_image<R: Read + Seek>(ximage: &mut pdf_ximage, fp: &mut R) -> i32 {
     let mut smask: i32 = 0;
     let pdf_version = pdf_get_version();
@@ -360,21 +362,17 @@ pub(crate) unsafe fn jp2_include_image<R: Read + Seek>(ximage: &mut pdf_ximage,
     0
 }
 
-#[allow(unused)]
-pub(crate) unsafe fn jp2_get_bbox<R: Read + Seek>(
-    fp: &mut R,
-    width: *mut i32,
-    height: *mut i32,
-    xdensity: *mut f64,
-    ydensity: *mut f64,
-) -> i32 {
+pub unsafe fn jp2_get_bbox<R: Read + Seek>(fp: &mut R) -> Result<(u32, u32, f64, f64), ()> {
     let mut smask: i32 = 0;
     let mut info = ximage_info::init();
     fp.seek(SeekFrom::Start(0)).unwrap();
-    let r = scan_file(&mut info, &mut smask, fp);
-    *width = info.width;
-    *height = info.height;
-    *xdensity = info.xdensity;
-    *ydensity = info.ydensity;
-    r
+    if scan_file(&mut info, &mut smask, fp) < 0 {
+        return Err(());
+    }
+    Ok((
+        info.width as u32,
+        info.height as u32,
+        info.xdensity,
+        info.ydensity,
+    ))
 }
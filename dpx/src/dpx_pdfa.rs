@@ -0,0 +1,410 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! PDF/A output: the XMP metadata and the output intent that the standard
+//! requires, and a record of the things in the document that it forbids.
+//!
+//! Problems that we can fix, such as fonts whose licenses discourage
+//! embedding, are fixed where they arise. The others are recorded with
+//! `pdfa_violation()` as they are found, and fail the conversion at the end.
+
+use super::dpx_dvipdfmx::PdfAConformance;
+use super::dpx_pdfobj::{pdf_dict, pdf_stream, pdf_string, IntoRef, Object, STREAM_COMPRESS};
+use crate::warn;
+
+static mut conformance: Option<PdfAConformance> = None;
+static mut violations: Vec<String> = Vec::new();
+
+impl PdfAConformance {
+    fn part(self) -> u32 {
+        match self {
+            PdfAConformance::A2b => 2,
+            PdfAConformance::A3b => 3,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            PdfAConformance::A2b => "PDF/A-2b",
+            PdfAConformance::A3b => "PDF/A-3b",
+        }
+    }
+}
+
+pub(crate) unsafe fn pdf_set_pdfa(level: Option<PdfAConformance>) {
+    conformance = level;
+    violations.clear();
+}
+
+/// The PDF/A conformance level that the output must meet, if any.
+pub(crate) unsafe fn pdf_get_pdfa() -> Option<PdfAConformance> {
+    conformance
+}
+
+/// Note something in the document that the PDF/A standard forbids.
+pub(crate) unsafe fn pdfa_violation(what: &str) {
+    if let Some(level) = conformance {
+        if !violations.iter().any(|v| v == what) {
+            warn!("{}: {}", level.name(), what);
+            violations.push(what.to_string());
+        }
+    }
+}
+
+/// Check that nothing has stopped the output from conforming, giving a
+/// message that lists the violations if something has.
+pub(crate) unsafe fn pdfa_check_conformance() -> Result<(), String> {
+    if let Some(level) = conformance {
+        if !violations.is_empty() {
+            return Err(format!(
+                "The output does not conform to {}: {}",
+                level.name(),
+                violations.join(" ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check an annotation, giving it the Print flag if it has no flags: PDF/A
+/// asks that annotations appear when the page is printed.
+pub(crate) unsafe fn pdfa_check_annot(annot: &mut pdf_dict) {
+    let level = match conformance {
+        Some(level) => level,
+        None => return,
+    };
+    if let Some(subtype) = annot.get("Subtype") {
+        if let Object::Name(_) = &subtype.data {
+            match subtype.as_name().to_bytes() {
+                b"Sound" | b"Movie" | b"Screen" | b"3D" => pdfa_violation(&format!(
+                    "{} annotations are not allowed.",
+                    subtype.as_name().to_string_lossy()
+                )),
+                b"FileAttachment" if level == PdfAConformance::A2b => pdfa_violation(
+                    "File attachments are only allowed in PDF/A-3b, unless they are PDF/A files.",
+                ),
+                _ => {}
+            }
+        }
+    }
+    if let Some(action) = annot.get("A") {
+        if let Object::Dict(action) = &action.data {
+            if let Some(s) = action.get("S") {
+                if let Object::Name(_) = &s.data {
+                    match s.as_name().to_bytes() {
+                        b"Launch" | b"Sound" | b"Movie" | b"ResetForm" | b"ImportData"
+                        | b"JavaScript" | b"Hide" | b"SetOCGState" | b"Rendition" | b"Trans"
+                        | b"GoTo3DView" => pdfa_violation(&format!(
+                            "{} actions are not allowed.",
+                            s.as_name().to_string_lossy()
+                        )),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    if !annot.has("F") {
+        annot.set("F", 4_f64);
+    } else if let Some(Object::Number(f)) = annot.get("F").map(|f| &f.data) {
+        /* Print must be set, and Invisible, Hidden and NoView clear. */
+        if *f as i32 & 4 == 0 || *f as i32 & (1 | 2 | 32) != 0 {
+            pdfa_violation("Annotations must be printable and visible.");
+        }
+    }
+}
+
+/// Make the XMP metadata stream for the catalog, which must agree with the
+/// document information dictionary.
+pub(crate) unsafe fn pdfa_metadata(docinfo: &pdf_dict) -> pdf_stream {
+    let level = conformance.unwrap();
+    let text = |key: &str| {
+        docinfo.get(key).and_then(|value| match &value.data {
+            Object::String(s) => Some(xml_escape(&decode_text_string(s.to_bytes()))),
+            _ => None,
+        })
+    };
+    let mut xmp = String::new();
+    xmp.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+    xmp.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+    xmp.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+    xmp.push_str("  <rdf:Description rdf:about=\"\"\n");
+    xmp.push_str("    xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n");
+    xmp.push_str("    xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n");
+    xmp.push_str("    xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n");
+    xmp.push_str("    xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n");
+    xmp.push_str(&format!("   <pdfaid:part>{}</pdfaid:part>\n", level.part()));
+    xmp.push_str("   <pdfaid:conformance>B</pdfaid:conformance>\n");
+    xmp.push_str("   <dc:format>application/pdf</dc:format>\n");
+    if let Some(title) = text("Title") {
+        xmp.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            title
+        ));
+    }
+    if let Some(author) = text("Author") {
+        xmp.push_str(&format!(
+            "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            author
+        ));
+    }
+    if let Some(subject) = text("Subject") {
+        xmp.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            subject
+        ));
+    }
+    if let Some(keywords) = text("Keywords") {
+        xmp.push_str(&format!("   <pdf:Keywords>{}</pdf:Keywords>\n", keywords));
+    }
+    if let Some(producer) = text("Producer") {
+        xmp.push_str(&format!("   <pdf:Producer>{}</pdf:Producer>\n", producer));
+    }
+    if let Some(trapped) = docinfo.get("Trapped") {
+        if let Object::Name(_) = &trapped.data {
+            xmp.push_str(&format!(
+                "   <pdf:Trapped>{}</pdf:Trapped>\n",
+                xml_escape(&trapped.as_name().to_string_lossy())
+            ));
+        }
+    }
+    if let Some(creator) = text("Creator") {
+        xmp.push_str(&format!(
+            "   <xmp:CreatorTool>{}</xmp:CreatorTool>\n",
+            creator
+        ));
+    }
+    let date = |key: &str| {
+        docinfo.get(key).and_then(|value| match &value.data {
+            Object::String(s) => xmp_date(s.to_bytes()),
+            _ => None,
+        })
+    };
+    let created = date("CreationDate");
+    let modified = date("ModDate").or_else(|| created.clone());
+    if let Some(created) = created {
+        xmp.push_str(&format!(
+            "   <xmp:CreateDate>{}</xmp:CreateDate>\n",
+            created
+        ));
+    }
+    if let Some(modified) = modified {
+        xmp.push_str(&format!(
+            "   <xmp:ModifyDate>{}</xmp:ModifyDate>\n",
+            modified
+        ));
+        xmp.push_str(&format!(
+            "   <xmp:MetadataDate>{}</xmp:MetadataDate>\n",
+            modified
+        ));
+    }
+    xmp.push_str("  </rdf:Description>\n");
+    xmp.push_str(" </rdf:RDF>\n");
+    xmp.push_str("</x:xmpmeta>\n");
+    xmp.push_str("<?xpacket end=\"w\"?>");
+
+    /* The metadata must stay readable, so it is never compressed. */
+    let mut stream = pdf_stream::new(0);
+    stream.get_dict_mut().set("Type", "Metadata");
+    stream.get_dict_mut().set("Subtype", "XML");
+    stream.add_slice(xmp.as_bytes());
+    stream
+}
+
+/// Make the output intent for the catalog: sRGB, with its ICC profile.
+pub(crate) unsafe fn pdfa_output_intent() -> pdf_dict {
+    let mut profile = pdf_stream::new(STREAM_COMPRESS);
+    profile.get_dict_mut().set("N", 3_f64);
+    profile.add_slice(&srgb_icc_profile());
+    let mut intent = pdf_dict::new();
+    intent.set("Type", "OutputIntent");
+    intent.set("S", "GTS_PDFA1");
+    intent.set(
+        "OutputConditionIdentifier",
+        pdf_string::new("sRGB IEC61966-2.1"),
+    );
+    intent.set("Info", pdf_string::new("sRGB IEC61966-2.1"));
+    intent.set("RegistryName", pdf_string::new("http://www.color.org"));
+    intent.set("DestOutputProfile", profile.into_ref());
+    intent
+}
+
+/// Decode a PDF text string: UTF-16BE if it starts with a byte order mark,
+/// and otherwise PDFDocEncoding, which we read as Latin-1.
+fn decode_text_string(bytes: &[u8]) -> String {
+    if bytes.starts_with(b"\xfe\xff") {
+        let units: Vec<u16> = bytes[2..]
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            /* XML 1.0 can't hold most control characters. */
+            c if (c as u32) < 0x20 && c != '\t' && c != '\n' && c != '\r' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Convert a PDF date, `D:YYYYMMDDHHmmSSOHH'mm'` with everything after the
+/// year optional, to the ISO 8601 form that XMP uses.
+fn xmp_date(date: &[u8]) -> Option<String> {
+    let date = std::str::from_utf8(date).ok()?;
+    let date = date.strip_prefix("D:").unwrap_or(date);
+    let digits = date.bytes().take_while(u8::is_ascii_digit).count();
+    if digits < 4 || digits % 2 != 0 || digits > 14 {
+        return None;
+    }
+    let field = |i: usize, default: &'static str| {
+        date.get(i..i + 2)
+            .filter(|_| i < digits)
+            .unwrap_or(default)
+            .to_string()
+    };
+    let mut iso = format!("{}-{}-{}", &date[..4], field(4, "01"), field(6, "01"));
+    if digits > 8 {
+        iso += &format!(
+            "T{}:{}:{}",
+            field(8, "00"),
+            field(10, "00"),
+            field(12, "00")
+        );
+        let zone = &date[digits..];
+        match zone.as_bytes().first() {
+            Some(b'Z') => iso.push('Z'),
+            Some(&sign) if sign == b'+' || sign == b'-' => {
+                let zone_digits: String = zone[1..].chars().filter(char::is_ascii_digit).collect();
+                if zone_digits.len() >= 2 {
+                    let minutes = zone_digits.get(2..4).unwrap_or("00");
+                    iso += &format!("{}{}:{}", sign as char, &zone_digits[..2], minutes);
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(iso)
+}
+
+/// Write an ICC version 2 display profile for sRGB. The colorants are
+/// adapted to D50 with the Bradford transform, as in the usual sRGB
+/// profiles, and the tone curves are sampled from the sRGB transfer function.
+fn srgb_icc_profile() -> Vec<u8> {
+    fn s15f16(v: f64) -> [u8; 4] {
+        ((v * 65536.).round() as i32).to_be_bytes()
+    }
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut tag = b"XYZ \0\0\0\0".to_vec();
+        tag.extend_from_slice(&s15f16(x));
+        tag.extend_from_slice(&s15f16(y));
+        tag.extend_from_slice(&s15f16(z));
+        tag
+    }
+
+    const DESCRIPTION: &[u8] = b"sRGB IEC61966-2.1";
+    let mut desc = b"desc\0\0\0\0".to_vec();
+    desc.extend_from_slice(&(DESCRIPTION.len() as u32 + 1).to_be_bytes());
+    desc.extend_from_slice(DESCRIPTION);
+    desc.push(0);
+    /* No Unicode or ScriptCode descriptions. */
+    desc.extend_from_slice(&[0; 4 + 4 + 2 + 1 + 67]);
+
+    let mut cprt = b"text\0\0\0\0".to_vec();
+    cprt.extend_from_slice(b"No copyright, use freely\0");
+
+    const CURVE_POINTS: usize = 1024;
+    let mut curv = b"curv\0\0\0\0".to_vec();
+    curv.extend_from_slice(&(CURVE_POINTS as u32).to_be_bytes());
+    for i in 0..CURVE_POINTS {
+        let v = i as f64 / (CURVE_POINTS - 1) as f64;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        curv.extend_from_slice(&((linear * 65535.).round() as u16).to_be_bytes());
+    }
+
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", desc),
+        (b"cprt", cprt),
+        (b"wtpt", xyz(0.9505, 1.0, 1.0891)),
+        (b"rXYZ", xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", curv),
+    ];
+
+    /* The three tone curves share their data. */
+    let num_entries = tags.len() + 2;
+    let mut table = Vec::new();
+    let mut data = Vec::new();
+    let data_start = 128 + 4 + 12 * num_entries;
+    let mut curve_entry = (0, 0);
+    for (sig, tag) in &tags {
+        let offset = data_start + data.len();
+        table.extend_from_slice(&sig[..]);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        if &sig[..] == b"rTRC" {
+            curve_entry = (offset, tag.len());
+        }
+        data.extend_from_slice(tag);
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+    }
+    for sig in &[b"gTRC", b"bTRC"] {
+        table.extend_from_slice(&sig[..]);
+        table.extend_from_slice(&(curve_entry.0 as u32).to_be_bytes());
+        table.extend_from_slice(&(curve_entry.1 as u32).to_be_bytes());
+    }
+
+    let size = data_start + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); /* preferred CMM */
+    profile.extend_from_slice(&[2, 0x10, 0, 0]); /* version 2.1 */
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    /* A fixed creation date keeps the output reproducible. */
+    for v in &[1998_u16, 2, 9, 6, 49, 0] {
+        profile.extend_from_slice(&v.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 4 + 4 + 4 + 4 + 8 + 4]); /* platform to rendering intent */
+    profile.extend_from_slice(&s15f16(0.9642));
+    profile.extend_from_slice(&s15f16(1.0));
+    profile.extend_from_slice(&s15f16(0.8249));
+    profile.resize(128, 0);
+    profile.extend_from_slice(&(num_entries as u32).to_be_bytes());
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
@@ -1209,8 +1209,14 @@ mod test {
 
     #[test]
     fn is_valid() {
-        assert!(WHITE.is_valid());
-        assert!(pdf_color::rgb(0.5, 0.5, 0.5).unwrap().is_valid());
-        assert!(pdf_color::cmyk(0.3, 0.4, 0.5, 0.6).unwrap().is_valid());
+        assert_eq!(PdfColor::from_gray(1.0).unwrap(), WHITE);
+        assert!(PdfColor::from_rgb(0.5, 0.5, 0.5).is_ok());
+        assert!(PdfColor::from_cmyk(0.3, 0.4, 0.5, 0.6).is_ok());
+        assert!(PdfColor::from_spot("Gold", 0.5).is_ok());
+
+        assert!(PdfColor::from_gray(-0.1).is_err());
+        assert!(PdfColor::from_rgb(0.5, 1.5, 0.5).is_err());
+        assert!(PdfColor::from_cmyk(0.3, 0.4, 0.5, 2.0).is_err());
+        assert!(PdfColor::from_spot("", 0.5).is_err());
     }
 }
@@ -378,6 +378,17 @@ pub(crate) fn pdf_sprint_length(buf: &mut Vec<u8>, value: f64) {
 pub(crate) fn pdf_sprint_number(buf: &mut Vec<u8>, value: f64) {
     p_dtoa(value, 8, buf);
 }
+/* Name including the /, with delimiters and other special bytes escaped. */
+pub(crate) fn pdf_sprint_name(buf: &mut Vec<u8>, name: &[u8]) {
+    buf.push(b'/');
+    for &byte in name {
+        if !(b'!'..=b'~').contains(&byte) || b"#()/<>[]{}%".contains(&byte) {
+            buf.extend(format!("#{:02x}", byte).as_bytes());
+        } else {
+            buf.push(byte);
+        }
+    }
+}
 static mut dev_param: DevParam = DevParam {
     autorotate: 1,
     colormode: 1,
@@ -38,13 +38,16 @@ use super::dpx_dpxutil::{
     ht_append_table, ht_clear_iter, ht_clear_table, ht_init_table, ht_iter_next, ht_lookup_table,
     ht_set_iter, ht_table_size,
 };
-use super::dpx_dvipdfmx::is_xdv;
+use super::dpx_dvipdfmx::{is_xdv, PdfAConformance};
 use super::dpx_jpegimage::check_for_jpeg;
 use super::dpx_mem::new;
+use super::dpx_pdfa::{
+    pdf_get_pdfa, pdfa_check_annot, pdfa_metadata, pdfa_output_intent, pdfa_violation,
+};
 use super::dpx_pdfcolor::{pdf_close_colors, pdf_color_set_verbose, pdf_init_colors, WHITE};
 use super::dpx_pdfdev::{
-    pdf_dev_bop, pdf_dev_eop, pdf_dev_get_coord, pdf_dev_get_param, pdf_dev_reset_color,
-    pdf_dev_reset_fonts,
+    graphics_mode, pdf_dev_bop, pdf_dev_eop, pdf_dev_get_coord, pdf_dev_get_param,
+    pdf_dev_reset_color, pdf_dev_reset_fonts, pdf_sprint_name,
 };
 use super::dpx_pdfdraw::{
     pdf_dev_current_depth, pdf_dev_grestore, pdf_dev_grestore_to, pdf_dev_gsave,
@@ -65,10 +68,10 @@ use super::dpx_pdfximage::{
 use super::dpx_pngimage::check_for_png;
 use crate::bridge::{InFile, TTInputFormat};
 use crate::dpx_pdfobj::{
-    pdf_deref_obj, pdf_dict, pdf_file, pdf_file_get_catalog, pdf_link_obj, pdf_new_ref, pdf_obj,
-    pdf_out_flush, pdf_out_init, pdf_ref_obj, pdf_release_obj, pdf_remove_dict, pdf_set_encrypt,
-    pdf_set_id, pdf_set_info, pdf_set_root, pdf_stream, pdf_string, DerefObj, IntoObj, IntoRef,
-    Object, PushObj, STREAM_COMPRESS,
+    pdf_deref_obj, pdf_dict, pdf_file, pdf_file_get_catalog, pdf_link_obj, pdf_name, pdf_new_ref,
+    pdf_obj, pdf_out_flush, pdf_out_init, pdf_ref_obj, pdf_release_obj, pdf_remove_dict,
+    pdf_set_encrypt, pdf_set_id, pdf_set_info, pdf_set_root, pdf_stream, pdf_string, DerefObj,
+    IntoObj, IntoRef, Object, PushObj, STREAM_COMPRESS,
 };
 use libc::free;
 
@@ -142,6 +145,7 @@ pub(crate) struct PdfDoc {
     pub(crate) gotos: ht_table,
     pub(crate) opt: DocOpt,
     pub(crate) pending_forms: *mut form_list_node,
+    pub(crate) structure: DocStruct,
 }
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -181,6 +185,27 @@ pub(crate) struct DocRoot {
     pub(crate) names: *mut pdf_obj,
     pub(crate) threads: *mut pdf_obj,
 }
+#[derive(Clone)]
+pub(crate) struct pdf_struct_elem {
+    pub(crate) role: pdf_name,
+    pub(crate) dict: *mut pdf_obj,
+    pub(crate) page_no: usize,
+    pub(crate) kids: Vec<*mut pdf_obj>,
+}
+#[derive(Clone)]
+pub(crate) struct DocStruct {
+    /* The StructTreeRoot, created with the first structure element. */
+    pub(crate) root: *mut pdf_obj,
+    pub(crate) kids: Vec<*mut pdf_obj>,
+    pub(crate) open: Vec<pdf_struct_elem>,
+    pub(crate) role_map: *mut pdf_obj,
+    /* The Nums array of the ParentTree, one entry per finished page. */
+    pub(crate) parent_tree: Vec<*mut pdf_obj>,
+    /* The structure element of each MCID on the current page. */
+    pub(crate) page_mcids: Vec<*mut pdf_obj>,
+    /* Whether a marked-content sequence is open on the current page. */
+    pub(crate) marked: bool,
+}
 use super::dpx_dpxutil::ht_iter;
 
 use crate::dpx_pdfximage::{load_options, xform_info};
@@ -295,6 +320,15 @@ static mut pdoc: PdfDoc = PdfDoc {
         annot_grow: 0.,
     },
     pending_forms: ptr::null_mut(),
+    structure: DocStruct {
+        root: ptr::null_mut(),
+        kids: Vec::new(),
+        open: Vec::new(),
+        role_map: ptr::null_mut(),
+        parent_tree: Vec::new(),
+        page_mcids: Vec::new(),
+        marked: false,
+    },
 };
 
 pub(crate) unsafe fn pdf_doc() -> &'static PdfDoc {
@@ -466,6 +500,19 @@ impl PdfDoc {
                 .as_dict_mut()
                 .set("CreationDate", pdf_string::new(now));
         }
+        if pdf_get_pdfa().is_some() {
+            /* A document that brings its own metadata, say from the pdfx
+             * package, is trusted to keep it in step with DocInfo. */
+            let catalog = (*self.root.dict).as_dict_mut();
+            if !catalog.has("Metadata") {
+                catalog.set("Metadata", pdfa_metadata(docinfo.as_dict()).into_ref());
+            }
+            if !catalog.has("OutputIntents") {
+                let mut intents = vec![];
+                intents.push_obj(pdfa_output_intent());
+                catalog.set("OutputIntents", intents);
+            }
+        }
         pdf_release_obj(docinfo);
         self.info = ptr::null_mut();
     }
@@ -1454,6 +1501,11 @@ impl PdfDoc {
             );
             return ERR;
         }
+        if category == b"EmbeddedFiles" && pdf_get_pdfa() == Some(PdfAConformance::A2b) {
+            pdfa_violation(
+                "Embedded files are only allowed in PDF/A-3b, unless they are PDF/A files.",
+            );
+        }
         if self.names[i].data.is_null() {
             self.names[i].data = pdf_new_name_tree()
         }
@@ -1709,6 +1761,7 @@ impl PdfDoc {
         rect_array.push_obj(((annbox.max.x + annot_grow) / 0.001 + 0.5).floor() * 0.001);
         rect_array.push_obj(((annbox.max.y + annot_grow) / 0.001 + 0.5).floor() * 0.001);
         (*annot_dict).as_dict_mut().set("Rect", rect_array);
+        pdfa_check_annot((*annot_dict).as_dict_mut());
         (*page.annots).as_array_mut().push(pdf_ref_obj(annot_dict));
         if new_annot != 0 {
             self.add_goto((*annot_dict).as_dict_mut());
@@ -1894,6 +1947,173 @@ impl PdfDoc {
         };
     }
 
+    /*
+     * Logical structure (tagged PDF)
+     *
+     * Structure elements nest like the specials that open and close them.
+     * The content of the innermost open element is enclosed in marked-content
+     * sequences, each with its own MCID. A sequence can't contain another one
+     * or span pages, so it is closed and a new one opened whenever a child
+     * element starts or ends, or the page changes.
+     */
+    unsafe fn init_structure(&mut self) {
+        let st = &mut self.structure;
+        st.root = ptr::null_mut();
+        st.kids = Vec::new();
+        st.open = Vec::new();
+        st.role_map = ptr::null_mut();
+        st.parent_tree = Vec::new();
+        st.page_mcids = Vec::new();
+        st.marked = false;
+    }
+
+    /// Start a structure element of type `role` inside the innermost open
+    /// one. `attrs` holds its other entries, such as `Alt` or `Lang`.
+    pub(crate) unsafe fn begin_struct(&mut self, role: &pdf_name, mut attrs: pdf_dict) {
+        self.end_marked_content();
+        let page_no = self.current_page_number();
+        let page_ref = self.ref_page(page_no);
+        let st = &mut self.structure;
+        if st.root.is_null() {
+            st.root = pdf_dict::new().into_obj();
+        }
+        let parent = st.open.last().map_or(st.root, |elem| elem.dict);
+        attrs.set("Type", "StructElem");
+        attrs.set("S", role.clone());
+        attrs.set("P", pdf_ref_obj(parent));
+        attrs.set("Pg", page_ref);
+        let dict = attrs.into_obj();
+        match st.open.last_mut() {
+            Some(elem) => elem.kids.push(pdf_ref_obj(dict)),
+            None => st.kids.push(pdf_ref_obj(dict)),
+        }
+        st.open.push(pdf_struct_elem {
+            role: role.clone(),
+            dict,
+            page_no,
+            kids: Vec::new(),
+        });
+        self.begin_marked_content();
+    }
+
+    /// End the innermost open structure element. Returns false if there is
+    /// none.
+    pub(crate) unsafe fn end_struct(&mut self) -> bool {
+        if self.structure.open.is_empty() {
+            return false;
+        }
+        self.end_marked_content();
+        let elem = self.structure.open.pop().unwrap();
+        (*elem.dict).as_dict_mut().set("K", elem.kids);
+        pdf_release_obj(elem.dict);
+        self.begin_marked_content();
+        true
+    }
+
+    /// Map custom structure types to standard ones in the RoleMap.
+    pub(crate) unsafe fn add_role_map(&mut self, map: &pdf_dict) {
+        if self.structure.role_map.is_null() {
+            self.structure.role_map = pdf_dict::new().into_obj();
+        }
+        (*self.structure.role_map).as_dict_mut().merge(map);
+    }
+
+    unsafe fn begin_marked_content(&mut self) {
+        let page_no = self.current_page_number();
+        let page = self.pages.entries[self.pages.num_entries];
+        let st = &mut self.structure;
+        let elem = match st.open.last_mut() {
+            Some(elem) if !st.marked => elem,
+            _ => return,
+        };
+        let mcid = st.page_mcids.len();
+        if mcid == 0 {
+            let key = st.parent_tree.len() / 2;
+            (*page.page_obj)
+                .as_dict_mut()
+                .set("StructParents", key as f64);
+            (*page.page_obj).as_dict_mut().set("Tabs", "S");
+        }
+        st.page_mcids.push(pdf_ref_obj(elem.dict));
+        if elem.page_no == page_no {
+            elem.kids.push_obj(mcid as f64);
+        } else {
+            /* The element started on an earlier page. */
+            let mut mcr = pdf_dict::new();
+            mcr.set("Type", "MCR");
+            mcr.set("Pg", pdf_link_obj(page.page_ref));
+            mcr.set("MCID", mcid as f64);
+            elem.kids.push_obj(mcr);
+        }
+        let mut buf = vec![b' '];
+        pdf_sprint_name(&mut buf, elem.role.to_bytes());
+        buf.extend(format!(" <</MCID {}>> BDC", mcid).as_bytes());
+        st.marked = true;
+        graphics_mode();
+        self.add_page_content(&buf);
+    }
+
+    unsafe fn end_marked_content(&mut self) {
+        if self.structure.marked {
+            graphics_mode();
+            self.add_page_content(b" EMC");
+            self.structure.marked = false;
+        }
+    }
+
+    unsafe fn finish_page_structure(&mut self) {
+        self.end_marked_content();
+        let st = &mut self.structure;
+        if !st.page_mcids.is_empty() {
+            let key = st.parent_tree.len() / 2;
+            st.parent_tree.push_obj(key as f64);
+            st.parent_tree.push_obj(std::mem::take(&mut st.page_mcids));
+        }
+    }
+
+    unsafe fn close_structure(&mut self) {
+        let st = &mut self.structure;
+        if !st.open.is_empty() {
+            warn!("Unbalanced bstruct and estruct found.");
+            while let Some(elem) = st.open.pop() {
+                (*elem.dict).as_dict_mut().set("K", elem.kids);
+                pdf_release_obj(elem.dict);
+            }
+        }
+        if st.root.is_null() {
+            if !st.role_map.is_null() {
+                pdf_release_obj(st.role_map);
+                st.role_map = ptr::null_mut();
+            }
+            return;
+        }
+        let root = (*st.root).as_dict_mut();
+        root.set("Type", "StructTreeRoot");
+        root.set("K", std::mem::take(&mut st.kids));
+        let mut parent_tree = pdf_dict::new();
+        let next_key = st.parent_tree.len() / 2;
+        parent_tree.set("Nums", std::mem::take(&mut st.parent_tree));
+        root.set("ParentTree", parent_tree.into_ref());
+        root.set("ParentTreeNextKey", next_key as f64);
+        if !st.role_map.is_null() {
+            root.set("RoleMap", st.role_map);
+            st.role_map = ptr::null_mut();
+        }
+        let catalog = (*self.root.dict).as_dict_mut();
+        if catalog.has("StructTreeRoot") {
+            warn!("Could not add StructTreeRoot: the catalog already has one.");
+        } else {
+            catalog.set("StructTreeRoot", pdf_ref_obj(st.root));
+        }
+        if !catalog.has("MarkInfo") {
+            let mut mark_info = pdf_dict::new();
+            mark_info.set("Marked", true);
+            catalog.set("MarkInfo", mark_info);
+        }
+        pdf_release_obj(st.root);
+        st.root = ptr::null_mut();
+    }
+
     /* page_no = 0 for root page tree node. */
     pub(crate) unsafe fn set_mediabox(&mut self, page_no: usize, mediabox: &Rect) {
         if page_no == 0 {
@@ -2154,9 +2374,11 @@ impl PdfDoc {
         /* pdf_doc_new_page() allocates page content stream. */
         self.new_page();
         pdf_dev_bop(&mut M);
+        self.begin_marked_content();
     }
 
     pub(crate) unsafe fn end_page(&mut self) {
+        self.finish_page_structure();
         pdf_dev_eop();
         self.fill_page_background();
         self.finish_page();
@@ -2204,6 +2426,7 @@ impl PdfDoc {
         self.init_articles();
         self.init_names(check_gotos);
         self.init_page_tree(media_width, media_height);
+        self.init_structure();
         pdf_doc_set_bgcolor(None);
         if enable_encrypt {
             let encrypt = pdf_encrypt_obj();
@@ -2228,6 +2451,7 @@ impl PdfDoc {
         self.close_articles(); /* Should be at last. */
         self.close_names();
         self.close_bookmarks();
+        self.close_structure();
         self.close_page_tree();
         self.close_docinfo();
         self.close_catalog();
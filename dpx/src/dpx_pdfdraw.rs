@@ -30,6 +30,7 @@ use euclid::point2;
 
 use crate::warn;
 
+use super::dpx_pdfa::pdfa_violation;
 use super::dpx_pdfcolor::{PdfColor, BLACK};
 use super::dpx_pdfdev::{
     graphics_mode, pdf_dev_get_param, pdf_dev_reset_fonts, pdf_sprint_coord, pdf_sprint_length,
@@ -726,6 +727,7 @@ pub(crate) unsafe fn pdf_dev_set_color(color: &PdfColor, mask: u8, force: i32) {
             }
         }
         PdfColor::Cmyk(..) => {
+            pdfa_violation("CMYK colors need a CMYK output intent; use RGB or gray colors.");
             if mask == 0 {
                 " K"
             } else {
@@ -900,6 +902,19 @@ pub(crate) unsafe fn pdf_dev_flushpath(p_op: u8, fill_rule: i32) -> i32 {
     error
 }
 
+/// Paint the current path, like `pdf_dev_flushpath()`.
+///
+/// `pdf_dev_flushpath()` only writes out paths marked in `path_added`, which
+/// the tpic and MetaPost code never does, so their output stays as it was.
+/// SVG drawing wants every path it builds to be painted.
+pub(crate) unsafe fn pdf_dev_paintpath(p_op: u8, fill_rule: i32) -> i32 {
+    let gss = unsafe { &mut gs_stack };
+    if !gss.last().unwrap().path.path.is_empty() {
+        path_added = 1;
+    }
+    pdf_dev_flushpath(p_op, fill_rule)
+}
+
 pub(crate) unsafe fn pdf_dev_newpath() -> i32 {
     let gss = unsafe { &mut gs_stack };
     let gs = gss.last_mut().unwrap();
@@ -36,6 +36,7 @@ use super::dpx_agl::{agl_lookup_list, agl_sput_UTF16BE};
 use super::dpx_cid::CSI_UNICODE;
 use super::dpx_cmap::CMap;
 use super::dpx_cmap_write::CMap_create_stream;
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_dpxfile::dpx_tt_open;
 use crate::dpx_pdfobj::{
     pdf_dict, pdf_get_version, pdf_link_obj, pdf_name, pdf_obj, pdf_release_obj, pdf_stream,
@@ -351,7 +352,8 @@ unsafe fn pdf_encoding_new_encoding(
     enc_id as i32
 }
 /* Creates Encoding resource and ToUnicode CMap
- * for all non-predefined encodings.
+ * for all non-predefined encodings, and ToUnicode CMaps
+ * for the predefined ones too when making PDF/A.
  */
 
 pub(crate) unsafe fn pdf_encoding_complete() {
@@ -376,6 +378,8 @@ pub(crate) unsafe fn pdf_encoding_complete() {
                     ptr::null_mut()
                 },
             );
+        }
+        if !encoding_is_predefined || pdf_get_pdfa().is_some() {
             assert!(encoding.tounicode.is_null());
             encoding.tounicode = pdf_create_ToUnicode_CMap(
                 &encoding.enc_name,
@@ -37,6 +37,7 @@ use super::dpx_cmap::{
 };
 use super::dpx_fontmap::fontmap;
 use super::dpx_mem::new;
+use super::dpx_pdfa::{pdf_get_pdfa, pdfa_violation};
 use super::dpx_pdfencoding::{
     pdf_close_encodings, pdf_encoding_add_usedchars, pdf_encoding_complete,
     pdf_encoding_findresource, pdf_encoding_get_name, pdf_encoding_get_tounicode,
@@ -125,9 +126,20 @@ pub(crate) unsafe fn pdf_font_set_dpi(font_dpi: i32) {
  */
 
 use std::time::SystemTime;
+
+static mut source_date_epoch: Option<u64> = None;
+
+pub(crate) unsafe fn pdf_font_set_source_date_epoch(epoch: Option<u64>) {
+    source_date_epoch = epoch;
+}
+
 pub(crate) fn get_unique_time_if_given() -> Option<SystemTime> {
     use std::time::Duration;
 
+    if let Some(x) = unsafe { source_date_epoch } {
+        return SystemTime::UNIX_EPOCH.checked_add(Duration::new(x, 0));
+    }
+
     let env = std::env::var("SOURCE_DATE_EPOCH");
 
     env.ok()
@@ -472,6 +484,19 @@ pub(crate) unsafe fn pdf_close_fonts() {
                 .as_dict_mut()
                 .set("Encoding", "MacRomanEncoding"); /* After encoding */
         }
+        if pdf_get_pdfa().is_some() && (*font_0).subtype != 4 && !(*font_0).resource.is_null() {
+            if pdf_font_get_flag(font_0, 1 << 0) != 0 {
+                pdfa_violation(&format!(
+                    "Font \"{}\" is not embedded.",
+                    (*font_0).fontname
+                ));
+            } else if (*font_0).subtype != 2 && !(*(*font_0).resource).as_dict().has("ToUnicode") {
+                warn!(
+                    "Font \"{}\" has no ToUnicode map; its text may not be searchable.",
+                    (*font_0).fontname
+                );
+            }
+        }
         pdf_flush_font(&mut *font_0);
         pdf_clean_font_struct(font_0);
         font_id += 1
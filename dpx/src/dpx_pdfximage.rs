@@ -29,25 +29,24 @@
 use euclid::point2;
 
 use crate::mfree;
-use crate::strstartswith;
 use crate::{info, warn};
 use std::ptr;
 
-use super::dpx_bmpimage::{bmp_include_image, check_for_bmp};
+use super::dpx_bmpimage::bmp_include_image;
 use super::dpx_dpxfile::{dpx_delete_temp_file, keep_cache};
-use super::dpx_jpegimage::{check_for_jpeg, jpeg_include_image};
-use super::dpx_mfileio::{tt_mfgets, work_buffer};
+use super::dpx_gifimage::gif_include_image;
+use super::dpx_imageprobe::{probe_image_type, ImageType};
+use super::dpx_jp2image::jp2_include_image;
+use super::dpx_jpegimage::jpeg_include_image;
 use super::dpx_pdfdraw::pdf_dev_transform;
-use super::dpx_pngimage::{check_for_png, png_include_image};
+use super::dpx_pngimage::png_include_image;
+use super::dpx_svgimage::svg_include_image;
+use super::dpx_tiffimage::tiff_include_image;
 use crate::dpx_epdf::pdf_include_page;
 use crate::dpx_pdfdoc::PdfPageBoundary;
-use crate::dpx_pdfobj::{
-    check_for_pdf, pdf_link_obj, pdf_obj, pdf_ref_obj, pdf_release_obj, Object,
-};
+use crate::dpx_pdfobj::{pdf_link_obj, pdf_obj, pdf_ref_obj, pdf_release_obj, Object};
 use crate::shims::sprintf;
 
-use std::io::{Read, Seek, SeekFrom};
-
 use crate::bridge::{InFile, TTInputFormat};
 
 use super::dpx_pdfdev::{transform_info, Point, Rect, TMatrix};
@@ -59,18 +58,6 @@ pub(crate) enum PdfXObjectType {
     Image,
 }
 
-#[derive(Copy, Clone)]
-pub(crate) enum ImageType {
-    Unknown,
-    Pdf,
-    Jpeg,
-    Png,
-    Eps,
-    Bmp,
-    #[allow(unused)]
-    Jp2,
-}
-
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub(crate) struct ximage_info {
@@ -210,26 +197,6 @@ pub(crate) unsafe fn pdf_close_images() {
     ximages = Vec::new();
     _opts.cmdtmpl = mfree(_opts.cmdtmpl as *mut libc::c_void) as *mut i8;
 }
-unsafe fn source_image_type<R: Read + Seek>(handle: &mut R) -> ImageType {
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    /* Original check order: jpeg, jp2, png, bmp, pdf, ps */
-    let format = if check_for_jpeg(handle) != 0 {
-        ImageType::Jpeg
-    } else if check_for_png(handle) != 0 {
-        ImageType::Png
-    } else if check_for_bmp(handle) {
-        ImageType::Bmp
-    } else if check_for_pdf(handle) {
-        ImageType::Pdf
-    } else if check_for_ps(handle) != 0 {
-        ImageType::Eps
-    } else {
-        warn!("Tectonic was unable to detect an image\'s format");
-        ImageType::Unknown
-    };
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    format
-}
 unsafe fn load_image(
     ident: &str,
     fullname: &str,
@@ -237,7 +204,6 @@ unsafe fn load_image(
     mut handle: InFile,
     options: load_options,
 ) -> i32 {
-    let id = ximages.len();
     let mut I = pdf_ximage::new();
     if !ident.is_empty() {
         I.ident = ident.to_string();
@@ -247,8 +213,6 @@ unsafe fn load_image(
     }
     I.attr.page_no = options.page_no;
     I.attr.bbox_type = options.bbox_type;
-    /* else if (check_for_jp2(fp))
-     *    format = IMAGE_TYPE_JP2; */
     I.attr.dict = options.dict; /* unsafe? */
     match format {
         ImageType::Jpeg => {
@@ -265,11 +229,10 @@ unsafe fn load_image(
             if _opts.verbose != 0 {
                 info!("[JP2]");
             }
-            /*if (jp2_include_image(I, fp) < 0)*/
-            warn!("Tectonic: JP2 not yet supported");
-            return -1;
-            /*I.subtype = PdfXObjectType::Image;
-            break;*/
+            if jp2_include_image(&mut I, &mut handle) < 0 {
+                return -1;
+            }
+            I.subtype = PdfXObjectType::Image;
         }
         ImageType::Png => {
             if _opts.verbose != 0 {
@@ -280,6 +243,24 @@ unsafe fn load_image(
             }
             I.subtype = PdfXObjectType::Image;
         }
+        ImageType::Gif => {
+            if _opts.verbose != 0 {
+                info!("[GIF]");
+            }
+            if gif_include_image(&mut I, &mut handle).is_err() {
+                return -1;
+            }
+            I.subtype = PdfXObjectType::Image;
+        }
+        ImageType::Tiff => {
+            if _opts.verbose != 0 {
+                info!("[TIFF]");
+            }
+            if tiff_include_image(&mut I, &mut handle).is_err() {
+                return -1;
+            }
+            I.subtype = PdfXObjectType::Image;
+        }
         ImageType::Bmp => {
             if _opts.verbose != 0 {
                 info!("[BMP]");
@@ -289,6 +270,15 @@ unsafe fn load_image(
             }
             I.subtype = PdfXObjectType::Image;
         }
+        ImageType::Svg => {
+            if _opts.verbose != 0 {
+                info!("[SVG]");
+            }
+            if svg_include_image(&mut I, &mut handle).is_err() {
+                return -1;
+            }
+            I.subtype = PdfXObjectType::Form;
+        }
         ImageType::Pdf => {
             if _opts.verbose != 0 {
                 info!("[PDF]");
@@ -320,6 +310,8 @@ unsafe fn load_image(
         }
     }
 
+    /* Not earlier: drawing an SVG image defines a resource of its own. */
+    let id = ximages.len();
     match I.subtype {
         PdfXObjectType::Image => {
             sprintf(
@@ -376,7 +368,10 @@ pub(crate) unsafe fn pdf_ximage_findresource(ident: &str, options: load_options)
     if _opts.verbose != 0 {
         info!("(Image:{}", ident);
     }
-    let format = source_image_type(&mut handle);
+    let format = probe_image_type(&mut handle);
+    if format == ImageType::Unknown {
+        warn!("Tectonic was unable to detect an image\'s format");
+    }
     let id = load_image(ident, ident, format, handle, options);
     if _opts.verbose != 0 {
         info!(")");
@@ -803,16 +798,3 @@ pub(crate) unsafe fn pdf_ximage_scale_image(id: i32, p: &transform_info) -> (Rec
 /*pub(crate) unsafe fn get_distiller_template() -> *mut i8 {
     _opts.cmdtmpl
 }*/
-unsafe fn check_for_ps<R: Read + Seek>(handle: &mut R) -> i32 {
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    tt_mfgets(work_buffer.as_mut_ptr(), 1024, handle);
-    if !strstartswith(
-        work_buffer.as_mut_ptr(),
-        b"%!\x00" as *const u8 as *const i8,
-    )
-    .is_null()
-    {
-        return 1;
-    }
-    0
-}
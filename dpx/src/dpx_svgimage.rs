@@ -0,0 +1,2437 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! SVG images, translated into form XObjects with the same drawing
+//! operators as MetaPost figures.
+//!
+//! This covers what plotting libraries write: paths and basic shapes,
+//! groups, `<use>`, transforms, solid and gradient fills and strokes,
+//! opacity, clip paths and simple style sheets. Text must have been
+//! converted to paths; `<text>`, `<image>`, markers, masks, patterns and
+//! filters are skipped. Group opacity is applied to each shape separately.
+
+use crate::dpx_pdfcolor::PdfColor;
+use crate::dpx_pdfdev::{graphics_mode, Point, Rect, TMatrix};
+use crate::dpx_pdfdoc::pdf_doc_mut;
+use crate::dpx_pdfdraw::{
+    pdf_dev_closepath, pdf_dev_concat, pdf_dev_curveto, pdf_dev_grestore, pdf_dev_gsave,
+    pdf_dev_lineto, pdf_dev_moveto, pdf_dev_paintpath, pdf_dev_rectclip, pdf_dev_set_color,
+    pdf_dev_setdash, pdf_dev_setlinecap, pdf_dev_setlinejoin, pdf_dev_setlinewidth,
+    pdf_dev_setmiterlimit,
+};
+use crate::dpx_pdfobj::{pdf_dict, pdf_obj, IntoObj, IntoRef, PushObj};
+use crate::dpx_pdfximage::{pdf_ximage, pdf_ximage_get_reference, xform_info};
+use crate::warn;
+
+use euclid::point2;
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom};
+use std::ptr;
+
+/// CSS pixels are 1/96 inch.
+const BP_PER_PX: f64 = 0.75;
+/// How deeply groups and `<use>` references may nest.
+const MAX_DEPTH: usize = 64;
+/// How many elements may be rendered in all. Nested `<use>` references can
+/// multiply a small file into a huge drawing without nesting deeply.
+const MAX_ELEMENTS: usize = 1 << 20;
+/// How many bytes to look at for the `<svg` tag when probing a file.
+const PROBE_LEN: usize = 4096;
+
+pub fn check_for_svg<R: Read + Seek>(handle: &mut R) -> bool {
+    let mut buf = Vec::with_capacity(PROBE_LEN);
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle
+        .by_ref()
+        .take(PROBE_LEN as u64)
+        .read_to_end(&mut buf)
+        .is_err()
+    {
+        return false;
+    }
+    let mut text = &buf[..];
+    if text.starts_with(b"\xef\xbb\xbf") {
+        text = &text[3..];
+    }
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    let text = &text[start..];
+    if !(text.starts_with(b"<?xml") || text.starts_with(b"<!") || text.starts_with(b"<svg")) {
+        return false;
+    }
+    text.windows(4).any(|w| w == b"<svg")
+}
+
+/// Get the width and height of an SVG image in big points.
+pub fn svg_get_size<R: Read + Seek>(handle: &mut R) -> Result<(f64, f64), ()> {
+    let root = read_svg(handle)?;
+    let (width, height, _) = root_viewport(&root)?;
+    Ok((width * BP_PER_PX, height * BP_PER_PX))
+}
+
+pub(crate) unsafe fn svg_include_image<R: Read + Seek>(
+    ximage: &mut pdf_ximage,
+    handle: &mut R,
+) -> Result<(), ()> {
+    let root = read_svg(handle)?;
+    let (width, height, view_box) = root_viewport(&root)?;
+    let bbox = Rect::new(
+        point2(0., 0.),
+        point2(width * BP_PER_PX, height * BP_PER_PX),
+    );
+    /* SVG's y axis points down. */
+    let mut ctm = TMatrix::row_major(BP_PER_PX, 0., 0., -BP_PER_PX, 0., bbox.max.y);
+    if let Some(view_box) = view_box {
+        let par = root.attr("preserveAspectRatio").unwrap_or("");
+        ctm = view_box_transform(view_box, par, width, height).post_transform(&ctm);
+    }
+
+    graphics_mode();
+    let xobj_id = pdf_doc_mut().begin_grabbing("", 0., 0., &bbox);
+    let mut renderer = Renderer::new(&root, (width, height));
+    let style = Style::default();
+    for child in &root.children {
+        renderer.render(child, &style, &ctm, 0);
+    }
+    pdf_doc_mut().end_grabbing(ptr::null_mut());
+
+    let mut info = xform_info::new();
+    info.bbox = bbox;
+    ximage.set_form(&info, pdf_ximage_get_reference(xobj_id));
+    Ok(())
+}
+
+fn read_svg<R: Read + Seek>(handle: &mut R) -> Result<Element, ()> {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("Reading SVG file failed...");
+        return Err(());
+    }
+    let root = parse_xml(&String::from_utf8_lossy(&data))?;
+    if root.name != "svg" {
+        warn!("SVG: The root element is <{}>, not <svg>.", root.name);
+        return Err(());
+    }
+    Ok(root)
+}
+
+/// Work out the size of the root viewport in CSS pixels, and its viewBox.
+fn root_viewport(root: &Element) -> Result<(f64, f64, Option<[f64; 4]>), ()> {
+    let view_box = root.attr("viewBox").and_then(parse_view_box);
+    let absolute = |name| {
+        root.attr(name)
+            .and_then(parse_length)
+            .filter(|&(v, unit)| unit != "%" && v > 0.)
+            .and_then(|(v, unit)| absolute_length(v, unit))
+    };
+    let size = match (absolute("width"), absolute("height"), view_box) {
+        (Some(w), Some(h), _) => Some((w, h)),
+        (Some(w), None, Some(vb)) => Some((w, w * vb[3] / vb[2])),
+        (None, Some(h), Some(vb)) => Some((h * vb[2] / vb[3], h)),
+        (None, None, Some(vb)) => Some((vb[2], vb[3])),
+        _ => None,
+    };
+    match size {
+        Some((w, h)) => Ok((w, h, view_box)),
+        None => {
+            warn!(
+                "SVG: Cannot determine the image size; it needs a width and height or a viewBox."
+            );
+            Err(())
+        }
+    }
+}
+
+fn parse_view_box(value: &str) -> Option<[f64; 4]> {
+    let mut numbers = Numbers::new(value);
+    let mut vb = [0.; 4];
+    for v in &mut vb {
+        *v = numbers.number()?;
+    }
+    if vb[2] > 0. && vb[3] > 0. {
+        Some(vb)
+    } else {
+        None
+    }
+}
+
+/// The transformation that maps a viewBox onto a `width` by `height`
+/// viewport, following its preserveAspectRatio attribute.
+fn view_box_transform(vb: [f64; 4], par: &str, width: f64, height: f64) -> TMatrix {
+    let mut words = par.split_whitespace();
+    let align = words.next().unwrap_or("xMidYMid");
+    let slice = words.next() == Some("slice");
+    let mut sx = width / vb[2];
+    let mut sy = height / vb[3];
+    let (mut tx, mut ty) = (0., 0.);
+    if align != "none" {
+        let s = if slice { sx.max(sy) } else { sx.min(sy) };
+        sx = s;
+        sy = s;
+        let free_x = width - vb[2] * s;
+        let free_y = height - vb[3] * s;
+        if align.contains("xMid") {
+            tx = free_x / 2.;
+        } else if align.contains("xMax") {
+            tx = free_x;
+        }
+        if align.contains("YMid") {
+            ty = free_y / 2.;
+        } else if align.contains("YMax") {
+            ty = free_y;
+        }
+    }
+    TMatrix::row_major(sx, 0., 0., sy, tx - vb[0] * sx, ty - vb[1] * sy)
+}
+
+/* Lengths */
+
+fn parse_length(value: &str) -> Option<(f64, &str)> {
+    let value = value.trim();
+    let end = value
+        .find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E' || c == '%')
+        .unwrap_or(value.len());
+    /* An exponent can't be told from the "em" and "ex" units up front. */
+    let (number, unit) = if value.ends_with("em") || value.ends_with("ex") {
+        value.split_at(value.len() - 2)
+    } else {
+        value.split_at(end)
+    };
+    number.trim().parse().ok().map(|v| (v, unit.trim()))
+}
+
+/// Convert a length in an absolute unit to CSS pixels.
+fn absolute_length(value: f64, unit: &str) -> Option<f64> {
+    let scale = match unit {
+        "" | "px" => 1.,
+        "pt" => 96. / 72.,
+        "pc" => 16.,
+        "mm" => 96. / 25.4,
+        "cm" => 96. / 2.54,
+        "in" => 96.,
+        "em" => 16.,
+        "ex" => 8.,
+        _ => return None,
+    };
+    Some(value * scale)
+}
+
+/// What a percentage length is relative to.
+#[derive(Copy, Clone)]
+enum Dir {
+    X,
+    Y,
+    Other,
+}
+
+/* Numbers, as they appear in path data, point lists and transforms */
+
+struct Numbers<'s> {
+    s: &'s [u8],
+    pos: usize,
+}
+
+impl<'s> Numbers<'s> {
+    fn new(s: &'s str) -> Self {
+        Numbers {
+            s: s.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn skip_separators(&mut self) {
+        while self.pos < self.s.len()
+            && (self.s[self.pos].is_ascii_whitespace() || self.s[self.pos] == b',')
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_separators();
+        self.s.get(self.pos).copied()
+    }
+
+    fn digits(&mut self) -> usize {
+        let start = self.pos;
+        while self.pos < self.s.len() && self.s[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_separators();
+        let start = self.pos;
+        if matches!(self.s.get(self.pos), Some(b'+') | Some(b'-')) {
+            self.pos += 1;
+        }
+        let mut num_digits = self.digits();
+        if self.s.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            num_digits += self.digits();
+        }
+        if num_digits == 0 {
+            self.pos = start;
+            return None;
+        }
+        if matches!(self.s.get(self.pos), Some(b'e') | Some(b'E')) {
+            let mantissa_end = self.pos;
+            self.pos += 1;
+            if matches!(self.s.get(self.pos), Some(b'+') | Some(b'-')) {
+                self.pos += 1;
+            }
+            if self.digits() == 0 {
+                self.pos = mantissa_end;
+            }
+        }
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()?
+            .parse()
+            .ok()
+    }
+
+    /// Arc flags are single digits, which needn't be separated from what
+    /// follows.
+    fn flag(&mut self) -> Option<bool> {
+        match self.peek()? {
+            b'0' => {
+                self.pos += 1;
+                Some(false)
+            }
+            b'1' => {
+                self.pos += 1;
+                Some(true)
+            }
+            _ => None,
+        }
+    }
+
+    fn point(&mut self) -> Option<Point> {
+        let x = self.number()?;
+        let y = self.number()?;
+        Some(point2(x, y))
+    }
+}
+
+/* Transforms */
+
+fn parse_transform(value: &str) -> TMatrix {
+    let mut matrix = TMatrix::identity();
+    for item in value.split(')') {
+        let mut parts = item.splitn(2, '(');
+        let name = parts
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c: char| c.is_whitespace() || c == ',');
+        let args = parts.next().unwrap_or("");
+        let mut numbers = Numbers::new(args);
+        let mut a = Vec::new();
+        while let Some(v) = numbers.number() {
+            a.push(v);
+        }
+        let t = match (name, a.len()) {
+            ("matrix", 6) => TMatrix::row_major(a[0], a[1], a[2], a[3], a[4], a[5]),
+            ("translate", 1) => TMatrix::create_translation(a[0], 0.),
+            ("translate", 2) => TMatrix::create_translation(a[0], a[1]),
+            ("scale", 1) => TMatrix::create_scale(a[0], a[0]),
+            ("scale", 2) => TMatrix::create_scale(a[0], a[1]),
+            ("rotate", 1) | ("rotate", 3) => {
+                let (s, c) = a[0].to_radians().sin_cos();
+                let r = TMatrix::row_major(c, s, -s, c, 0., 0.);
+                if a.len() == 3 {
+                    TMatrix::create_translation(-a[1], -a[2])
+                        .post_transform(&r)
+                        .post_transform(&TMatrix::create_translation(a[1], a[2]))
+                } else {
+                    r
+                }
+            }
+            ("skewX", 1) => TMatrix::row_major(1., 0., a[0].to_radians().tan(), 1., 0., 0.),
+            ("skewY", 1) => TMatrix::row_major(1., a[0].to_radians().tan(), 0., 1., 0., 0.),
+            ("", _) => continue,
+            _ => {
+                warn!("SVG: Invalid transform \"{}\".", value);
+                return TMatrix::identity();
+            }
+        };
+        /* The transforms of the list apply right to left. */
+        matrix = t.post_transform(&matrix);
+    }
+    matrix
+}
+
+/* Paths */
+
+#[derive(Copy, Clone)]
+enum Seg {
+    Move(Point),
+    Line(Point),
+    Cubic(Point, Point, Point),
+    Close,
+}
+
+/// Parse path data into absolute moves, lines and cubic Béziers. As the
+/// specification asks, an error ends the path rather than dropping it.
+fn parse_path_data(d: &str) -> Vec<Seg> {
+    let mut n = Numbers::new(d);
+    let mut segs = Vec::new();
+    let mut cmd: Option<u8> = None;
+    let mut cur = Point::zero();
+    let mut start = Point::zero();
+    let mut last_cubic: Option<Point> = None;
+    let mut last_quad: Option<Point> = None;
+    let mut need_move = false;
+
+    while let Some(c) = n.peek() {
+        if c.is_ascii_alphabetic() {
+            n.pos += 1;
+            if cmd.is_none() && c != b'M' && c != b'm' {
+                break;
+            }
+            if c == b'Z' || c == b'z' {
+                segs.push(Seg::Close);
+                cur = start;
+                need_move = true;
+                last_cubic = None;
+                last_quad = None;
+                cmd = Some(c);
+                continue;
+            }
+            cmd = Some(c);
+        }
+        let c = match cmd {
+            Some(c) if c != b'Z' && c != b'z' => c,
+            _ => break,
+        };
+        let base = if c.is_ascii_lowercase() {
+            cur.to_vector()
+        } else {
+            Point::zero().to_vector()
+        };
+        let upper = c.to_ascii_uppercase();
+        if upper == b'M' {
+            let p = match n.point() {
+                Some(p) => p + base,
+                None => break,
+            };
+            segs.push(Seg::Move(p));
+            cur = p;
+            start = p;
+            need_move = false;
+            last_cubic = None;
+            last_quad = None;
+            /* Further coordinate pairs are implicit linetos. */
+            cmd = Some(if c == b'm' { b'l' } else { b'L' });
+            continue;
+        }
+        if need_move {
+            segs.push(Seg::Move(cur));
+            need_move = false;
+        }
+        let (seg, cubic, quad) = match upper {
+            b'L' => match n.point() {
+                Some(p) => (Seg::Line(p + base), None, None),
+                None => break,
+            },
+            b'H' => match n.number() {
+                Some(x) => (Seg::Line(point2(x + base.x, cur.y)), None, None),
+                None => break,
+            },
+            b'V' => match n.number() {
+                Some(y) => (Seg::Line(point2(cur.x, y + base.y)), None, None),
+                None => break,
+            },
+            b'C' => match (n.point(), n.point(), n.point()) {
+                (Some(c1), Some(c2), Some(p)) => (
+                    Seg::Cubic(c1 + base, c2 + base, p + base),
+                    Some(c2 + base),
+                    None,
+                ),
+                _ => break,
+            },
+            b'S' => match (n.point(), n.point()) {
+                (Some(c2), Some(p)) => {
+                    let c1 = last_cubic.map_or(cur, |l| cur + (cur - l));
+                    (Seg::Cubic(c1, c2 + base, p + base), Some(c2 + base), None)
+                }
+                _ => break,
+            },
+            b'Q' => match (n.point(), n.point()) {
+                (Some(q), Some(p)) => {
+                    (quad_to_cubic(cur, q + base, p + base), None, Some(q + base))
+                }
+                _ => break,
+            },
+            b'T' => match n.point() {
+                Some(p) => {
+                    let q = last_quad.map_or(cur, |l| cur + (cur - l));
+                    (quad_to_cubic(cur, q, p + base), None, Some(q))
+                }
+                None => break,
+            },
+            b'A' => {
+                let args = (
+                    n.number(),
+                    n.number(),
+                    n.number(),
+                    n.flag(),
+                    n.flag(),
+                    n.point(),
+                );
+                match args {
+                    (Some(rx), Some(ry), Some(phi), Some(large), Some(sweep), Some(p)) => {
+                        let p = p + base;
+                        arc_to_cubics(cur, rx, ry, phi, large, sweep, p, &mut segs);
+                        cur = p;
+                        last_cubic = None;
+                        last_quad = None;
+                        continue;
+                    }
+                    _ => break,
+                }
+            }
+            _ => break,
+        };
+        cur = match seg {
+            Seg::Line(p) | Seg::Cubic(_, _, p) => p,
+            _ => cur,
+        };
+        segs.push(seg);
+        last_cubic = cubic;
+        last_quad = quad;
+    }
+    segs
+}
+
+fn quad_to_cubic(p0: Point, q: Point, p: Point) -> Seg {
+    let c1 = p0 + (q - p0) * (2. / 3.);
+    let c2 = p + (q - p) * (2. / 3.);
+    Seg::Cubic(c1, c2, p)
+}
+
+/// Approximate an elliptical arc with cubic Béziers, following the
+/// conversion from endpoint to center parameterization in the SVG
+/// specification's implementation notes.
+#[allow(clippy::too_many_arguments)]
+fn arc_to_cubics(
+    p0: Point,
+    rx: f64,
+    ry: f64,
+    phi: f64,
+    large: bool,
+    sweep: bool,
+    p: Point,
+    segs: &mut Vec<Seg>,
+) {
+    if p0 == p {
+        return;
+    }
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx == 0. || ry == 0. {
+        segs.push(Seg::Line(p));
+        return;
+    }
+    let (sin_phi, cos_phi) = phi.to_radians().sin_cos();
+    let dx2 = (p0.x - p.x) / 2.;
+    let dy2 = (p0.y - p.y) / 2.;
+    let x1 = cos_phi * dx2 + sin_phi * dy2;
+    let y1 = -sin_phi * dx2 + cos_phi * dy2;
+    let lambda = (x1 * x1) / (rx * rx) + (y1 * y1) / (ry * ry);
+    if lambda > 1. {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let num = rx * rx * ry * ry - rx * rx * y1 * y1 - ry * ry * x1 * x1;
+    let den = rx * rx * y1 * y1 + ry * ry * x1 * x1;
+    let mut coef = (num / den).max(0.).sqrt();
+    if large == sweep {
+        coef = -coef;
+    }
+    let cx1 = coef * rx * y1 / ry;
+    let cy1 = -coef * ry * x1 / rx;
+    let cx = cos_phi * cx1 - sin_phi * cy1 + (p0.x + p.x) / 2.;
+    let cy = sin_phi * cx1 + cos_phi * cy1 + (p0.y + p.y) / 2.;
+    let angle = |ux: f64, uy: f64, vx: f64, vy: f64| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let theta1 = angle(1., 0., (x1 - cx1) / rx, (y1 - cy1) / ry);
+    let mut dtheta = angle(
+        (x1 - cx1) / rx,
+        (y1 - cy1) / ry,
+        (-x1 - cx1) / rx,
+        (-y1 - cy1) / ry,
+    );
+    if !sweep && dtheta > 0. {
+        dtheta -= 2. * std::f64::consts::PI;
+    } else if sweep && dtheta < 0. {
+        dtheta += 2. * std::f64::consts::PI;
+    }
+
+    let n = (dtheta.abs() / std::f64::consts::FRAC_PI_2).ceil().max(1.) as usize;
+    let delta = dtheta / n as f64;
+    let k = 4. / 3. * (delta / 4.).tan();
+    let point_at = |t: f64| {
+        let (sin_t, cos_t) = t.sin_cos();
+        point2(
+            cx + rx * cos_t * cos_phi - ry * sin_t * sin_phi,
+            cy + rx * cos_t * sin_phi + ry * sin_t * cos_phi,
+        )
+    };
+    let tangent_at = |t: f64| {
+        let (sin_t, cos_t) = t.sin_cos();
+        euclid::vec2(
+            -rx * sin_t * cos_phi - ry * cos_t * sin_phi,
+            -rx * sin_t * sin_phi + ry * cos_t * cos_phi,
+        )
+    };
+    for i in 0..n {
+        let t1 = theta1 + i as f64 * delta;
+        let t2 = t1 + delta;
+        let end = if i + 1 == n { p } else { point_at(t2) };
+        segs.push(Seg::Cubic(
+            point_at(t1) + tangent_at(t1) * k,
+            point_at(t2) - tangent_at(t2) * k,
+            end,
+        ));
+    }
+}
+
+/// The Bézier circle constant.
+const KAPPA: f64 = 0.552_284_749_830_793_4;
+
+fn ellipse_segments(cx: f64, cy: f64, rx: f64, ry: f64) -> Vec<Seg> {
+    let (kx, ky) = (rx * KAPPA, ry * KAPPA);
+    vec![
+        Seg::Move(point2(cx + rx, cy)),
+        Seg::Cubic(
+            point2(cx + rx, cy + ky),
+            point2(cx + kx, cy + ry),
+            point2(cx, cy + ry),
+        ),
+        Seg::Cubic(
+            point2(cx - kx, cy + ry),
+            point2(cx - rx, cy + ky),
+            point2(cx - rx, cy),
+        ),
+        Seg::Cubic(
+            point2(cx - rx, cy - ky),
+            point2(cx - kx, cy - ry),
+            point2(cx, cy - ry),
+        ),
+        Seg::Cubic(
+            point2(cx + kx, cy - ry),
+            point2(cx + rx, cy - ky),
+            point2(cx + rx, cy),
+        ),
+        Seg::Close,
+    ]
+}
+
+fn rect_segments(x: f64, y: f64, w: f64, h: f64, rx: f64, ry: f64) -> Vec<Seg> {
+    if rx <= 0. || ry <= 0. {
+        return vec![
+            Seg::Move(point2(x, y)),
+            Seg::Line(point2(x + w, y)),
+            Seg::Line(point2(x + w, y + h)),
+            Seg::Line(point2(x, y + h)),
+            Seg::Close,
+        ];
+    }
+    let (kx, ky) = (rx * (1. - KAPPA), ry * (1. - KAPPA));
+    let (r, b) = (x + w, y + h);
+    vec![
+        Seg::Move(point2(x + rx, y)),
+        Seg::Line(point2(r - rx, y)),
+        Seg::Cubic(point2(r - kx, y), point2(r, y + ky), point2(r, y + ry)),
+        Seg::Line(point2(r, b - ry)),
+        Seg::Cubic(point2(r, b - ky), point2(r - kx, b), point2(r - rx, b)),
+        Seg::Line(point2(x + rx, b)),
+        Seg::Cubic(point2(x + kx, b), point2(x, b - ky), point2(x, b - ry)),
+        Seg::Line(point2(x, y + ry)),
+        Seg::Cubic(point2(x, y + ky), point2(x + kx, y), point2(x + rx, y)),
+        Seg::Close,
+    ]
+}
+
+fn transform_segments(segs: &[Seg], m: &TMatrix) -> Vec<Seg> {
+    let t = |p: Point| m.transform_point(p);
+    segs.iter()
+        .map(|seg| match *seg {
+            Seg::Move(p) => Seg::Move(t(p)),
+            Seg::Line(p) => Seg::Line(t(p)),
+            Seg::Cubic(c1, c2, p) => Seg::Cubic(t(c1), t(c2), t(p)),
+            Seg::Close => Seg::Close,
+        })
+        .collect()
+}
+
+/// The exact bounding box of a path, taking the extrema of its curves.
+fn segments_bbox(segs: &[Seg]) -> Option<Rect> {
+    let mut bbox: Option<Rect> = None;
+    let mut add = |p: Point| {
+        bbox = Some(match bbox {
+            Some(b) => Rect::new(b.min.min(p), b.max.max(p)),
+            None => Rect::new(p, p),
+        })
+    };
+    let mut cur = Point::zero();
+    for seg in segs {
+        match *seg {
+            Seg::Move(p) | Seg::Line(p) => {
+                add(p);
+                cur = p;
+            }
+            Seg::Cubic(c1, c2, p) => {
+                add(p);
+                for (a0, a1, a2, a3, is_x) in [
+                    (cur.x, c1.x, c2.x, p.x, true),
+                    (cur.y, c1.y, c2.y, p.y, false),
+                ]
+                .iter()
+                .copied()
+                {
+                    /* Roots of the derivative, a t^2 + b t + c. */
+                    let a = a3 - 3. * a2 + 3. * a1 - a0;
+                    let b = 2. * (a2 - 2. * a1 + a0);
+                    let c = a1 - a0;
+                    let mut roots = Vec::new();
+                    if a.abs() < 1e-12 {
+                        if b.abs() > 1e-12 {
+                            roots.push(-c / b);
+                        }
+                    } else {
+                        let disc = b * b - 4. * a * c;
+                        if disc >= 0. {
+                            roots.push((-b + disc.sqrt()) / (2. * a));
+                            roots.push((-b - disc.sqrt()) / (2. * a));
+                        }
+                    }
+                    for t in roots.into_iter().filter(|t| *t > 0. && *t < 1.) {
+                        let s = 1. - t;
+                        let v = s * s * s * a0
+                            + 3. * s * s * t * a1
+                            + 3. * s * t * t * a2
+                            + t * t * t * a3;
+                        let other = s * s * s * if is_x { cur.y } else { cur.x }
+                            + 3. * s * s * t * if is_x { c1.y } else { c1.x }
+                            + 3. * s * t * t * if is_x { c2.y } else { c2.x }
+                            + t * t * t * if is_x { p.y } else { p.x };
+                        add(if is_x {
+                            point2(v, other)
+                        } else {
+                            point2(other, v)
+                        });
+                    }
+                }
+                cur = p;
+            }
+            Seg::Close => {}
+        }
+    }
+    bbox
+}
+
+/* Colors and paints */
+
+#[derive(Clone, PartialEq)]
+enum Paint {
+    None,
+    Color([f64; 3]),
+    CurrentColor,
+    /// A reference to a gradient, with the paint to use if it can't be.
+    Url(String, Box<Paint>),
+}
+
+fn parse_paint(value: &str) -> Option<Paint> {
+    let value = value.trim();
+    if value.starts_with("url(") {
+        let end = value.find(')')?;
+        let id = url_id(&value[..=end])?;
+        let fallback = match value[end + 1..].trim() {
+            "" => Paint::None,
+            rest => parse_paint(rest)?,
+        };
+        return Some(Paint::Url(id.to_string(), Box::new(fallback)));
+    }
+    match value {
+        "none" | "transparent" => Some(Paint::None),
+        "currentColor" => Some(Paint::CurrentColor),
+        _ => parse_color(value).map(Paint::Color),
+    }
+}
+
+/// The identifier in `url(#id)`.
+fn url_id(value: &str) -> Option<&str> {
+    let inner = value.trim().strip_prefix("url(")?.strip_suffix(')')?;
+    inner
+        .trim()
+        .trim_matches(|c| c == '\'' || c == '"')
+        .strip_prefix('#')
+}
+
+fn parse_color(value: &str) -> Option<[f64; 3]> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        let digit = |i: usize| u8::from_str_radix(hex.get(i..=i)?, 16).ok();
+        let byte = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        let rgb = match hex.len() {
+            3 | 4 => [digit(0)? * 17, digit(1)? * 17, digit(2)? * 17],
+            6 | 8 => [byte(0)?, byte(2)?, byte(4)?],
+            _ => return None,
+        };
+        return Some([
+            rgb[0] as f64 / 255.,
+            rgb[1] as f64 / 255.,
+            rgb[2] as f64 / 255.,
+        ]);
+    }
+    if value.starts_with("rgb(") || value.starts_with("rgba(") {
+        let inner = value[value.find('(')? + 1..].trim_end_matches(')');
+        let mut rgb = [0.; 3];
+        for (c, part) in rgb.iter_mut().zip(
+            inner
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|s| !s.is_empty()),
+        ) {
+            *c = if let Some(percent) = part.strip_suffix('%') {
+                percent.trim().parse::<f64>().ok()? / 100.
+            } else {
+                part.parse::<f64>().ok()? / 255.
+            }
+            .clamp(0., 1.);
+        }
+        return Some(rgb);
+    }
+    let name = value.to_ascii_lowercase();
+    NAMED_COLORS
+        .iter()
+        .find(|&&(n, _)| n == name)
+        .map(|&(_, rgb)| {
+            [
+                (rgb >> 16) as f64 / 255.,
+                ((rgb >> 8) & 0xff) as f64 / 255.,
+                (rgb & 0xff) as f64 / 255.,
+            ]
+        })
+}
+
+fn parse_opacity(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let v = if let Some(percent) = value.strip_suffix('%') {
+        percent.trim().parse::<f64>().ok()? / 100.
+    } else {
+        value.parse().ok()?
+    };
+    Some(v.clamp(0., 1.))
+}
+
+/* Styles */
+
+/// The properties that may be given as attributes as well as in style
+/// sheets.
+const PRESENTATION_ATTRIBUTES: &[&str] = &[
+    "clip-path",
+    "clip-rule",
+    "color",
+    "display",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "opacity",
+    "stop-color",
+    "stop-opacity",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "visibility",
+];
+
+#[derive(Clone)]
+struct Style {
+    fill: Paint,
+    fill_opacity: f64,
+    fill_evenodd: bool,
+    stroke: Paint,
+    stroke_opacity: f64,
+    stroke_width: f64,
+    linecap: i32,
+    linejoin: i32,
+    miterlimit: f64,
+    dasharray: Vec<f64>,
+    dashoffset: f64,
+    clip_evenodd: bool,
+    color: [f64; 3],
+    /// The product of the opacities of the element and its ancestors.
+    opacity: f64,
+    visible: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fill: Paint::Color([0., 0., 0.]),
+            fill_opacity: 1.,
+            fill_evenodd: false,
+            stroke: Paint::None,
+            stroke_opacity: 1.,
+            stroke_width: 1.,
+            linecap: 0,
+            linejoin: 0,
+            miterlimit: 4.,
+            dasharray: Vec::new(),
+            dashoffset: 0.,
+            clip_evenodd: false,
+            color: [0., 0., 0.],
+            opacity: 1.,
+            visible: true,
+        }
+    }
+}
+
+impl Style {
+    fn apply(&mut self, name: &str, value: &str, viewport: (f64, f64)) {
+        let value = value.trim();
+        if value == "inherit" {
+            return;
+        }
+        match name {
+            "fill" => {
+                if let Some(p) = parse_paint(value) {
+                    self.fill = p;
+                }
+            }
+            "stroke" => {
+                if let Some(p) = parse_paint(value) {
+                    self.stroke = p;
+                }
+            }
+            "fill-opacity" => {
+                if let Some(v) = parse_opacity(value) {
+                    self.fill_opacity = v;
+                }
+            }
+            "stroke-opacity" => {
+                if let Some(v) = parse_opacity(value) {
+                    self.stroke_opacity = v;
+                }
+            }
+            "opacity" => {
+                if let Some(v) = parse_opacity(value) {
+                    self.opacity *= v;
+                }
+            }
+            "fill-rule" => self.fill_evenodd = value == "evenodd",
+            "clip-rule" => self.clip_evenodd = value == "evenodd",
+            "stroke-width" => {
+                if let Some(w) = resolve_length(value, Dir::Other, viewport) {
+                    if w >= 0. {
+                        self.stroke_width = w;
+                    }
+                }
+            }
+            "stroke-linecap" => match value {
+                "butt" => self.linecap = 0,
+                "round" => self.linecap = 1,
+                "square" => self.linecap = 2,
+                _ => {}
+            },
+            "stroke-linejoin" => match value {
+                "miter" | "miter-clip" | "arcs" => self.linejoin = 0,
+                "round" => self.linejoin = 1,
+                "bevel" => self.linejoin = 2,
+                _ => {}
+            },
+            "stroke-miterlimit" => {
+                if let Ok(v) = value.parse::<f64>() {
+                    if v >= 1. {
+                        self.miterlimit = v;
+                    }
+                }
+            }
+            "stroke-dasharray" => {
+                if value == "none" {
+                    self.dasharray.clear();
+                    return;
+                }
+                let dashes: Option<Vec<f64>> = value
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| resolve_length(s, Dir::Other, viewport).filter(|&v| v >= 0.))
+                    .collect();
+                if let Some(mut dashes) = dashes {
+                    if dashes.iter().all(|&d| d == 0.) {
+                        dashes.clear();
+                    } else if dashes.len() % 2 == 1 {
+                        /* An odd number of lengths is repeated. */
+                        let copy = dashes.clone();
+                        dashes.extend(copy);
+                    }
+                    /* The device keeps at most 16 dash lengths. */
+                    dashes.truncate(16);
+                    self.dasharray = dashes;
+                }
+            }
+            "stroke-dashoffset" => {
+                if let Some(v) = resolve_length(value, Dir::Other, viewport) {
+                    self.dashoffset = v;
+                }
+            }
+            "color" => {
+                if let Some(c) = parse_color(value) {
+                    self.color = c;
+                }
+            }
+            "visibility" => self.visible = value == "visible",
+            _ => {}
+        }
+    }
+}
+
+/// Resolve a length to user units, taking percentages relative to the
+/// viewport.
+fn resolve_length(value: &str, dir: Dir, viewport: (f64, f64)) -> Option<f64> {
+    let (v, unit) = parse_length(value)?;
+    if unit == "%" {
+        let (w, h) = viewport;
+        let base = match dir {
+            Dir::X => w,
+            Dir::Y => h,
+            Dir::Other => ((w * w + h * h) / 2.).sqrt(),
+        };
+        Some(v / 100. * base)
+    } else {
+        absolute_length(v, unit)
+    }
+}
+
+enum Selector {
+    Universal,
+    Name(String),
+    Class(Option<String>, String),
+    Id(String),
+}
+
+struct CssRule {
+    selector: Selector,
+    specificity: u32,
+    decls: Vec<(String, String)>,
+}
+
+fn parse_declarations(text: &str) -> Vec<(String, String)> {
+    text.split(';')
+        .filter_map(|decl| {
+            let mut parts = decl.splitn(2, ':');
+            let name = parts.next()?.trim();
+            let value = parts.next()?.trim().trim_end_matches("!important").trim();
+            if name.is_empty() {
+                None
+            } else {
+                Some((name.to_string(), value.to_string()))
+            }
+        })
+        .collect()
+}
+
+/// Parse a style sheet, keeping the rules whose selectors are a single
+/// `*`, element name, class or identifier.
+fn parse_style_sheet(text: &str, rules: &mut Vec<CssRule>) {
+    let mut text = text.to_string();
+    while let Some(start) = text.find("/*") {
+        let end = text[start..]
+            .find("*/")
+            .map_or(text.len(), |e| start + e + 2);
+        text.replace_range(start..end, "");
+    }
+    for block in text.split('}') {
+        let mut parts = block.splitn(2, '{');
+        let selectors = parts.next().unwrap_or("");
+        let decls = match parts.next() {
+            Some(d) => parse_declarations(d),
+            None => continue,
+        };
+        for sel in selectors.split(',').map(str::trim) {
+            let valid_name = |s: &str| {
+                !s.is_empty()
+                    && s.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            };
+            let (selector, specificity) = if sel == "*" {
+                (Selector::Universal, 0)
+            } else if sel.starts_with('#') && valid_name(&sel[1..]) {
+                (Selector::Id(sel[1..].to_string()), 100)
+            } else if let Some(dot) = sel.find('.') {
+                let (name, class) = (&sel[..dot], &sel[dot + 1..]);
+                if !valid_name(class) || !(name.is_empty() || valid_name(name)) {
+                    continue;
+                }
+                if name.is_empty() {
+                    (Selector::Class(None, class.to_string()), 10)
+                } else {
+                    (
+                        Selector::Class(Some(name.to_string()), class.to_string()),
+                        11,
+                    )
+                }
+            } else if valid_name(sel) {
+                (Selector::Name(sel.to_string()), 1)
+            } else {
+                continue;
+            };
+            rules.push(CssRule {
+                selector,
+                specificity,
+                decls: decls.clone(),
+            });
+        }
+    }
+}
+
+/* Rendering */
+
+/// A paint, once any gradient has been turned into a pattern resource.
+enum DevicePaint {
+    Color([f64; 3]),
+    Pattern(String),
+}
+
+struct Renderer<'a> {
+    ids: HashMap<&'a str, &'a Element>,
+    rules: Vec<CssRule>,
+    viewport: (f64, f64),
+    /// ExtGState resource names by fill and stroke alpha, in thousandths.
+    alphas: HashMap<(u32, u32), String>,
+    num_patterns: usize,
+    /// How many elements have been rendered so far.
+    num_rendered: usize,
+    warned: Vec<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(root: &'a Element, viewport: (f64, f64)) -> Self {
+        let mut renderer = Renderer {
+            ids: HashMap::new(),
+            rules: Vec::new(),
+            viewport,
+            alphas: HashMap::new(),
+            num_patterns: 0,
+            num_rendered: 0,
+            warned: Vec::new(),
+        };
+        renderer.collect(root);
+        /* A stable sort keeps the document order among equal selectors. */
+        renderer.rules.sort_by_key(|r| r.specificity);
+        renderer
+    }
+
+    fn collect(&mut self, el: &'a Element) {
+        if let Some(id) = el.attr("id") {
+            self.ids.entry(id).or_insert(el);
+        }
+        if el.name == "style" {
+            parse_style_sheet(&el.text, &mut self.rules);
+        }
+        for child in &el.children {
+            self.collect(child);
+        }
+    }
+
+    fn warn_once(&mut self, message: String) {
+        if !self.warned.contains(&message) {
+            warn!("SVG: {}", message);
+            self.warned.push(message);
+        }
+    }
+
+    fn href_target(&self, el: &Element) -> Option<&'a Element> {
+        let href = el.attr("href").or_else(|| el.attr("xlink:href"))?.trim();
+        self.ids.get(href.strip_prefix('#')?).copied()
+    }
+
+    /// The style declarations of an element in cascade order: presentation
+    /// attributes, then style sheet rules, then its style attribute.
+    fn declarations(&self, el: &Element) -> Vec<(String, String)> {
+        let mut decls: Vec<(String, String)> = el
+            .attrs
+            .iter()
+            .filter(|(k, _)| PRESENTATION_ATTRIBUTES.contains(&k.as_str()))
+            .cloned()
+            .collect();
+        let classes: Vec<&str> = el.attr("class").unwrap_or("").split_whitespace().collect();
+        for rule in &self.rules {
+            let matches = match &rule.selector {
+                Selector::Universal => true,
+                Selector::Name(name) => *name == el.name,
+                Selector::Class(name, class) => {
+                    name.iter().all(|n| *n == el.name) && classes.contains(&class.as_str())
+                }
+                Selector::Id(id) => el.attr("id") == Some(id.as_str()),
+            };
+            if matches {
+                decls.extend(rule.decls.iter().cloned());
+            }
+        }
+        if let Some(style) = el.attr("style") {
+            decls.extend(parse_declarations(style));
+        }
+        decls
+    }
+
+    fn length(&self, el: &Element, name: &str, dir: Dir, default: f64) -> f64 {
+        el.attr(name)
+            .and_then(|v| resolve_length(v, dir, self.viewport))
+            .unwrap_or(default)
+    }
+
+    /// The geometry of a basic shape or path, in its user space.
+    fn shape_segments(&self, el: &Element) -> Option<Vec<Seg>> {
+        let segs = match el.name.as_str() {
+            "path" => parse_path_data(el.attr("d").unwrap_or("")),
+            "rect" => {
+                let x = self.length(el, "x", Dir::X, 0.);
+                let y = self.length(el, "y", Dir::Y, 0.);
+                let w = self.length(el, "width", Dir::X, 0.);
+                let h = self.length(el, "height", Dir::Y, 0.);
+                if w <= 0. || h <= 0. {
+                    return Some(Vec::new());
+                }
+                let rx = el
+                    .attr("rx")
+                    .and_then(|v| resolve_length(v, Dir::X, self.viewport));
+                let ry = el
+                    .attr("ry")
+                    .and_then(|v| resolve_length(v, Dir::Y, self.viewport));
+                let (rx, ry) = match (rx, ry) {
+                    (Some(rx), Some(ry)) => (rx, ry),
+                    (Some(r), None) | (None, Some(r)) => (r, r),
+                    (None, None) => (0., 0.),
+                };
+                rect_segments(x, y, w, h, rx.min(w / 2.), ry.min(h / 2.))
+            }
+            "circle" => {
+                let r = self.length(el, "r", Dir::Other, 0.);
+                if r <= 0. {
+                    return Some(Vec::new());
+                }
+                let cx = self.length(el, "cx", Dir::X, 0.);
+                let cy = self.length(el, "cy", Dir::Y, 0.);
+                ellipse_segments(cx, cy, r, r)
+            }
+            "ellipse" => {
+                let rx = self.length(el, "rx", Dir::X, 0.);
+                let ry = self.length(el, "ry", Dir::Y, 0.);
+                if rx <= 0. || ry <= 0. {
+                    return Some(Vec::new());
+                }
+                let cx = self.length(el, "cx", Dir::X, 0.);
+                let cy = self.length(el, "cy", Dir::Y, 0.);
+                ellipse_segments(cx, cy, rx, ry)
+            }
+            "line" => vec![
+                Seg::Move(point2(
+                    self.length(el, "x1", Dir::X, 0.),
+                    self.length(el, "y1", Dir::Y, 0.),
+                )),
+                Seg::Line(point2(
+                    self.length(el, "x2", Dir::X, 0.),
+                    self.length(el, "y2", Dir::Y, 0.),
+                )),
+            ],
+            "polyline" | "polygon" => {
+                let mut numbers = Numbers::new(el.attr("points").unwrap_or(""));
+                let mut segs = Vec::new();
+                while let Some(p) = numbers.point() {
+                    segs.push(if segs.is_empty() {
+                        Seg::Move(p)
+                    } else {
+                        Seg::Line(p)
+                    });
+                }
+                if el.name == "polygon" && !segs.is_empty() {
+                    segs.push(Seg::Close);
+                }
+                segs
+            }
+            _ => return None,
+        };
+        Some(segs)
+    }
+
+    /// Render an element and its descendants. `ctm` maps its parent's user
+    /// space to the form's space.
+    unsafe fn render(&mut self, el: &'a Element, parent: &Style, ctm: &TMatrix, depth: usize) {
+        if depth > MAX_DEPTH {
+            self.warn_once("Elements are nested too deeply; is there a <use> cycle?".to_string());
+            return;
+        }
+        if self.num_rendered >= MAX_ELEMENTS {
+            self.warn_once("The image has too many elements; the rest are skipped.".to_string());
+            return;
+        }
+        self.num_rendered += 1;
+        match el.name.as_str() {
+            "g" | "a" | "switch" | "svg" | "use" | "symbol" => {}
+            "path" | "rect" | "circle" | "ellipse" | "line" | "polyline" | "polygon" => {}
+            "text" | "image" | "foreignObject" => {
+                self.warn_once(format!(
+                    "<{}> elements are not supported; skipping them.",
+                    el.name
+                ));
+                return;
+            }
+            /* Definitions and metadata, or elements of other namespaces. */
+            _ => return,
+        }
+        let decls = self.declarations(el);
+        if decls
+            .iter()
+            .any(|(k, v)| k == "display" && v.trim() == "none")
+        {
+            return;
+        }
+        let mut style = parent.clone();
+        for (name, value) in &decls {
+            style.apply(name, value, self.viewport);
+        }
+
+        let mut ctm = parse_transform(el.attr("transform").unwrap_or("")).post_transform(ctm);
+        if ctm.determinant().abs() < 1e-12 {
+            return;
+        }
+        let clip = decls
+            .iter()
+            .rev()
+            .find(|(k, _)| k == "clip-path")
+            .and_then(|(_, v)| url_id(v))
+            .and_then(|id| self.ids.get(id).copied())
+            .filter(|c| c.name == "clipPath");
+        let segs = self.shape_segments(el);
+        if let Some(clip) = clip {
+            pdf_dev_gsave();
+            self.clip(clip, &ctm, segs.as_deref());
+        }
+
+        match el.name.as_str() {
+            "use" => {
+                let x = self.length(el, "x", Dir::X, 0.);
+                let y = self.length(el, "y", Dir::Y, 0.);
+                ctm = TMatrix::create_translation(x, y).post_transform(&ctm);
+                if let Some(target) = self.href_target(el) {
+                    if target.name == "symbol" {
+                        self.render_children(target, &style, &ctm, depth);
+                    } else {
+                        self.render(target, &style, &ctm, depth + 1);
+                    }
+                }
+            }
+            "svg" => {
+                /* A nested viewport. */
+                let x = self.length(el, "x", Dir::X, 0.);
+                let y = self.length(el, "y", Dir::Y, 0.);
+                ctm = TMatrix::create_translation(x, y).post_transform(&ctm);
+                if let Some(vb) = el.attr("viewBox").and_then(parse_view_box) {
+                    let w = self.length(el, "width", Dir::X, self.viewport.0);
+                    let h = self.length(el, "height", Dir::Y, self.viewport.1);
+                    let par = el.attr("preserveAspectRatio").unwrap_or("");
+                    ctm = view_box_transform(vb, par, w, h).post_transform(&ctm);
+                }
+                self.render_children(el, &style, &ctm, depth);
+            }
+            "symbol" => {}
+            _ => match segs {
+                Some(segs) => self.paint(&segs, &style, &ctm),
+                None => self.render_children(el, &style, &ctm, depth),
+            },
+        }
+
+        if clip.is_some() {
+            pdf_dev_grestore();
+        }
+    }
+
+    unsafe fn render_children(
+        &mut self,
+        el: &'a Element,
+        style: &Style,
+        ctm: &TMatrix,
+        depth: usize,
+    ) {
+        for child in &el.children {
+            self.render(child, style, ctm, depth + 1);
+        }
+    }
+
+    /// Clip to a `<clipPath>`. `bbox_segs` is the geometry of the clipped
+    /// element, if it is a shape, for clip paths in objectBoundingBox units.
+    unsafe fn clip(&mut self, clip: &'a Element, ctm: &TMatrix, bbox_segs: Option<&[Seg]>) {
+        let mut m = parse_transform(clip.attr("transform").unwrap_or(""));
+        if clip.attr("clipPathUnits") == Some("objectBoundingBox") {
+            match bbox_segs.and_then(segments_bbox) {
+                Some(b) => {
+                    let bbox_matrix =
+                        TMatrix::row_major(b.width(), 0., 0., b.height(), b.min.x, b.min.y);
+                    m = m.post_transform(&bbox_matrix);
+                }
+                None => {
+                    self.warn_once(
+                        "Clip paths in objectBoundingBox units are only supported on shapes."
+                            .to_string(),
+                    );
+                    return;
+                }
+            }
+        }
+        let mut segs = Vec::new();
+        let mut evenodd = None;
+        let m = m.post_transform(ctm);
+        for child in &clip.children {
+            self.clip_segments(child, &m, &mut segs, &mut evenodd, 0);
+        }
+        if segs.is_empty() {
+            /* An empty clip path hides everything. */
+            pdf_dev_rectclip(&Rect::zero());
+            return;
+        }
+        emit_path(&segs);
+        pdf_dev_paintpath(b'W', evenodd.unwrap_or(false) as i32);
+    }
+
+    /// Gather the geometry of a child of a `<clipPath>`, or of what a
+    /// `<use>` in it refers to, in the form's space.
+    fn clip_segments(
+        &self,
+        el: &'a Element,
+        m: &TMatrix,
+        segs: &mut Vec<Seg>,
+        evenodd: &mut Option<bool>,
+        depth: usize,
+    ) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let decls = self.declarations(el);
+        if decls
+            .iter()
+            .any(|(k, v)| k == "display" && v.trim() == "none")
+        {
+            return;
+        }
+        let m = parse_transform(el.attr("transform").unwrap_or("")).post_transform(m);
+        if el.name == "use" {
+            let x = self.length(el, "x", Dir::X, 0.);
+            let y = self.length(el, "y", Dir::Y, 0.);
+            let m = TMatrix::create_translation(x, y).post_transform(&m);
+            if let Some(target) = self.href_target(el) {
+                self.clip_segments(target, &m, segs, evenodd, depth + 1);
+            }
+        } else if let Some(shape) = self.shape_segments(el) {
+            if evenodd.is_none() {
+                let mut style = Style::default();
+                for (name, value) in &decls {
+                    style.apply(name, value, self.viewport);
+                }
+                *evenodd = Some(style.clip_evenodd);
+            }
+            segs.extend(transform_segments(&shape, &m));
+        }
+    }
+
+    /// Fill and stroke a shape.
+    unsafe fn paint(&mut self, segs: &[Seg], style: &Style, ctm: &TMatrix) {
+        if !style.visible || segs.is_empty() {
+            return;
+        }
+        let fill = self.device_paint(&style.fill, style, segs, ctm);
+        let stroke = if style.stroke_width > 0. {
+            self.device_paint(&style.stroke, style, segs, ctm)
+        } else {
+            None
+        };
+        if fill.is_none() && stroke.is_none() {
+            return;
+        }
+
+        /* Split the transformation into a scale, which we apply to the
+         * coordinates and line widths, and the rest, which is a rotation or
+         * reflection unless the shape is skewed or stretched. */
+        let scale = ctm.determinant().abs().sqrt();
+        let rest = TMatrix::row_major(
+            ctm.m11 / scale,
+            ctm.m12 / scale,
+            ctm.m21 / scale,
+            ctm.m22 / scale,
+            ctm.m31,
+            ctm.m32,
+        );
+        let conformal = (rest.m11.abs() - rest.m22.abs()).abs() < 1e-6
+            && (rest.m12.abs() - rest.m21.abs()).abs() < 1e-6;
+        let fill_alpha = if fill.is_some() {
+            style.fill_opacity * style.opacity
+        } else {
+            1.
+        };
+        let stroke_alpha = if stroke.is_some() {
+            style.stroke_opacity * style.opacity
+        } else {
+            1.
+        };
+        let uses_pattern = matches!(fill, Some(DevicePaint::Pattern(_)))
+            || matches!(stroke, Some(DevicePaint::Pattern(_)));
+        let needs_gsave = !conformal || uses_pattern || fill_alpha < 1. || stroke_alpha < 1.;
+        if needs_gsave {
+            pdf_dev_gsave();
+        }
+        if fill_alpha < 1. || stroke_alpha < 1. {
+            self.set_alpha(fill_alpha, stroke_alpha);
+        }
+        let user_segs = if conformal {
+            transform_segments(segs, ctm)
+        } else {
+            pdf_dev_concat(&rest);
+            transform_segments(segs, &TMatrix::create_scale(scale, scale))
+        };
+        let p = pdf_doc_mut();
+        match &fill {
+            Some(DevicePaint::Color(c)) => {
+                pdf_dev_set_color(&PdfColor::Rgb(c[0], c[1], c[2]), 0x20, 0);
+            }
+            Some(DevicePaint::Pattern(name)) => {
+                p.add_page_content(format!(" /Pattern cs /{} scn", name).as_bytes());
+            }
+            None => {}
+        }
+        if let Some(stroke) = &stroke {
+            match stroke {
+                DevicePaint::Color(c) => {
+                    pdf_dev_set_color(&PdfColor::Rgb(c[0], c[1], c[2]), 0, 0);
+                }
+                DevicePaint::Pattern(name) => {
+                    p.add_page_content(format!(" /Pattern CS /{} SCN", name).as_bytes());
+                }
+            }
+            pdf_dev_setlinewidth(style.stroke_width * scale);
+            pdf_dev_setlinecap(style.linecap);
+            pdf_dev_setlinejoin(style.linejoin);
+            pdf_dev_setmiterlimit(style.miterlimit);
+            let dashes: Vec<f64> = style.dasharray.iter().map(|d| d * scale).collect();
+            pdf_dev_setdash(&dashes, style.dashoffset * scale);
+        }
+        emit_path(&user_segs);
+        match (fill.is_some(), stroke.is_some()) {
+            (true, true) => pdf_dev_paintpath(b'B', style.fill_evenodd as i32),
+            (true, false) => pdf_dev_paintpath(b'f', style.fill_evenodd as i32),
+            _ => pdf_dev_paintpath(b'S', 0),
+        };
+        if needs_gsave {
+            pdf_dev_grestore();
+        }
+    }
+
+    unsafe fn set_alpha(&mut self, fill_alpha: f64, stroke_alpha: f64) {
+        let key = (
+            (fill_alpha * 1000.).round() as u32,
+            (stroke_alpha * 1000.).round() as u32,
+        );
+        let p = pdf_doc_mut();
+        let name = match self.alphas.get(&key) {
+            Some(name) => name.clone(),
+            None => {
+                let name = format!("SvgA{}", self.alphas.len() + 1);
+                let mut dict = pdf_dict::new();
+                dict.set("Type", "ExtGState");
+                dict.set("ca", key.0 as f64 / 1000.);
+                dict.set("CA", key.1 as f64 / 1000.);
+                p.add_page_resource("ExtGState", name.as_bytes(), dict.into_ref().into_obj());
+                self.alphas.insert(key, name.clone());
+                name
+            }
+        };
+        p.add_page_content(format!(" /{} gs", name).as_bytes());
+    }
+
+    /// Work out how to paint a shape; `None` means not to.
+    unsafe fn device_paint(
+        &mut self,
+        paint: &Paint,
+        style: &Style,
+        segs: &[Seg],
+        ctm: &TMatrix,
+    ) -> Option<DevicePaint> {
+        match paint {
+            Paint::None => None,
+            Paint::Color(c) => Some(DevicePaint::Color(*c)),
+            Paint::CurrentColor => Some(DevicePaint::Color(style.color)),
+            Paint::Url(id, fallback) => {
+                let target = self.ids.get(id.as_str()).copied();
+                match target {
+                    Some(g) if g.name == "linearGradient" || g.name == "radialGradient" => {
+                        self.gradient_paint(g, segs, ctm)
+                    }
+                    Some(other) => {
+                        self.warn_once(format!(
+                            "Painting with <{}> is not supported; using the fallback color.",
+                            other.name
+                        ));
+                        self.device_paint(fallback, style, segs, ctm)
+                    }
+                    None => self.device_paint(fallback, style, segs, ctm),
+                }
+            }
+        }
+    }
+
+    /// An attribute of a gradient, or of those it refers to.
+    fn gradient_attr(&self, el: &'a Element, name: &str) -> Option<&'a str> {
+        let mut el = el;
+        for _ in 0..MAX_DEPTH {
+            if let Some(v) = el.attr(name) {
+                return Some(v);
+            }
+            el = self.href_target(el)?;
+        }
+        None
+    }
+
+    fn gradient_stops(&self, el: &'a Element) -> Vec<(f64, [f64; 3])> {
+        let mut el = el;
+        for _ in 0..MAX_DEPTH {
+            let stops: Vec<&Element> = el.children.iter().filter(|c| c.name == "stop").collect();
+            if !stops.is_empty() {
+                let mut result: Vec<(f64, [f64; 3])> = Vec::new();
+                for stop in stops {
+                    let offset = stop.attr("offset").and_then(parse_opacity).unwrap_or(0.);
+                    let offset = result.last().map_or(offset, |l| offset.max(l.0));
+                    let mut color = [0., 0., 0.];
+                    let mut current = [0., 0., 0.];
+                    for (name, value) in self.declarations(stop) {
+                        if name == "color" {
+                            current = parse_color(&value).unwrap_or(current);
+                        } else if name == "stop-color" {
+                            color = if value.trim() == "currentColor" {
+                                current
+                            } else {
+                                parse_color(&value).unwrap_or(color)
+                            };
+                        }
+                    }
+                    result.push((offset, color));
+                }
+                return result;
+            }
+            el = match self.href_target(el) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Vec::new()
+    }
+
+    /// Turn a gradient into a shading pattern for a shape, added to the
+    /// form's resources.
+    unsafe fn gradient_paint(
+        &mut self,
+        g: &'a Element,
+        segs: &[Seg],
+        ctm: &TMatrix,
+    ) -> Option<DevicePaint> {
+        let stops = self.gradient_stops(g);
+        match stops.len() {
+            0 => return None,
+            1 => return Some(DevicePaint::Color(stops[0].1)),
+            _ => {}
+        }
+        let last = stops[stops.len() - 1].1;
+        let bbox_units = self.gradient_attr(g, "gradientUnits") != Some("userSpaceOnUse");
+        let mut matrix = parse_transform(self.gradient_attr(g, "gradientTransform").unwrap_or(""));
+        if bbox_units {
+            let b = segments_bbox(segs)?;
+            if b.width() <= 0. || b.height() <= 0. {
+                return None;
+            }
+            matrix = matrix.post_transform(&TMatrix::row_major(
+                b.width(),
+                0.,
+                0.,
+                b.height(),
+                b.min.x,
+                b.min.y,
+            ));
+        }
+        match self.gradient_attr(g, "spreadMethod") {
+            None | Some("pad") => {}
+            Some(_) => {
+                self.warn_once("Only the pad spread method is supported for gradients.".to_string())
+            }
+        }
+        let viewport = self.viewport;
+        let coord = |name: &str, dir: Dir, default: &str| {
+            let value = self.gradient_attr(g, name).unwrap_or(default);
+            if bbox_units {
+                match parse_length(value) {
+                    Some((v, "%")) => Some(v / 100.),
+                    Some((v, "")) => Some(v),
+                    _ => None,
+                }
+            } else {
+                resolve_length(value, dir, viewport)
+            }
+        };
+
+        let mut shading = pdf_dict::new();
+        shading.set("ColorSpace", "DeviceRGB");
+        let mut coords = vec![];
+        if g.name == "linearGradient" {
+            let x1 = coord("x1", Dir::X, "0%")?;
+            let y1 = coord("y1", Dir::Y, "0%")?;
+            let x2 = coord("x2", Dir::X, "100%")?;
+            let y2 = coord("y2", Dir::Y, "0%")?;
+            if x1 == x2 && y1 == y2 {
+                return Some(DevicePaint::Color(last));
+            }
+            shading.set("ShadingType", 2_f64);
+            for v in &[x1, y1, x2, y2] {
+                coords.push_obj(*v);
+            }
+        } else {
+            let cx = coord("cx", Dir::X, "50%")?;
+            let cy = coord("cy", Dir::Y, "50%")?;
+            let r = coord("r", Dir::Other, "50%")?;
+            if r <= 0. {
+                return Some(DevicePaint::Color(last));
+            }
+            let mut fx = self
+                .gradient_attr(g, "fx")
+                .map_or(Some(cx), |_| coord("fx", Dir::X, "50%"))?;
+            let mut fy = self
+                .gradient_attr(g, "fy")
+                .map_or(Some(cy), |_| coord("fy", Dir::Y, "50%"))?;
+            /* Keep the focus inside the circle, as SVG 1.1 does. */
+            let d = ((fx - cx).powi(2) + (fy - cy).powi(2)).sqrt();
+            if d > 0.99 * r {
+                fx = cx + (fx - cx) * 0.99 * r / d;
+                fy = cy + (fy - cy) * 0.99 * r / d;
+            }
+            shading.set("ShadingType", 3_f64);
+            for v in &[fx, fy, 0., cx, cy, r] {
+                coords.push_obj(*v);
+            }
+        }
+        shading.set("Coords", coords);
+        shading.set("Function", stops_function(&stops));
+        let mut extend = vec![];
+        extend.push_obj(true);
+        extend.push_obj(true);
+        shading.set("Extend", extend);
+
+        let m = matrix.post_transform(ctm);
+        let mut pattern_matrix = vec![];
+        for v in &[m.m11, m.m12, m.m21, m.m22, m.m31, m.m32] {
+            pattern_matrix.push_obj(*v);
+        }
+        let mut pattern = pdf_dict::new();
+        pattern.set("Type", "Pattern");
+        pattern.set("PatternType", 2_f64);
+        pattern.set("Shading", shading);
+        pattern.set("Matrix", pattern_matrix);
+        self.num_patterns += 1;
+        let name = format!("SvgP{}", self.num_patterns);
+        pdf_doc_mut().add_page_resource("Pattern", name.as_bytes(), pattern.into_ref().into_obj());
+        Some(DevicePaint::Pattern(name))
+    }
+}
+
+/// A PDF function over [0, 1] interpolating between gradient stops: a
+/// single exponential function for two stops, or a stitching of them.
+unsafe fn stops_function(stops: &[(f64, [f64; 3])]) -> *mut pdf_obj {
+    let mut stops = stops.to_vec();
+    if stops[0].0 > 0. {
+        stops.insert(0, (0., stops[0].1));
+    }
+    let last = stops[stops.len() - 1];
+    if last.0 < 1. {
+        stops.push((1., last.1));
+    }
+    let interpolate = |c0: [f64; 3], c1: [f64; 3]| {
+        let mut f = pdf_dict::new();
+        f.set("FunctionType", 2_f64);
+        let mut domain = vec![];
+        domain.push_obj(0_f64);
+        domain.push_obj(1_f64);
+        f.set("Domain", domain);
+        let mut a0 = vec![];
+        let mut a1 = vec![];
+        for i in 0..3 {
+            a0.push_obj(c0[i]);
+            a1.push_obj(c1[i]);
+        }
+        f.set("C0", a0);
+        f.set("C1", a1);
+        f.set("N", 1_f64);
+        f
+    };
+    /* Intervals of zero length are hard color changes, which the
+     * neighbouring intervals already show. */
+    let intervals: Vec<_> = stops
+        .windows(2)
+        .filter(|w| w[1].0 > w[0].0)
+        .map(|w| (w[0], w[1]))
+        .collect();
+    if intervals.len() == 1 {
+        return interpolate(intervals[0].0 .1, intervals[0].1 .1).into_obj();
+    }
+    let mut functions = vec![];
+    let mut bounds = vec![];
+    let mut encode = vec![];
+    for (i, (s0, s1)) in intervals.iter().enumerate() {
+        functions.push_obj(interpolate(s0.1, s1.1));
+        if i > 0 {
+            bounds.push_obj(s0.0);
+        }
+        encode.push_obj(0_f64);
+        encode.push_obj(1_f64);
+    }
+    let mut f = pdf_dict::new();
+    f.set("FunctionType", 3_f64);
+    let mut domain = vec![];
+    domain.push_obj(0_f64);
+    domain.push_obj(1_f64);
+    f.set("Domain", domain);
+    f.set("Functions", functions);
+    f.set("Bounds", bounds);
+    f.set("Encode", encode);
+    f.into_obj()
+}
+
+unsafe fn emit_path(segs: &[Seg]) {
+    for seg in segs {
+        match *seg {
+            Seg::Move(p) => pdf_dev_moveto(p.x, p.y),
+            Seg::Line(p) => pdf_dev_lineto(p.x, p.y),
+            Seg::Cubic(c1, c2, p) => pdf_dev_curveto(c1.x, c1.y, c2.x, c2.y, p.x, p.y),
+            Seg::Close => pdf_dev_closepath(),
+        };
+    }
+}
+
+/* A small XML parser: enough for SVG files, which are well-formed. */
+
+struct Element {
+    /// The local name, without any `svg:` prefix.
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Element>,
+    /// The character data directly inside the element.
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+fn parse_xml(src: &str) -> Result<Element, ()> {
+    let mut stack: Vec<Element> = Vec::new();
+    let mut pos = 0;
+    let fail = |what: &str| {
+        warn!("SVG: Malformed XML: {}.", what);
+    };
+    while pos < src.len() {
+        let rest = &src[pos..];
+        if rest.starts_with("<?") {
+            pos += rest
+                .find("?>")
+                .ok_or_else(|| fail("unterminated processing instruction"))?
+                + 2;
+        } else if rest.starts_with("<!--") {
+            pos += rest
+                .find("-->")
+                .ok_or_else(|| fail("unterminated comment"))?
+                + 3;
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest
+                .find("]]>")
+                .ok_or_else(|| fail("unterminated CDATA section"))?;
+            if let Some(top) = stack.last_mut() {
+                top.text.push_str(&rest[9..end]);
+            }
+            pos += end + 3;
+        } else if rest.starts_with("<!") {
+            /* A document type declaration, maybe with an internal subset. */
+            let mut depth = 0;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '[' => depth += 1,
+                        ']' => depth -= 1,
+                        '>' if depth == 0 => return true,
+                        _ => {}
+                    }
+                    false
+                })
+                .ok_or_else(|| fail("unterminated declaration"))?
+                .0;
+            pos += end + 1;
+        } else if rest.starts_with("</") {
+            let end = rest.find('>').ok_or_else(|| fail("unterminated end tag"))?;
+            let el = stack.pop().ok_or_else(|| fail("unexpected end tag"))?;
+            if local_name(rest[2..end].trim()) != el.name {
+                fail("mismatched end tag");
+                return Err(());
+            }
+            pos += end + 1;
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(el),
+                None => return Ok(el),
+            }
+        } else if rest.starts_with('<') {
+            let (el, len, empty) = parse_start_tag(rest).ok_or_else(|| fail("bad start tag"))?;
+            pos += len;
+            if empty {
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(el),
+                    None => return Ok(el),
+                }
+            } else {
+                stack.push(el);
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if let Some(top) = stack.last_mut() {
+                top.text.push_str(&decode_entities(&rest[..end]));
+            }
+            pos += end;
+        }
+    }
+    fail("unexpected end of file");
+    Err(())
+}
+
+fn local_name(name: &str) -> String {
+    name.trim_start_matches("svg:").to_string()
+}
+
+/// Parse a start tag at the beginning of `s`, giving the element, the
+/// length of the tag and whether it was an empty-element tag.
+fn parse_start_tag(s: &str) -> Option<(Element, usize, bool)> {
+    let bytes = s.as_bytes();
+    let mut pos = 1;
+    let name_end = s[pos..]
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .map(|e| e + pos)?;
+    let mut el = Element {
+        name: local_name(&s[pos..name_end]),
+        attrs: Vec::new(),
+        children: Vec::new(),
+        text: String::new(),
+    };
+    pos = name_end;
+    loop {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        match bytes.get(pos)? {
+            b'>' => return Some((el, pos + 1, false)),
+            b'/' => {
+                if bytes.get(pos + 1) == Some(&b'>') {
+                    return Some((el, pos + 2, true));
+                }
+                return None;
+            }
+            _ => {}
+        }
+        let eq = s[pos..].find('=')? + pos;
+        let name = s[pos..eq].trim().to_string();
+        pos = eq + 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        let quote = *bytes.get(pos)?;
+        if quote != b'"' && quote != b'\'' {
+            return None;
+        }
+        let end = s[pos + 1..].find(quote as char)? + pos + 1;
+        el.attrs.push((name, decode_entities(&s[pos + 1..end])));
+        pos = end + 1;
+    }
+}
+
+fn decode_entities(s: &str) -> String {
+    if !s.contains('&') {
+        return s.to_string();
+    }
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ if entity.starts_with("#x") => u32::from_str_radix(&entity[2..], 16)
+                .ok()
+                .and_then(std::char::from_u32),
+            _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(std::char::from_u32),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The CSS color keywords.
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn assert_point(p: Point, x: f64, y: f64) {
+        assert!(close(p.x, x) && close(p.y, y), "{:?} != ({}, {})", p, x, y);
+    }
+
+    /// The segments of a path, with their kinds as path data commands.
+    fn describe(segs: &[Seg]) -> Vec<(char, Vec<(f64, f64)>)> {
+        segs.iter()
+            .map(|seg| match *seg {
+                Seg::Move(p) => ('M', vec![(p.x, p.y)]),
+                Seg::Line(p) => ('L', vec![(p.x, p.y)]),
+                Seg::Cubic(c1, c2, p) => ('C', vec![(c1.x, c1.y), (c2.x, c2.y), (p.x, p.y)]),
+                Seg::Close => ('Z', vec![]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn path_data() {
+        assert_eq!(
+            describe(&parse_path_data("M10 20 l5 0 h5 v5 z m1 1 L 0 0")),
+            [
+                ('M', vec![(10., 20.)]),
+                ('L', vec![(15., 20.)]),
+                ('L', vec![(20., 20.)]),
+                ('L', vec![(20., 25.)]),
+                ('Z', vec![]),
+                ('M', vec![(11., 21.)]),
+                ('L', vec![(0., 0.)]),
+            ]
+        );
+
+        /* Coordinates after a moveto are linetos, and numbers can run
+         * together. */
+        assert_eq!(
+            describe(&parse_path_data("M1.5.5-2e1,3")),
+            [('M', vec![(1.5, 0.5)]), ('L', vec![(-20., 3.)])]
+        );
+
+        /* A command after closepath starts a new subpath at the same
+         * place. */
+        assert_eq!(
+            describe(&parse_path_data("M0 0 L1 0 Z L 2 2")),
+            [
+                ('M', vec![(0., 0.)]),
+                ('L', vec![(1., 0.)]),
+                ('Z', vec![]),
+                ('M', vec![(0., 0.)]),
+                ('L', vec![(2., 2.)]),
+            ]
+        );
+
+        /* Quadratic curves become cubic ones, and smooth curves reflect the
+         * previous control point. */
+        assert_eq!(
+            describe(&parse_path_data("M0 0 Q 3 3 6 0 T 12 0")),
+            [
+                ('M', vec![(0., 0.)]),
+                ('C', vec![(2., 2.), (4., 2.), (6., 0.)]),
+                ('C', vec![(8., -2.), (10., -2.), (12., 0.)]),
+            ]
+        );
+        assert_eq!(
+            describe(&parse_path_data("M0 0 C 0 1 1 1 1 0 s 1 -1 1 0")),
+            [
+                ('M', vec![(0., 0.)]),
+                ('C', vec![(0., 1.), (1., 1.), (1., 0.)]),
+                ('C', vec![(1., -1.), (2., -1.), (2., 0.)]),
+            ]
+        );
+
+        /* Errors end the path. */
+        assert_eq!(
+            describe(&parse_path_data("M0 0 L10 0 L 5")),
+            [('M', vec![(0., 0.)]), ('L', vec![(10., 0.)])]
+        );
+        assert!(parse_path_data("L 1 1").is_empty());
+        assert!(parse_path_data("").is_empty());
+    }
+
+    #[test]
+    fn arcs() {
+        /* A half circle, drawn clockwise on the page, in two quarters. */
+        let mut segs = Vec::new();
+        arc_to_cubics(
+            point2(0., 0.),
+            1.,
+            1.,
+            0.,
+            false,
+            true,
+            point2(2., 0.),
+            &mut segs,
+        );
+        assert_eq!(segs.len(), 2);
+        match (segs[0], segs[1]) {
+            (Seg::Cubic(c1, _, p1), Seg::Cubic(_, c2, p2)) => {
+                assert_point(p1, 1., -1.);
+                assert_point(p2, 2., 0.);
+                /* The curves start and end tangentially. */
+                assert!(close(c1.x, 0.) && c1.y < 0.);
+                assert!(close(c2.x, 2.) && c2.y < 0.);
+            }
+            _ => panic!("the arc is not made of curves"),
+        }
+
+        /* Radii that are too small are scaled up, giving the same arc. */
+        let mut scaled = Vec::new();
+        arc_to_cubics(
+            point2(0., 0.),
+            0.5,
+            0.5,
+            0.,
+            false,
+            true,
+            point2(2., 0.),
+            &mut scaled,
+        );
+        assert_eq!(describe(&scaled), describe(&segs));
+
+        /* Arcs without a radius are lines, and those that don't go anywhere
+         * are nothing. */
+        let mut segs = Vec::new();
+        arc_to_cubics(
+            point2(0., 0.),
+            0.,
+            1.,
+            0.,
+            false,
+            true,
+            point2(2., 0.),
+            &mut segs,
+        );
+        arc_to_cubics(
+            point2(2., 0.),
+            1.,
+            1.,
+            0.,
+            false,
+            true,
+            point2(2., 0.),
+            &mut segs,
+        );
+        assert_eq!(describe(&segs), [('L', vec![(2., 0.)])]);
+    }
+
+    #[test]
+    fn transforms() {
+        /* Bad transforms are warned about, and there is no engine output to
+         * warn on here. */
+        unsafe { crate::dpx_error::shut_up(2) };
+
+        let apply = |t: &str, x: f64, y: f64| parse_transform(t).transform_point(point2(x, y));
+        assert_point(apply("translate(10 20) scale(2)", 1., 1.), 12., 22.);
+        assert_point(apply("scale(2),translate(10,20)", 1., 1.), 22., 42.);
+        assert_point(apply("rotate(90)", 1., 0.), 0., 1.);
+        assert_point(apply("rotate(90 1 1)", 2., 1.), 1., 2.);
+        assert_point(apply("matrix(1 2 3 4 5 6)", 1., 1.), 9., 12.);
+        assert_point(apply("skewX(45)", 0., 1.), 1., 1.);
+        assert_point(apply("", 3., 4.), 3., 4.);
+        /* Invalid transforms are ignored. */
+        assert_point(apply("translate(1 1) frobnicate(2)", 3., 4.), 3., 4.);
+        assert_point(apply("rotate(1 2)", 3., 4.), 3., 4.);
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(parse_color("#f00"), Some([1., 0., 0.]));
+        assert_eq!(parse_color(" #00ff00 "), Some([0., 1., 0.]));
+        assert_eq!(parse_color("#0000ff80"), Some([0., 0., 1.]));
+        assert_eq!(parse_color("rgb(255, 0, 0)"), Some([1., 0., 0.]));
+        assert_eq!(parse_color("rgba(0 255 0 / 50%)"), Some([0., 1., 0.]));
+        assert_eq!(parse_color("rgb(100%,50%,0%)"), Some([1., 0.5, 0.]));
+        assert_eq!(parse_color("rgb(300, -5, 0)"), Some([1., 0., 0.]));
+        assert_eq!(parse_color("Red"), Some([1., 0., 0.]));
+        assert_eq!(
+            parse_color("cornflowerblue"),
+            Some([100. / 255., 149. / 255., 237. / 255.])
+        );
+        assert_eq!(parse_color("#12"), None);
+        assert_eq!(parse_color("#ggg"), None);
+        assert_eq!(parse_color("nonsense"), None);
+    }
+
+    #[test]
+    fn xml() {
+        /* Likewise for the markup that gets skipped. */
+        unsafe { crate::dpx_error::shut_up(2) };
+
+        let root = parse_xml(
+            "<?xml version=\"1.0\"?>\n\
+             <!DOCTYPE svg [<!ENTITY x \"y\">]>\n\
+             <!-- <notanelement> -->\n\
+             <svg:svg a='1' b=\"&lt;&amp;\">\n\
+             <g><rect /></g>\n\
+             <style><![CDATA[a > b {}]]></style>\n\
+             &#x41;&#66;\n\
+             </svg:svg>\n",
+        )
+        .unwrap();
+        assert_eq!(root.name, "svg");
+        assert_eq!(root.attr("a"), Some("1"));
+        assert_eq!(root.attr("b"), Some("<&"));
+        assert_eq!(root.attr("c"), None);
+        let names: Vec<&str> = root.children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["g", "style"]);
+        assert_eq!(root.children[0].children[0].name, "rect");
+        assert_eq!(root.children[1].text, "a > b {}");
+        assert_eq!(root.text.trim(), "AB");
+
+        assert!(parse_xml("<svg><g></svg>").is_err());
+        assert!(parse_xml("<svg><g>").is_err());
+        assert!(parse_xml("<svg a=1/>").is_err());
+        assert!(parse_xml("<!-- <svg/>").is_err());
+    }
+
+    #[test]
+    fn entities() {
+        assert_eq!(decode_entities("plain"), "plain");
+        assert_eq!(
+            decode_entities("a &amp; b &quot;&apos;&gt; &#60;&#x3e;"),
+            "a & b \"'> <>"
+        );
+        /* Anything that isn't an entity is left alone. */
+        assert_eq!(
+            decode_entities("&bogus; & &#xzz; &#1114112; &verylongname;"),
+            "&bogus; & &#xzz; &#1114112; &verylongname;"
+        );
+    }
+
+    #[test]
+    fn view_boxes() {
+        let apply = |vb, par, x: f64, y: f64| {
+            view_box_transform(vb, par, 100., 100.).transform_point(point2(x, y))
+        };
+        let vb = [0., 0., 10., 20.];
+        /* By default the view box is scaled uniformly to fit, and centered. */
+        assert_point(apply(vb, "", 0., 0.), 25., 0.);
+        assert_point(apply(vb, "xMidYMid meet", 10., 20.), 75., 100.);
+        assert_point(apply(vb, "xMaxYMax", 0., 0.), 50., 0.);
+        assert_point(apply(vb, "xMinYMin slice", 10., 20.), 100., 200.);
+        assert_point(apply(vb, "xMidYMax slice", 0., 0.), 0., -100.);
+        assert_point(apply(vb, "none", 10., 20.), 100., 100.);
+        assert_point(apply([5., 5., 10., 10.], "", 5., 5.), 0., 0.);
+
+        assert_eq!(parse_view_box("0 0 10 20"), Some(vb));
+        assert_eq!(parse_view_box("0,0,10,20"), Some(vb));
+        assert_eq!(parse_view_box("0 0 0 20"), None);
+        assert_eq!(parse_view_box("0 0 10"), None);
+    }
+}
@@ -0,0 +1,568 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Baseline TIFF images: bilevel, grayscale, palette and RGB images, either
+//! uncompressed or PackBits-compressed, in strips. Only the first image of
+//! the file is used. An alpha channel becomes a soft mask, as for PNG.
+
+use crate::dpx_imageprobe::image_data_size;
+use crate::dpx_pdfobj::{
+    pdf_stream, pdf_stream_set_predictor, pdf_string, IntoObj, IntoRef, PushObj, STREAM_COMPRESS,
+};
+use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
+use crate::warn;
+
+use std::io::{Read, Seek, SeekFrom};
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+const TAG_COLOR_MAP: u16 = 320;
+const TAG_EXTRA_SAMPLES: u16 = 338;
+
+const COMPRESSION_NONE: u16 = 1;
+const COMPRESSION_PACKBITS: u16 = 32773;
+
+const PHOTOMETRIC_WHITE_IS_ZERO: u16 = 0;
+const PHOTOMETRIC_BLACK_IS_ZERO: u16 = 1;
+const PHOTOMETRIC_RGB: u16 = 2;
+const PHOTOMETRIC_PALETTE: u16 = 3;
+
+const EXTRA_SAMPLE_ASSOCIATED_ALPHA: u32 = 1;
+const EXTRA_SAMPLE_UNASSOCIATED_ALPHA: u32 = 2;
+
+pub fn check_for_tiff<R: Read + Seek>(handle: &mut R) -> bool {
+    let mut sigbytes: [u8; 4] = [0; 4];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    handle.read_exact(&mut sigbytes[..]).is_ok()
+        && (&sigbytes == b"II*\x00" || &sigbytes == b"MM\x00*")
+}
+
+pub fn tiff_get_bbox<R: Read + Seek>(handle: &mut R) -> Result<(u32, u32, f64, f64), ()> {
+    let data = read_file(handle)?;
+    let tiff = Tiff::new(&data)?;
+    let hdr = tiff.read_header()?;
+    let (xdensity, ydensity) = get_density(&hdr);
+    Ok((hdr.width, hdr.height, xdensity, ydensity))
+}
+
+pub(crate) unsafe fn tiff_include_image<R: Read + Seek>(
+    ximage: &mut pdf_ximage,
+    handle: &mut R,
+) -> Result<(), ()> {
+    let data = read_file(handle)?;
+    let tiff = Tiff::new(&data)?;
+    let hdr = tiff.read_header()?;
+    let mut info = ximage_info::init();
+    let (xdensity, ydensity) = get_density(&hdr);
+    info.xdensity = xdensity;
+    info.ydensity = ydensity;
+    info.width = hdr.width as i32;
+    info.height = hdr.height as i32;
+    info.bits_per_component = hdr.bits_per_sample as i32;
+    if info.width <= 0 || info.height <= 0 {
+        warn!(
+            "Invalid TIFF file: width={}, height={}",
+            info.width, info.height,
+        );
+        return Err(());
+    }
+    if hdr.planar_configuration != 1 && hdr.samples_per_pixel > 1 {
+        warn!("TIFF: Separate color planes are not supported.");
+        return Err(());
+    }
+    info.num_components = match hdr.photometric {
+        PHOTOMETRIC_WHITE_IS_ZERO | PHOTOMETRIC_BLACK_IS_ZERO | PHOTOMETRIC_PALETTE => 1,
+        PHOTOMETRIC_RGB => 3,
+        p => {
+            warn!("TIFF: Unsupported photometric interpretation {}.", p);
+            return Err(());
+        }
+    };
+    let bps = hdr.bits_per_sample as u32;
+    let supported_depth = match hdr.photometric {
+        PHOTOMETRIC_PALETTE => [1, 2, 4, 8].contains(&bps),
+        PHOTOMETRIC_RGB => [8, 16].contains(&bps),
+        _ => [1, 2, 4, 8, 16].contains(&bps),
+    };
+    if !supported_depth {
+        warn!("TIFF: Unsupported number of bits per sample: {}", bps);
+        return Err(());
+    }
+    let spp = hdr.samples_per_pixel as u32;
+    let num_extra = spp.checked_sub(info.num_components as u32).ok_or_else(|| {
+        warn!("TIFF: Too few samples per pixel: {}", spp);
+    })?;
+    if num_extra > 1 {
+        warn!("TIFF: Unsupported number of extra samples: {}", num_extra);
+        return Err(());
+    }
+    let alpha = if num_extra == 1 {
+        if bps < 8 {
+            warn!("TIFF: Extra samples of fewer than 8 bits are not supported.");
+            return Err(());
+        }
+        match hdr.extra_samples.first() {
+            Some(&EXTRA_SAMPLE_ASSOCIATED_ALPHA) => Some(true),
+            Some(&EXTRA_SAMPLE_UNASSOCIATED_ALPHA) => Some(false),
+            _ => None, /* Not alpha; just dropped. */
+        }
+    } else {
+        None
+    };
+
+    let size = match image_data_size(hdr.width, hdr.height, spp * bps) {
+        Some(size) => size,
+        None => {
+            warn!("TIFF: Unsupported image size {}x{}.", hdr.width, hdr.height);
+            return Err(());
+        }
+    };
+    let mut raster = tiff.read_raster(&hdr, size)?;
+    if bps == 16 && !tiff.big_endian {
+        /* PDF wants big-endian samples. */
+        for sample in raster.chunks_exact_mut(2) {
+            sample.swap(0, 1);
+        }
+    }
+
+    let mut stream = pdf_stream::new(STREAM_COMPRESS);
+    let stream_dict = stream.get_dict_mut();
+    let colorspace = match hdr.photometric {
+        PHOTOMETRIC_PALETTE => {
+            let num_palette = 1_usize << bps;
+            if hdr.color_map.len() < 3 * num_palette {
+                warn!("TIFF: Palette image without a proper ColorMap.");
+                return Err(());
+            }
+            let mut palette = Vec::with_capacity(3 * num_palette);
+            for i in 0..num_palette {
+                for c in 0..3 {
+                    palette.push((hdr.color_map[c * num_palette + i] >> 8) as u8);
+                }
+            }
+            let mut colorspace = vec![];
+            colorspace.push_obj("Indexed");
+            colorspace.push_obj("DeviceRGB");
+            colorspace.push_obj((num_palette - 1) as f64);
+            colorspace.push_obj(pdf_string::new(&palette));
+            colorspace.into_obj()
+        }
+        PHOTOMETRIC_RGB => "DeviceRGB".into_obj(),
+        _ => "DeviceGray".into_obj(),
+    };
+    stream_dict.set("ColorSpace", colorspace);
+    if hdr.photometric == PHOTOMETRIC_WHITE_IS_ZERO {
+        let mut decode = vec![];
+        decode.push_obj(1_f64);
+        decode.push_obj(0_f64);
+        stream_dict.set("Decode", decode.into_obj());
+    }
+
+    if num_extra == 0 {
+        stream.add_slice(&raster);
+    } else {
+        /* Split off the extra samples, which are at least a byte each. */
+        let bytes_per_sample = bps as usize / 8;
+        let color_bytes = info.num_components as usize * bytes_per_sample;
+        let pixel_bytes = color_bytes + bytes_per_sample;
+        let mut color = Vec::with_capacity(raster.len() / pixel_bytes * color_bytes);
+        let mut extra = Vec::with_capacity(raster.len() / pixel_bytes * bytes_per_sample);
+        for pixel in raster.chunks_exact(pixel_bytes) {
+            color.extend_from_slice(&pixel[..color_bytes]);
+            extra.extend_from_slice(&pixel[color_bytes..]);
+        }
+        stream.add_slice(&color);
+        if let Some(associated) = alpha {
+            let mut smask = pdf_stream::new(STREAM_COMPRESS);
+            let dict = smask.get_dict_mut();
+            dict.set("Type", "XObject");
+            dict.set("Subtype", "Image");
+            dict.set("Width", info.width as f64);
+            dict.set("Height", info.height as f64);
+            dict.set("ColorSpace", "DeviceGray");
+            dict.set("BitsPerComponent", bps as f64);
+            if associated {
+                /* The colors have been premultiplied by the alpha values. */
+                let mut matte = vec![];
+                for _ in 0..info.num_components {
+                    matte.push_obj(0_f64);
+                }
+                dict.set("Matte", matte.into_obj());
+            }
+            smask.add_slice(&extra);
+            if info.height > 64 {
+                pdf_stream_set_predictor(&mut smask, 15, info.width, bps as i32, 1);
+            }
+            stream.get_dict_mut().set("SMask", smask.into_ref());
+        }
+    }
+    /* Predictor is usually not so efficient for indexed images. */
+    if hdr.photometric != PHOTOMETRIC_PALETTE && bps >= 8 && info.height > 64 {
+        pdf_stream_set_predictor(
+            &mut stream,
+            15,
+            info.width,
+            info.bits_per_component,
+            info.num_components,
+        );
+    }
+    ximage.set_image(&info, stream.into_obj());
+    Ok(())
+}
+
+/// The fields of the first image file directory that we use.
+struct Header {
+    width: u32,
+    height: u32,
+    bits_per_sample: u16,
+    compression: u16,
+    photometric: u16,
+    samples_per_pixel: u16,
+    planar_configuration: u16,
+    strip_offsets: Vec<u32>,
+    strip_byte_counts: Vec<u32>,
+    x_resolution: Option<f64>,
+    y_resolution: Option<f64>,
+    resolution_unit: u16,
+    color_map: Vec<u32>,
+    extra_samples: Vec<u32>,
+}
+
+fn get_density(hdr: &Header) -> (f64, f64) {
+    let per_inch = match hdr.resolution_unit {
+        2 => 1.,
+        3 => 2.54,
+        _ => return (1., 1.),
+    };
+    match (hdr.x_resolution, hdr.y_resolution) {
+        (Some(x), Some(y)) if x > 0. && y > 0. => (72. / (x * per_inch), 72. / (y * per_inch)),
+        _ => (1., 1.),
+    }
+}
+
+fn read_file<R: Read + Seek>(handle: &mut R) -> Result<Vec<u8>, ()> {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("Reading TIFF file failed...");
+        return Err(());
+    }
+    Ok(data)
+}
+
+/// Decode PackBits data from `src` onto `dest`, stopping once `dest` holds
+/// `limit` bytes.
+fn unpack_bits(src: &[u8], dest: &mut Vec<u8>, limit: usize) -> Result<(), ()> {
+    let mut i = 0;
+    while i < src.len() && dest.len() < limit {
+        let n = src[i] as i8;
+        i += 1;
+        if n >= 0 {
+            let len = n as usize + 1;
+            match src.get(i..i + len) {
+                Some(literal) => dest.extend_from_slice(literal),
+                None => {
+                    warn!("TIFF: PackBits data is corrupt.");
+                    return Err(());
+                }
+            }
+            i += len;
+        } else if n != -128 {
+            let len = (1 - n as i32) as usize;
+            match src.get(i) {
+                Some(&b) => dest.extend(std::iter::repeat(b).take(len)),
+                None => {
+                    warn!("TIFF: PackBits data is corrupt.");
+                    return Err(());
+                }
+            }
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, ()> {
+        let big_endian = match data.get(..2) {
+            Some(b"II") => false,
+            Some(b"MM") => true,
+            _ => {
+                warn!("Not a TIFF file?");
+                return Err(());
+            }
+        };
+        Ok(Tiff { data, big_endian })
+    }
+
+    fn bytes(&self, offset: usize, len: usize) -> Result<&'a [u8], ()> {
+        match offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+        {
+            Some(b) => Ok(b),
+            None => {
+                warn!("TIFF: Unexpected end of file.");
+                Err(())
+            }
+        }
+    }
+
+    fn u16_at(&self, offset: usize) -> Result<u16, ()> {
+        let b = self.bytes(offset, 2)?;
+        let b = [b[0], b[1]];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32_at(&self, offset: usize) -> Result<u32, ()> {
+        let b = self.bytes(offset, 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    /// The integer values of the directory entry at `entry`.
+    fn values(&self, entry: usize) -> Result<Vec<u32>, ()> {
+        let field_type = self.u16_at(entry + 2)?;
+        let count = self.u32_at(entry + 4)? as usize;
+        let size = match field_type {
+            1 => 1,
+            3 => 2,
+            4 => 4,
+            t => {
+                warn!("TIFF: Unexpected field type {}.", t);
+                return Err(());
+            }
+        };
+        /* Values that fit in four bytes are stored in the entry itself. */
+        let start = if count.saturating_mul(size) <= 4 {
+            entry + 8
+        } else {
+            self.u32_at(entry + 8)? as usize
+        };
+        self.bytes(start, count.saturating_mul(size))?;
+        (0..count)
+            .map(|i| match size {
+                1 => Ok(self.data[start + i] as u32),
+                2 => self.u16_at(start + 2 * i).map(u32::from),
+                _ => self.u32_at(start + 4 * i),
+            })
+            .collect()
+    }
+
+    fn value(&self, entry: usize) -> Result<u32, ()> {
+        match self.values(entry)?.first() {
+            Some(&v) => Ok(v),
+            None => {
+                warn!("TIFF: Directory entry without a value.");
+                Err(())
+            }
+        }
+    }
+
+    fn rational(&self, entry: usize) -> Result<f64, ()> {
+        if self.u16_at(entry + 2)? != 5 {
+            warn!("TIFF: Expected a rational value.");
+            return Err(());
+        }
+        let offset = self.u32_at(entry + 8)? as usize;
+        let num = self.u32_at(offset)?;
+        let den = self.u32_at(offset + 4)?;
+        Ok(if den == 0 {
+            0.
+        } else {
+            num as f64 / den as f64
+        })
+    }
+
+    fn read_header(&self) -> Result<Header, ()> {
+        let mut hdr = Header {
+            width: 0,
+            height: 0,
+            bits_per_sample: 1,
+            compression: COMPRESSION_NONE,
+            photometric: u16::MAX,
+            samples_per_pixel: 1,
+            planar_configuration: 1,
+            strip_offsets: Vec::new(),
+            strip_byte_counts: Vec::new(),
+            x_resolution: None,
+            y_resolution: None,
+            resolution_unit: 2,
+            color_map: Vec::new(),
+            extra_samples: Vec::new(),
+        };
+        let ifd = self.u32_at(4)? as usize;
+        let num_entries = self.u16_at(ifd)? as usize;
+        for i in 0..num_entries {
+            let entry = ifd + 2 + 12 * i;
+            match self.u16_at(entry)? {
+                TAG_IMAGE_WIDTH => hdr.width = self.value(entry)?,
+                TAG_IMAGE_LENGTH => hdr.height = self.value(entry)?,
+                TAG_BITS_PER_SAMPLE => {
+                    let bits = self.values(entry)?;
+                    if bits.iter().any(|&b| b != bits[0]) {
+                        warn!("TIFF: Samples of different sizes are not supported.");
+                        return Err(());
+                    }
+                    hdr.bits_per_sample = bits.first().copied().unwrap_or(1) as u16;
+                }
+                TAG_COMPRESSION => hdr.compression = self.value(entry)? as u16,
+                TAG_PHOTOMETRIC => hdr.photometric = self.value(entry)? as u16,
+                TAG_STRIP_OFFSETS => hdr.strip_offsets = self.values(entry)?,
+                TAG_SAMPLES_PER_PIXEL => hdr.samples_per_pixel = self.value(entry)? as u16,
+                TAG_STRIP_BYTE_COUNTS => hdr.strip_byte_counts = self.values(entry)?,
+                TAG_X_RESOLUTION => hdr.x_resolution = Some(self.rational(entry)?),
+                TAG_Y_RESOLUTION => hdr.y_resolution = Some(self.rational(entry)?),
+                TAG_PLANAR_CONFIGURATION => hdr.planar_configuration = self.value(entry)? as u16,
+                TAG_RESOLUTION_UNIT => hdr.resolution_unit = self.value(entry)? as u16,
+                TAG_COLOR_MAP => hdr.color_map = self.values(entry)?,
+                TAG_EXTRA_SAMPLES => hdr.extra_samples = self.values(entry)?,
+                _ => {}
+            }
+        }
+        Ok(hdr)
+    }
+
+    /// Read and decompress the image's strips, which must hold at least
+    /// `len` bytes.
+    fn read_raster(&self, hdr: &Header, len: usize) -> Result<Vec<u8>, ()> {
+        if hdr.strip_offsets.is_empty() {
+            warn!("TIFF: No strips found; tiled images are not supported.");
+            return Err(());
+        }
+        if hdr.strip_offsets.len() != hdr.strip_byte_counts.len() {
+            warn!("TIFF: Inconsistent StripOffsets and StripByteCounts.");
+            return Err(());
+        }
+        /* PackBits expands two bytes into at most 128. */
+        let expansion = match hdr.compression {
+            COMPRESSION_NONE => 1,
+            COMPRESSION_PACKBITS => 64,
+            c => {
+                warn!("Unknown/Unsupported compression type for TIFF image: {}", c);
+                return Err(());
+            }
+        };
+        /* The size of the image comes from the file, so don't trust it
+         * further than the strips can go. */
+        let max_yield = hdr
+            .strip_byte_counts
+            .iter()
+            .map(|&count| (count as usize).min(self.data.len()))
+            .fold(0_usize, usize::saturating_add)
+            .saturating_mul(expansion);
+        if max_yield < len {
+            warn!("TIFF: Image data is truncated.");
+            return Err(());
+        }
+        let mut raster = Vec::with_capacity(len);
+        for (&offset, &count) in hdr.strip_offsets.iter().zip(&hdr.strip_byte_counts) {
+            let strip = self.bytes(offset as usize, count as usize)?;
+            if hdr.compression == COMPRESSION_PACKBITS {
+                unpack_bits(strip, &mut raster, len)?;
+            } else {
+                raster.extend_from_slice(strip);
+            }
+            if raster.len() >= len {
+                break;
+            }
+        }
+        if raster.len() < len {
+            warn!("TIFF: Image data is truncated.");
+            return Err(());
+        }
+        raster.truncate(len);
+        Ok(raster)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unpack(src: &[u8], limit: usize) -> Result<Vec<u8>, ()> {
+        let mut dest = Vec::new();
+        unpack_bits(src, &mut dest, limit)?;
+        Ok(dest)
+    }
+
+    #[test]
+    fn unpack_bits_runs() {
+        /* The example from the TIFF specification. */
+        let packed = [
+            0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7,
+            0xaa,
+        ];
+        let unpacked = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        assert_eq!(unpack(&packed, 100).unwrap(), &unpacked[..]);
+
+        /* -128 is a no-op. */
+        assert_eq!(unpack(&[0x80, 0x00, 0x07], 100).unwrap(), [0x07]);
+
+        /* A run of the longest length. */
+        assert_eq!(unpack(&[0x81, 0x01], 1000).unwrap(), [0x01; 128]);
+    }
+
+    #[test]
+    fn unpack_bits_limit() {
+        /* Decoding stops once the limit is reached, even mid-way through the
+         * data; the last run may overshoot it. */
+        let unpacked = unpack(&[0xfd, 0x01, 0xfd, 0x02], 3).unwrap();
+        assert_eq!(unpacked, [0x01; 4]);
+
+        let mut dest = vec![0x09; 5];
+        unpack_bits(&[0x00, 0x01], &mut dest, 5).unwrap();
+        assert_eq!(dest, [0x09; 5]);
+    }
+
+    #[test]
+    fn unpack_bits_truncated() {
+        /* Silence the warnings, which would need the engine's output. */
+        unsafe { crate::dpx_error::shut_up(2) };
+
+        /* A literal run that goes beyond the data. */
+        assert!(unpack(&[0x03, 0x01, 0x02], 100).is_err());
+        /* A repeat run without its byte. */
+        assert!(unpack(&[0x00, 0x01, 0xfd], 100).is_err());
+    }
+}
@@ -36,6 +36,7 @@ use super::dpx_cid::{
 };
 use super::dpx_cmap::CMap_cache_get;
 use super::dpx_mem::new;
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_pdfencoding::pdf_load_ToUnicode_stream;
 use super::dpx_pdfresource::{pdf_defineresource, pdf_findresource, pdf_get_resource_reference};
 use super::dpx_tt_cmap::otf_create_ToUnicode_stream;
@@ -151,8 +152,10 @@ unsafe fn add_ToUnicode(font: *mut Type0Font) {
         panic!("{}: No descendant CID-keyed font.", "Type0");
     }
     if CIDFont_is_ACCFont(&*cidfont) {
-        /* No need to embed ToUnicode */
-        return;
+        /* No need to embed ToUnicode, except that PDF/A asks for one. */
+        if pdf_get_pdfa().is_none() {
+            return;
+        }
     } else {
         if CIDFont_is_UCSFont(&*cidfont) {
             /*
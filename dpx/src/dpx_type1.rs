@@ -36,6 +36,7 @@ use super::dpx_cff::{
     cff_release_index, cff_set_name, cff_update_string, CffIndex, Pack,
 };
 use super::dpx_mem::{new, renew};
+use super::dpx_pdfa::pdf_get_pdfa;
 use super::dpx_pdfencoding::{pdf_create_ToUnicode_CMap, pdf_encoding_get_encoding};
 use super::dpx_pdffont::{
     pdf_font, pdf_font_get_descriptor, pdf_font_get_encoding, pdf_font_get_resource,
@@ -107,7 +108,8 @@ unsafe fn is_basefont(name: &str) -> bool {
 
 pub(crate) unsafe fn pdf_font_open_type1(font: &mut pdf_font) -> i32 {
     let ident = font.ident.as_str();
-    if is_basefont(ident) {
+    /* PDF/A doesn't let the standard fonts go unembedded. */
+    if is_basefont(ident) && pdf_get_pdfa().is_none() {
         font.fontname = ident.to_owned();
         pdf_font_set_subtype(font, 0);
         pdf_font_set_flags(font, 1 << 0 | 1 << 2);
@@ -179,6 +179,8 @@ pub(crate) mod dpx_dvipdfmx;
 pub(crate) mod dpx_epdf;
 pub(crate) mod dpx_error;
 pub(crate) mod dpx_fontmap;
+pub(crate) mod dpx_gifimage;
+pub(crate) mod dpx_imageprobe;
 pub(crate) mod dpx_jp2image;
 pub(crate) mod dpx_jpegimage;
 pub(crate) mod dpx_mem;
@@ -186,6 +188,7 @@ pub(crate) mod dpx_mfileio;
 pub(crate) mod dpx_mpost;
 pub(crate) mod dpx_numbers;
 pub(crate) mod dpx_otl_opt;
+pub(crate) mod dpx_pdfa;
 pub(crate) mod dpx_pdfcolor;
 pub(crate) mod dpx_pdfdev;
 pub(crate) mod dpx_pdfdoc;
@@ -203,9 +206,11 @@ pub(crate) mod dpx_pngimage;
 pub(crate) mod dpx_pst;
 pub(crate) mod dpx_sfnt;
 pub(crate) mod dpx_subfont;
+pub(crate) mod dpx_svgimage;
 pub(crate) mod dpx_t1_char;
 pub(crate) mod dpx_t1_load;
 pub(crate) mod dpx_tfm;
+pub(crate) mod dpx_tiffimage;
 pub(crate) mod dpx_truetype;
 pub(crate) mod dpx_tt_aux;
 pub(crate) mod dpx_tt_cmap;
@@ -221,12 +226,12 @@ pub(crate) mod dpx_vf;
 mod shims;
 pub(crate) mod specials;
 
-pub use crate::dpx_bmpimage::{bmp_get_bbox, check_for_bmp};
-pub use crate::dpx_dvipdfmx::{dvipdfmx_main, XdvipdfmxConfig};
-pub use crate::dpx_jpegimage::{check_for_jpeg, jpeg_get_bbox};
+pub use crate::dpx_dvipdfmx::{
+    dvipdfmx_main, EncryptionConfig, PageRange, PdfAConformance, XdvipdfmxConfig,
+};
+pub use crate::dpx_imageprobe::{image_get_bbox, image_get_size, probe_image_type, ImageType};
 pub use crate::dpx_pdfdev::Corner;
 pub use crate::dpx_pdfdoc::{pdf_doc_get_page, pdf_doc_get_page_count, PdfPageBoundary};
 pub use crate::dpx_pdfdraw::pdf_dev_transform;
 pub use crate::dpx_pdfobj::{pdf_file, pdf_obj, pdf_open};
 pub use crate::dpx_pdfobj::{pdf_files_close, pdf_files_init};
-pub use crate::dpx_pngimage::{check_for_png, png_get_bbox};
@@ -138,9 +138,21 @@ unsafe fn spc_handler_pdfm__init(sd: &mut spc_pdf_) -> Result<()> {
     /* The folllowing dictionary entry keys are considered as keys for
      * text strings. Be sure that string object is NOT always a text string.
      */
-    const DEFAULT_TAINTKEYS: [&str; 11] = [
-        "Title", "Author", "Subject", "Keywords", "Creator", "Producer", "Contents", "Subj", "TU",
-        "T", "TM",
+    const DEFAULT_TAINTKEYS: [&str; 14] = [
+        "Title",
+        "Author",
+        "Subject",
+        "Keywords",
+        "Creator",
+        "Producer",
+        "Contents",
+        "Subj",
+        "TU",
+        "T",
+        "TM",
+        "Alt",
+        "ActualText",
+        "E",
     ];
     sd.annot_dict = ptr::null_mut();
     sd.lowest_level = 255;
@@ -538,6 +550,61 @@ unsafe fn spc_handler_pdfm_eann(spe: &mut SpcEnv, _args: &mut SpcArg) -> Result<
     sd.annot_dict = ptr::null_mut();
     error
 }
+/* Structure elements for tagged PDF: "bstruct /Role <<...>>" ... "estruct". */
+unsafe fn spc_handler_pdfm_bstruct(spe: &mut SpcEnv, args: &mut SpcArg) -> Result<()> {
+    let sd = &mut _PDF_STAT;
+    args.cur.skip_white();
+    let role = if let Some(role) = args.cur.parse_pdf_name() {
+        role
+    } else {
+        spc_warn!(spe, "Structure type expected but not found.");
+        return ERR;
+    };
+    args.cur.skip_white();
+    let attrs = if args.cur.is_empty() {
+        pdf_dict::new()
+    } else if let Some(attrs) = args.cur.parse_pdf_dict_with_tounicode(&mut sd.cd) {
+        attrs
+    } else {
+        spc_warn!(spe, "Ignoring structure element with invalid dictionary.");
+        return ERR;
+    };
+    let p = pdf_doc_mut();
+    if !p.pending_forms.is_null() {
+        spc_warn!(
+            spe,
+            "Can\'t begin a structure element within a form XObject."
+        );
+        return ERR;
+    }
+    p.begin_struct(&role, attrs);
+    Ok(())
+}
+unsafe fn spc_handler_pdfm_estruct(spe: &mut SpcEnv, _args: &mut SpcArg) -> Result<()> {
+    let p = pdf_doc_mut();
+    if !p.pending_forms.is_null() {
+        spc_warn!(spe, "Can\'t end a structure element within a form XObject.");
+        return ERR;
+    }
+    if !p.end_struct() {
+        spc_warn!(
+            spe,
+            "Tried to end a structure element without starting one!"
+        );
+        return ERR;
+    }
+    Ok(())
+}
+unsafe fn spc_handler_pdfm_rolemap(spe: &mut SpcEnv, args: &mut SpcArg) -> Result<()> {
+    args.cur.skip_white();
+    if let Some(dict) = args.cur.parse_pdf_dict(ptr::null_mut()) {
+        pdf_doc_mut().add_role_map(&dict);
+        Ok(())
+    } else {
+        spc_warn!(spe, "Dictionary object expected but not found.");
+        ERR
+    }
+}
 /* Color:.... */
 unsafe fn spc_handler_pdfm_bcolor(spe: &mut SpcEnv, ap: &mut SpcArg) -> Result<()> {
     let (psc, pfc) = pdf_color_get_current();
@@ -1463,7 +1530,7 @@ unsafe fn spc_handler_pdfm_tounicode(spe: &mut SpcEnv, args: &mut SpcArg) -> Res
         ERR
     }
 }
-const PDFM_HANDLERS: [SpcHandler; 80] = [
+const PDFM_HANDLERS: [SpcHandler; 83] = [
     SpcHandler {
         key: "annotation",
         exec: Some(spc_handler_pdfm_annot),
@@ -1776,6 +1843,18 @@ const PDFM_HANDLERS: [SpcHandler; 80] = [
         key: "code",
         exec: Some(spc_handler_pdfm_code),
     },
+    SpcHandler {
+        key: "bstruct",
+        exec: Some(spc_handler_pdfm_bstruct),
+    },
+    SpcHandler {
+        key: "estruct",
+        exec: Some(spc_handler_pdfm_estruct),
+    },
+    SpcHandler {
+        key: "rolemap",
+        exec: Some(spc_handler_pdfm_rolemap),
+    },
     SpcHandler {
         key: "minorversion",
         exec: Some(spc_handler_pdfm_do_nothing),
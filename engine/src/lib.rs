@@ -20,12 +20,13 @@ use bibtex::bibtex_main;
 pub use bibtex::BibtexConfig;
 use bridge::TTHistory;
 use dpx::dvipdfmx_main;
-pub use dpx::XdvipdfmxConfig;
+pub use dpx::{EncryptionConfig, PageRange, PdfAConformance, XdvipdfmxConfig};
 use xetex_ini::tt_run_engine;
 
 pub use bridge::tt_bridge_api_t;
 pub use bridge::tt_get_error_message;
-pub use xetex_engine_interface::tt_xetex_set_int_variable;
+pub use bridge::{SHELL_ESCAPE_DISABLED, SHELL_ESCAPE_FAILED, SHELL_ESCAPE_NOT_ALLOWED};
+pub use xetex_engine_interface::{tt_xetex_set_int_variable, tt_xetex_set_source_date_epoch};
 
 pub unsafe fn tex_simple_main(
     api: *const tt_bridge_api_t,
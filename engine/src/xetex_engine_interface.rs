@@ -1,9 +1,10 @@
 #![allow(non_camel_case_types, non_snake_case, non_upper_case_globals)]
 
 use crate::xetex_ini::{
-    halt_on_error_p, in_initex_mode, semantic_pagination_enabled, shell_escape_enabled,
-    synctex_enabled,
+    file_line_error_style_p, halt_on_error_p, in_initex_mode, initex_load_format,
+    semantic_pagination_enabled, shell_escape_enabled, shell_escape_restricted, synctex_enabled,
 };
+use crate::xetex_texmfmp::source_date_epoch;
 
 /* tectonic/core-strutils.h: miscellaneous C string utilities
    Copyright 2016-2018 the Tectonic Project
@@ -18,17 +19,29 @@ use crate::xetex_ini::{
 */
 /* These functions aren't used within the C/C++ library, but are called
  * by the Rust code to configure the XeTeX engine before launching it. */
+/// Fix the date and time that TeX reports, in seconds since the epoch, or
+/// with `None` go back to taking them from `SOURCE_DATE_EPOCH` or the clock.
+pub unsafe fn tt_xetex_set_source_date_epoch(epoch: Option<u64>) {
+    source_date_epoch = epoch;
+}
+
 pub unsafe fn tt_xetex_set_int_variable(var_name: &str, value: i32) -> i32 {
     if var_name == "halt_on_error_p" {
         halt_on_error_p = value
+    } else if var_name == "file_line_error_style_p" {
+        file_line_error_style_p = value
     } else if var_name == "in_initex_mode" {
         in_initex_mode = value != 0
+    } else if var_name == "initex_load_format" {
+        initex_load_format = value != 0
     } else if var_name == "synctex_enabled" {
         synctex_enabled = value != 0
     } else if var_name == "semantic_pagination_enabled" {
         semantic_pagination_enabled = value != 0
     } else if var_name == "shell_escape_enabled" {
         shell_escape_enabled = value != 0
+    } else if var_name == "shell_escape_restricted" {
+        shell_escape_restricted = value != 0
     } else {
         return 1;
     } /* Uh oh: unrecognized variable */
@@ -365,6 +365,7 @@ pub(crate) use super::xetex_io::UFILE;
 */
 /* All the following variables are declared in xetex-xetexd.h */
 pub(crate) static mut shell_escape_enabled: bool = false;
+pub(crate) static mut shell_escape_restricted: bool = false;
 
 pub(crate) static mut EQTB: Vec<EqtbWord> = Vec::new();
 #[no_mangle]
@@ -382,6 +383,10 @@ pub(crate) static mut last: usize = 0;
 pub(crate) static mut max_buf_stack: usize = 0;
 #[no_mangle]
 pub(crate) static mut in_initex_mode: bool = false;
+/// In initex mode, load the format anyway, so that a new format can be
+/// dumped on top of it.
+#[no_mangle]
+pub(crate) static mut initex_load_format: bool = false;
 #[no_mangle]
 pub(crate) static mut error_line: i32 = 0;
 #[no_mangle]
@@ -4173,7 +4178,7 @@ pub(crate) unsafe fn tt_run_engine(dump_name: &str, input_file_name: &str) -> TT
     }
     no_new_control_sequence = true;
 
-    if !in_initex_mode && !load_fmt_file() {
+    if (!in_initex_mode || initex_load_format) && !load_fmt_file() {
         return history;
     }
 
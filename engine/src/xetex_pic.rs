@@ -16,9 +16,7 @@ use bridge::{InFile, TTInputFormat};
 use dpx::pdf_dev_transform;
 use dpx::pdf_open;
 use dpx::Corner;
-use dpx::{bmp_get_bbox, check_for_bmp};
-use dpx::{check_for_jpeg, jpeg_get_bbox};
-use dpx::{check_for_png, png_get_bbox};
+use dpx::{image_get_size, probe_image_type, ImageType};
 use dpx::{pdf_doc_get_page, pdf_doc_get_page_count, PdfPageBoundary};
 
 use euclid::{point2, size2, Angle};
@@ -104,20 +102,13 @@ unsafe fn pdf_get_rect(
     }
 }
 unsafe fn get_image_size_in_inches(handle: &mut InFile) -> Result<(f32, f32), i32> {
-    let (width_pix, height_pix, xdensity, ydensity) = if check_for_jpeg(handle) != 0 {
-        jpeg_get_bbox(handle).map_err(|_| -1)?
-    } else if check_for_bmp(handle) {
-        bmp_get_bbox(handle).map_err(|_| -1)?
-    } else if check_for_png(handle) != 0 {
-        png_get_bbox(handle).map_err(|_| -1)?
-    } else {
+    let format = probe_image_type(handle);
+    if matches!(format, ImageType::Unknown | ImageType::Pdf | ImageType::Eps) {
         return Err(1);
-    };
-    /* xdvipdfmx defines density = 72 / dpi, so ... */
-    Ok((
-        (width_pix as f64 * xdensity / 72.) as f32,
-        (height_pix as f64 * ydensity / 72.) as f32,
-    ))
+    }
+    /* Bitmaps are sized by their resolution, SVG images by their viewport. */
+    let (width, height) = image_get_size(handle, format).map_err(|_| -1)?;
+    Ok(((width / 72.) as f32, (height / 72.) as f32))
 }
 /*
   pdfBoxType indicates which pdf bounding box to use (0 for \XeTeXpicfile)
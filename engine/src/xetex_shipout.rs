@@ -7,7 +7,7 @@ use crate::t_eprint;
 use crate::xetex_consts::*;
 use crate::xetex_errors::{confusion, error, fatal_error};
 use crate::xetex_ext::{apply_tfm_font_mapping, make_font_def};
-use crate::xetex_ini::shell_escape_enabled;
+use crate::xetex_ini::{shell_escape_enabled, shell_escape_restricted};
 use crate::xetex_ini::Selector;
 use crate::xetex_ini::{
     avail, cur_dir, cur_h, cur_h_offset, cur_input, cur_list, cur_page_height, cur_page_width,
@@ -2066,10 +2066,29 @@ unsafe fn write_out(input: &mut input_state_t, p: &WriteFile) {
         if !shell_escape_enabled {
             t_print_nl!("runsystem({})...disabled.", s);
         } else {
-            // Currently, -Z shell-escape is implemented but hidden (see
-            // src/unstable_opts.rs). When this gets actually implemented,
-            // uncomment the relevant parts in that file.
-            t_print_nl!("runsystem({})...enabled but not implemented yet!", s);
+            // The actual work happens on the Rust side of the bridge, which
+            // knows how to shuttle files between the I/O stack and the
+            // command's working directory.
+            match bridge::ttstub_shell_escape(&s) {
+                bridge::SHELL_ESCAPE_DISABLED => {
+                    t_print_nl!("runsystem({})...disabled.", s);
+                }
+                bridge::SHELL_ESCAPE_NOT_ALLOWED => {
+                    t_print_nl!("runsystem({})...disabled (restricted).", s);
+                }
+                bridge::SHELL_ESCAPE_FAILED => {
+                    t_print_nl!("runsystem({})...failed.", s);
+                }
+                0 if shell_escape_restricted => {
+                    t_print_nl!("runsystem({})...executed safely (allowed).", s);
+                }
+                0 => {
+                    t_print_nl!("runsystem({})...executed.", s);
+                }
+                code => {
+                    t_print_nl!("runsystem({})...executed (exit status {}).", s, code);
+                }
+            }
         }
 
         t_print_nl!("");
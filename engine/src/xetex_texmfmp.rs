@@ -17,17 +17,23 @@ possible) to do in C.
 This file is public domain.  */
 static mut last_source_name: String = String::new();
 static mut last_lineno: i32 = 0;
+/// If set, the time of the run, in seconds since the epoch; this takes
+/// precedence over `SOURCE_DATE_EPOCH`.
+pub(crate) static mut source_date_epoch: Option<u64> = None;
 pub(crate) fn get_date_and_time() -> (i32, i32, i32, i32) {
     use chrono::prelude::*;
 
-    let tm = match env::var("SOURCE_DATE_EPOCH").ok() {
-        Some(s) => {
-            let epoch = u64::from_str_radix(&s, 10).expect("invalid build date (not a number)");
-            std::time::SystemTime::UNIX_EPOCH
-                .checked_add(std::time::Duration::from_secs(epoch))
-                .expect("time overflow")
-                .into()
-        }
+    let epoch = unsafe { source_date_epoch }.or_else(|| {
+        env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .map(|s| u64::from_str_radix(&s, 10).expect("invalid build date (not a number)"))
+    });
+
+    let tm = match epoch {
+        Some(epoch) => std::time::SystemTime::UNIX_EPOCH
+            .checked_add(std::time::Duration::from_secs(epoch))
+            .expect("time overflow")
+            .into(),
         None => Local::now(),
     };
 
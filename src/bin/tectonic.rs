@@ -2,30 +2,55 @@
 // Copyright 2016-2018 the Tectonic Project
 // Licensed under the MIT License.
 
+use byte_unit::Byte;
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
+use std::env;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
+use std::time::Duration;
 
-use tectonic::config::PersistentConfig;
-use tectonic::driver::{OutputFormat, PassSetting, ProcessingSessionBuilder};
+use tectonic::config::{
+    EngineDefaults, PersistentConfig, ProjectConfig, TargetConfig, PROJECT_CONFIG_FILE_NAME,
+};
+use tectonic::driver::{
+    DepfileFormat, Diagnostic, OutputFormat, PassSetting, ProcessingSession,
+    ProcessingSessionBuilder,
+};
+use tectonic::engines::xdvipdfmx::{
+    EncryptionAlgorithm, PageRange, PdfAConformance, PdfEncryption,
+};
 use tectonic::errors::{ErrorKind, Result};
+use tectonic::io::bundle_builder::BundleBuilder;
+use tectonic::io::cache_admin::CacheAdmin;
+use tectonic::io::recording::{BundleRecorder, RecordingBundle};
+use tectonic::io::shared::SharedBundle;
+use tectonic::io::zipbundle::ZipBundle;
+use tectonic::io::Bundle;
+use tectonic::status::json::JsonStatusBackend;
 use tectonic::status::plain::PlainStatusBackend;
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::{ChatterLevel, StatusBackend};
+use tectonic::synctex::SyncTex;
 use tectonic::unstable_opts::{UnstableArg, UnstableOptions};
-use tectonic::{errmsg, tt_error, tt_note};
+use tectonic::watch::FileWatcher;
+use tectonic::{ctry, errmsg, tt_error, tt_note};
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "Tectonic", about = "Process a (La)TeX document")]
+#[structopt(
+    name = "Tectonic",
+    about = "Process a (La)TeX document",
+    setting = AppSettings::SubcommandsNegateReqs
+)]
 struct CliOptions {
-    /// The file to process, or "-" to process the standard input stream
-    #[structopt(name = "input")]
-    input: String,
-    /// The name of the "format" file used to initialize the TeX engine
-    #[structopt(long, short, name = "path", default_value = "latex")]
-    format: String,
+    /// The file to process, or "-" to process the standard input stream. Write a file named like a subcommand as "./build"
+    #[structopt(name = "input", required_unless = "print-config")]
+    input: Option<String>,
+    /// The name of the "format" file used to initialize the TeX engine [default: latex]
+    #[structopt(long, short, name = "path")]
+    format: Option<String>,
     /// Use this directory or Zip-format bundle file to find resource files instead of the default
     #[structopt(takes_value(true), parse(from_os_str), long, short, name = "file_path")]
     bundle: Option<PathBuf>,
@@ -39,6 +64,9 @@ struct CliOptions {
     /// Enable/disable colorful log output.
     #[structopt(long = "color", name = "when", default_value = "auto", possible_values(&["always", "auto", "never"]))]
     cli_color: String,
+    /// How to format status messages; "json" prints one JSON object per message to standard output
+    #[structopt(long = "message-format", name = "fmt", default_value = "human", possible_values(&["human", "json"]))]
+    message_format: String,
     /// Use only resource files cached locally
     #[structopt(short = "C", long)]
     only_cached: bool,
@@ -48,6 +76,12 @@ struct CliOptions {
     /// Write Makefile-format rules expressing the dependencies of this run to <dest_path>
     #[structopt(long, name = "dest_path")]
     makefile_rules: Option<PathBuf>,
+    /// The syntax of the rules written by --makefile-rules; "ninja" makes a depfile whose only target is the main output
+    #[structopt(long, name = "syntax", default_value = "make", possible_values(&["make", "ninja"]))]
+    depfile_format: String,
+    /// Write a JSON report of the inputs and outputs of this run, with their digests, to <json_path>
+    #[structopt(long, name = "json_path")]
+    deps_json: Option<PathBuf>,
     /// Which engines to run
     #[structopt(long, default_value = "default", possible_values(&["default", "tex", "bibtex_first"]))]
     pass: String,
@@ -58,55 +92,939 @@ struct CliOptions {
     #[structopt(short, long)]
     keep_intermediates: bool,
     /// Keep the log files generated during processing
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "no-keep-logs")]
     keep_logs: bool,
+    /// Don't keep the log files, even if a configuration file asks to
+    #[structopt(long, overrides_with = "keep-logs")]
+    no_keep_logs: bool,
     /// Generate SyncTeX data
-    #[structopt(long)]
+    #[structopt(long, overrides_with = "no-synctex")]
     synctex: bool,
+    /// Don't generate SyncTeX data, even if a configuration file asks to
+    #[structopt(long, overrides_with = "synctex")]
+    no_synctex: bool,
+    /// Compile the document's preamble, up to \begin{document} or a "%%endpreamble" line, into a format that later runs reuse while it is unchanged
+    #[structopt(long)]
+    precompile_preamble: bool,
+    /// Look for input files in <dir> if they aren't next to the input file; end it with "//" to search subdirectories too
+    #[structopt(
+        short = "I",
+        long = "include-dir",
+        name = "dir",
+        parse(from_os_str),
+        number_of_values = 1
+    )]
+    include_dirs: Vec<PathBuf>,
     /// Tell the engine that no file at <hide_path> exists, if it tries to read it
     #[structopt(long, name = "hide_path")]
     hide: Option<Vec<PathBuf>>,
+    /// Only include these pages in the PDF output, e.g. "1-4,10"
+    #[structopt(long, name = "ranges")]
+    pages: Option<String>,
+    /// Encrypt the PDF output with this algorithm
+    #[structopt(long, name = "algorithm", possible_values(&["aes128", "aes256"]))]
+    encrypt: Option<String>,
+    /// The password needed to open the encrypted PDF [default: none]
+    #[structopt(long, name = "user_password", requires = "algorithm")]
+    user_password: Option<String>,
+    /// The password needed to change the permissions of the encrypted PDF [default: the user password]
+    #[structopt(long, name = "owner_password", requires = "algorithm")]
+    owner_password: Option<String>,
+    /// What readers of the encrypted PDF may do, as a bitmask or a list like "print,copy"
+    #[structopt(long, name = "perms", requires = "algorithm")]
+    permissions: Option<String>,
+    /// Make the PDF output conform to this level of PDF/A, reporting anything that breaks it as an error
+    #[structopt(
+        long,
+        name = "pdfa_level",
+        possible_values(&["2b", "3b"]),
+        conflicts_with = "algorithm"
+    )]
+    pdfa: Option<String>,
     /// Print the engine's chatter during processing
     #[structopt(long = "print", short)]
     print_stdout: bool,
     /// The directory in which to place output files [default: the directory containing <input>]
     #[structopt(name = "outdir", short, long, parse(from_os_str))]
     outdir: Option<PathBuf>,
+    /// Treat the document as untrusted: keep file access within its directory, the include directories and the output directory, ignore its Tectonic.toml, and forbid shell escape
+    #[structopt(long)]
+    untrusted: bool,
+    /// Make byte-identical outputs from identical inputs, dating them by SOURCE_DATE_EPOCH [default: 0]; forbids shell escape
+    #[structopt(long)]
+    reproducible: bool,
+    /// Keep running, and reprocess the document whenever one of its input files changes
+    #[structopt(long)]
+    watch: bool,
+    /// Print the effective configuration, combining the configuration files and command-line options, and exit
+    #[structopt(long)]
+    print_config: bool,
     /// Unstable options. Pass -Zhelp to show a list
     // TODO we can't pass -Zhelp without also passing <input>
     #[structopt(name = "option", short = "Z", number_of_values = 1)]
     unstable: Vec<UnstableArg>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Build the documents listed in the project's Tectonic.toml file
+    #[structopt(name = "build")]
+    Build {
+        /// The names of the targets to build [default: all of them]
+        #[structopt(name = "target")]
+        targets: Vec<String>,
+    },
+    /// Query the SyncTeX data of a processed document
+    #[structopt(name = "synctex")]
+    Synctex(SynctexCommand),
+    /// Download every bundle file that a document needs, so that it can later be processed offline
+    #[structopt(name = "fetch")]
+    Fetch {
+        /// Also copy the files into a new Zip-format bundle at <zip_path>
+        #[structopt(long = "export-zip", name = "zip_path", parse(from_os_str))]
+        export_zip: Option<PathBuf>,
+        /// The file to process
+        #[structopt(name = "input")]
+        input: String,
+    },
+    /// Create bundles of resource files
+    #[structopt(name = "bundle")]
+    Bundle(BundleCommand),
+    /// Manage the local cache of bundle files
+    #[structopt(name = "cache")]
+    Cache(CacheCommand),
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Build { .. } => "build",
+            Command::Synctex(_) => "synctex",
+            Command::Fetch { .. } => "fetch",
+            Command::Bundle(_) => "bundle",
+            Command::Cache(_) => "cache",
+        }
+    }
+}
+
+/// A document named like a subcommand can't be processed by naming it on
+/// the command line in the usual way, so point out how to do it if such a
+/// file exists.
+fn note_shadowed_input(subcommand: &str, status: &mut dyn StatusBackend) {
+    for name in &[subcommand.to_owned(), format!("{}.tex", subcommand)] {
+        if Path::new(name).is_file() {
+            tt_note!(
+                status,
+                "running the `{}` subcommand; to process the file \"{}\" instead, run `tectonic ./{}`",
+                subcommand,
+                name,
+                name
+            );
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+enum BundleCommand {
+    /// Create a Zip bundle and an indexed tar bundle from the files in one or more directory trees
+    #[structopt(name = "create")]
+    Create {
+        /// The base name of the output files: "<base>.zip", "<base>.tar" and "<base>.tar.index.gz"
+        #[structopt(short = "o", long = "output", name = "base", parse(from_os_str))]
+        output: PathBuf,
+        /// The directories to take files from; earlier ones take precedence
+        #[structopt(name = "dir", required = true, parse(from_os_str))]
+        dirs: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+enum CacheCommand {
+    /// List the bundles in the cache and the space they use
+    #[structopt(name = "list")]
+    List,
+    /// Check cached files against their digests, removing any that are damaged
+    #[structopt(name = "verify")]
+    Verify,
+    /// Remove cached data that the current bundle does not use
+    #[structopt(name = "gc")]
+    Gc {
+        /// Also evict the least recently used files until the cache fits in this size, such as "500MB"
+        #[structopt(long = "max-size", name = "size")]
+        max_size: Option<String>,
+        /// Go ahead even if the cache has no data for the current bundle, which means removing the data of every bundle
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Remove everything from the cache
+    #[structopt(name = "clear")]
+    Clear,
+}
+
+#[derive(Debug, StructOpt)]
+enum SynctexCommand {
+    /// Forward search: find where a source line appears in the output
+    #[structopt(name = "view")]
+    View {
+        /// The source position, as <line>:<column>:<input>
+        #[structopt(short = "i", name = "line:column:input")]
+        input: String,
+        /// The output file, such as "doc.pdf"
+        #[structopt(short = "o", name = "output")]
+        output: PathBuf,
+    },
+    /// Inverse search: find the source line that produced a point in the output
+    #[structopt(name = "edit")]
+    Edit {
+        /// The point, as <page>:<x>:<y>:<output>, with coordinates in big points from the top-left corner
+        #[structopt(short = "o", name = "page:x:y:output")]
+        output: String,
+    },
+}
+
+/// Implement `tectonic bundle`.
+fn run_bundle(cmd: BundleCommand, status: &mut dyn StatusBackend) -> Result<()> {
+    match cmd {
+        BundleCommand::Create { output, dirs } => {
+            let mut builder = BundleBuilder::new();
+
+            for d in &dirs {
+                builder.add_tree(d, status)?;
+            }
+
+            if builder.is_empty() {
+                return Err(errmsg!("no files were found to put in the bundle"));
+            }
+
+            let with_extension = |ext: &str| {
+                let mut p = output.as_os_str().to_owned();
+                p.push(ext);
+                PathBuf::from(p)
+            };
+
+            let zip_path = with_extension(".zip");
+            builder.write_zip(&zip_path)?;
+            let tar_path = with_extension(".tar");
+            let index_path = builder.write_itar(&tar_path)?;
+
+            tt_note!(
+                status,
+                "wrote {} files to \"{}\", \"{}\" and \"{}\"",
+                builder.len(),
+                zip_path.display(),
+                tar_path.display(),
+                index_path.display()
+            );
+            tt_note!(
+                status,
+                "the digest of the bundle is {}",
+                builder.digest().to_string()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn human_size(n: u64) -> String {
+    Byte::from_bytes(n as u128)
+        .get_appropriate_unit(true)
+        .to_string()
+}
+
+/// Implement `tectonic cache`. The bundle given with `--web-bundle`, or
+/// failing that the configured default bundle, counts as the current one:
+/// `gc` keeps its data and throws away everything else.
+fn run_cache(
+    cmd: CacheCommand,
+    args: &CliOptions,
+    config: &PersistentConfig,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    let admin = CacheAdmin::new(None)?;
+
+    let current_url = args
+        .web_bundle
+        .as_ref()
+        .map(|u| &u[..])
+        .or_else(|| config.default_bundle_url());
+    let current = match current_url {
+        Some(url) => admin.digest_for_url(url)?,
+        None => None,
+    };
+
+    match cmd {
+        CacheCommand::List => {
+            let bundles = admin.list()?;
+
+            if bundles.is_empty() {
+                println!("the cache is empty");
+            }
+
+            for b in &bundles {
+                let mark = if current.as_ref() == Some(&b.digest) {
+                    " (current)"
+                } else {
+                    ""
+                };
+                println!("{}{}", b.digest, mark);
+                println!(
+                    "    {} files, {}; {} formats, {}",
+                    b.n_files,
+                    human_size(b.files_size),
+                    b.n_formats,
+                    human_size(b.formats_size)
+                );
+            }
+
+            println!("total size: {}", human_size(admin.total_size()?));
+        }
+
+        CacheCommand::Verify => {
+            let report = admin.verify(status)?;
+
+            for (name, digest) in &report.bad {
+                println!("damaged: {} (bundle {})", name, digest);
+            }
+
+            tt_note!(
+                status,
+                "checked {} files; {} damaged",
+                report.n_checked,
+                report.bad.len()
+            );
+
+            if !report.bad.is_empty() {
+                return Err(errmsg!(
+                    "{} cached files were damaged and have been removed; they will be downloaded again when needed",
+                    report.bad.len()
+                ));
+            }
+        }
+
+        CacheCommand::Gc { max_size, force } => {
+            let max_size = match max_size {
+                Some(s) => {
+                    let b = ctry!(Byte::from_str(&s); "invalid size \"{}\"", s);
+                    Some(b.get_bytes() as u64)
+                }
+                None => None,
+            };
+
+            if current.is_none() {
+                if !force {
+                    return Err(errmsg!(
+                        "the cache has no data for the current bundle, so the data of every \
+                         bundle would be removed; use --force to do that anyway"
+                    ));
+                }
+
+                tt_note!(
+                    status,
+                    "no data for the current bundle is cached; everything is eligible for removal"
+                );
+            }
+
+            let keep: Vec<String> = current.into_iter().collect();
+            let freed = admin.gc(&keep, max_size, status)?;
+            tt_note!(status, "freed {}", human_size(freed));
+        }
+
+        CacheCommand::Clear => {
+            let freed = admin.clear()?;
+            tt_note!(status, "freed {}", human_size(freed));
+        }
+    }
+
+    Ok(())
+}
+
+/// Load the SyncTeX data that accompanies the output file `output`.
+fn open_synctex(output: &Path) -> Result<SyncTex> {
+    let gz = output.with_extension("synctex.gz");
+
+    if gz.exists() {
+        return SyncTex::open(gz);
+    }
+
+    let plain = output.with_extension("synctex");
+
+    if plain.exists() {
+        return SyncTex::open(plain);
+    }
+
+    Err(errmsg!(
+        "no SyncTeX data found for \"{}\"; was it processed with --synctex?",
+        output.display()
+    ))
+}
+
+/// Implement `tectonic synctex`. The output mirrors that of the `synctex`
+/// program that ships with TeX Live, so that editors already set up to run
+/// that can use Tectonic instead.
+fn run_synctex(cmd: SynctexCommand) -> Result<()> {
+    match cmd {
+        SynctexCommand::View { input, output } => {
+            let mut pieces = input.splitn(3, ':');
+            let (line, column, file) = match (pieces.next(), pieces.next(), pieces.next()) {
+                (Some(l), Some(c), Some(f)) => (l, c, f),
+                _ => {
+                    return Err(errmsg!(
+                        "expected <line>:<column>:<input>, got \"{}\"",
+                        input
+                    ))
+                }
+            };
+            let line = ctry!(line.parse(); "invalid line number \"{}\"", line);
+            let column = ctry!(column.parse(); "invalid column number \"{}\"", column);
+
+            let data = open_synctex(&output)?;
+            println!("SyncTeX result begin");
+
+            for b in data.forward(file, line, column) {
+                println!("Output:{}", output.display());
+                println!("Page:{}", b.page);
+                println!("x:{:.6}", b.x);
+                println!("y:{:.6}", b.y + b.height);
+                println!("h:{:.6}", b.x);
+                println!("v:{:.6}", b.y + b.height);
+                println!("W:{:.6}", b.width);
+                println!("H:{:.6}", b.height);
+                println!("before:");
+                println!("offset:0");
+                println!("middle:");
+                println!("after:");
+            }
+
+            println!("SyncTeX result end");
+        }
+
+        SynctexCommand::Edit { output } => {
+            let mut pieces = output.splitn(4, ':');
+            let (page, x, y, file) =
+                match (pieces.next(), pieces.next(), pieces.next(), pieces.next()) {
+                    (Some(p), Some(x), Some(y), Some(f)) => (p, x, y, f),
+                    _ => {
+                        return Err(errmsg!(
+                            "expected <page>:<x>:<y>:<output>, got \"{}\"",
+                            output
+                        ))
+                    }
+                };
+            let page = ctry!(page.parse(); "invalid page number \"{}\"", page);
+            let x = ctry!(x.parse(); "invalid coordinate \"{}\"", x);
+            let y = ctry!(y.parse(); "invalid coordinate \"{}\"", y);
+
+            let data = open_synctex(Path::new(file))?;
+            println!("SyncTeX result begin");
+
+            if let Some(loc) = data.inverse(page, x, y) {
+                println!("Output:{}", file);
+                println!("Input:{}", loc.file);
+                println!("Line:{}", loc.line);
+                println!("Column:{}", loc.column.map_or(-1, |c| c as i64));
+                println!("Offset:0");
+                println!("Context:");
+            }
+
+            println!("SyncTeX result end");
+        }
+    }
+
+    Ok(())
+}
+/// The directory whose project configuration applies to the input file.
+fn input_project_dir(args: &CliOptions) -> PathBuf {
+    let project_dir = match args.input {
+        Some(ref p) if p != "-" => Path::new(p)
+            .parent()
+            .map(|d| d.to_owned())
+            .unwrap_or_default(),
+        _ => PathBuf::new(),
+    };
+
+    // An empty parent means the current directory.
+    if project_dir.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        project_dir
+    }
+}
+
+/// Combine the per-user configuration, the project configuration (if any),
+/// and the command-line options, in increasing order of priority.
+fn effective_defaults(
+    args: &CliOptions,
+    unstable: &UnstableOptions,
+    config: &PersistentConfig,
+    project: Option<EngineDefaults>,
+) -> EngineDefaults {
+    let mut defaults = config.defaults().clone();
+
+    if let Some(project) = project {
+        defaults.merge(project);
+    }
+
+    defaults.merge(EngineDefaults {
+        format: args.format.clone(),
+        paper_size: unstable.paper_size.clone(),
+        keep_logs: cli_flag(args.keep_logs, args.no_keep_logs),
+        synctex: cli_flag(args.synctex, args.no_synctex),
+        reruns: args.reruns,
+        outdir: args.outdir.clone(),
+        hide: args.hide.clone().unwrap_or_default(),
+    });
+
+    defaults
+}
+
+/// Turn a pair of `--foo`/`--no-foo` flags into a setting, leaving it unset
+/// if neither was given.
+fn cli_flag(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// The PDF/A conformance level asked for with `--pdfa`, if any.
+fn pdfa_conformance(args: &CliOptions) -> Result<Option<PdfAConformance>> {
+    args.pdfa
+        .as_deref()
+        .map(|l| PdfAConformance::from_str(l).map_err(|e| errmsg!("{} \"{}\"", e, l)))
+        .transpose()
+}
+
+/// Open the bundle selected by the command-line options, or the default
+/// bundle.
+fn open_bundle(
+    args: &CliOptions,
+    config: &PersistentConfig,
+    status: &mut dyn StatusBackend,
+) -> Result<Box<dyn Bundle>> {
+    if let Some(ref path) = args.bundle {
+        config.make_local_file_provider(path.clone(), status)
+    } else if let Some(ref u) = args.web_bundle {
+        config.make_cached_url_provider(u, args.only_cached, None, status)
+    } else {
+        config.default_bundle(args.only_cached, status)
+    }
+}
+
+/// Find the project configuration that applies in `dir`, looking in its
+/// parent directories too.
+fn find_project(dir: &Path) -> Result<Option<(PathBuf, ProjectConfig)>> {
+    for d in dir.ancestors() {
+        if let Some(found) = ProjectConfig::open_in(d)? {
+            return Ok(Some(found));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Implement `tectonic build`: process each of the project's targets in
+/// turn. The targets share a single bundle and format cache, and a failure
+/// in one target doesn't stop the others from being built.
+fn run_build(
+    args: &CliOptions,
+    names: &[String],
+    unstable: &UnstableOptions,
+    config: &PersistentConfig,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    let conflicting = [
+        ("--makefile-rules", args.makefile_rules.is_some()),
+        ("--deps-json", args.deps_json.is_some()),
+        ("--pages", args.pages.is_some()),
+        ("--encrypt", args.encrypt.is_some()),
+        ("--watch", args.watch),
+        ("--print-config", args.print_config),
+        ("--untrusted", args.untrusted),
+    ];
+
+    for (flag, given) in &conflicting {
+        if *given {
+            return Err(errmsg!("{} cannot be used with `tectonic build`", flag));
+        }
+    }
+
+    let cwd = ctry!(env::current_dir(); "couldn't determine the current directory");
+    let (manifest_path, project) = match find_project(&cwd)? {
+        Some(found) => found,
+        None => {
+            return Err(errmsg!(
+                "no {} file found in \"{}\" or its parent directories",
+                PROJECT_CONFIG_FILE_NAME,
+                cwd.display()
+            ))
+        }
+    };
+
+    if project.targets.is_empty() {
+        return Err(errmsg!(
+            "\"{}\" does not define any targets",
+            manifest_path.display()
+        ));
+    }
+
+    for n in names {
+        if !project.targets.iter().any(|t| t.name == *n) {
+            return Err(errmsg!(
+                "there is no target named \"{}\" in \"{}\"",
+                n,
+                manifest_path.display()
+            ));
+        }
+    }
+
+    tt_note!(status, "building project \"{}\"", manifest_path.display());
+
+    // The targets share one bundle, which outlives any sessions that fail.
+    let mut bundle: Option<SharedBundle> = None;
+    let mut format_cache = None;
+    let mut results = Vec::new();
+
+    for target in &project.targets {
+        if !names.is_empty() && !names.contains(&target.name) {
+            continue;
+        }
+
+        tt_note!(status, "building target \"{}\"", target.name);
+
+        let mut layer = project.defaults.clone();
+        layer.merge(EngineDefaults {
+            format: target.format.clone(),
+            outdir: target.outdir.clone(),
+            ..EngineDefaults::default()
+        });
+        let defaults = effective_defaults(args, unstable, config, Some(layer));
+
+        let b = match bundle {
+            Some(ref b) => b.clone(),
+            None => {
+                let b = SharedBundle::new(open_bundle(args, config, status)?);
+                bundle = Some(b.clone());
+                b
+            }
+        };
+
+        let mut sess = match target_session(
+            args,
+            target,
+            &defaults,
+            unstable,
+            config,
+            Box::new(b),
+            format_cache.clone(),
+            status,
+        ) {
+            Ok(s) => s,
+            Err(e) => {
+                tt_error!(status, "couldn't set up target \"{}\"", target.name; e);
+                results.push((&target.name, None));
+                continue;
+            }
+        };
+
+        let result = run_session(&mut sess, status);
+
+        if format_cache.is_none() {
+            format_cache = sess.io.format_cache.clone();
+        }
+
+        match result {
+            Ok(diagnostics) => results.push((&target.name, Some(diagnostics.len()))),
+            Err(e) => {
+                tt_error!(status, "failed to build target \"{}\"", target.name; e);
+                results.push((&target.name, None));
+            }
+        }
+    }
+
+    let mut n_failed = 0;
+
+    for (name, result) in &results {
+        match result {
+            Some(0) => tt_note!(status, "{}: succeeded", name),
+            Some(n) => tt_note!(status, "{}: succeeded with {} warnings", name, n),
+            None => {
+                tt_note!(status, "{}: FAILED", name);
+                n_failed += 1;
+            }
+        }
+    }
+
+    if n_failed > 0 {
+        return Err(errmsg!(
+            "{} of {} targets failed to build",
+            n_failed,
+            results.len()
+        ));
+    }
+
+    Ok(())
 }
-fn inner(args: CliOptions, config: PersistentConfig, status: &mut dyn StatusBackend) -> Result<()> {
-    let unstable = UnstableOptions::from_unstable_args(args.unstable.into_iter());
+
+/// Set up the processing session for one target of a project.
+#[allow(clippy::too_many_arguments)]
+fn target_session(
+    args: &CliOptions,
+    target: &TargetConfig,
+    defaults: &EngineDefaults,
+    unstable: &UnstableOptions,
+    config: &PersistentConfig,
+    bundle: Box<dyn Bundle>,
+    format_cache: Option<tectonic::io::format_cache::FormatCache>,
+    status: &mut dyn StatusBackend,
+) -> Result<ProcessingSession> {
+    let mut unstable = unstable.clone();
+    unstable.paper_size = defaults.paper_size.clone();
+
+    let pdfa = pdfa_conformance(args)?;
+
+    let mut sess_builder = ProcessingSessionBuilder::default();
+    let format_path = defaults.format.as_deref().unwrap_or("latex");
+    sess_builder
+        .unstables(unstable)
+        .format_name(format_path)
+        .keep_logs(defaults.keep_logs.unwrap_or(false))
+        .keep_intermediates(args.keep_intermediates)
+        .format_cache_path(config.format_cache_path()?)
+        .synctex(defaults.synctex.unwrap_or(false))
+        .file_line_errors(args.message_format == "json")
+        .output_format(target.output_format()?)
+        .pass(target.pass()?)
+        .print_stdout(args.print_stdout)
+        .precompile_preamble(args.precompile_preamble)
+        .reproducible(args.reproducible)
+        .pdfa(pdfa)
+        .bundle(bundle);
+
+    if let Some(c) = format_cache {
+        sess_builder.format_cache(c);
+    }
+
+    if let Some(n) = defaults.reruns {
+        sess_builder.reruns(n);
+    }
+
+    for d in target.search_paths.iter().chain(&args.include_dirs) {
+        sess_builder.search_dir(d);
+    }
+
+    for p in &defaults.hide {
+        sess_builder.hide(p);
+    }
+
+    sess_builder.primary_input_path(&target.input);
+
+    if let Some(p) = config.prefetch_list_path(&target.input)? {
+        sess_builder.prefetch_list_path(p);
+    }
+
+    match target.input.file_name() {
+        Some(fname) => sess_builder.tex_input_name(&fname.to_string_lossy()),
+        None => {
+            return Err(errmsg!(
+                "can't figure out a basename for input path \"{}\"",
+                target.input.display()
+            ))
+        }
+    };
+
+    if let Some(ref output_dir) = defaults.outdir {
+        if !output_dir.is_dir() {
+            return Err(errmsg!(
+                "output directory \"{}\" does not exist",
+                output_dir.display()
+            ));
+        }
+        sess_builder.output_dir(output_dir);
+    }
+
+    sess_builder.create(status)
+}
+
+fn print_config(defaults: &EngineDefaults) {
+    println!(
+        "format = {:?}",
+        defaults.format.as_ref().map_or("latex", |s| &s[..])
+    );
+    println!(
+        "paper_size = {:?}",
+        defaults.paper_size.as_ref().map_or("letter", |s| &s[..])
+    );
+    println!("keep_logs = {}", defaults.keep_logs.unwrap_or(false));
+    println!("synctex = {}", defaults.synctex.unwrap_or(false));
+
+    match defaults.reruns {
+        Some(n) => println!("reruns = {}", n),
+        None => println!("# reruns: automatic"),
+    }
+
+    match defaults.outdir {
+        Some(ref p) => println!("outdir = {:?}", p.display().to_string()),
+        None => println!("# outdir: the directory containing the input"),
+    }
+
+    let hide: Vec<String> = defaults
+        .hide
+        .iter()
+        .map(|p| format!("{:?}", p.display().to_string()))
+        .collect();
+    println!("hide = [{}]", hide.join(", "));
+}
+
+fn inner(
+    mut args: CliOptions,
+    config: PersistentConfig,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    let mut unstable = UnstableOptions::from_unstable_args(args.unstable.drain(..));
+
+    // JSON messages go to standard output, so nothing else may be printed
+    // there.
+    if args.message_format == "json" {
+        for (flag, given) in &[
+            ("--print", args.print_stdout),
+            ("--print-config", args.print_config),
+        ] {
+            if *given {
+                return Err(errmsg!(
+                    "{} cannot be used with --message-format=json",
+                    flag
+                ));
+            }
+        }
+    }
+
+    if let Some(ref c) = args.command {
+        note_shadowed_input(c.name(), status);
+    }
+
+    let fetch = match args.command.take() {
+        None => None,
+        Some(Command::Fetch { export_zip, input }) => {
+            args.input = Some(input);
+            Some(export_zip)
+        }
+        Some(Command::Build { targets }) => {
+            return run_build(&args, &targets, &unstable, &config, status)
+        }
+        Some(Command::Synctex(c)) => return run_synctex(c),
+        Some(Command::Bundle(c)) => return run_bundle(c, status),
+        Some(Command::Cache(c)) => return run_cache(c, &args, &config, status),
+    };
+
+    if fetch.is_some() {
+        if args.watch {
+            return Err(errmsg!("--watch cannot be used with `tectonic fetch`"));
+        }
+
+        if args.only_cached {
+            return Err(errmsg!(
+                "--only-cached cannot be used with `tectonic fetch`"
+            ));
+        }
+    }
+
+    // An untrusted document could use its project settings to send output
+    // anywhere, so they're ignored.
+    let project = if args.untrusted {
+        None
+    } else {
+        ProjectConfig::open_in(input_project_dir(&args))?
+    };
+
+    if let Some((ref path, _)) = project {
+        tt_note!(status, "using project settings from \"{}\"", path.display());
+    }
+
+    let defaults = effective_defaults(&args, &unstable, &config, project.map(|p| p.1.defaults));
+
+    if args.print_config {
+        print_config(&defaults);
+        return Ok(());
+    }
+
+    unstable.paper_size = defaults.paper_size.clone();
+
+    let pdfa = pdfa_conformance(&args)?;
 
     let mut sess_builder = ProcessingSessionBuilder::default();
-    let format_path = args.format;
+    let format_path = defaults.format.unwrap_or_else(|| "latex".to_owned());
     sess_builder
         .unstables(unstable)
         .format_name(&format_path)
-        .keep_logs(args.keep_logs)
+        .keep_logs(defaults.keep_logs.unwrap_or(false))
         .keep_intermediates(args.keep_intermediates)
         .format_cache_path(config.format_cache_path()?)
-        .synctex(args.synctex);
+        .synctex(defaults.synctex.unwrap_or(false))
+        .file_line_errors(args.message_format == "json")
+        .precompile_preamble(args.precompile_preamble)
+        .reproducible(args.reproducible)
+        .pdfa(pdfa)
+        .sandboxed(args.untrusted);
 
     sess_builder.output_format(OutputFormat::from_str(&args.outfmt).unwrap());
 
     let pass = PassSetting::from_str(&args.pass).unwrap();
     sess_builder.pass(pass);
 
-    if let Some(s) = args.reruns {
+    if let Some(s) = defaults.reruns {
         sess_builder.reruns(s);
     }
 
-    if let Some(p) = args.makefile_rules {
+    if let Some(p) = args.makefile_rules.take() {
         sess_builder.makefile_output_path(p);
     }
 
+    sess_builder.depfile_format(DepfileFormat::from_str(&args.depfile_format).unwrap());
+
+    if let Some(p) = args.deps_json.take() {
+        sess_builder.deps_json_path(p);
+    }
+
+    if let Some(spec) = args.pages.take() {
+        let ranges =
+            ctry!(PageRange::parse_list(&spec); "invalid page range specification \"{}\"", spec);
+        sess_builder.page_ranges(&ranges);
+    }
+
+    if let Some(alg) = args.encrypt.take() {
+        let mut encryption = PdfEncryption::new(EncryptionAlgorithm::from_str(&alg).unwrap());
+
+        if let Some(pw) = args.user_password.take() {
+            encryption.user_password = pw;
+        }
+
+        if let Some(pw) = args.owner_password.take() {
+            encryption.owner_password = pw;
+        }
+
+        if let Some(spec) = args.permissions.take() {
+            encryption.permissions = spec.parse()?;
+        }
+
+        sess_builder.encryption(encryption);
+    }
+
     // Input and path setup
 
-    let input_path = args.input;
+    let input_path = args.input.take().unwrap(); // guaranteed by the argument parser
     if input_path == "-" {
+        if args.watch {
+            return Err(errmsg!(
+                "--watch cannot be used when reading from standard input"
+            ));
+        }
+
         // Don't provide an input path to the ProcessingSession, so it will default to stdin.
         sess_builder.tex_input_name("texput.tex");
         sess_builder.output_dir(Path::new(""));
@@ -118,6 +1036,10 @@ fn inner(args: CliOptions, config: PersistentConfig, status: &mut dyn StatusBack
         let input_path = Path::new(&input_path);
         sess_builder.primary_input_path(input_path);
 
+        if let Some(p) = config.prefetch_list_path(input_path)? {
+            sess_builder.prefetch_list_path(p);
+        }
+
         if let Some(fname) = input_path.file_name() {
             sess_builder.tex_input_name(&fname.to_string_lossy());
         } else {
@@ -137,7 +1059,7 @@ fn inner(args: CliOptions, config: PersistentConfig, status: &mut dyn StatusBack
         }
     }
 
-    if let Some(output_dir) = args.outdir {
+    if let Some(output_dir) = defaults.outdir {
         if !output_dir.is_dir() {
             return Err(errmsg!(
                 "output directory \"{}\" does not exist",
@@ -151,25 +1073,136 @@ fn inner(args: CliOptions, config: PersistentConfig, status: &mut dyn StatusBack
 
     sess_builder.print_stdout(args.print_stdout);
 
-    if let Some(items) = args.hide {
-        for v in items {
-            sess_builder.hide(v);
-        }
+    for v in defaults.hide {
+        sess_builder.hide(v);
     }
 
-    let only_cached = args.only_cached;
-    if only_cached {
+    for d in &args.include_dirs {
+        sess_builder.search_dir(d);
+    }
+
+    if args.only_cached {
         tt_note!(status, "using only cached resource files");
     }
-    if let Some(path) = args.bundle {
-        sess_builder.bundle(config.make_local_file_provider(path, status)?);
-    } else if let Some(u) = args.web_bundle {
-        sess_builder.bundle(config.make_cached_url_provider(&u, only_cached, None, status)?);
-    } else {
-        sess_builder.bundle(config.default_bundle(only_cached, status)?);
+
+    let bundle = open_bundle(&args, &config, status)?;
+
+    if let Some(export_zip) = fetch {
+        // Nothing gets written, and the format is built from scratch in a
+        // throwaway cache so that the files needed to build it are fetched
+        // too.
+        let format_dir = ctry!(tempfile::Builder::new().prefix("tectonic_fetch").tempdir();
+                               "can't create a temporary directory for the format file");
+        let bundle = RecordingBundle::new(bundle);
+        let recorder = bundle.recorder();
+        sess_builder
+            .bundle(Box::new(bundle))
+            .format_cache_path(format_dir.path())
+            .do_not_write_output_files();
+        let sess = sess_builder.create(status)?;
+        let is_cached = args.bundle.is_none()
+            && (args.web_bundle.is_some() || config.default_bundle_url().is_some());
+        return run_fetch(sess, &recorder, export_zip, is_cached, status);
     }
 
+    sess_builder.bundle(bundle);
+
     let mut sess = sess_builder.create(status)?;
+
+    if !args.watch {
+        return run_session(&mut sess, status).map(|_diagnostics| ());
+    }
+
+    // The watcher set up after each run is already watching while the next
+    // one runs, so that edits made in the middle of a run aren't missed.
+    let mut prev_watcher: Option<FileWatcher> = None;
+
+    loop {
+        if let Err(ref e) = run_session(&mut sess, status) {
+            tt_error!(status, "processing failed"; e);
+        }
+
+        let inputs = sess.filesystem_inputs();
+        let mut watcher = FileWatcher::new(&inputs)?;
+
+        let mut changed = match prev_watcher.take() {
+            Some(mut w) => w.take_changes()?,
+            None => Vec::new(),
+        };
+
+        if changed.is_empty() {
+            tt_note!(
+                status,
+                "watching {} input files for changes (press Ctrl-C to stop)",
+                inputs.len()
+            );
+            changed = watcher.wait(WATCH_DEBOUNCE)?;
+        } else {
+            // We're about to pick these up anyway.
+            watcher.take_changes()?;
+        }
+
+        let names: Vec<_> = changed.iter().map(|p| p.display().to_string()).collect();
+        tt_note!(status, "changed: {}; reprocessing", names.join(", "));
+        prev_watcher = Some(watcher);
+        sess.reset();
+    }
+}
+
+/// Implement `tectonic fetch`: process the document, then report (and
+/// optionally export) the bundle files that it used. Opening a file from a
+/// network bundle puts it in the local cache, so once processing has
+/// succeeded, all of the files are available offline.
+fn run_fetch(
+    mut sess: ProcessingSession,
+    recorder: &BundleRecorder,
+    export_zip: Option<PathBuf>,
+    is_cached: bool,
+    status: &mut dyn StatusBackend,
+) -> Result<()> {
+    if let Err(e) = run_session(&mut sess, status) {
+        return Err(e.chain_err(|| {
+            "the document could not be processed, so its list of files may be incomplete"
+        }));
+    }
+
+    let names = recorder.names();
+
+    if is_cached {
+        tt_note!(
+            status,
+            "the document needs {} files from the bundle; all of them are now in the local cache",
+            names.len()
+        );
+    } else {
+        tt_note!(
+            status,
+            "the document needs {} files from the bundle",
+            names.len()
+        );
+    }
+
+    if let Some(path) = export_zip {
+        let mut bundle = sess.io.bundle.take().expect("the session keeps its bundle");
+        ZipBundle::create_from(&path, &mut bundle, &names, status)?;
+        tt_note!(
+            status,
+            "wrote the files to the bundle \"{}\"",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// How long to wait for a burst of changes to the input files to finish
+/// before reprocessing the document in `--watch` mode.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(200);
+
+fn run_session(
+    sess: &mut ProcessingSession,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<Diagnostic>> {
     let result = sess.run(status);
 
     if let Err(e) = &result {
@@ -185,6 +1218,8 @@ fn inner(args: CliOptions, config: PersistentConfig, status: &mut dyn StatusBack
             }
         }
     }
+
+    // Any problems in the TeX log have already been reported to `status`.
     result
 }
 
@@ -234,7 +1269,9 @@ fn main() {
         _ => unreachable!(),
     };
 
-    let mut status = if use_cli_color {
+    let mut status = if args.message_format == "json" {
+        Box::new(JsonStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
+    } else if use_cli_color {
         Box::new(TermcolorStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
     } else {
         Box::new(PlainStatusBackend::new(chatter_level)) as Box<dyn StatusBackend>
@@ -16,13 +16,19 @@ use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use std::str::FromStr;
+
 use crate::app_dirs;
+use crate::digest::{self, Digest, DigestData};
+use crate::driver::{OutputFormat, PassSetting};
 use crate::errors::{ErrorKind, Result};
 use crate::io::cached_itarbundle::CachedITarBundle;
 use crate::io::dirbundle::DirBundle;
 use crate::io::zipbundle::ZipBundle;
 use crate::io::Bundle;
 use crate::status::StatusBackend;
+#[cfg(feature = "serialization")]
+use crate::{ctry, errmsg};
 
 /// Awesome hack time!!!
 ///
@@ -40,6 +46,8 @@ pub fn activate_config_test_mode(forced: bool) {
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PersistentConfig {
     default_bundles: Vec<BundleInfo>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    defaults: EngineDefaults,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
@@ -72,7 +80,15 @@ impl PersistentConfig {
             Ok(mut f) => {
                 let mut buf = Vec::<u8>::new();
                 f.read_to_end(&mut buf)?;
-                toml::from_slice(&buf)?
+                let mut config: PersistentConfig = toml::from_slice(&buf)?;
+
+                // Relative paths are relative to the configuration file, not
+                // to wherever Tectonic happens to be run from.
+                if let Some(dir) = cfg_path.parent() {
+                    config.defaults.resolve_paths(dir);
+                }
+
+                config
             }
             Err(e) => {
                 if e.kind() == IoErrorKind::NotFound {
@@ -117,6 +133,21 @@ impl PersistentConfig {
         Ok(Box::new(bundle) as _)
     }
 
+    /// Like [`Self::make_cached_url_provider`], but for a bundle that is
+    /// available from several mirrors; see
+    /// [`CachedITarBundle::with_mirrors`].
+    pub fn make_cached_mirrors_provider<S: AsRef<str>>(
+        &self,
+        urls: &[S],
+        only_cached: bool,
+        custom_cache_root: Option<&Path>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Box<dyn Bundle>> {
+        let bundle = CachedITarBundle::with_mirrors(urls, only_cached, custom_cache_root, status)?;
+
+        Ok(Box::new(bundle) as _)
+    }
+
     pub fn make_local_file_provider(
         &self,
         file_path: PathBuf,
@@ -142,25 +173,45 @@ impl PersistentConfig {
             return Ok(Box::new(crate::test_util::TestBundle::default()));
         }
 
-        if self.default_bundles.len() != 1 {
-            return Err(ErrorKind::Msg(
-                "exactly one default_bundle item must be specified (for now)".to_owned(),
-            )
-            .into());
+        if self.default_bundles.is_empty() {
+            return Err(
+                ErrorKind::Msg("a default_bundle item must be specified".to_owned()).into(),
+            );
         }
 
         let url = Url::parse(&self.default_bundles[0].url)
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "failed to parse url"))?;
         if url.scheme() == "file" {
+            if self.default_bundles.len() != 1 {
+                return Err(ErrorKind::Msg(
+                    "a local default_bundle item can't have mirrors".to_owned(),
+                )
+                .into());
+            }
+
             // load the local zip file.
             let file_path = url.to_file_path().map_err(|_| {
                 io::Error::new(io::ErrorKind::InvalidInput, "failed to parse local path")
             })?;
             return self.make_local_file_provider(file_path, status);
         }
-        let bundle =
-            self.make_cached_url_provider(&self.default_bundles[0].url, only_cached, None, status)?;
-        Ok(Box::new(bundle) as _)
+
+        // Any further items are mirrors of the first.
+        let urls: Vec<&str> = self.default_bundles.iter().map(|b| &b.url[..]).collect();
+        self.make_cached_mirrors_provider(&urls, only_cached, None, status)
+    }
+
+    /// Get the URL of the default bundle, if it is a network bundle.
+    pub fn default_bundle_url(&self) -> Option<&str> {
+        self.default_bundles
+            .first()
+            .map(|b| &b.url[..])
+            .filter(|u| !u.starts_with("file:"))
+    }
+
+    /// Get the per-user default settings for processing documents.
+    pub fn defaults(&self) -> &EngineDefaults {
+        &self.defaults
     }
 
     pub fn format_cache_path(&self) -> Result<PathBuf> {
@@ -170,6 +221,30 @@ impl PersistentConfig {
             Ok(app_dirs::user_cache_dir("formats")?)
         }
     }
+
+    /// Where to keep the list of the bundle files used by the document whose
+    /// primary input is `input`; see
+    /// [`crate::driver::ProcessingSessionBuilder::prefetch_list_path`]. The
+    /// lists are kept in the cache directory, named after a digest of the
+    /// input's absolute path. In test mode there's no such list.
+    pub fn prefetch_list_path<P: AsRef<Path>>(&self, input: P) -> Result<Option<PathBuf>> {
+        if CONFIG_TEST_MODE_ACTIVATED.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+
+        let input = input.as_ref();
+        let input = input.canonicalize().unwrap_or_else(|_| input.to_owned());
+
+        let mut dc = digest::create();
+        dc.input(input.to_string_lossy().as_bytes());
+        let key = DigestData::from(dc).to_string();
+
+        Ok(Some(
+            app_dirs::user_cache_dir("documents")?
+                .join(key)
+                .with_extension("txt"),
+        ))
+    }
 }
 
 impl Default for PersistentConfig {
@@ -178,6 +253,259 @@ impl Default for PersistentConfig {
             default_bundles: vec![BundleInfo {
                 url: String::from("https://archive.org/services/purl/net/pkgwpub/tectonic-default"),
             }],
+            defaults: EngineDefaults::default(),
         }
     }
 }
+
+/// Default settings for processing documents.
+///
+/// These can be set in the `[defaults]` section of the per-user
+/// configuration file and of a project’s `Tectonic.toml` file. Each field is
+/// optional; unset fields fall through to the next layer down. The layers
+/// are, from lowest to highest priority: the per-user configuration, the
+/// project configuration, and the command-line arguments.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct EngineDefaults {
+    /// The name of the format file used to initialize the TeX engine.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub format: Option<String>,
+
+    /// The default paper size, as understood by xdvipdfmx.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub paper_size: Option<String>,
+
+    /// Whether to keep the log files generated during processing.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub keep_logs: Option<bool>,
+
+    /// Whether to generate SyncTeX data.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub synctex: Option<bool>,
+
+    /// How many times to rerun the TeX engine after the first pass.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub reruns: Option<usize>,
+
+    /// The directory in which to place output files.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub outdir: Option<PathBuf>,
+
+    /// Paths that the engine should pretend do not exist. Unlike the other
+    /// settings, these accumulate across layers.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub hide: Vec<PathBuf>,
+}
+
+impl EngineDefaults {
+    /// Apply a higher-priority layer of settings on top of this one.
+    pub fn merge(&mut self, over: EngineDefaults) {
+        if over.format.is_some() {
+            self.format = over.format;
+        }
+
+        if over.paper_size.is_some() {
+            self.paper_size = over.paper_size;
+        }
+
+        if over.keep_logs.is_some() {
+            self.keep_logs = over.keep_logs;
+        }
+
+        if over.synctex.is_some() {
+            self.synctex = over.synctex;
+        }
+
+        if over.reruns.is_some() {
+            self.reruns = over.reruns;
+        }
+
+        if over.outdir.is_some() {
+            self.outdir = over.outdir;
+        }
+
+        self.hide.extend(over.hide);
+    }
+
+    /// Interpret relative paths as being relative to `base`.
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(ref mut p) = self.outdir {
+            if p.is_relative() {
+                *p = base.join(&p);
+            }
+        }
+
+        for p in &mut self.hide {
+            if p.is_relative() {
+                *p = base.join(&p);
+            }
+        }
+    }
+}
+
+/// The name of the per-project configuration file, which lives next to the
+/// main input file.
+pub const PROJECT_CONFIG_FILE_NAME: &str = "Tectonic.toml";
+
+/// Per-project configuration, read from a `Tectonic.toml` file.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ProjectConfig {
+    /// Default settings for processing documents in this project. Relative
+    /// paths are interpreted relative to the directory containing the
+    /// configuration file.
+    pub defaults: EngineDefaults,
+
+    /// The documents that make up this project, each given in a
+    /// `[[target]]` section. These are processed by `tectonic build`.
+    #[cfg_attr(feature = "serde", serde(rename = "target"))]
+    pub targets: Vec<TargetConfig>,
+}
+
+/// One document to be built as part of a project.
+///
+/// Settings that aren't given fall back to the project's `[defaults]`. For
+/// example:
+///
+/// ```toml
+/// [[target]]
+/// name = "slides"
+/// input = "slides/main.tex"
+/// output_format = "pdf"
+/// search_paths = ["shared"]
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct TargetConfig {
+    /// The name used to refer to the target on the command line.
+    pub name: String,
+
+    /// The main input file.
+    pub input: PathBuf,
+
+    /// The name of the format file used to initialize the TeX engine.
+    pub format: Option<String>,
+
+    /// The kind of output to generate, as understood by [`OutputFormat`]'s
+    /// `FromStr` implementation. Defaults to `"pdf"`.
+    pub output_format: Option<String>,
+
+    /// Which engines to run, as understood by [`PassSetting`]'s `FromStr`
+    /// implementation. Defaults to `"default"`.
+    pub pass: Option<String>,
+
+    /// Directories in which to look for input files that aren't found next
+    /// to the main input file, in order. A path ending in `//` means that
+    /// the directory's subdirectories are searched too.
+    pub search_paths: Vec<PathBuf>,
+
+    /// The directory in which to place output files. Defaults to the
+    /// project's `outdir` setting, or the directory containing the input.
+    pub outdir: Option<PathBuf>,
+}
+
+impl TargetConfig {
+    /// The kind of output to generate.
+    pub fn output_format(&self) -> Result<OutputFormat> {
+        match self.output_format {
+            None => Ok(OutputFormat::Pdf),
+            Some(ref s) => OutputFormat::from_str(s).map_err(|e| {
+                ErrorKind::Msg(format!("target \"{}\": {} \"{}\"", self.name, e, s)).into()
+            }),
+        }
+    }
+
+    /// Which engines to run.
+    pub fn pass(&self) -> Result<PassSetting> {
+        match self.pass {
+            None => Ok(PassSetting::Default),
+            Some(ref s) => PassSetting::from_str(s).map_err(|e| {
+                ErrorKind::Msg(format!("target \"{}\": {} \"{}\"", self.name, e, s)).into()
+            }),
+        }
+    }
+
+    /// Interpret relative paths as being relative to `base`.
+    fn resolve_paths(&mut self, base: &Path) {
+        if self.input.is_relative() {
+            self.input = base.join(&self.input);
+        }
+
+        for p in &mut self.search_paths {
+            if p.is_relative() {
+                *p = base.join(&p);
+            }
+        }
+
+        if let Some(ref mut p) = self.outdir {
+            if p.is_relative() {
+                *p = base.join(&p);
+            }
+        }
+    }
+}
+
+impl ProjectConfig {
+    #[cfg(feature = "serialization")]
+    /// Load the project configuration file in the directory `dir`, if there
+    /// is one.
+    ///
+    /// Returns the path of the file along with its contents, or None if the
+    /// file does not exist.
+    pub fn open_in<P: AsRef<Path>>(dir: P) -> Result<Option<(PathBuf, ProjectConfig)>> {
+        use std::io::ErrorKind as IoErrorKind;
+        use std::io::Read;
+
+        let dir = dir.as_ref();
+        let path = dir.join(PROJECT_CONFIG_FILE_NAME);
+
+        let mut f = match File::open(&path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut buf = Vec::<u8>::new();
+        f.read_to_end(&mut buf)?;
+        let mut config: ProjectConfig =
+            ctry!(toml::from_slice(&buf); "failed to parse \"{}\"", path.display());
+        config.defaults.resolve_paths(dir);
+
+        for (i, target) in config.targets.iter_mut().enumerate() {
+            if target.name.is_empty() {
+                return Err(errmsg!(
+                    "target #{} in \"{}\" has no name",
+                    i + 1,
+                    path.display()
+                ));
+            }
+
+            if target.input.as_os_str().is_empty() {
+                return Err(errmsg!(
+                    "target \"{}\" in \"{}\" has no input file",
+                    target.name,
+                    path.display()
+                ));
+            }
+
+            target.resolve_paths(dir);
+        }
+
+        Ok(Some((path, config)))
+    }
+
+    #[cfg(not(feature = "serialization"))]
+    /// Load the project configuration file in the directory `dir`, if there
+    /// is one.
+    ///
+    /// This version of Tectonic has been built without the `serde` feature,
+    /// so it cannot deserialize the file. Therefore, this function always
+    /// returns None.
+    pub fn open_in<P: AsRef<Path>>(_dir: P) -> Result<Option<(PathBuf, ProjectConfig)>> {
+        Ok(None)
+    }
+}
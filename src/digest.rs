@@ -99,3 +99,31 @@ impl From<DigestComputer> for DigestData {
         result
     }
 }
+
+/// Compute the digest that summarizes the contents of a bundle, given the
+/// names and digests of its files.
+///
+/// The files are visited in order of their names. For each one, the UTF8
+/// bytes of its name, a NUL byte, and the 32 raw bytes of its SHA256 digest
+/// are fed into the computation. The bundle's own `SHA256SUM` file, if it
+/// is listed, is left out.
+pub fn bundle_digest<'a, I>(files: I) -> DigestData
+where
+    I: IntoIterator<Item = (&'a str, &'a DigestData)>,
+{
+    let mut files: Vec<_> = files
+        .into_iter()
+        .filter(|(name, _)| *name != DIGEST_NAME)
+        .collect();
+    files.sort_by_key(|(name, _)| *name);
+
+    let mut dc = create();
+
+    for (name, digest) in files {
+        dc.input(name.as_bytes());
+        dc.input([0u8]);
+        dc.input(digest.0);
+    }
+
+    DigestData::from(dc)
+}
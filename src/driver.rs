@@ -13,17 +13,26 @@
 
 use byte_unit::Byte;
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::Write;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::str::FromStr;
+use tempfile::TempDir;
 
-use crate::digest::DigestData;
+use crate::digest::{self, Digest, DigestData};
+use crate::engines::shell_escape::ShellEscape;
+use crate::engines::xdvipdfmx::{PageRange, PdfAConformance, PdfEncryption};
 use crate::engines::IoEventBackend;
 use crate::errors::{ErrorKind, Result, ResultExt};
+use crate::io::filesystem::confined_path;
+use crate::io::format_cache::FormatCache;
+use crate::io::stdstreams::BufferedPrimaryIo;
 use crate::io::{Bundle, InputOrigin, IoProvider, IoSetup, IoSetupBuilder, OpenResult};
+use crate::status::json::push_json_str;
 use crate::status::StatusBackend;
 use crate::unstable_opts::UnstableOptions;
 use crate::{ctry, errmsg, tt_error, tt_note, tt_warning};
@@ -65,6 +74,11 @@ pub struct FileSummary {
     /// If this file was read, where did it come from?
     pub input_origin: InputOrigin,
 
+    /// If this file was read from the filesystem, this is where it was found.
+    /// That needn't be the directory of the primary input: it may have been
+    /// found in one of the search directories.
+    pub input_path: Option<PathBuf>,
+
     /// If this file was read, this is the digest of its contents at the time it was *first* read.
     /// The "first" is significant for files that were read and then written (for example, `.aux`
     /// files).
@@ -77,7 +91,9 @@ pub struct FileSummary {
     /// If this file was written, this is the digest of its contents at the time it was last
     /// written.
     pub write_digest: Option<DigestData>,
-    got_written_to_disk: bool,
+
+    /// If this file was written to disk, this is where.
+    pub disk_path: Option<PathBuf>,
 }
 
 impl FileSummary {
@@ -85,9 +101,10 @@ impl FileSummary {
         FileSummary {
             access_pattern,
             input_origin,
+            input_path: None,
             read_digest: None,
             write_digest: None,
-            got_written_to_disk: false,
+            disk_path: None,
         }
     }
 }
@@ -167,7 +184,7 @@ impl IoEventBackend for IoEvents {
         self.0.insert(name.to_os_string(), fs);
     }
 
-    fn input_opened(&mut self, name: &OsStr, origin: InputOrigin) {
+    fn input_opened(&mut self, name: &OsStr, origin: InputOrigin, path: Option<&Path>) {
         if let Some(summ) = self.0.get_mut(name) {
             summ.access_pattern = match summ.access_pattern {
                 AccessPattern::Written => AccessPattern::WrittenThenRead,
@@ -176,10 +193,9 @@ impl IoEventBackend for IoEvents {
             return;
         }
 
-        self.0.insert(
-            name.to_os_string(),
-            FileSummary::new(AccessPattern::Read, origin),
-        );
+        let mut summ = FileSummary::new(AccessPattern::Read, origin);
+        summ.input_path = path.map(|p| p.to_owned());
+        self.0.insert(name.to_os_string(), summ);
     }
 
     //fn primary_input_opened(&mut self, _origin: InputOrigin) {}
@@ -199,6 +215,299 @@ impl IoEventBackend for IoEvents {
     }
 }
 
+/// The kind of problem described by a [`Diagnostic`], with the details
+/// particular to each kind.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DiagnosticKind {
+    /// A reference to a label that is not defined. Contains the label.
+    UndefinedReference(String),
+
+    /// A citation of a bibliography key that is not defined. Contains the
+    /// key.
+    UndefinedCitation(String),
+
+    /// A line that sticks out into the margin. Contains the excess width,
+    /// in points.
+    OverfullHbox(f64),
+
+    /// A line whose spaces had to be stretched too far. Contains the
+    /// badness.
+    UnderfullHbox(u32),
+
+    /// A character that the font does not contain.
+    MissingCharacter {
+        /// The character, as TeX printed it.
+        character: String,
+        /// The name of the font.
+        font: String,
+    },
+
+    /// A font shape was not available, so another one was used instead.
+    FontSubstitution {
+        /// The font shape that was asked for.
+        requested: String,
+        /// The font shape that was used.
+        used: String,
+    },
+}
+
+/// A problem noticed in the log file of a TeX pass.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// What sort of problem this is.
+    pub kind: DiagnosticKind,
+
+    /// The input file that was being processed when the problem occurred, if
+    /// it could be determined. This is the name as TeX printed it in the log.
+    pub file: Option<String>,
+
+    /// The range of input lines that the problem concerns, if known. Both
+    /// ends are inclusive.
+    pub lines: Option<(u32, u32)>,
+
+    /// The message as it appeared in the log, with wrapped lines rejoined.
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.lines) {
+            (Some(file), Some((first, _))) => write!(f, "{}:{}: {}", file, first, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// The width at which TeX wraps the lines that it writes to the log.
+const LOG_LINE_WIDTH: usize = 79;
+
+/// Scan the contents of a TeX log file for common problems.
+///
+/// This recognizes undefined references and citations, overfull and
+/// underfull `\hbox`es, missing characters, and font substitutions. The
+/// input file associated with each problem is inferred by tracking the
+/// parentheses that TeX prints when it opens and closes files, which is a
+/// heuristic, but it is the same one used by other tools that parse TeX
+/// logs.
+pub fn analyze_log(log: &str) -> Vec<Diagnostic> {
+    // TeX hard-wraps its log lines, so put them back together first.
+    let mut lines: Vec<String> = Vec::new();
+    let mut continues = false;
+
+    for l in log.lines() {
+        if continues {
+            lines.last_mut().unwrap().push_str(l);
+        } else {
+            lines.push(l.to_owned());
+        }
+
+        continues = l.chars().count() == LOG_LINE_WIDTH;
+    }
+
+    let mut diags = Vec::new();
+    let mut files: Vec<Option<String>> = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = &lines[i];
+        i += 1;
+
+        let cur_file = || files.iter().rev().filter_map(|f| f.clone()).next();
+
+        if let Some((kind, lines_range)) = parse_box_warning(line) {
+            diags.push(Diagnostic {
+                kind,
+                file: cur_file(),
+                lines: lines_range,
+                message: line.clone(),
+            });
+
+            // TeX follows up with a dump of the box contents, which ends
+            // with a blank line. It may contain unbalanced parentheses, so
+            // don't let it confuse our file tracking.
+            while i < lines.len() && !lines[i].trim().is_empty() {
+                i += 1;
+            }
+
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("Missing character: There is no ") {
+            if let Some(idx) = rest.rfind(" in font ") {
+                let font = rest[idx + " in font ".len()..].trim_end_matches('!');
+
+                diags.push(Diagnostic {
+                    kind: DiagnosticKind::MissingCharacter {
+                        character: rest[..idx].to_owned(),
+                        font: font.to_owned(),
+                    },
+                    file: cur_file(),
+                    lines: None,
+                    message: line.clone(),
+                });
+            }
+
+            continue;
+        }
+
+        if line.contains("Warning: ") {
+            // Package warnings are continued on lines starting with the
+            // package name in parentheses, e.g. "(Font)" or "(natbib)".
+            let mut message = line.clone();
+
+            while i < lines.len() && is_warning_continuation(&lines[i]) {
+                let cont = &lines[i];
+                let body = cont[cont.find(')').unwrap() + 1..].trim();
+                message.push(' ');
+                message.push_str(body);
+                i += 1;
+            }
+
+            if let Some(kind) = parse_latex_warning(&message) {
+                diags.push(Diagnostic {
+                    kind,
+                    file: cur_file(),
+                    lines: input_line(&message).map(|n| (n, n)),
+                    message,
+                });
+            }
+
+            continue;
+        }
+
+        track_files(line, &mut files);
+    }
+
+    diags
+}
+
+/// Update the stack of open files given a line of TeX log output.
+///
+/// TeX prints `(name` when it opens a file and `)` when it closes it. We
+/// push `None` for parentheses that don't seem to introduce a file name so
+/// that the closing parentheses stay balanced.
+fn track_files(line: &str, files: &mut Vec<Option<String>>) {
+    let mut chars = line.char_indices();
+
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '(' => {
+                let rest = &line[idx + 1..];
+                let end = rest
+                    .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+                    .unwrap_or(rest.len());
+                let name = &rest[..end];
+
+                if name.contains('.') || name.contains('/') {
+                    files.push(Some(name.to_owned()));
+
+                    for _ in 0..name.chars().count() {
+                        chars.next();
+                    }
+                } else {
+                    files.push(None);
+                }
+            }
+
+            ')' => {
+                files.pop();
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Is this a continuation line of a package warning, like
+/// `(hyperref)                Token not allowed`?
+fn is_warning_continuation(line: &str) -> bool {
+    if !line.starts_with('(') {
+        return false;
+    }
+
+    match line.find(')') {
+        Some(idx) => {
+            let name = &line[1..idx];
+            !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-')
+        }
+        None => false,
+    }
+}
+
+/// Parse the header of an overfull or underfull `\hbox` message.
+fn parse_box_warning(line: &str) -> Option<(DiagnosticKind, Option<(u32, u32)>)> {
+    let kind = if let Some(rest) = line.strip_prefix("Overfull \\hbox (") {
+        let amount = rest[..rest.find("pt too wide")?].parse().ok()?;
+        DiagnosticKind::OverfullHbox(amount)
+    } else if let Some(rest) = line.strip_prefix("Underfull \\hbox (badness ") {
+        let badness = rest[..rest.find(')')?].parse().ok()?;
+        DiagnosticKind::UnderfullHbox(badness)
+    } else {
+        return None;
+    };
+
+    let lines = if let Some(idx) = line.find(" at lines ") {
+        let mut range = line[idx + " at lines ".len()..].splitn(2, "--");
+        let first = leading_number(range.next()?);
+        let last = range.next().and_then(leading_number);
+
+        match (first, last) {
+            (Some(a), Some(b)) => Some((a, b)),
+            (Some(a), None) => Some((a, a)),
+            _ => None,
+        }
+    } else if let Some(idx) = line.find(" detected at line ") {
+        leading_number(&line[idx + " detected at line ".len()..]).map(|n| (n, n))
+    } else {
+        None
+    };
+
+    Some((kind, lines))
+}
+
+/// Parse a LaTeX or package warning that we know about.
+fn parse_latex_warning(message: &str) -> Option<DiagnosticKind> {
+    if message.contains("Warning: Reference `") {
+        let label = quoted_after(message, "Reference `")?;
+        Some(DiagnosticKind::UndefinedReference(label.to_owned()))
+    } else if message.contains("Warning: Citation `") {
+        let key = quoted_after(message, "Citation `")?;
+        Some(DiagnosticKind::UndefinedCitation(key.to_owned()))
+    } else if message.starts_with("LaTeX Font Warning: Font shape `")
+        && message.contains(" using `")
+    {
+        let requested = quoted_after(message, "Font shape `")?;
+        let used = quoted_after(message, " using `")?;
+        Some(DiagnosticKind::FontSubstitution {
+            requested: requested.to_owned(),
+            used: used.to_owned(),
+        })
+    } else {
+        None
+    }
+}
+
+/// Find the text between `prefix` (which should end with a backtick) and
+/// the next apostrophe.
+fn quoted_after<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let start = text.find(prefix)? + prefix.len();
+    let len = text[start..].find('\'')?;
+    Some(&text[start..start + len])
+}
+
+/// Find the line number in a LaTeX warning ending "on input line N."
+fn input_line(message: &str) -> Option<u32> {
+    let idx = message.rfind("on input line ")?;
+    leading_number(&message[idx + "on input line ".len()..])
+}
+
+fn leading_number(text: &str) -> Option<u32> {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    text[..end].parse().ok()
+}
+
 /// The different types of output files that tectonic knows how to produce.
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum OutputFormat {
@@ -266,6 +575,30 @@ impl FromStr for PassSetting {
     }
 }
 
+/// The syntaxes in which [`ProcessingSession`] can write out the dependencies
+/// of its outputs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DepfileFormat {
+    /// A Makefile rule, with all of the files written to disk as its targets.
+    #[default]
+    Make,
+    /// A depfile for Ninja, whose only target is the main output file, since
+    /// Ninja rejects targets that its build rule doesn't declare.
+    Ninja,
+}
+
+impl FromStr for DepfileFormat {
+    type Err = &'static str;
+
+    fn from_str(a_str: &str) -> StdResult<Self, Self::Err> {
+        match a_str {
+            "make" => Ok(DepfileFormat::Make),
+            "ninja" => Ok(DepfileFormat::Ninja),
+            _ => Err("unsupported or unknown depfile format"),
+        }
+    }
+}
+
 /// Different places from which the "primary input" might originate.
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum PrimaryInputMode {
@@ -315,9 +648,15 @@ pub struct ProcessingSessionBuilder {
     output_dest: OutputDestination,
     format_name: Option<String>,
     format_cache_path: Option<PathBuf>,
+    format_cache: Option<FormatCache>,
     output_format: OutputFormat,
     makefile_output_path: Option<PathBuf>,
+    depfile_format: DepfileFormat,
+    deps_json_path: Option<PathBuf>,
+    prefetch_list_path: Option<PathBuf>,
+    precompile_preamble: bool,
     hidden_input_paths: HashSet<PathBuf>,
+    search_dirs: Vec<PathBuf>,
     pass: PassSetting,
     reruns: Option<usize>,
     print_stdout: bool,
@@ -325,6 +664,12 @@ pub struct ProcessingSessionBuilder {
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
+    file_line_errors: bool,
+    page_ranges: Vec<PageRange>,
+    encryption: Option<PdfEncryption>,
+    pdfa: Option<PdfAConformance>,
+    sandboxed: bool,
+    reproducible: bool,
     unstables: UnstableOptions,
 }
 
@@ -397,6 +742,18 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Uses an existing format file cache.
+    ///
+    /// The cache must belong to the bundle given to [`Self::bundle`]. This is
+    /// useful when running several sessions that share a bundle: each
+    /// session's cache can be taken from its `io.format_cache` field when it
+    /// is done and handed to the next one, saving the work of identifying
+    /// the bundle again. If set, the `format_cache_path` is ignored.
+    pub fn format_cache(&mut self, c: FormatCache) -> &mut Self {
+        self.format_cache = Some(c);
+        self
+    }
+
     /// The type of output to create.
     pub fn output_format(&mut self, f: OutputFormat) -> &mut Self {
         self.output_format = f;
@@ -409,6 +766,57 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// The syntax of the file written to the path given to
+    /// [`Self::makefile_output_path`]. Defaults to [`DepfileFormat::Make`].
+    pub fn depfile_format(&mut self, f: DepfileFormat) -> &mut Self {
+        self.depfile_format = f;
+        self
+    }
+
+    /// If set, a JSON report of the session's dependencies will be written
+    /// out at the given path. It lists the primary input; the files read from
+    /// the filesystem and from the bundle, with the digests of their
+    /// contents; and the files written to disk, with the digests of what was
+    /// written.
+    ///
+    /// Each bundle file carries the digest of the bundle that it came from,
+    /// which is also given at the top level as `bundle_digest`. That field is
+    /// `null` if the session has no bundle or its digest can't be
+    /// determined, which is only allowed if no bundle files were used:
+    /// otherwise writing the report fails.
+    pub fn deps_json_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.deps_json_path = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// If set, the names of the bundle files that the document uses are kept
+    /// in a file at the given path. When the session is run, the files
+    /// listed there by an earlier run are handed to [`Bundle::prefetch`]
+    /// before any processing starts, so that a bundle that has to download
+    /// them can do so in bulk. The list is updated after each successful
+    /// run.
+    pub fn prefetch_list_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.prefetch_list_path = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// If set, the preamble of the primary input — everything before
+    /// `\begin{document}`, or before a line starting with `%%endpreamble` —
+    /// is compiled into a custom format, which is kept in the format cache
+    /// and used to process the rest of the document. The format is reused by
+    /// later sessions as long as the preamble, the files that it loads, the
+    /// base format, the job name and the engine options don't change.
+    ///
+    /// Only what the preamble leaves in TeX's memory is kept: files that it
+    /// writes are discarded, and XeTeX can't save fonts loaded through
+    /// `fontspec` in a format, so preambles that do so won't precompile. If
+    /// the preamble can't be precompiled, the document is processed as
+    /// usual, with a warning.
+    pub fn precompile_preamble(&mut self, precompile: bool) -> &mut Self {
+        self.precompile_preamble = precompile;
+        self
+    }
+
     /// Which kind of pass should the `ProcessingSession` run? Defaults to `PassSetting::Default`
     /// (duh).
     pub fn pass(&mut self, p: PassSetting) -> &mut Self {
@@ -439,6 +847,15 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// Adds a directory in which to look for input files that aren't found
+    /// next to the primary input file. Directories are searched in the order
+    /// in which they were added, before the bundle. A path ending in two
+    /// slashes, like `shared//`, means that subdirectories are searched too.
+    pub fn search_dir<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.search_dirs.push(p.as_ref().to_owned());
+        self
+    }
+
     /// Sets the bundle, which the various engines will use for finding style files, font files,
     /// etc.
     pub fn bundle(&mut self, b: Box<dyn Bundle>) -> &mut Self {
@@ -464,6 +881,63 @@ impl ProcessingSessionBuilder {
         self
     }
 
+    /// If set to `true`, the TeX engine will report errors in the style
+    /// `file:line: message`, which is easier for other programs to parse.
+    pub fn file_line_errors(&mut self, f: bool) -> &mut Self {
+        self.file_line_errors = f;
+        self
+    }
+
+    /// Restricts the PDF output to the given page ranges. By default, all
+    /// pages are output. Page ranges can only be used with PDF output, and
+    /// processing fails if a range lies outside the document.
+    pub fn page_ranges(&mut self, ranges: &[PageRange]) -> &mut Self {
+        self.page_ranges = ranges.to_owned();
+        self
+    }
+
+    /// Encrypts the PDF output with the given settings. By default, the
+    /// output is not encrypted.
+    pub fn encryption(&mut self, encryption: PdfEncryption) -> &mut Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    /// Makes the PDF output conform to the given level of PDF/A, the
+    /// archival subset of PDF. Anything in the document that would break
+    /// conformance, such as a font that can't be embedded, is then an error.
+    /// PDF/A output can't be encrypted.
+    pub fn pdfa(&mut self, level: Option<PdfAConformance>) -> &mut Self {
+        self.pdfa = level;
+        self
+    }
+
+    /// Confines the session's filesystem access to the directory of the
+    /// primary input file, the search directories, and the output directory,
+    /// for processing documents that can't be trusted.
+    ///
+    /// Absolute paths, and paths that lead out of those directories through
+    /// `..` or symbolic links, are then refused with
+    /// [`ErrorKind::PathForbidden`], whether the engines try to read them or
+    /// to write them. Shell escape can't be used in a sandboxed session.
+    pub fn sandboxed(&mut self, s: bool) -> &mut Self {
+        self.sandboxed = s;
+        self
+    }
+
+    /// Makes the outputs depend only on the inputs, so that processing the
+    /// same document twice gives byte-identical files.
+    ///
+    /// The date and time that TeX reports, and the creation date and ID of
+    /// the PDF, are taken from the `SOURCE_DATE_EPOCH` environment variable,
+    /// or are the start of the epoch if it isn't set; and the tags of font
+    /// subsets are numbered rather than random. Shell escape, whose commands
+    /// could do anything, can't be used in a reproducible session.
+    pub fn reproducible(&mut self, r: bool) -> &mut Self {
+        self.reproducible = r;
+        self
+    }
+
     /// Loads unstable options into the processing session
     pub fn unstables(&mut self, opts: UnstableOptions) -> &mut Self {
         self.unstables = opts;
@@ -472,13 +946,45 @@ impl ProcessingSessionBuilder {
 
     /// Creates a `ProcessingSession`.
     pub fn create(self, status: &mut dyn StatusBackend) -> Result<ProcessingSession> {
+        if self.sandboxed && self.unstables.shell_escape {
+            return Err(errmsg!(
+                "shell escape cannot be used in a sandboxed session"
+            ));
+        }
+
+        if !self.page_ranges.is_empty() && self.output_format != OutputFormat::Pdf {
+            return Err(errmsg!("page ranges can only be used with PDF output"));
+        }
+
+        if self.pdfa.is_some() && self.encryption.is_some() {
+            return Err(errmsg!("encryption cannot be used with PDF/A output"));
+        }
+
+        let source_date_epoch = if !self.reproducible {
+            None
+        } else if self.unstables.shell_escape {
+            return Err(errmsg!(
+                "shell escape cannot be used in a reproducible session"
+            ));
+        } else {
+            match env::var("SOURCE_DATE_EPOCH") {
+                Ok(s) => Some(ctry!(s.trim().parse(); "invalid SOURCE_DATE_EPOCH \"{}\"", s)),
+                Err(_) => Some(0),
+            }
+        };
+
         let mut io = IoSetupBuilder::default();
         io.bundle(self.bundle.expect("a bundle must be specified"))
-            .use_genuine_stdout(self.print_stdout);
+            .use_genuine_stdout(self.print_stdout)
+            .sandboxed(self.sandboxed);
         for p in &self.hidden_input_paths {
             io.hide_path(p);
         }
 
+        for d in &self.search_dirs {
+            io.search_dir(d);
+        }
+
         let (primary_input_path, default_output_path) = match self.primary_input {
             PrimaryInputMode::Path(p) => {
                 io.primary_input_path(&p);
@@ -521,7 +1027,9 @@ impl ProcessingSessionBuilder {
             OutputDestination::Nowhere => None,
         };
 
-        if let Some(ref p) = self.format_cache_path {
+        if let Some(c) = self.format_cache {
+            io.format_cache(c);
+        } else if let Some(ref p) = self.format_cache_path {
             io.format_cache_path(p);
         }
 
@@ -538,6 +1046,17 @@ impl ProcessingSessionBuilder {
         });
         let mut pdf_path = aux_path.clone();
         pdf_path.set_extension("pdf");
+        let mut log_path = aux_path.clone();
+        log_path.set_extension("log");
+
+        let shell_escape_work = if self.unstables.shell_escape {
+            Some(
+                ctry!(tempfile::Builder::new().prefix("tectonic_shell_escape").tempdir();
+                       "can't create a working directory for shell escape"),
+            )
+        } else {
+            None
+        };
 
         Ok(ProcessingSession {
             io: io.create(status)?,
@@ -549,22 +1068,54 @@ impl ProcessingSessionBuilder {
             tex_aux_path: aux_path.into_os_string(),
             tex_xdv_path: xdv_path.into_os_string(),
             tex_pdf_path: pdf_path.into_os_string(),
+            tex_log_path: log_path.into_os_string(),
             output_format: self.output_format,
             makefile_output_path: self.makefile_output_path,
+            depfile_format: self.depfile_format,
+            deps_json_path: self.deps_json_path,
+            prefetch_list_path: self.prefetch_list_path,
+            precompile_preamble: self.precompile_preamble,
+            preamble_format: None,
             output_path,
             tex_rerun_specification: self.reruns,
             keep_intermediates: self.keep_intermediates,
             keep_logs: self.keep_logs,
             synctex_enabled: self.synctex,
+            file_line_errors: self.file_line_errors,
+            pass_number: 0,
+            diagnostics: Vec::new(),
+            page_ranges: self.page_ranges,
+            encryption: self.encryption,
+            pdfa: self.pdfa,
+            shell_escape_work,
+            sandboxed: self.sandboxed,
+            source_date_epoch,
             unstables: self.unstables,
         })
     }
 }
 
-#[derive(Debug, Clone)]
-enum RerunReason {
+/// The reason that the TeX engine is being rerun.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RerunReason {
+    /// BibTeX was run, so its output needs to be incorporated.
     Bibtex,
+
+    /// The named file was changed by the previous pass.
     FileChange(String),
+
+    /// The user asked for a fixed number of reruns.
+    Requested,
+}
+
+impl fmt::Display for RerunReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RerunReason::Bibtex => write!(f, "bibtex was run"),
+            RerunReason::FileChange(ref name) => write!(f, "\"{}\" changed", name),
+            RerunReason::Requested => write!(f, "I was told to"),
+        }
+    }
 }
 
 /// The ProcessingSession struct runs the whole show when we're actually
@@ -600,11 +1151,30 @@ pub struct ProcessingSession {
     tex_aux_path: OsString,
     tex_xdv_path: OsString,
     tex_pdf_path: OsString,
+    tex_log_path: OsString,
 
     /// If we're writing out Makefile rules, this is where they go. The TeX
     /// engine doesn't know about this path at all.
     makefile_output_path: Option<PathBuf>,
 
+    /// The syntax of the Makefile rules.
+    depfile_format: DepfileFormat,
+
+    /// If set, a JSON report of the dependencies goes here.
+    deps_json_path: Option<PathBuf>,
+
+    /// If set, the list of the bundle files that the document used is kept
+    /// here, for fetching them in bulk.
+    prefetch_list_path: Option<PathBuf>,
+
+    /// Whether to precompile the preamble of the primary input into a format.
+    precompile_preamble: bool,
+
+    /// If the preamble has been precompiled, this is the name of the format
+    /// holding it, and the rest of the primary input, which the TeX passes
+    /// process instead of the whole of it.
+    preamble_format: Option<(String, BufferedPrimaryIo)>,
+
     /// This is the path that the processed file will be saved at. It defaults
     /// to the path of `primary_input_path` or `.` if STDIN is used. If set to
     /// None, the output files will not be saved to disk — in which case, the
@@ -618,6 +1188,28 @@ pub struct ProcessingSession {
     keep_intermediates: bool,
     keep_logs: bool,
     synctex_enabled: bool,
+    file_line_errors: bool,
+
+    /// The number of engine passes started so far during the current run.
+    pass_number: usize,
+
+    /// The problems found in the log of the most recent TeX pass.
+    diagnostics: Vec<Diagnostic>,
+
+    page_ranges: Vec<PageRange>,
+    encryption: Option<PdfEncryption>,
+    pdfa: Option<PdfAConformance>,
+
+    /// If shell escape is enabled, external commands are run in this
+    /// temporary directory. It is deleted when the session is dropped.
+    shell_escape_work: Option<TempDir>,
+
+    /// Whether output files must stay inside the output directory.
+    sandboxed: bool,
+
+    /// If set, the session is reproducible, and this is the time at which
+    /// its outputs are deemed to be made, in seconds since the epoch.
+    source_date_epoch: Option<u64>,
 
     unstables: UnstableOptions,
 }
@@ -695,7 +1287,16 @@ impl ProcessingSession {
     /// - run BibTeX, if it seems to be required
     /// - repeat the last two steps as often as needed
     /// - write the output files to disk, including a Makefile if it was requested.
-    pub fn run(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+    ///
+    /// The problems found in the log file of the final TeX pass, such as
+    /// undefined references and overfull boxes, are reported to `status`
+    /// and returned. They are reported even if processing fails. See
+    /// [`analyze_log`].
+    pub fn run(&mut self, status: &mut dyn StatusBackend) -> Result<Vec<Diagnostic>> {
+        self.pass_number = 0;
+        self.diagnostics.clear();
+        self.prefetch_bundle_files(status);
+
         // Do we need to generate the format file?
 
         let generate_format = if self.output_format == OutputFormat::Format {
@@ -721,6 +1322,12 @@ impl ProcessingSession {
             self.make_format_pass(status)?;
         }
 
+        self.preamble_format = None;
+
+        if self.precompile_preamble && self.output_format != OutputFormat::Format {
+            self.prepare_preamble_format(status)?;
+        }
+
         // Do the meat of the work.
 
         let result = match self.pass {
@@ -736,30 +1343,24 @@ impl ProcessingSession {
             PassSetting::BibtexFirst => self.default_pass(true, status),
         };
 
+        // Report what the log says even if processing failed, since it may
+        // well explain why.
+        for diag in &self.diagnostics {
+            if let DiagnosticKind::UnderfullHbox(_) = diag.kind {
+                tt_note!(status, "{}", diag);
+            } else {
+                tt_warning!(status, "{}", diag);
+            }
+        }
+
         if let Err(e) = result {
-            self.write_files(None, status, true)?;
+            self.write_files(status, true)?;
             return Err(e);
         };
 
-        // Write output files and the first line of our Makefile output.
+        // Write output files, and then what they depend on.
 
-        let mut mf_dest_maybe = match self.makefile_output_path {
-            Some(ref p) => {
-                if self.output_path.is_none() {
-                    tt_warning!(
-                        status,
-                        "requested to generate Makefile rules, but no files written to disk!"
-                    );
-                    None
-                } else {
-                    Some(File::create(p)?)
-                }
-            }
-
-            None => None,
-        };
-
-        let n_skipped_intermediates = self.write_files(mf_dest_maybe.as_mut(), status, false)?;
+        let n_skipped_intermediates = self.write_files(status, false)?;
 
         if n_skipped_intermediates > 0 {
             status.note_highlighted(
@@ -769,58 +1370,325 @@ impl ProcessingSession {
             );
         }
 
-        // Finish Makefile rules, maybe.
+        self.write_makefile_rules(status)?;
+        self.write_deps_json(status)?;
+        self.write_prefetch_list(status);
+
+        // All done.
+
+        Ok(std::mem::take(&mut self.diagnostics))
+    }
 
-        if let Some(ref mut mf_dest) = mf_dest_maybe {
-            ctry!(write!(mf_dest, ": "); "couldn't write to Makefile-rules file");
+    /// The files written to disk by the most recent run, in a stable order.
+    fn disk_outputs(&self) -> Vec<(&OsString, &FileSummary)> {
+        let mut outputs: Vec<_> = self
+            .events
+            .0
+            .iter()
+            .filter(|(_, info)| info.disk_path.is_some())
+            .collect();
+        outputs.sort_unstable_by_key(|(_, info)| info.disk_path.as_ref());
+        outputs
+    }
 
-            if let Some(ref pip) = self.primary_input_path {
-                ctry!(mf_dest.write_all(pip.to_string_lossy().as_ref().as_bytes()); "couldn't write to Makefile-rules file");
+    /// The files read from the filesystem by the most recent run, in a
+    /// stable order.
+    fn filesystem_reads(&self) -> Vec<(&OsString, &FileSummary)> {
+        let mut inputs: Vec<_> = self
+            .events
+            .0
+            .iter()
+            .filter(|(_, info)| info.input_origin == InputOrigin::Filesystem)
+            .collect();
+        inputs.sort_unstable_by_key(|(name, _)| *name);
+        inputs
+    }
+
+    /// Write out the Makefile rules for the outputs of the most recent run,
+    /// if requested.
+    fn write_makefile_rules(&self, status: &mut dyn StatusBackend) -> Result<()> {
+        let mf_path = match self.makefile_output_path {
+            Some(ref p) => p,
+            None => return Ok(()),
+        };
+
+        let root = match self.output_path {
+            Some(ref p) => p,
+            None => {
+                tt_warning!(
+                    status,
+                    "requested to generate Makefile rules, but no files written to disk!"
+                );
+                return Ok(());
             }
+        };
 
-            // The check above ensures that this is never None.
-            let root = self.output_path.as_ref().unwrap();
+        let outputs = self.disk_outputs();
 
-            for (name, info) in &self.events.0 {
-                if info.input_origin != InputOrigin::Filesystem {
-                    continue;
-                }
+        // Ninja only accepts the outputs that its build rule declares as
+        // targets, which will be the main output file.
+        let main_output = match self.output_format {
+            OutputFormat::Pdf => Some(&self.tex_pdf_path),
+            OutputFormat::Xdv => Some(&self.tex_xdv_path),
+            OutputFormat::Aux => Some(&self.tex_aux_path),
+            _ => None,
+        };
 
-                if info.got_written_to_disk {
-                    // If the file originally came from the filesystem, and it
-                    // was written as well as read, and we actually wrote it
-                    // to disk, there's a circular dependency that's
-                    // inappropriate to express in a Makefile. If it was
-                    // "written" by the engine but we didn't actually write
-                    // those modifications to disk, we're OK. If there's a
-                    // two-stage compilation involving the .aux file, the
-                    // latter case is what arises unless --keep-intermediates
-                    // is specified.
-                    tt_warning!(
-                        status,
-                        "omitting circular Makefile dependency for {}",
-                        name.to_string_lossy()
-                    );
-                    continue;
+        let mut targets: Vec<&Path> = outputs
+            .iter()
+            .filter_map(|(_, info)| info.disk_path.as_deref())
+            .collect();
+
+        if self.depfile_format == DepfileFormat::Ninja {
+            if let Some(main) = main_output.and_then(|m| self.events.0.get(m)) {
+                if let Some(ref p) = main.disk_path {
+                    targets = vec![p];
                 }
+            }
+        }
+
+        let mut text = String::new();
+
+        for path in targets {
+            text.push_str(&escape_make_path(path));
+            text.push(' ');
+        }
+
+        text.push(':');
+
+        if let Some(ref pip) = self.primary_input_path {
+            text.push(' ');
+            text.push_str(&escape_make_path(pip));
+        }
 
-                ctry!(write!(mf_dest, " \\\n  {}", root.join(name).display()); "couldn't write to Makefile-rules file");
+        for (name, info) in self.filesystem_reads() {
+            if info.disk_path.is_some() {
+                // If the file originally came from the filesystem, and it
+                // was written as well as read, and we actually wrote it
+                // to disk, there's a circular dependency that's
+                // inappropriate to express in a Makefile. If it was
+                // "written" by the engine but we didn't actually write
+                // those modifications to disk, we're OK. If there's a
+                // two-stage compilation involving the .aux file, the
+                // latter case is what arises unless --keep-intermediates
+                // is specified.
+                tt_warning!(
+                    status,
+                    "omitting circular Makefile dependency for {}",
+                    name.to_string_lossy()
+                );
+                continue;
             }
 
-            ctry!(writeln!(mf_dest, ""); "couldn't write to Makefile-rules file");
+            let path = match info.input_path {
+                Some(ref p) => p.clone(),
+                None => root.join(name),
+            };
+            text.push_str(" \\\n  ");
+            text.push_str(&escape_make_path(&path));
         }
 
-        // All done.
+        text.push('\n');
 
+        // Maybe it'd be better to have this just be a warning? But if the
+        // program is supposed to write the file, you don't want it exiting
+        // with error code zero if it couldn't do that successfully.
+        ctry!(fs::write(mf_path, text); "couldn't write to Makefile-rules file");
         Ok(())
     }
 
-    fn write_files(
-        &mut self,
-        mut mf_dest_maybe: Option<&mut File>,
-        status: &mut dyn StatusBackend,
-        only_logs: bool,
-    ) -> Result<u32> {
+    /// Write out the JSON report of the dependencies of the most recent run,
+    /// if requested.
+    fn write_deps_json(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        let json_path = match self.deps_json_path {
+            Some(ref p) => p.clone(),
+            None => return Ok(()),
+        };
+
+        let mut bundle_files: Vec<_> = self
+            .events
+            .0
+            .iter()
+            .filter(|(_, info)| {
+                info.input_origin == InputOrigin::Other
+                    && info.access_pattern == AccessPattern::Read
+            })
+            .collect();
+        bundle_files.sort_unstable_by_key(|(name, _)| *name);
+
+        // Without the bundle's digest, the report can't say where the bundle
+        // files came from, so it is only optional if none were used.
+
+        let bundle_digest = match self.io.bundle {
+            Some(ref mut b) => match b.get_digest(status) {
+                Ok(d) => Some(d),
+                Err(e) if bundle_files.is_empty() => {
+                    tt_warning!(status, "couldn't determine the digest of the bundle"; e);
+                    None
+                }
+                Err(e) => {
+                    return Err(e.chain_err(|| {
+                        "couldn't determine the digest of the bundle for the dependency report"
+                    }))
+                }
+            },
+            None => None,
+        };
+
+        if bundle_digest.is_none() && !bundle_files.is_empty() {
+            return Err(errmsg!(
+                "the dependency report lists bundle files, but there is no bundle"
+            ));
+        }
+
+        let mut text = String::from("{\n  \"primary_input\": ");
+        push_json_opt(
+            &mut text,
+            self.primary_input_path
+                .as_ref()
+                .map(|p| p.to_string_lossy())
+                .as_deref(),
+        );
+
+        text.push_str(",\n  \"bundle_digest\": ");
+        push_json_opt(
+            &mut text,
+            bundle_digest.as_ref().map(|d| d.to_string()).as_deref(),
+        );
+
+        // Files read from the filesystem. Unlike the Makefile rules, these
+        // include the ones that were written back to disk.
+
+        text.push_str(",\n  \"inputs\": [");
+
+        for (i, (name, info)) in self.filesystem_reads().into_iter().enumerate() {
+            push_json_file(
+                &mut text,
+                i,
+                name,
+                info.input_path.as_deref(),
+                info.read_digest.as_ref(),
+                None,
+            );
+        }
+
+        text.push_str("\n  ],\n  \"bundle_files\": [");
+
+        for (i, (name, info)) in bundle_files.into_iter().enumerate() {
+            push_json_file(
+                &mut text,
+                i,
+                name,
+                None,
+                info.read_digest.as_ref(),
+                bundle_digest.as_ref(),
+            );
+        }
+
+        text.push_str("\n  ],\n  \"outputs\": [");
+
+        for (i, (name, info)) in self.disk_outputs().into_iter().enumerate() {
+            push_json_file(
+                &mut text,
+                i,
+                name,
+                info.disk_path.as_deref(),
+                info.write_digest.as_ref(),
+                None,
+            );
+        }
+
+        text.push_str("\n  ]\n}\n");
+
+        ctry!(fs::write(&json_path, text); "couldn't write the dependency report \"{}\"", json_path.display());
+        Ok(())
+    }
+
+    /// Hand the bundle files listed by an earlier run to the bundle, so that
+    /// it can fetch them all at once. Problems here are only warnings, since
+    /// the files can still be obtained one at a time when they're opened.
+    fn prefetch_bundle_files(&mut self, status: &mut dyn StatusBackend) {
+        let (path, bundle) = match (&self.prefetch_list_path, &mut self.io.bundle) {
+            (Some(p), Some(b)) => (p, b),
+            _ => return,
+        };
+
+        let names: Vec<String> = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(|l| l.to_owned()).collect(),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                tt_warning!(status, "couldn't read the list of bundle files \"{}\"", path.display(); e.into());
+                return;
+            }
+        };
+
+        if let Err(e) = bundle.prefetch(&names, status) {
+            tt_warning!(status, "couldn't download the document's bundle files ahead of time"; e);
+        }
+    }
+
+    /// Record which files the most recent run read from the bundle, for
+    /// [`Self::prefetch_bundle_files`] to use next time.
+    fn write_prefetch_list(&self, status: &mut dyn StatusBackend) {
+        let path = match self.prefetch_list_path {
+            Some(ref p) => p,
+            None => return,
+        };
+
+        let mut names: Vec<&str> = self
+            .events
+            .0
+            .iter()
+            .filter(|(_, info)| {
+                info.input_origin == InputOrigin::Other
+                    && info.access_pattern == AccessPattern::Read
+            })
+            .filter_map(|(name, _)| name.to_str())
+            .collect();
+        names.sort_unstable();
+
+        let mut text = names.join("\n");
+        text.push('\n');
+
+        if let Err(e) = fs::write(path, text) {
+            tt_warning!(status, "couldn't save the list of bundle files \"{}\"", path.display(); e.into());
+        }
+    }
+
+    /// Get the paths of all of the files that the session read from the
+    /// filesystem during its most recent run, including the primary input
+    /// file.
+    ///
+    /// Files that the session then wrote back to disk are not included, since
+    /// the session itself will have changed them. This is the set of files to
+    /// watch in order to rerun the session when its inputs change.
+    pub fn filesystem_inputs(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+
+        if let Some(ref p) = self.primary_input_path {
+            paths.push(p.clone());
+        }
+
+        for info in self.events.0.values() {
+            if info.input_origin == InputOrigin::Filesystem && info.disk_path.is_none() {
+                paths.extend(info.input_path.clone());
+            }
+        }
+
+        paths
+    }
+
+    /// Prepare the session to be run again.
+    ///
+    /// This discards the I/O events and the contents of the memory layer
+    /// from the previous run, so that the next run starts from scratch, but
+    /// keeps the bundle and the format cache, which are expensive to set up.
+    pub fn reset(&mut self) {
+        self.events = IoEvents::new();
+        self.io.mem.files.borrow_mut().clear();
+    }
+
+    fn write_files(&mut self, status: &mut dyn StatusBackend, only_logs: bool) -> Result<u32> {
         let root = match self.output_path {
             Some(ref p) => p,
 
@@ -878,7 +1746,11 @@ impl ProcessingSession {
                 continue;
             }
 
-            let real_path = root.join(name);
+            let real_path = if self.sandboxed {
+                confined_path(root, name)?
+            } else {
+                root.join(name)
+            };
             let byte_len = Byte::from_bytes(contents.len() as u128);
             status.note_highlighted(
                 "Writing ",
@@ -888,18 +1760,7 @@ impl ProcessingSession {
 
             let mut f = File::create(&real_path)?;
             f.write_all(contents)?;
-            summ.got_written_to_disk = true;
-
-            if let Some(ref mut mf_dest) = mf_dest_maybe {
-                // Maybe it'd be better to have this just be a warning? But if
-                // the program is supposed to write the file, you don't want
-                // it exiting with error code zero if it couldn't do that
-                // successfully.
-                //
-                // Not quite sure why, but I can't pull out the target path
-                // here. I think 'self' is borrow inside the loop?
-                ctry!(write!(mf_dest, "{} ", real_path.to_string_lossy()); "couldn't write to Makefile-rules file");
-            }
+            summ.disk_path = Some(real_path);
         }
 
         Ok(n_skipped_intermediates)
@@ -936,12 +1797,11 @@ impl ProcessingSession {
         };
 
         for i in 0..pass_count {
-            let rerun_explanation = if reruns_fixed {
-                "I was told to".to_owned()
+            let rerun_reason = if reruns_fixed {
+                RerunReason::Requested
             } else {
                 match rerun_result {
-                    Some(RerunReason::Bibtex) => "bibtex was run".to_owned(),
-                    Some(RerunReason::FileChange(ref s)) => format!("\"{}\" changed", s),
+                    Some(ref r) => r.clone(),
                     None => break,
                 }
             };
@@ -956,7 +1816,7 @@ impl ProcessingSession {
                 summ.read_digest = None;
             }
 
-            warnings = self.tex_pass(Some(&rerun_explanation), status)?;
+            warnings = self.tex_pass(Some(&rerun_reason), status)?;
 
             if !reruns_fixed {
                 rerun_result = self.is_rerun_needed(status);
@@ -1019,6 +1879,8 @@ impl ProcessingSession {
             .into());
         }
 
+        self.start_pass("TeX", None, status);
+
         // PathBuf.file_stem() doesn't do what we want since it only strips
         // one extension. As of 1.17, the compiler needs a type annotation for
         // some reason, which is why we use the `r` variable.
@@ -1089,35 +1951,325 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// If the primary input has a preamble, find the format that it has been
+    /// precompiled into, making it if needed, and arrange for the TeX passes
+    /// to use it.
+    fn prepare_preamble_format(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        if self.io.format_cache.is_none() {
+            tt_warning!(
+                status,
+                "cannot precompile the preamble without a place to save formats"
+            );
+            return Ok(());
+        }
+
+        let text = {
+            let mut stack = self.io.as_stack();
+            let mut ih = match stack.input_open_primary(status) {
+                OpenResult::Ok(ih) => ih,
+                OpenResult::NotAvailable => return Ok(()),
+                OpenResult::Err(e) => return Err(e),
+            };
+            let mut text = Vec::new();
+            ctry!(ih.read_to_end(&mut text); "couldn't read the primary input");
+            text
+        };
+
+        let (preamble, body) = match split_preamble(&text) {
+            Some(parts) => parts,
+            None => {
+                tt_note!(status, "the document has no preamble to precompile");
+                return Ok(());
+            }
+        };
+
+        // The format is keyed by everything that can affect what the
+        // preamble leaves in TeX's memory: the base format, the job name, the
+        // engine options, the preamble itself, and the files that it reads.
+        // Those files are only known once it has been run, so we record their
+        // names alongside the format made for the latest run of this
+        // preamble.
+
+        let base_format = {
+            let mut stack = self.io.as_stack();
+            let mut ih = match stack.input_open_format(OsStr::new(&self.format_name), status) {
+                OpenResult::Ok(ih) => ih,
+                OpenResult::NotAvailable => {
+                    return Err(errmsg!("could not open format file {}", self.format_name))
+                }
+                OpenResult::Err(e) => return Err(e),
+            };
+            let mut data = Vec::new();
+            ctry!(ih.read_to_end(&mut data); "couldn't read format file {}", self.format_name);
+            let mut dc = digest::create();
+            dc.input(&data);
+            DigestData::from(dc)
+        };
+
+        let preamble_key = {
+            let options = format!(
+                "synctex={} file_line_errors={} shell_escape={} shell_escape_commands={:?} \
+                 paper_size={:?} source_date_epoch={:?}",
+                self.synctex_enabled,
+                self.file_line_errors,
+                self.shell_escape_work.is_some(),
+                self.unstables.shell_escape_commands,
+                self.unstables.paper_size,
+                self.source_date_epoch,
+            );
+
+            let mut dc = digest::create();
+            dc.input(self.format_name.as_bytes());
+            dc.input([0u8]);
+            dc.input(base_format.to_string().as_bytes());
+            dc.input([0u8]);
+            dc.input(self.primary_input_tex_path.as_bytes());
+            dc.input([0u8]);
+            dc.input(options.as_bytes());
+            dc.input([0u8]);
+            dc.input(preamble.as_bytes());
+            DigestData::from(dc)
+        };
+        let inputs_name = format!("preamble-{}", preamble_key.to_string());
+
+        let format_cache = self.io.format_cache.as_mut().unwrap();
+        let mut found = None;
+
+        if let Some(names) = format_cache.load_inputs(&inputs_name)? {
+            if let Some(inputs) = self.read_inputs(&names, status) {
+                let name = preamble_format_name(&preamble_key, &inputs);
+                let mut stack = self.io.as_stack();
+
+                if let OpenResult::Ok(_) = stack.input_open_format(OsStr::new(&name), status) {
+                    found = Some((name, inputs));
+                }
+            }
+        }
+
+        let (name, inputs) = match found {
+            Some(f) => f,
+            None => {
+                match self.make_preamble_format(preamble, &preamble_key, &inputs_name, status) {
+                    Ok(f) => f,
+                    Err(e) => {
+                        tt_warning!(status, "couldn't precompile the preamble; processing the document as usual"; e);
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        // The preamble's inputs are inputs of the document, even though the
+        // TeX passes won't read them.
+
+        for (input_name, _, path) in &inputs {
+            self.events.input_opened(
+                OsStr::new(input_name),
+                InputOrigin::Filesystem,
+                path.as_deref(),
+            );
+        }
+
+        self.preamble_format = Some((name, BufferedPrimaryIo::from_text(body)));
+        Ok(())
+    }
+
+    /// Run the TeX engine on a document's preamble and save the format that
+    /// it dumps. Returns the name of the format and the inputs that it was
+    /// made from.
+    fn make_preamble_format(
+        &mut self,
+        preamble: &str,
+        preamble_key: &DigestData,
+        inputs_name: &str,
+        status: &mut dyn StatusBackend,
+    ) -> Result<(String, Vec<PreambleInput>)> {
+        // This isn't one of the engine passes of the run, so `start_pass`
+        // isn't called.
+        status.note_highlighted("Precompiling ", "the preamble", " ...");
+
+        let existing: HashSet<OsString> = self.io.mem.files.borrow().keys().cloned().collect();
+        let mut events = IoEvents::new();
+        let mut primary = BufferedPrimaryIo::from_text(format!("{}\n\\dump\n", preamble));
+
+        let result = {
+            let mut stack = self.io.as_stack_with_primary(&mut primary);
+            TexEngine::new()
+                .halt_on_error_mode(true)
+                .initex_mode(true)
+                .initex_load_format(true)
+                .process(
+                    &mut stack,
+                    &mut events,
+                    status,
+                    &self.format_name,
+                    &self.primary_input_tex_path,
+                    &self.unstables,
+                )
+        };
+
+        // Whatever happened, the files created by this run are of no further
+        // use, except for the format. Files that already existed are left
+        // alone.
+
+        let mut format_data = None;
+
+        {
+            let mut files = self.io.mem.files.borrow_mut();
+
+            for (name, summ) in &events.0 {
+                if summ.access_pattern == AccessPattern::Read {
+                    continue;
+                }
+
+                let data = if existing.contains(name) {
+                    files.get(name).cloned()
+                } else {
+                    files.remove(name)
+                };
+
+                if name.to_string_lossy().ends_with(".fmt") {
+                    format_data = data;
+                }
+            }
+        }
+
+        match result {
+            Ok(TexResult::Spotless) | Ok(TexResult::Warnings) => {}
+            Ok(TexResult::Errors) => {
+                return Err(errmsg!("errors were issued by the TeX engine"));
+            }
+            Err(e) => {
+                return Err(e.chain_err(|| ErrorKind::EngineError("TeX")));
+            }
+        }
+
+        let format_data = ctry!(format_data; "the preamble did not produce a format file");
+
+        let mut names: Vec<String> = events
+            .0
+            .iter()
+            .filter(|(_, summ)| {
+                summ.access_pattern == AccessPattern::Read
+                    && summ.input_origin == InputOrigin::Filesystem
+            })
+            .filter_map(|(name, _)| name.to_str().map(|s| s.to_owned()))
+            .collect();
+        names.sort();
+
+        let inputs = ctry!(self.read_inputs(&names, status); "the files read by the preamble could not be reopened");
+        let name = preamble_format_name(preamble_key, &inputs);
+
+        let format_cache = self.io.format_cache.as_mut().unwrap();
+        format_cache.write_format(&name, &format_data, status)?;
+        format_cache.save_inputs(inputs_name, &names)?;
+        Ok((name, inputs))
+    }
+
+    /// Open the files that a preamble read, as the TeX engine would, and
+    /// compute their digests. Returns `None` if any of them can't be opened.
+    fn read_inputs(
+        &mut self,
+        names: &[String],
+        status: &mut dyn StatusBackend,
+    ) -> Option<Vec<PreambleInput>> {
+        let mut stack = self.io.as_stack();
+        let mut inputs = Vec::with_capacity(names.len());
+
+        for name in names {
+            let mut ih = match stack.input_open_name(OsStr::new(name), status) {
+                OpenResult::Ok(ih) => ih,
+                _ => return None,
+            };
+
+            let mut data = Vec::new();
+
+            if ih.read_to_end(&mut data).is_err() {
+                return None;
+            }
+
+            let mut dc = digest::create();
+            dc.input(&data);
+            inputs.push((
+                name.clone(),
+                DigestData::from(dc),
+                ih.path().map(|p| p.to_owned()),
+            ));
+        }
+
+        Some(inputs)
+    }
+
+    /// Let the status backend know that an engine pass is starting.
+    fn start_pass(
+        &mut self,
+        engine: &str,
+        rerun_reason: Option<&RerunReason>,
+        status: &mut dyn StatusBackend,
+    ) {
+        self.pass_number += 1;
+        status.pass_started(engine, self.pass_number, rerun_reason);
+    }
+
     /// Run one pass of the TeX engine.
     fn tex_pass(
         &mut self,
-        rerun_explanation: Option<&str>,
+        rerun_reason: Option<&RerunReason>,
         status: &mut dyn StatusBackend,
     ) -> Result<Option<&'static str>> {
+        self.start_pass("TeX", rerun_reason, status);
+
+        let shell_escape = self.shell_escape_work.as_ref().map(|dir| {
+            ShellEscape::new(
+                dir.path(),
+                self.io.mem.files.clone(),
+                self.unstables.shell_escape_commands.clone(),
+            )
+        });
+
+        let format_name = match self.preamble_format {
+            Some((ref name, _)) => name.clone(),
+            None => self.format_name.clone(),
+        };
+
         let result = {
-            let mut stack = self.io.as_stack();
-            if let Some(s) = rerun_explanation {
-                status.note_highlighted("Rerunning ", "TeX", &format!(" because {} ...", s));
+            let mut stack = match self.preamble_format {
+                Some((_, ref mut body)) => self.io.as_stack_with_primary(body),
+                None => self.io.as_stack(),
+            };
+
+            if let Some(r) = rerun_reason {
+                status.note_highlighted("Rerunning ", "TeX", &format!(" because {} ...", r));
             } else {
                 status.note_highlighted("Running ", "TeX", " ...");
             }
 
             TexEngine::new()
                 .halt_on_error_mode(true)
+                .file_line_errors(self.file_line_errors)
                 .initex_mode(self.output_format == OutputFormat::Format)
                 .synctex(self.synctex_enabled)
                 .semantic_pagination(self.output_format == OutputFormat::Html)
+                .shell_escape(shell_escape)
+                .source_date_epoch(self.source_date_epoch)
                 .process(
                     &mut stack,
                     &mut self.events,
                     status,
-                    &self.format_name,
+                    &format_name,
                     &self.primary_input_tex_path,
                     &self.unstables,
                 )
         };
 
+        // Only the diagnostics of the final pass are interesting, since
+        // earlier passes will complain about things like references that
+        // haven't been resolved yet.
+        self.diagnostics = match self.io.mem.files.borrow().get(&self.tex_log_path) {
+            Some(log) => analyze_log(&String::from_utf8_lossy(log)),
+            None => Vec::new(),
+        };
+
         let warnings = match result {
             Ok(TexResult::Spotless) => None,
             Ok(TexResult::Warnings) =>
@@ -1133,6 +2285,8 @@ impl ProcessingSession {
     }
 
     fn bibtex_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        self.start_pass("BibTeX", None, status);
+
         let result = {
             let mut stack = self.io.as_stack();
             let mut engine = BibtexEngine::new();
@@ -1170,9 +2324,16 @@ impl ProcessingSession {
     }
 
     fn xdvipdfmx_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        self.start_pass("xdvipdfmx", None, status);
+
         {
             let mut stack = self.io.as_stack();
-            let mut engine = XdvipdfmxEngine::new();
+            let mut engine = XdvipdfmxEngine::new()
+                .with_page_ranges(&self.page_ranges)
+                .with_encryption(self.encryption.clone())
+                .with_pdfa(self.pdfa)
+                .with_deterministic_tags(self.source_date_epoch.is_some())
+                .with_source_date_epoch(self.source_date_epoch);
             status.note_highlighted("Running ", "xdvipdfmx", " ...");
             engine.process(
                 &mut stack,
@@ -1189,6 +2350,8 @@ impl ProcessingSession {
     }
 
     fn spx2html_pass(&mut self, status: &mut dyn StatusBackend) -> Result<i32> {
+        self.start_pass("spx2html", None, status);
+
         {
             let mut stack = self.io.as_stack();
             let mut engine = Spx2HtmlEngine::new();
@@ -1221,3 +2384,239 @@ impl ProcessingSession {
             .into_inner()
     }
 }
+
+/// Escape a path for use in a Makefile or Ninja depfile rule.
+fn escape_make_path(path: &Path) -> String {
+    let mut escaped = String::new();
+
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '$' => escaped.push_str("$$"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Append `text` to `dest` as a JSON string, or `null`.
+fn push_json_opt(dest: &mut String, text: Option<&str>) {
+    match text {
+        Some(t) => push_json_str(dest, t),
+        None => dest.push_str("null"),
+    }
+}
+
+/// Append an entry describing a file to a JSON array in the dependency
+/// report. `index` is the entry's position in the array, and `bundle_digest`
+/// is given for files that came from the bundle.
+fn push_json_file(
+    dest: &mut String,
+    index: usize,
+    name: &OsStr,
+    path: Option<&Path>,
+    digest: Option<&DigestData>,
+    bundle_digest: Option<&DigestData>,
+) {
+    if index > 0 {
+        dest.push(',');
+    }
+
+    dest.push_str("\n    {\"name\": ");
+    push_json_str(dest, &name.to_string_lossy());
+
+    if let Some(p) = path {
+        dest.push_str(", \"path\": ");
+        push_json_str(dest, &p.to_string_lossy());
+    }
+
+    dest.push_str(", \"digest\": ");
+    push_json_opt(dest, digest.map(|d| d.to_string()).as_deref());
+
+    if let Some(d) = bundle_digest {
+        dest.push_str(", \"bundle_digest\": ");
+        push_json_str(dest, &d.to_string());
+    }
+
+    dest.push('}');
+}
+
+/// A file read by a document's preamble: its name, the digest of its
+/// contents, and where it was found on the filesystem.
+type PreambleInput = (String, DigestData, Option<PathBuf>);
+
+/// Lines starting with this marker end the part of a document's preamble that
+/// is precompiled.
+const END_PREAMBLE_MARKER: &str = "%%endpreamble";
+
+/// Split a document into its preamble and the rest of it, which is returned
+/// with the preamble replaced by blank lines so that line numbers are
+/// unchanged. The preamble ends at a line starting with
+/// [`END_PREAMBLE_MARKER`], or at the first `\begin{document}` that isn't
+/// commented out. Returns `None` if the document has no such preamble.
+fn split_preamble(text: &[u8]) -> Option<(&str, String)> {
+    let text = std::str::from_utf8(text).ok()?;
+    let mut offset = 0;
+    let mut end = None;
+
+    for line in text.split_inclusive('\n') {
+        if line.trim_start().starts_with(END_PREAMBLE_MARKER) {
+            end = Some(offset);
+            break;
+        }
+
+        if let Some(i) = line.find("\\begin{document}") {
+            let before = &line[..i];
+            let commented = before
+                .char_indices()
+                .any(|(j, c)| c == '%' && !before[..j].ends_with('\\'));
+
+            if !commented {
+                end = Some(offset + i);
+                break;
+            }
+        }
+
+        offset += line.len();
+    }
+
+    let (preamble, rest) = text.split_at(end?);
+
+    if preamble.trim().is_empty() {
+        return None;
+    }
+
+    let mut body = "\n".repeat(preamble.matches('\n').count());
+    body.push_str(rest);
+    Some((preamble, body))
+}
+
+/// The name under which the format made from a preamble is cached.
+fn preamble_format_name(preamble_key: &DigestData, inputs: &[PreambleInput]) -> String {
+    let mut dc = digest::create();
+    dc.input(preamble_key.to_string().as_bytes());
+
+    for (name, digest, _) in inputs {
+        dc.input([0u8]);
+        dc.input(name.as_bytes());
+        dc.input([0u8]);
+        dc.input(digest.to_string().as_bytes());
+    }
+
+    format!("preamble-{}", DigestData::from(dc).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_warnings() {
+        let log = "This is XeTeX, Version 3.14159265 (preloaded format=latex)\n\
+                   (./doc.tex\n\
+                   LaTeX2e <2019-10-01>\n\
+                   (./sections/intro.tex\n\
+                   LaTeX Warning: Reference `fig:plot' on page 1 undefined on input line 12.\n\
+                   \n\
+                   Overfull \\hbox (12.5pt too wide) in paragraph at lines 20--25\n\
+                   []\\OT1/cmr/m/n/10 Some text (with a paren\n\
+                   \n\
+                   )\n\
+                   LaTeX Warning: Citation `knuth84' on page 2 undefined on input line 30.\n\
+                   \n\
+                   LaTeX Font Warning: Font shape `OT1/cmr/bx/it' undefined\n\
+                   (Font)              using `OT1/cmr/bx/n' instead on input line 31.\n\
+                   \n\
+                   Underfull \\hbox (badness 10000) detected at line 40\n\
+                   \n\
+                   Missing character: There is no ^^A in font cmr10!\n\
+                   ) )\n";
+
+        let diags = analyze_log(log);
+        let kinds: Vec<_> = diags.iter().map(|d| d.kind.clone()).collect();
+
+        assert_eq!(
+            kinds,
+            vec![
+                DiagnosticKind::UndefinedReference("fig:plot".to_owned()),
+                DiagnosticKind::OverfullHbox(12.5),
+                DiagnosticKind::UndefinedCitation("knuth84".to_owned()),
+                DiagnosticKind::FontSubstitution {
+                    requested: "OT1/cmr/bx/it".to_owned(),
+                    used: "OT1/cmr/bx/n".to_owned(),
+                },
+                DiagnosticKind::UnderfullHbox(10000),
+                DiagnosticKind::MissingCharacter {
+                    character: "^^A".to_owned(),
+                    font: "cmr10".to_owned(),
+                },
+            ]
+        );
+
+        assert_eq!(diags[0].file.as_deref(), Some("./sections/intro.tex"));
+        assert_eq!(diags[0].lines, Some((12, 12)));
+        assert_eq!(
+            diags[0].to_string(),
+            "./sections/intro.tex:12: LaTeX Warning: Reference `fig:plot' on page 1 \
+             undefined on input line 12."
+        );
+        assert_eq!(diags[1].file.as_deref(), Some("./sections/intro.tex"));
+        assert_eq!(diags[1].lines, Some((20, 25)));
+        assert_eq!(diags[2].file.as_deref(), Some("./doc.tex"));
+        assert_eq!(diags[3].lines, Some((31, 31)));
+        assert_eq!(diags[4].lines, Some((40, 40)));
+    }
+
+    #[test]
+    fn log_wrapped_lines() {
+        let long = format!("LaTeX Warning: Reference `{}", "x".repeat(53));
+        assert_eq!(long.len(), LOG_LINE_WIDTH);
+        let log = format!("{}\n' on page 1 undefined on input line 3.\n", long);
+
+        let diags = analyze_log(&log);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(
+            diags[0].kind,
+            DiagnosticKind::UndefinedReference("x".repeat(53))
+        );
+        assert_eq!(diags[0].lines, Some((3, 3)));
+    }
+
+    #[test]
+    fn preamble_at_begin_document() {
+        let text = b"\\documentclass{article}\n\\usepackage{amsmath}\n% \\begin{document}\n\\begin{document}\nHi.\n\\end{document}\n";
+        let (preamble, body) = split_preamble(text).unwrap();
+        assert_eq!(
+            preamble,
+            "\\documentclass{article}\n\\usepackage{amsmath}\n% \\begin{document}\n"
+        );
+        assert_eq!(body, "\n\n\n\\begin{document}\nHi.\n\\end{document}\n");
+    }
+
+    #[test]
+    fn preamble_at_marker() {
+        let text = b"\\documentclass{article}\n  %%endpreamble\n\\usepackage{fontspec}\n\\begin{document}\n";
+        let (preamble, body) = split_preamble(text).unwrap();
+        assert_eq!(preamble, "\\documentclass{article}\n");
+        assert_eq!(
+            body,
+            "\n  %%endpreamble\n\\usepackage{fontspec}\n\\begin{document}\n"
+        );
+
+        let text = b"\\input macros \\def\\x{1}\\begin{document}x";
+        let (preamble, body) = split_preamble(text).unwrap();
+        assert_eq!(preamble, "\\input macros \\def\\x{1}");
+        assert_eq!(body, "\\begin{document}x");
+    }
+
+    #[test]
+    fn no_preamble() {
+        assert_eq!(split_preamble(b"Hello.\n\\bye\n"), None);
+        assert_eq!(split_preamble(b"\n  \n\\begin{document}\n"), None);
+        assert_eq!(split_preamble(b"\\def\\a{\xff}\n%%endpreamble\n"), None);
+    }
+}
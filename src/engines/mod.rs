@@ -23,8 +23,9 @@ use std::path::Path;
 use std::sync::Mutex;
 use std::{ptr, slice};
 
+use self::shell_escape::{ShellEscape, ShellEscapeResult};
 use crate::digest::DigestData;
-use crate::errors::{Error, Result};
+use crate::errors::{Error, ErrorKind, Result};
 use crate::io::{InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle};
 use crate::status::StatusBackend;
 use crate::{tt_error, tt_warning};
@@ -32,6 +33,7 @@ use crate::{tt_error, tt_warning};
 // Public sub-modules and reexports.
 
 pub mod bibtex;
+pub mod shell_escape;
 pub mod spx2html;
 pub mod tex;
 pub mod xdvipdfmx;
@@ -75,8 +77,9 @@ pub trait IoEventBackend {
     /// digest.
     fn output_closed(&mut self, _name: OsString, _digest: DigestData) {}
 
-    /// This function is called when a file is opened for input.
-    fn input_opened(&mut self, _name: &OsStr, _origin: InputOrigin) {}
+    /// This function is called when a file is opened for input. If the file
+    /// was found on the filesystem, `path` says where.
+    fn input_opened(&mut self, _name: &OsStr, _origin: InputOrigin, _path: Option<&Path>) {}
 
     /// This function is called when the "primary input" stream is opened.
     fn primary_input_opened(&mut self, _origin: InputOrigin) {}
@@ -130,6 +133,7 @@ struct ExecutionState<'a, I: 'a + IoProvider> {
     input_handles: Vec<Box<InputHandle>>,
     #[allow(clippy::vec_box)]
     output_handles: Vec<Box<OutputHandle>>,
+    shell_escape: Option<ShellEscape>,
 }
 
 impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
@@ -144,9 +148,15 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             status,
             output_handles: Vec::new(),
             input_handles: Vec::new(),
+            shell_escape: None,
         }
     }
 
+    pub fn with_shell_escape(mut self, shell_escape: Option<ShellEscape>) -> ExecutionState<'a, I> {
+        self.shell_escape = shell_escape;
+        self
+    }
+
     // Helpers.
 
     fn input_open_name_format(
@@ -244,7 +254,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             }
         };
 
-        self.events.input_opened(ih.name(), ih.origin());
+        self.events.input_opened(ih.name(), ih.origin(), ih.path());
 
         // No canned way to stream the whole file into the digest, it seems.
 
@@ -375,13 +385,18 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
                 return ptr::null();
             }
             OpenResult::Err(e) => {
-                tt_warning!(self.status, "open of input {} failed", name.to_string_lossy(); e);
+                if let ErrorKind::PathForbidden(_) = e.kind() {
+                    tt_error!(self.status, "refusing to read \"{}\": it is outside of the directories that this document may use",
+                              name.to_string_lossy(); e);
+                } else {
+                    tt_warning!(self.status, "open of input {} failed", name.to_string_lossy(); e);
+                }
                 return ptr::null();
             }
         };
 
         // the file name may have had an extension added, so we use ih.name() here:
-        self.events.input_opened(ih.name(), ih.origin());
+        self.events.input_opened(ih.name(), ih.origin(), ih.path());
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len() - 1]
     }
@@ -452,6 +467,19 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
         true
     }
+
+    fn shell_escape(&mut self, command: &str) -> libc::c_int {
+        let handler = match self.shell_escape {
+            Some(ref mut h) => h,
+            None => return tectonic_engine::SHELL_ESCAPE_DISABLED,
+        };
+
+        match handler.run(command, self.events, self.status) {
+            ShellEscapeResult::Executed(code) => code,
+            ShellEscapeResult::NotAllowed => tectonic_engine::SHELL_ESCAPE_NOT_ALLOWED,
+            ShellEscapeResult::Failed => tectonic_engine::SHELL_ESCAPE_FAILED,
+        }
+    }
 }
 
 // Now, here' the actual C API. There are two parts to this: the functions in
@@ -480,6 +508,7 @@ struct TectonicBridgeApi {
     input_read: *const libc::c_void,
     input_getc: *const libc::c_void,
     input_close: *const libc::c_void,
+    shell_escape: *const libc::c_void,
 }
 */
 #[repr(transparent)]
@@ -494,7 +523,8 @@ impl std::ops::Deref for TectonicBridgeApi {
 
 use tectonic_engine::{
     bibtex_simple_main, dvipdfmx_simple_main, tex_simple_main, tt_get_error_message,
-    tt_xetex_set_int_variable, BibtexConfig, XdvipdfmxConfig,
+    tt_xetex_set_int_variable, tt_xetex_set_source_date_epoch, BibtexConfig, EncryptionConfig,
+    PageRange, PdfAConformance, XdvipdfmxConfig,
 };
 
 // Entry points for the C/C++ API functions.
@@ -769,6 +799,17 @@ extern "C" fn input_close<'a, I: 'a + IoProvider>(
     }
 }
 
+extern "C" fn shell_escape<'a, I: 'a + IoProvider>(
+    es: *mut ExecutionState<'a, I>,
+    cmd: *const u8,
+    len: libc::size_t,
+) -> libc::c_int {
+    let es = unsafe { &mut *es };
+    let bytes = unsafe { slice::from_raw_parts(cmd, len) };
+    let cmd = String::from_utf8_lossy(bytes);
+    es.shell_escape(&cmd)
+}
+
 // All of these entry points are used to populate the bridge API struct:
 
 impl TectonicBridgeApi {
@@ -796,6 +837,7 @@ impl TectonicBridgeApi {
                 input_seek: transmute(input_seek::<'a, I> as *const libc::c_void),
                 input_read: transmute(input_read::<'a, I> as *const libc::c_void),
                 input_close: transmute(input_close::<'a, I> as *const libc::c_void),
+                shell_escape: transmute(shell_escape::<'a, I> as *const libc::c_void),
             })
         }
     }
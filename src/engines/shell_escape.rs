@@ -0,0 +1,472 @@
+// src/engines/shell_escape.rs -- running external commands for \write18
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Support for TeX’s “shell escape” feature.
+//!
+//! When shell escape is enabled, `\write18{...}` and `\immediate\write18{...}`
+//! run an external command. Tectonic’s engines never touch the filesystem
+//! directly, so the command cannot simply be run in the document directory.
+//! Instead, each command is run in a temporary working directory that is
+//! populated with the current contents of the memory I/O layer. After the
+//! command exits, any files that it created or modified are copied back into
+//! the memory layer, where the engine (and the driver’s output logic) will
+//! find them, and files that it deleted are dropped from it.
+//!
+//! Shell escape can also be *restricted*, in the style of TeX Live’s
+//! `shell_escape_commands` setting: only commands whose program name appears
+//! in an allowlist may be run, and they are run directly rather than through
+//! the shell, so that shell metacharacters have no special meaning.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Output};
+use std::rc::Rc;
+
+use super::IoEventBackend;
+use crate::digest::{self, Digest, DigestData};
+use crate::errors::Result;
+use crate::status::StatusBackend;
+use crate::{ctry, tt_note, tt_warning};
+
+/// The outcome of a shell-escape request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ShellEscapeResult {
+    /// The command ran and exited with the given status code.
+    Executed(i32),
+
+    /// Shell escape is restricted and the command is not in the allowlist.
+    NotAllowed,
+
+    /// The command could not be run at all.
+    Failed,
+}
+
+/// The state needed to service `\write18` requests.
+///
+/// This type is cheap to clone: the memory-layer contents are shared by
+/// reference.
+#[derive(Clone, Debug)]
+pub struct ShellEscape {
+    work_dir: PathBuf,
+    files: Rc<RefCell<HashMap<OsString, Vec<u8>>>>,
+    allowed_commands: Option<Vec<String>>,
+}
+
+impl ShellEscape {
+    /// Create a new shell-escape handler.
+    ///
+    /// Commands will run in `work_dir`, which should be an empty, private
+    /// directory. The `files` map should be the `files` field of the
+    /// `MemoryIo` layer used by the engine. If `allowed_commands` is `Some`,
+    /// shell escape is restricted to the named programs.
+    pub fn new<P: AsRef<Path>>(
+        work_dir: P,
+        files: Rc<RefCell<HashMap<OsString, Vec<u8>>>>,
+        allowed_commands: Option<Vec<String>>,
+    ) -> ShellEscape {
+        ShellEscape {
+            work_dir: work_dir.as_ref().to_owned(),
+            files,
+            allowed_commands,
+        }
+    }
+
+    /// Returns true if only allowlisted commands may be run.
+    pub fn is_restricted(&self) -> bool {
+        self.allowed_commands.is_some()
+    }
+
+    /// Run a command on behalf of the engine.
+    ///
+    /// Files created or modified by the command are loaded into the memory
+    /// layer and reported to `events` as outputs, so that they are handled
+    /// just like files that the engine itself wrote. Files that the command
+    /// deleted are removed from the memory layer.
+    pub fn run(
+        &mut self,
+        command: &str,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+    ) -> ShellEscapeResult {
+        let mut cmd = match self.allowed_commands {
+            None => shell_command(command),
+
+            Some(ref allowed) => {
+                let argv = match split_command_line(command) {
+                    Some(a) if !a.is_empty() => a,
+                    _ => {
+                        tt_warning!(status, "cannot parse shell-escape command `{}`", command);
+                        return ShellEscapeResult::NotAllowed;
+                    }
+                };
+
+                if !allowed.iter().any(|a| *a == argv[0]) {
+                    tt_warning!(
+                        status,
+                        "refusing to run `{}`: shell escape is restricted and \"{}\" is not allowed",
+                        command,
+                        argv[0]
+                    );
+                    return ShellEscapeResult::NotAllowed;
+                }
+
+                let mut c = Command::new(&argv[0]);
+                c.args(&argv[1..]);
+                c
+            }
+        };
+
+        let before = match self.populate_work_dir() {
+            Ok(b) => b,
+            Err(e) => {
+                tt_warning!(status, "failed to set up the shell-escape working directory"; e);
+                return ShellEscapeResult::Failed;
+            }
+        };
+
+        tt_note!(status, "running shell-escape command `{}`", command);
+
+        let output = match cmd.current_dir(&self.work_dir).output() {
+            Ok(o) => o,
+            Err(e) => {
+                tt_warning!(status, "failed to run shell-escape command `{}`", command; e.into());
+                return ShellEscapeResult::Failed;
+            }
+        };
+
+        let code = exit_code(&output);
+
+        if code != 0 {
+            tt_warning!(
+                status,
+                "shell-escape command `{}` exited with status {}",
+                command,
+                code
+            );
+
+            if !output.stderr.is_empty() {
+                status.dump_error_logs(&output.stderr);
+            }
+        }
+
+        if let Err(e) = self.collect_outputs(&before, events) {
+            tt_warning!(status, "failed to gather the outputs of shell-escape command `{}`", command; e);
+            return ShellEscapeResult::Failed;
+        }
+
+        ShellEscapeResult::Executed(code)
+    }
+
+    /// Copy the memory layer into the working directory, returning the
+    /// memory-layer names and digests of the files that we wrote so that we
+    /// can later figure out what the command changed.
+    fn populate_work_dir(&self) -> Result<HashMap<PathBuf, (OsString, DigestData)>> {
+        let mut digests = HashMap::new();
+
+        // Start from a clean slate, so that leftovers from a previous command
+        // can't be mistaken for new outputs.
+        for entry in fs::read_dir(&self.work_dir)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        for (name, contents) in &*self.files.borrow() {
+            // Skip the stdout buffer and anything that would land outside of
+            // the working directory.
+            let rel = match safe_relative_path(name) {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let path = self.work_dir.join(&rel);
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut f =
+                ctry!(fs::File::create(&path); "couldn't open {} for writing", path.display());
+            f.write_all(contents)?;
+            digests.insert(rel, (name.clone(), digest_of(contents)));
+        }
+
+        Ok(digests)
+    }
+
+    /// Load new or modified files from the working directory into the memory
+    /// layer, and drop the files that are gone from it.
+    fn collect_outputs(
+        &self,
+        before: &HashMap<PathBuf, (OsString, DigestData)>,
+        events: &mut dyn IoEventBackend,
+    ) -> Result<()> {
+        let mut found = Vec::new();
+        walk_dir(&self.work_dir, Path::new(""), &mut found)?;
+
+        for (rel, (name, _)) in before {
+            if !found.contains(rel) {
+                self.files.borrow_mut().remove(name);
+            }
+        }
+
+        for rel in found {
+            let contents = fs::read(self.work_dir.join(&rel))?;
+            let digest = digest_of(&contents);
+
+            if before.get(&rel).map(|(_, d)| d) == Some(&digest) {
+                continue;
+            }
+
+            // TeX paths always use forward slashes.
+            let name = OsString::from(
+                rel.components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+            );
+
+            events.output_opened(&name);
+            self.files.borrow_mut().insert(name.clone(), contents);
+            events.output_closed(name, digest);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut c = Command::new("sh");
+    c.arg("-c").arg(command);
+    c
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut c = Command::new("cmd");
+    c.arg("/C").arg(command);
+    c
+}
+
+#[cfg(unix)]
+fn exit_code(output: &Output) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+
+    match output.status.code() {
+        Some(c) => c,
+        // Killed by a signal; follow the shell convention.
+        None => 128 + output.status.signal().unwrap_or(0),
+    }
+}
+
+#[cfg(not(unix))]
+fn exit_code(output: &Output) -> i32 {
+    output.status.code().unwrap_or(1)
+}
+
+fn digest_of(data: &[u8]) -> DigestData {
+    let mut dc = digest::create();
+    dc.input(data);
+    DigestData::from(dc)
+}
+
+/// Convert a memory-layer file name into a path relative to the working
+/// directory, or None if it isn't something we should materialize.
+fn safe_relative_path(name: &OsStr) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+
+    let path = Path::new(name);
+    let mut rel = PathBuf::new();
+
+    for c in path.components() {
+        match c {
+            Component::Normal(p) => rel.push(p),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+
+    if rel.as_os_str().is_empty() {
+        None
+    } else {
+        Some(rel)
+    }
+}
+
+fn walk_dir(root: &Path, rel: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(rel))? {
+        let entry = entry?;
+        let child = rel.join(entry.file_name());
+        let ftype = entry.file_type()?;
+
+        if ftype.is_dir() {
+            walk_dir(root, &child, found)?;
+        } else if ftype.is_file() {
+            found.push(child);
+        }
+    }
+
+    Ok(())
+}
+
+/// Split a command line into words, honoring single and double quotes and
+/// backslash escapes in the manner of a POSIX shell. Returns None if the
+/// quoting is unbalanced.
+fn split_command_line(text: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut cur = String::new();
+    let mut in_word = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => cur.push(c),
+                    }
+                }
+            }
+
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => cur.push(chars.next()?),
+                        c => cur.push(c),
+                    }
+                }
+            }
+
+            '\\' => {
+                in_word = true;
+                cur.push(chars.next()?);
+            }
+
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut cur));
+                    in_word = false;
+                }
+            }
+
+            c => {
+                in_word = true;
+                cur.push(c);
+            }
+        }
+    }
+
+    if in_word {
+        words.push(cur);
+    }
+
+    Some(words)
+}
+
+/// Parse a comma-separated allowlist of command names, as given to
+/// `-Z shell-escape-commands`.
+pub fn parse_allowed_commands(text: &str) -> Result<Vec<String>> {
+    let cmds: Vec<String> = text
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_owned())
+        .collect();
+
+    if cmds.is_empty() {
+        return Err("the shell-escape command allowlist is empty".into());
+    }
+
+    Ok(cmds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_simple() {
+        assert_eq!(
+            split_command_line("pygmentize -l latex -o out.pygtex in.pyg"),
+            Some(vec![
+                "pygmentize".to_owned(),
+                "-l".to_owned(),
+                "latex".to_owned(),
+                "-o".to_owned(),
+                "out.pygtex".to_owned(),
+                "in.pyg".to_owned(),
+            ])
+        );
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(
+            split_command_line(r#"gnuplot "my plot.gnuplot" 'a;b' c\ d"#),
+            Some(vec![
+                "gnuplot".to_owned(),
+                "my plot.gnuplot".to_owned(),
+                "a;b".to_owned(),
+                "c d".to_owned(),
+            ])
+        );
+        assert_eq!(split_command_line("''"), Some(vec!["".to_owned()]));
+        assert_eq!(split_command_line("echo \"unbalanced"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn deleted_files_leave_memory() {
+        use crate::engines::NoopIoEventBackend;
+        use crate::status::NoopStatusBackend;
+
+        let work = tempfile::tempdir().unwrap();
+        let files = Rc::new(RefCell::new(HashMap::new()));
+        files
+            .borrow_mut()
+            .insert(OsString::from("gone.txt"), b"bye".to_vec());
+        files
+            .borrow_mut()
+            .insert(OsString::from("kept.txt"), b"hi".to_vec());
+
+        let mut se = ShellEscape::new(work.path(), files.clone(), None);
+        let result = se.run(
+            "rm gone.txt && echo new > made.txt",
+            &mut NoopIoEventBackend::new(),
+            &mut NoopStatusBackend::new(),
+        );
+
+        assert_eq!(result, ShellEscapeResult::Executed(0));
+        let files = files.borrow();
+        assert!(!files.contains_key(OsStr::new("gone.txt")));
+        assert_eq!(files[OsStr::new("kept.txt")], b"hi");
+        assert_eq!(files[OsStr::new("made.txt")], b"new\n");
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(
+            safe_relative_path(OsStr::new("sub/file.tex")),
+            Some(PathBuf::from("sub/file.tex"))
+        );
+        assert_eq!(safe_relative_path(OsStr::new("")), None);
+        assert_eq!(safe_relative_path(OsStr::new("../escape.tex")), None);
+        assert_eq!(safe_relative_path(OsStr::new("/etc/passwd")), None);
+    }
+}
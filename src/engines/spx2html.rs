@@ -1,20 +1,36 @@
-// Copyright 2018 the Tectonic Project
+// Copyright 2018-2020 the Tectonic Project
 // Licensed under the MIT License.
 
 //! Convert Tectonic’s SPX format to HTML
 //!
 //! Yay, an engine actually written in pure Rust!
+//!
+//! The SPX file tells us which characters were set, in which fonts, and
+//! where. We turn that back into running text: characters that share a
+//! baseline form a line, and lines separated by no more than the usual
+//! baseline skip form a paragraph. A word hyphenated across a line break is
+//! joined back together. Each paragraph becomes a `<p>` element, and runs of
+//! text in the same font become `<span>`s whose classes are defined in a
+//! companion CSS file.
 
+use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::fmt::Write as FmtWrite;
 use std::io::Write;
-use tectonic_xdv::{FileType, XdvEvents, XdvParser};
+use tectonic_xdv::{FileType, FontNum, XdvEvents, XdvParser};
 
 use super::IoEventBackend;
 use crate::errors::{Error, Result};
-use crate::io::{IoProvider, IoStack, OpenResult, OutputHandle};
+use crate::io::{IoProvider, IoStack, OpenResult};
 use crate::status::StatusBackend;
 use crate::{errmsg, tt_warning};
 
+/// The number of SPX units (TeX scaled points) in a TeX point.
+const SP_PER_PT: f64 = 65536.;
+
+/// The font size we assume when text is set without a known font.
+const DEFAULT_FONT_SIZE: i32 = 10 * 65536;
+
 #[derive(Default)]
 pub struct Spx2HtmlEngine {}
 
@@ -31,81 +47,307 @@ impl Spx2HtmlEngine {
         spx: &str,
     ) -> Result<()> {
         let mut input = io.input_open_name(OsStr::new(spx), status).must_exist()?;
-        events.input_opened(input.name(), input.origin());
+        events.input_opened(input.name(), input.origin(), input.path());
 
         // FIXME? The engine should probably be responsible for choosing this.
-        let outname = {
-            let mut s = spx.to_owned();
-
-            if spx.ends_with(".spx") {
-                s.truncate(s.len() - 4);
-            }
-
-            s.push_str(".html");
-            s
+        let stem = if let Some(stem) = spx.strip_suffix(".spx") {
+            stem
+        } else {
+            spx
         };
 
-        {
-            let state = State::new(outname, io, events, status);
-            let (state, _n_bytes) = XdvParser::process(&mut input, state)?;
-            state.finished();
-        }
+        let (state, _n_bytes) = XdvParser::process(&mut input, State::new(status))?;
+        let (html, css) = state.finish(stem);
 
         let (name, digest_opt) = input.into_name_digest();
         events.input_closed(name, digest_opt);
 
+        write_output(io, events, &format!("{}.css", stem), css.as_bytes())?;
+        write_output(io, events, &format!("{}.html", stem), html.as_bytes())?;
         Ok(())
     }
 }
 
-struct State<'a, 'b: 'a> {
-    outname: String,
-    io: &'a mut IoStack<'b>,
-    events: &'a mut dyn IoEventBackend,
+fn write_output(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    name: &str,
+    contents: &[u8],
+) -> Result<()> {
+    let mut handle = match io.output_open_name(OsStr::new(name)) {
+        OpenResult::Ok(h) => h,
+        OpenResult::NotAvailable => {
+            return Err(errmsg!("no way to write output file \"{}\"", name));
+        }
+        OpenResult::Err(e) => {
+            return Err(e);
+        }
+    };
+
+    events.output_opened(OsStr::new(name));
+    handle.write_all(contents)?;
+    let (name, digest) = handle.into_name_digest();
+    events.output_closed(name, digest);
+    Ok(())
+}
+
+/// How the character codes of a font map onto Unicode.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum FontEncoding {
+    /// Native fonts, and traditional fonts we don't know any better about.
+    Unicode,
+
+    /// Knuth’s original 7-bit text encoding, used by Computer Modern.
+    Ot1,
+
+    /// The typewriter variant of OT1, which has ASCII in the printable range.
+    Ot1Typewriter,
+
+    /// The 8-bit “Cork” encoding, used by EC and Latin Modern fonts.
+    T1,
+}
+
+/// The CSS-relevant properties of a font.
+#[derive(Clone, Debug, PartialEq)]
+struct FontStyle {
+    family: String,
+    size: i32,
+    bold: bool,
+    italic: bool,
+}
+
+impl FontStyle {
+    fn css(&self) -> String {
+        format!(
+            "font-family: {}; font-size: {}pt; font-weight: {}; font-style: {};",
+            self.family,
+            format_pt(self.size),
+            if self.bold { "bold" } else { "normal" },
+            if self.italic { "italic" } else { "normal" }
+        )
+    }
+}
+
+struct FontInfo {
+    encoding: FontEncoding,
+    /// The index of this font’s style in `State::styles`.
+    style: usize,
+}
+
+/// A run of text in a single style.
+struct Span {
+    style: Option<usize>,
+    text: String,
+}
+
+struct Line {
+    v: i32,
+    spans: Vec<Span>,
+}
+
+struct State<'a> {
     status: &'a mut dyn StatusBackend,
-    cur_output: Option<OutputHandle>,
-    warned_lost_chars: bool,
-    buf: Vec<u8>,
-}
-
-impl<'a, 'b: 'a> State<'a, 'b> {
-    pub fn new(
-        outname: String,
-        io: &'a mut IoStack<'b>,
-        events: &'a mut dyn IoEventBackend,
-        status: &'a mut dyn StatusBackend,
-    ) -> Self {
+    fonts: HashMap<FontNum, FontInfo>,
+    styles: Vec<FontStyle>,
+    paragraphs: Vec<Vec<Line>>,
+    cur_paragraph: Vec<Line>,
+    cur_line: Option<Line>,
+    /// The size of the most recently used font; this sets the scale for
+    /// deciding what counts as a word space or a line break.
+    cur_size: i32,
+    /// Where the last glyph run ended, if we know. This isn’t known after
+    /// text in traditional fonts, because we don't have their metrics.
+    last_end_h: Option<i32>,
+    pending_space: bool,
+    pending_accent: Option<char>,
+    warned_lost_glyphs: bool,
+}
+
+impl<'a> State<'a> {
+    pub fn new(status: &'a mut dyn StatusBackend) -> Self {
         Self {
-            outname,
-            io,
-            events,
             status,
-            cur_output: None,
-            warned_lost_chars: false,
-            buf: Vec::new(),
+            fonts: HashMap::new(),
+            styles: Vec::new(),
+            paragraphs: Vec::new(),
+            cur_paragraph: Vec::new(),
+            cur_line: None,
+            cur_size: DEFAULT_FONT_SIZE,
+            last_end_h: None,
+            pending_space: false,
+            pending_accent: None,
+            warned_lost_glyphs: false,
         }
     }
-}
 
-#[inline(always)]
-fn as_printable_ascii(c: i32) -> Option<u8> {
-    if c > 0x20 && c < 0x7F {
-        Some(c as u8)
-    } else {
-        None
+    fn add_font(&mut self, font_num: FontNum, encoding: FontEncoding, style: FontStyle) {
+        // Fonts are defined again in the postamble.
+        if self.fonts.contains_key(&font_num) {
+            return;
+        }
+
+        let style = match self.styles.iter().position(|s| *s == style) {
+            Some(i) => i,
+            None => {
+                self.styles.push(style);
+                self.styles.len() - 1
+            }
+        };
+
+        self.fonts.insert(font_num, FontInfo { encoding, style });
     }
-}
 
-impl<'a, 'b: 'a> State<'a, 'b> {
-    pub fn finished(self) {
-        if let Some(oh) = self.cur_output {
-            let (name, digest) = oh.into_name_digest();
-            self.events.output_closed(name, digest);
+    /// Add some text to the output, set in the given font with its starting
+    /// point at the given position.
+    fn add_text(&mut self, font_num: Option<FontNum>, h: i32, v: i32, text: &str) {
+        let (style, size) = match font_num.and_then(|n| self.fonts.get(&n)) {
+            Some(f) => (Some(f.style), self.styles[f.style].size),
+            None => (None, self.cur_size),
+        };
+
+        let same_line = match self.cur_line {
+            // Sub- and superscripts are shifted by less than this.
+            Some(ref l) => (v - l.v).abs() < size * 6 / 10,
+            None => false,
+        };
+
+        if !same_line {
+            self.start_line(v, size);
+        } else if let Some(end) = self.last_end_h {
+            if h - end > size / 5 {
+                self.pending_space = true;
+            }
+        }
+
+        self.cur_size = size;
+        let line = self.cur_line.as_mut().unwrap();
+
+        if self.pending_space && !line.spans.is_empty() {
+            push_text(&mut line.spans, style, " ");
         }
+
+        self.pending_space = false;
+        push_text(&mut line.spans, style, text);
+    }
+
+    /// Finish the current line and start a new one at the given baseline,
+    /// deciding whether this also starts a new paragraph.
+    fn start_line(&mut self, v: i32, size: i32) {
+        let prev = match self.cur_line.take() {
+            Some(l) => l,
+            None => {
+                self.cur_line = Some(Line {
+                    v,
+                    spans: Vec::new(),
+                });
+                return;
+            }
+        };
+
+        let gap = v - prev.v;
+
+        // Estimate the baseline skip from the lines of this paragraph seen
+        // so far; otherwise fall back on the usual 120% of the font size.
+        let skip = self
+            .cur_paragraph
+            .windows(2)
+            .map(|w| w[1].v - w[0].v)
+            .chain(if self.cur_paragraph.is_empty() {
+                None
+            } else {
+                Some(prev.v - self.cur_paragraph.last().unwrap().v)
+            })
+            .filter(|g| *g > 0)
+            .min()
+            .unwrap_or(size * 12 / 10);
+
+        self.cur_paragraph.push(prev);
+
+        // Moving back up means a new column or page.
+        if gap <= 0 || gap > skip * 3 / 2 {
+            self.end_paragraph();
+        }
+
+        self.cur_line = Some(Line {
+            v,
+            spans: Vec::new(),
+        });
+        self.last_end_h = None;
+    }
+
+    fn end_paragraph(&mut self) {
+        if let Some(l) = self.cur_line.take() {
+            self.cur_paragraph.push(l);
+        }
+
+        self.cur_paragraph.retain(|l| !l.spans.is_empty());
+
+        if !self.cur_paragraph.is_empty() {
+            let p = std::mem::take(&mut self.cur_paragraph);
+            self.paragraphs.push(join_hyphenated_lines(p));
+        }
+
+        self.last_end_h = None;
+        self.pending_space = false;
+    }
+
+    /// Consume the state and produce the HTML and CSS output.
+    fn finish(mut self, stem: &str) -> (String, String) {
+        self.end_paragraph();
+
+        let basename = stem.rsplit('/').next().unwrap_or(stem);
+        let mut css = String::new();
+
+        css.push_str("body {\n  max-width: 40em;\n  margin: 2em auto;\n  line-height: 1.2;\n}\n");
+
+        for (i, style) in self.styles.iter().enumerate() {
+            writeln!(css, ".f{} {{ {} }}", i, style.css()).unwrap();
+        }
+
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        writeln!(html, "<title>{}</title>", escape_html(basename)).unwrap();
+        writeln!(
+            html,
+            "<link rel=\"stylesheet\" href=\"{}.css\">",
+            escape_html(basename)
+        )
+        .unwrap();
+        html.push_str("</head>\n<body>\n");
+
+        for para in &self.paragraphs {
+            html.push_str("<p>");
+
+            for (i, line) in para.iter().enumerate() {
+                if i > 0 {
+                    html.push('\n');
+                }
+
+                for span in &line.spans {
+                    match span.style {
+                        Some(s) => {
+                            write!(
+                                html,
+                                "<span class=\"f{}\">{}</span>",
+                                s,
+                                escape_html(&span.text)
+                            )
+                            .unwrap();
+                        }
+                        None => html.push_str(&escape_html(&span.text)),
+                    }
+                }
+            }
+
+            html.push_str("</p>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+        (html, css)
     }
 }
 
-impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
+impl<'a> XdvEvents for State<'a> {
     type Error = Error;
 
     fn handle_header(&mut self, filetype: FileType, _comment: &[u8]) -> Result<()> {
@@ -113,50 +355,486 @@ impl<'a, 'b: 'a> XdvEvents for State<'a, 'b> {
             return Err(errmsg!("file should be SPX format but got {}", filetype));
         }
 
-        self.cur_output = Some(match self.io.output_open_name(OsStr::new(&self.outname)) {
-            OpenResult::Ok(h) => h,
-            OpenResult::NotAvailable => {
-                return Err(errmsg!("no way to write output file \"{}\"", self.outname));
-            }
-            OpenResult::Err(e) => {
-                return Err(e);
-            }
-        });
+        Ok(())
+    }
 
-        self.events.output_opened(OsStr::new(&self.outname));
+    fn handle_begin_page(&mut self, _counters: &[i32], _previous_bop: i32) -> Result<()> {
+        self.end_paragraph();
+        Ok(())
+    }
 
+    fn handle_define_font(
+        &mut self,
+        font_num: FontNum,
+        _area: &str,
+        name: &str,
+        scale_factor: u32,
+        _design_size: u32,
+    ) -> Result<()> {
+        let (encoding, family, bold, italic) = tfm_font_style(name);
+        self.add_font(
+            font_num,
+            encoding,
+            FontStyle {
+                family: family.to_owned(),
+                size: scale_factor as i32,
+                bold,
+                italic,
+            },
+        );
         Ok(())
     }
 
-    fn handle_char_run(&mut self, chars: &[i32]) -> Result<()> {
-        let dest = match self.cur_output {
-            Some(ref mut h) => h,
-            None => {
-                if !self.warned_lost_chars {
-                    tt_warning!(
-                        self.status,
-                        "losing characters in SPX file: no current output"
-                    );
-                    self.warned_lost_chars = true;
+    fn handle_define_native_font(
+        &mut self,
+        font_num: FontNum,
+        name: &str,
+        size: i32,
+        _face_index: u32,
+        _color_rgba: Option<u32>,
+        _extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<()> {
+        let (family, bold, italic) = native_font_style(name);
+        self.add_font(
+            font_num,
+            FontEncoding::Unicode,
+            FontStyle {
+                family,
+                size,
+                bold: bold || embolden.is_some(),
+                italic: italic || slant.is_some(),
+            },
+        );
+        Ok(())
+    }
+
+    fn handle_horizontal_move(&mut self, delta: i32) -> Result<()> {
+        if delta > self.cur_size / 5 {
+            self.pending_space = true;
+        }
+
+        Ok(())
+    }
+
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: Option<FontNum>,
+        h: i32,
+        v: i32,
+        chars: &[i32],
+    ) -> Result<()> {
+        let encoding = font_num
+            .and_then(|n| self.fonts.get(&n))
+            .map_or(FontEncoding::Unicode, |f| f.encoding);
+
+        let mut text = String::new();
+
+        for &c in chars {
+            match decode_char(encoding, c) {
+                Decoded::Text(s) => {
+                    text.push_str(s);
+
+                    if let Some(a) = self.pending_accent.take() {
+                        text.push(a);
+                    }
                 }
+                Decoded::Char(ch) => {
+                    text.push(ch);
 
-                return Ok(());
+                    if let Some(a) = self.pending_accent.take() {
+                        text.push(a);
+                    }
+                }
+                // TeX's \accent sets the accent before the character that
+                // it modifies; Unicode wants the combining mark after it.
+                Decoded::Accent(a) => self.pending_accent = Some(a),
+                Decoded::Nothing => {}
             }
-        };
+        }
+
+        if !text.is_empty() {
+            self.add_text(font_num, h, v, &text);
+        }
+
+        // We don't know how wide the characters were.
+        self.last_end_h = None;
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: Option<FontNum>,
+        h: i32,
+        v: i32,
+        text: &str,
+        width: i32,
+        _glyphs: &[u16],
+        _x: &[i32],
+        _y: &[i32],
+    ) -> Result<()> {
+        self.add_text(font_num, h, v, text);
+        self.last_end_h = Some(h + width);
+        Ok(())
+    }
 
-        self.buf.clear();
+    fn handle_glyph_run(
+        &mut self,
+        _font_num: Option<FontNum>,
+        _h: i32,
+        _v: i32,
+        _width: i32,
+        _glyphs: &[u16],
+        _x: &[i32],
+        _y: &[i32],
+    ) -> Result<()> {
+        // Without the font's character map, we can't tell what text the
+        // glyphs represent.
+        if !self.warned_lost_glyphs {
+            tt_warning!(
+                self.status,
+                "some text in native fonts could not be converted to HTML; \
+                 set \\XeTeXgenerateactualtext=1 to preserve it"
+            );
+            self.warned_lost_glyphs = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// Rejoin words that TeX hyphenated at a line break. A line ending in a
+/// letter and a hyphen, followed by one starting with a lowercase letter, is
+/// taken to be a broken word; anything else, such as a dash before a capital
+/// or a digit, keeps its line break.
+fn join_hyphenated_lines(lines: Vec<Line>) -> Vec<Line> {
+    let mut joined: Vec<Line> = Vec::with_capacity(lines.len());
+
+    for line in lines {
+        if let Some(prev) = joined.last_mut() {
+            let first = line.spans.first().and_then(|s| s.text.chars().next());
+            let starts_lower = matches!(first, Some(c) if c.is_lowercase());
+
+            if starts_lower {
+                if let Some(last) = prev.spans.last_mut() {
+                    let mut tail = last.text.chars().rev();
+
+                    if tail.next() == Some('-')
+                        && matches!(tail.next(), Some(c) if c.is_alphabetic())
+                    {
+                        last.text.pop();
+
+                        for span in line.spans {
+                            push_text(&mut prev.spans, span.style, &span.text);
+                        }
 
-        for c in chars.iter() {
-            if let Some(b) = as_printable_ascii(*c) {
-                self.buf.push(b);
+                        continue;
+                    }
+                }
             }
         }
 
-        if !self.buf.is_empty() {
-            self.buf.push(0x0a); // newline
-            dest.write_all(&self.buf)?;
+        joined.push(line);
+    }
+
+    joined
+}
+
+fn push_text(spans: &mut Vec<Span>, style: Option<usize>, text: &str) {
+    if let Some(last) = spans.last_mut() {
+        if last.style == style {
+            last.text.push_str(text);
+            return;
         }
+    }
 
-        Ok(())
+    spans.push(Span {
+        style,
+        text: text.to_owned(),
+    });
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn format_pt(sp: i32) -> String {
+    let pt = f64::from(sp) / SP_PER_PT;
+    let s = format!("{:.2}", pt);
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+/// Guess the encoding and style of a traditional TeX font from its name,
+/// such as `cmbx12` or `ec-lmri10`.
+fn tfm_font_style(name: &str) -> (FontEncoding, &'static str, bool, bool) {
+    let (encoding, base) = if let Some(base) = name
+        .strip_prefix("ec-")
+        .or_else(|| name.strip_prefix("t1-"))
+    {
+        (FontEncoding::T1, base)
+    } else if let Some(base) = name.strip_prefix("rm-") {
+        (FontEncoding::Ot1, base)
+    } else if name.starts_with("ec") || name.starts_with("tc") {
+        (FontEncoding::T1, name)
+    } else if name.starts_with("cm") || name.starts_with("lm") {
+        (FontEncoding::Ot1, name)
+    } else {
+        (FontEncoding::Unicode, name)
+    };
+
+    // Drop the design size, and the foundry prefix that starts the name.
+    let shape = base.trim_end_matches(|c: char| c.is_ascii_digit());
+    let shape = if shape.len() > 2 { &shape[2..] } else { "" };
+
+    let typewriter = shape.contains("tt");
+    let sans = shape.starts_with("ss");
+    let encoding = if typewriter && encoding == FontEncoding::Ot1 {
+        FontEncoding::Ot1Typewriter
+    } else {
+        encoding
+    };
+
+    let family = if typewriter {
+        "\"Latin Modern Mono\", monospace"
+    } else if sans {
+        "\"Latin Modern Sans\", sans-serif"
+    } else {
+        "\"Latin Modern Roman\", serif"
+    };
+
+    let bold = shape.contains('b');
+    let italic = shape.contains("ti")
+        || shape.contains("it")
+        || shape.contains("sl")
+        || shape.ends_with('i');
+
+    (encoding, family, bold, italic)
+}
+
+/// Guess the family and style of a native font from its name, which is
+/// usually a filename like `lmroman10-bolditalic.otf`.
+fn native_font_style(name: &str) -> (String, bool, bool) {
+    // Names may be bracketed, and may carry XeTeX feature options.
+    let name = name.trim_start_matches('[');
+    let name = name.split([']', ':']).next().unwrap_or(name);
+    let name = name.rsplit('/').next().unwrap_or(name);
+    let name = match name.rfind('.') {
+        Some(i) if i > 0 => &name[..i],
+        _ => name,
+    };
+
+    let (family, variant) = match name.find('-') {
+        Some(i) => (&name[..i], name[i + 1..].to_lowercase()),
+        None => (name, String::new()),
+    };
+
+    let bold = variant.contains("bold") || variant.contains("black") || variant.contains("heavy");
+    let italic = variant.contains("italic") || variant.contains("oblique");
+
+    (format!("\"{}\"", family.replace('"', "")), bold, italic)
+}
+
+enum Decoded {
+    Char(char),
+    Text(&'static str),
+    Accent(char),
+    Nothing,
+}
+
+/// Map a character code in a traditional font to Unicode.
+fn decode_char(encoding: FontEncoding, c: i32) -> Decoded {
+    use self::Decoded::*;
+
+    let ch = match std::char::from_u32(c as u32) {
+        Some(ch) => ch,
+        None => return Nothing,
+    };
+
+    match encoding {
+        FontEncoding::Unicode => {
+            if c < 0x20 {
+                Nothing
+            } else {
+                Char(ch)
+            }
+        }
+
+        FontEncoding::Ot1 | FontEncoding::Ot1Typewriter => match c {
+            0x0B => Text("ff"),
+            0x0C => Text("fi"),
+            0x0D => Text("fl"),
+            0x0E => Text("ffi"),
+            0x0F => Text("ffl"),
+            0x10 => Char('ı'),
+            0x11 => Char('ȷ'),
+            0x12 => Accent('\u{300}'),
+            0x13 => Accent('\u{301}'),
+            0x14 => Accent('\u{30C}'),
+            0x15 => Accent('\u{306}'),
+            0x16 => Accent('\u{304}'),
+            0x17 => Accent('\u{30A}'),
+            0x18 => Accent('\u{327}'),
+            0x19 => Char('ß'),
+            0x1A => Char('æ'),
+            0x1B => Char('œ'),
+            0x1C => Char('ø'),
+            0x1D => Char('Æ'),
+            0x1E => Char('Œ'),
+            0x1F => Char('Ø'),
+            _ if c < 0x20 => Nothing,
+            _ if encoding == FontEncoding::Ot1Typewriter => Char(ch),
+            0x22 => Char('”'),
+            0x3C => Char('¡'),
+            0x3E => Char('¿'),
+            0x5C => Char('“'),
+            0x5E => Accent('\u{302}'),
+            0x5F => Accent('\u{307}'),
+            0x7B => Char('–'),
+            0x7C => Char('—'),
+            0x7D => Accent('\u{30B}'),
+            0x7E => Accent('\u{303}'),
+            0x7F => Accent('\u{308}'),
+            _ => Char(ch),
+        },
+
+        FontEncoding::T1 => match c {
+            0x00 => Accent('\u{300}'),
+            0x01 => Accent('\u{301}'),
+            0x02 => Accent('\u{302}'),
+            0x03 => Accent('\u{303}'),
+            0x04 => Accent('\u{308}'),
+            0x05 => Accent('\u{30B}'),
+            0x06 => Accent('\u{30A}'),
+            0x07 => Accent('\u{30C}'),
+            0x08 => Accent('\u{306}'),
+            0x09 => Accent('\u{304}'),
+            0x0A => Accent('\u{307}'),
+            0x0B => Accent('\u{327}'),
+            0x0C => Accent('\u{328}'),
+            0x0D => Char('‚'),
+            0x0E => Char('‹'),
+            0x0F => Char('›'),
+            0x10 => Char('“'),
+            0x11 => Char('”'),
+            0x12 => Char('„'),
+            0x13 => Char('«'),
+            0x14 => Char('»'),
+            0x15 => Char('–'),
+            0x16 => Char('—'),
+            0x19 => Char('ı'),
+            0x1A => Char('ȷ'),
+            0x1B => Text("ff"),
+            0x1C => Text("fi"),
+            0x1D => Text("fl"),
+            0x1E => Text("ffi"),
+            0x1F => Text("ffl"),
+            _ if c < 0x20 => Nothing,
+            0x7F => Char('-'),
+            0xD7 => Char('Œ'),
+            0xDF => Text("SS"),
+            0xF7 => Char('œ'),
+            0xFF => Char('ß'),
+            // The rest of the upper half matches Latin-1 closely enough.
+            _ => Char(ch),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::NoopStatusBackend;
+
+    const PT: i32 = 65536;
+
+    /// Set each line of text at a 12pt baseline skip, starting a new
+    /// paragraph after each empty line, and return the paragraphs of HTML.
+    fn reconstruct(lines: &[&str]) -> Vec<String> {
+        let mut status = NoopStatusBackend::default();
+        let mut state = State::new(&mut status);
+        let mut v = 0;
+
+        for text in lines {
+            v += 12 * PT;
+
+            if text.is_empty() {
+                v += 12 * PT;
+            } else {
+                state.add_text(None, 0, v, text);
+            }
+        }
+
+        let (html, _css) = state.finish("test");
+        let body = &html[html.find("<body>\n").unwrap() + 7..html.find("</body>").unwrap()];
+        body.split_terminator("</p>\n")
+            .map(|p| p.trim_start_matches("<p>").to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn paragraph_breaks() {
+        assert_eq!(
+            reconstruct(&["First line", "second line.", "", "Next paragraph."]),
+            vec!["First line\nsecond line.", "Next paragraph."]
+        );
+    }
+
+    #[test]
+    fn hyphenated_lines() {
+        assert_eq!(
+            reconstruct(&[
+                "A hyphen-",
+                "ated word and a",
+                "dash-",
+                "Then a new sentence."
+            ]),
+            vec!["A hyphenated word and a\ndash-\nThen a new sentence."]
+        );
+        assert_eq!(
+            reconstruct(&["page 12-", "13 and", "end-", "", "of paragraph."]),
+            vec!["page 12-\n13 and\nend-", "of paragraph."]
+        );
+    }
+
+    #[test]
+    fn tfm_styles() {
+        let (enc, family, bold, italic) = tfm_font_style("cmbx12");
+        assert_eq!(enc, FontEncoding::Ot1);
+        assert!(family.ends_with("serif"));
+        assert!(bold && !italic);
+
+        let (enc, family, bold, italic) = tfm_font_style("cmtt10");
+        assert_eq!(enc, FontEncoding::Ot1Typewriter);
+        assert!(family.ends_with("monospace"));
+        assert!(!bold && !italic);
+
+        let (enc, _, bold, italic) = tfm_font_style("ec-lmri10");
+        assert_eq!(enc, FontEncoding::T1);
+        assert!(!bold && italic);
+    }
+
+    #[test]
+    fn native_styles() {
+        assert_eq!(
+            native_font_style("[lmroman10-bolditalic.otf]:mapping=tex-text;"),
+            ("\"lmroman10\"".to_owned(), true, true)
+        );
+        assert_eq!(
+            native_font_style("/fonts/SourceSansPro-Regular.otf"),
+            ("\"SourceSansPro\"".to_owned(), false, false)
+        );
     }
 }
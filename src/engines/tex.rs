@@ -2,6 +2,7 @@
 // Copyright 2017-2018 the Tectonic Project
 // Licensed under the MIT License.
 
+use super::shell_escape::ShellEscape;
 use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
 use crate::errors::{DefinitelySame, ErrorKind, Result};
 use crate::io::IoStack;
@@ -31,18 +32,26 @@ pub struct TexEngine {
     // One day, the engine will hold its own state. For the time being,
     // though, it's just a proxy for the global constants in the C code.
     halt_on_error: bool,
+    file_line_errors: bool,
     initex_mode: bool,
+    initex_load_format: bool,
     synctex_enabled: bool,
     semantic_pagination_enabled: bool,
+    shell_escape: Option<ShellEscape>,
+    source_date_epoch: Option<u64>,
 }
 
 impl Default for TexEngine {
     fn default() -> Self {
         TexEngine {
             halt_on_error: true,
+            file_line_errors: false,
             initex_mode: false,
+            initex_load_format: false,
             synctex_enabled: false,
             semantic_pagination_enabled: false,
+            shell_escape: None,
+            source_date_epoch: None,
         }
     }
 }
@@ -57,6 +66,14 @@ impl TexEngine {
         self
     }
 
+    /// Configure the engine to prefix error messages with the file name and
+    /// line number at which they occurred, in the style `file:line: message`,
+    /// rather than with TeX's traditional `!`.
+    pub fn file_line_errors(&mut self, enabled: bool) -> &mut Self {
+        self.file_line_errors = enabled;
+        self
+    }
+
     /// Configure the engine to run in "initex" mode, in which it generates a
     /// "format" file that serializes the engine state rather than a PDF
     /// document.
@@ -65,6 +82,14 @@ impl TexEngine {
         self
     }
 
+    /// Configure the engine to load the format file even in initex mode, so
+    /// that the format that it generates builds on the existing one. This is
+    /// how a document's preamble can be precompiled.
+    pub fn initex_load_format(&mut self, load: bool) -> &mut Self {
+        self.initex_load_format = load;
+        self
+    }
+
     /// Configure the engine to produce SyncTeX data.
     pub fn synctex(&mut self, synctex_enabled: bool) -> &mut Self {
         self.synctex_enabled = synctex_enabled;
@@ -86,6 +111,22 @@ impl TexEngine {
         self
     }
 
+    /// Enable `\write18` shell escape, using the given handler to run
+    /// commands. Passing `None` disables shell escape, which is the default.
+    pub fn shell_escape(&mut self, handler: Option<ShellEscape>) -> &mut Self {
+        self.shell_escape = handler;
+        self
+    }
+
+    /// Fix the date and time that TeX reports through `\time`, `\day`,
+    /// `\month` and `\year`, in seconds since the epoch. If unset, they come
+    /// from the `SOURCE_DATE_EPOCH` environment variable if it is set, and
+    /// the clock otherwise.
+    pub fn source_date_epoch(&mut self, epoch: Option<u64>) -> &mut Self {
+        self.source_date_epoch = epoch;
+        self
+    }
+
     // This function can't be generic across the IoProvider trait, for now,
     // since the global pointer that stashes the ExecutionState must have a
     // complete type.
@@ -101,15 +142,23 @@ impl TexEngine {
     ) -> Result<TexResult> {
         let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
 
-        let /*mut*/ state = ExecutionState::new(io, events, status);
+        let /*mut*/ state =
+            ExecutionState::new(io, events, status).with_shell_escape(self.shell_escape.clone());
         let bridge = TectonicBridgeApi::new(&state);
 
         // initialize globals
 
-        let v = if unstables.shell_escape { 1 } else { 0 };
+        let v = if self.shell_escape.is_some() { 1 } else { 0 };
         unsafe {
             super::tt_xetex_set_int_variable("shell_escape_enabled", v);
         }
+        let v = match self.shell_escape {
+            Some(ref h) if h.is_restricted() => 1,
+            _ => 0,
+        };
+        unsafe {
+            super::tt_xetex_set_int_variable("shell_escape_restricted", v);
+        }
 
         let mut halt_on_error = self.halt_on_error;
         if unstables.continue_on_errors {
@@ -120,10 +169,19 @@ impl TexEngine {
             super::tt_xetex_set_int_variable("halt_on_error_p", v);
         }
 
+        let v = if self.file_line_errors { 1 } else { 0 };
+        unsafe {
+            super::tt_xetex_set_int_variable("file_line_error_style_p", v);
+        }
+
         let v = if self.initex_mode { 1 } else { 0 };
         unsafe {
             super::tt_xetex_set_int_variable("in_initex_mode", v);
         }
+        let v = if self.initex_load_format { 1 } else { 0 };
+        unsafe {
+            super::tt_xetex_set_int_variable("initex_load_format", v);
+        }
         let v = if self.synctex_enabled { 1 } else { 0 };
         unsafe {
             super::tt_xetex_set_int_variable("synctex_enabled", v);
@@ -136,6 +194,9 @@ impl TexEngine {
         unsafe {
             super::tt_xetex_set_int_variable("semantic_pagination_enabled", v);
         }
+        unsafe {
+            super::tt_xetex_set_source_date_epoch(self.source_date_epoch);
+        }
 
         unsafe {
             match super::tex_simple_main(&*bridge, format_file_name, input_file_name) {
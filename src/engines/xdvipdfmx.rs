@@ -2,15 +2,335 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
+use std::convert::TryFrom;
+use std::ops::BitOr;
+use std::result::Result as StdResult;
+use std::str::FromStr;
+
 use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
-use crate::errors::{ErrorKind, Result};
+use crate::errmsg;
+use crate::errors::{Error, ErrorKind, Result};
 use crate::io::IoStack;
 use crate::status::StatusBackend;
 use crate::unstable_opts::UnstableOptions;
 
+/// A range of pages to include in the output PDF.
+///
+/// Pages are numbered from 1, following the physical page order of the
+/// document. If `last` is None, the range extends to the end of the document.
+/// If `last` is smaller than `first`, the pages are emitted in reverse order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PageRange {
+    pub first: u32,
+    pub last: Option<u32>,
+}
+
+impl PageRange {
+    /// Parse a comma-separated list of page ranges, such as `1-4,10,20-`.
+    pub fn parse_list(spec: &str) -> Result<Vec<PageRange>> {
+        spec.split(',').map(PageRange::from_str).collect()
+    }
+
+    fn to_dpx(self) -> Result<super::PageRange> {
+        let index = |page: u32| -> Result<i32> {
+            i32::try_from(page - 1).map_err(|_| errmsg!("page number {} is too large", page))
+        };
+
+        Ok(super::PageRange {
+            first: index(self.first)?,
+            last: match self.last {
+                Some(l) => index(l)?,
+                None => -1,
+            },
+        })
+    }
+}
+
+impl FromStr for PageRange {
+    type Err = Error;
+
+    /// Parse a single range: `N`, `N-M`, `N-` or `-M`.
+    fn from_str(s: &str) -> Result<Self> {
+        let parse_page = |t: &str| -> Result<u32> {
+            match t.trim().parse::<u32>() {
+                Ok(0) | Err(_) => {
+                    Err(format!("invalid page number \"{}\" in page range", t).into())
+                }
+                Ok(n) => Ok(n),
+            }
+        };
+
+        let mut pieces = s.splitn(2, '-');
+        let first = pieces.next().unwrap().trim(); // splitn will always have at least 1 item
+
+        let range = match pieces.next() {
+            None => {
+                let n = parse_page(first)?;
+                PageRange {
+                    first: n,
+                    last: Some(n),
+                }
+            }
+
+            Some(last) => {
+                let first = if first.is_empty() {
+                    1
+                } else {
+                    parse_page(first)?
+                };
+
+                let last = if last.trim().is_empty() {
+                    None
+                } else {
+                    Some(parse_page(last)?)
+                };
+
+                PageRange { first, last }
+            }
+        };
+
+        Ok(range)
+    }
+}
+
+/// The cipher used to encrypt the output PDF.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EncryptionAlgorithm {
+    /// AES with a 128-bit key.
+    Aes128,
+    /// AES with a 256-bit key (PDF 1.7 extension level 3 and later). This
+    /// bumps the output PDF version to 1.7 if needed.
+    Aes256,
+}
+
+impl EncryptionAlgorithm {
+    fn key_bits(self) -> i32 {
+        match self {
+            EncryptionAlgorithm::Aes128 => 128,
+            EncryptionAlgorithm::Aes256 => 256,
+        }
+    }
+}
+
+impl FromStr for EncryptionAlgorithm {
+    type Err = &'static str;
+
+    fn from_str(a_str: &str) -> StdResult<Self, Self::Err> {
+        match a_str {
+            "aes128" => Ok(EncryptionAlgorithm::Aes128),
+            "aes256" => Ok(EncryptionAlgorithm::Aes256),
+            _ => Err("unsupported encryption algorithm"),
+        }
+    }
+}
+
+/// The operations that a reader of an encrypted PDF is allowed to perform
+/// without knowing the owner password. These are the bits of the `P` entry
+/// of the PDF encryption dictionary.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Permissions(u32);
+
+impl Permissions {
+    pub const PRINT: Permissions = Permissions(1 << 2);
+    pub const MODIFY: Permissions = Permissions(1 << 3);
+    pub const COPY: Permissions = Permissions(1 << 4);
+    pub const ANNOTATE: Permissions = Permissions(1 << 5);
+    pub const FILL_FORMS: Permissions = Permissions(1 << 8);
+    pub const EXTRACT_FOR_ACCESSIBILITY: Permissions = Permissions(1 << 9);
+    pub const ASSEMBLE: Permissions = Permissions(1 << 10);
+    pub const PRINT_HIGH_QUALITY: Permissions = Permissions(1 << 11);
+
+    const NAMES: &'static [(&'static str, Permissions)] = &[
+        ("print", Permissions::PRINT),
+        ("modify", Permissions::MODIFY),
+        ("copy", Permissions::COPY),
+        ("annotate", Permissions::ANNOTATE),
+        ("fill-forms", Permissions::FILL_FORMS),
+        ("accessibility", Permissions::EXTRACT_FOR_ACCESSIBILITY),
+        ("assemble", Permissions::ASSEMBLE),
+        ("print-high", Permissions::PRINT_HIGH_QUALITY),
+    ];
+
+    /// No permissions at all.
+    pub fn none() -> Permissions {
+        Permissions(0)
+    }
+
+    /// Every permission.
+    pub fn all() -> Permissions {
+        Permissions(0xf3c)
+    }
+
+    /// Create a permission set from a raw `P` bitmask. Bits that have no
+    /// meaning are ignored.
+    pub fn from_bits(bits: u32) -> Permissions {
+        Permissions(bits & 0xf3c)
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: Permissions) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// The permissions granted by xdvipdfmx by default: printing, modifying,
+/// copying and annotating.
+impl Default for Permissions {
+    fn default() -> Self {
+        Permissions(0x3c)
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, rhs: Permissions) -> Permissions {
+        Permissions(self.0 | rhs.0)
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = Error;
+
+    /// Parse either a numeric bitmask (decimal, or hexadecimal with a `0x`
+    /// prefix) or a comma-separated list of permission names such as
+    /// `print,copy`. The string `none` grants nothing.
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if let Some(hex) = s.strip_prefix("0x") {
+            return u32::from_str_radix(hex, 16)
+                .map(Permissions::from_bits)
+                .map_err(|e| format!("invalid permission bitmask \"{}\": {}", s, e).into());
+        }
+
+        if let Ok(bits) = s.parse::<u32>() {
+            return Ok(Permissions::from_bits(bits));
+        }
+
+        let mut perms = Permissions::none();
+
+        if s == "none" {
+            return Ok(perms);
+        }
+
+        for name in s.split(',').map(|n| n.trim()) {
+            if name == "all" {
+                perms = perms | Permissions::all();
+                continue;
+            }
+
+            match Permissions::NAMES.iter().find(|(n, _)| *n == name) {
+                Some((_, p)) => perms = perms | *p,
+                None => return Err(format!("unknown PDF permission \"{}\"", name).into()),
+            }
+        }
+
+        Ok(perms)
+    }
+}
+
+/// Settings for encrypting the output PDF.
+///
+/// If the owner password is empty, xdvipdfmx uses the user password for it,
+/// which means that anyone who can open the document can also change its
+/// permissions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PdfEncryption {
+    pub algorithm: EncryptionAlgorithm,
+    pub user_password: String,
+    pub owner_password: String,
+    pub permissions: Permissions,
+}
+
+impl PdfEncryption {
+    pub fn new(algorithm: EncryptionAlgorithm) -> PdfEncryption {
+        PdfEncryption {
+            algorithm,
+            user_password: String::new(),
+            owner_password: String::new(),
+            permissions: Permissions::default(),
+        }
+    }
+
+    /// Check that the passwords can be represented with the chosen
+    /// algorithm, then convert to the engine representation.
+    fn to_dpx(&self) -> Result<super::EncryptionConfig> {
+        for pw in &[&self.user_password, &self.owner_password] {
+            if pw.len() > MAX_PASSWORD_LENGTH {
+                return Err(errmsg!(
+                    "PDF passwords may be at most {} bytes long",
+                    MAX_PASSWORD_LENGTH
+                ));
+            }
+
+            // Before AES-256, passwords are limited to printable ASCII.
+            if self.algorithm == EncryptionAlgorithm::Aes128
+                && pw.bytes().any(|b| !(0x20..=0x7e).contains(&b))
+            {
+                return Err(errmsg!(
+                    "PDF passwords must be printable ASCII when using AES-128 encryption"
+                ));
+            }
+        }
+
+        Ok(super::EncryptionConfig {
+            key_bits: self.algorithm.key_bits(),
+            permission: self.permissions.bits() as i32,
+            owner_password: self.owner_password.clone(),
+            user_password: self.user_password.clone(),
+        })
+    }
+}
+
+/// The PDF/A conformance level to produce.
+///
+/// Both levels require every font to be embedded and forbid encryption. The
+/// difference is that PDF/A-3 allows arbitrary embedded files, while PDF/A-2
+/// rejects them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PdfAConformance {
+    /// PDF/A-2b (ISO 19005-2, basic conformance).
+    A2b,
+    /// PDF/A-3b (ISO 19005-3, basic conformance).
+    A3b,
+}
+
+impl PdfAConformance {
+    fn to_dpx(self) -> super::PdfAConformance {
+        match self {
+            PdfAConformance::A2b => super::PdfAConformance::A2b,
+            PdfAConformance::A3b => super::PdfAConformance::A3b,
+        }
+    }
+}
+
+impl FromStr for PdfAConformance {
+    type Err = &'static str;
+
+    fn from_str(a_str: &str) -> StdResult<Self, Self::Err> {
+        match a_str {
+            "2b" => Ok(PdfAConformance::A2b),
+            "3b" => Ok(PdfAConformance::A3b),
+            _ => Err("unsupported PDF/A conformance level"),
+        }
+    }
+}
+
+/// The password buffers in xdvipdfmx hold 127 bytes, including the
+/// terminating NUL.
+const MAX_PASSWORD_LENGTH: usize = 126;
+
 pub struct XdvipdfmxEngine {
     enable_compression: bool,
     deterministic_tags: bool,
+    page_ranges: Vec<PageRange>,
+    encryption: Option<PdfEncryption>,
+    source_date_epoch: Option<u64>,
+    pdfa: Option<PdfAConformance>,
 }
 
 impl XdvipdfmxEngine {
@@ -18,6 +338,10 @@ impl XdvipdfmxEngine {
         XdvipdfmxEngine {
             enable_compression: true,
             deterministic_tags: false,
+            page_ranges: Vec::new(),
+            encryption: None,
+            source_date_epoch: None,
+            pdfa: None,
         }
     }
 
@@ -31,6 +355,35 @@ impl XdvipdfmxEngine {
         self
     }
 
+    /// Only output the given pages. By default, or if `ranges` is empty, all
+    /// pages are output.
+    pub fn with_page_ranges(mut self, ranges: &[PageRange]) -> Self {
+        self.page_ranges = ranges.to_owned();
+        self
+    }
+
+    /// Encrypt the output PDF. This is disabled by default.
+    pub fn with_encryption(mut self, encryption: Option<PdfEncryption>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Fix the creation date of the PDF, which also seeds its ID, in seconds
+    /// since the epoch. If unset, it comes from the `SOURCE_DATE_EPOCH`
+    /// environment variable if it is set, and the clock otherwise.
+    pub fn with_source_date_epoch(mut self, epoch: Option<u64>) -> Self {
+        self.source_date_epoch = epoch;
+        self
+    }
+
+    /// Produce PDF/A output with the given conformance level. Problems that
+    /// would break conformance are reported as errors. This is disabled by
+    /// default.
+    pub fn with_pdfa(mut self, pdfa: Option<PdfAConformance>) -> Self {
+        self.pdfa = pdfa;
+        self
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
@@ -47,6 +400,17 @@ impl XdvipdfmxEngine {
         // We default to "letter" paper size by default
         let config = super::XdvipdfmxConfig {
             paperspec: paperspec_str.map_or("letter".into(), |s| s.into()),
+            page_ranges: self
+                .page_ranges
+                .iter()
+                .map(|r| r.to_dpx())
+                .collect::<Result<_>>()?,
+            encryption: match self.encryption {
+                Some(ref e) => Some(e.to_dpx()?),
+                None => None,
+            },
+            source_date_epoch: self.source_date_epoch,
+            pdfa: self.pdfa.map(|p| p.to_dpx()),
         };
 
         let /*mut*/ state = ExecutionState::new(io, events, status);
@@ -76,3 +440,83 @@ impl Default for XdvipdfmxEngine {
         XdvipdfmxEngine::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_page_ranges() {
+        let r = PageRange::parse_list("1-4,10, 20-,-3").unwrap();
+        assert_eq!(
+            r,
+            vec![
+                PageRange {
+                    first: 1,
+                    last: Some(4)
+                },
+                PageRange {
+                    first: 10,
+                    last: Some(10)
+                },
+                PageRange {
+                    first: 20,
+                    last: None
+                },
+                PageRange {
+                    first: 1,
+                    last: Some(3)
+                },
+            ]
+        );
+
+        assert!(PageRange::parse_list("0").is_err());
+        assert!(PageRange::parse_list("1-x").is_err());
+        assert!(PageRange::parse_list("1,,2").is_err());
+
+        let huge = PageRange::parse_list("3000000000").unwrap();
+        assert!(huge[0].to_dpx().is_err());
+    }
+
+    #[test]
+    fn parse_permissions() {
+        assert_eq!(
+            "print,copy".parse::<Permissions>().unwrap(),
+            Permissions::PRINT | Permissions::COPY
+        );
+        assert_eq!(
+            "0x3c".parse::<Permissions>().unwrap(),
+            Permissions::default()
+        );
+        assert_eq!("none".parse::<Permissions>().unwrap(), Permissions::none());
+        assert_eq!("all".parse::<Permissions>().unwrap(), Permissions::all());
+        assert!("print,frobnicate".parse::<Permissions>().is_err());
+        assert!("0xfrob".parse::<Permissions>().is_err());
+        assert!("".parse::<Permissions>().is_err());
+    }
+
+    #[test]
+    fn encryption_to_dpx() {
+        let mut enc = PdfEncryption::new(EncryptionAlgorithm::Aes128);
+        enc.owner_password = "owner".to_owned();
+        enc.permissions = Permissions::PRINT;
+        let cfg = enc.to_dpx().unwrap();
+        assert_eq!(cfg.key_bits, 128);
+        assert_eq!(cfg.permission, 0x4);
+        assert_eq!(cfg.owner_password, "owner");
+        assert_eq!(cfg.user_password, "");
+
+        enc.user_password = "caf\u{e9}".to_owned();
+        assert!(enc.to_dpx().is_err());
+
+        enc.algorithm = EncryptionAlgorithm::Aes256;
+        let cfg = enc.to_dpx().unwrap();
+        assert_eq!(cfg.key_bits, 256);
+        assert_eq!(cfg.user_password, "caf\u{e9}");
+
+        enc.owner_password = "x".repeat(MAX_PASSWORD_LENGTH + 1);
+        assert!(enc.to_dpx().is_err());
+        enc.owner_password = "x".repeat(MAX_PASSWORD_LENGTH);
+        assert!(enc.to_dpx().is_ok());
+    }
+}
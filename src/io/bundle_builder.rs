@@ -0,0 +1,472 @@
+// src/io/bundle_builder.rs -- creating bundles from local files
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Create bundles out of a tree of TeX support files.
+//!
+//! The engines look up support files by their bare names, so a bundle is a
+//! flat collection of files: `tex/latex/base/article.cls` in a TeX tree
+//! becomes just `article.cls`. A [`BundleBuilder`] gathers the files of one
+//! or more trees in this way and writes them out in the two formats that
+//! Tectonic can read:
+//!
+//! - a Zip archive, usable with [`ZipBundle`](super::zipbundle::ZipBundle);
+//!
+//! - an “indexed tar” file, usable with
+//!   [`CachedITarBundle`](super::cached_itarbundle::CachedITarBundle) once it
+//!   has been put on a web server. This is an ordinary uncompressed tar file,
+//!   say `bundle.tar`, together with a gzipped text file named
+//!   `bundle.tar.index.gz` that lists the tar's contents, one file per line:
+//!
+//!   ```text
+//!   article.cls 1536 20467
+//!   ```
+//!
+//!   That is: the name of the file, the byte offset at which its data start
+//!   in the tar file, and the length of the data, separated by spaces. This
+//!   is what allows a client to fetch individual files using HTTP range
+//!   requests.
+//!
+//! Either way, the bundle includes a `SHA256SUM` file holding its digest as
+//! computed by [`digest::bundle_digest`].
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use zip::write::{FileOptions, ZipWriter};
+
+use crate::digest::{self, Digest, DigestData};
+use crate::errors::Result;
+use crate::status::StatusBackend;
+use crate::{ctry, errmsg, tt_warning};
+
+/// The size of the blocks that make up a tar file.
+const TAR_BLOCK: usize = 512;
+
+/// The longest file name that fits in a plain tar header.
+const TAR_MAX_NAME: usize = 100;
+
+/// The largest file whose size fits in the 11 octal digits of a plain tar
+/// header, just under 8 GiB.
+const TAR_MAX_SIZE: u64 = 0o777_7777_7777;
+
+/// Where the data of one file of the bundle come from.
+#[derive(Clone, Debug)]
+enum Source {
+    Path(PathBuf),
+    Data(Vec<u8>),
+}
+
+impl Source {
+    fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            Source::Path(p) => Ok(ctry!(fs::read(p); "couldn't read \"{}\"", p.display())),
+            Source::Data(d) => Ok(d.clone()),
+        }
+    }
+}
+
+/// A builder for bundle files.
+#[derive(Debug, Default)]
+pub struct BundleBuilder {
+    files: BTreeMap<String, (Source, DigestData)>,
+}
+
+impl BundleBuilder {
+    pub fn new() -> BundleBuilder {
+        BundleBuilder::default()
+    }
+
+    /// Add all of the files found in the directory tree rooted at `root`.
+    ///
+    /// Hidden files and directories are skipped, as are files whose names
+    /// can't be used in a bundle. If several files have the same name, the
+    /// first one found wins — directories are searched in sorted order, and
+    /// files added earlier take precedence over files added later — and a
+    /// warning is issued unless their contents are identical.
+    pub fn add_tree<P: AsRef<Path>>(
+        &mut self,
+        root: P,
+        status: &mut dyn StatusBackend,
+    ) -> Result<&mut Self> {
+        let root = root.as_ref();
+
+        if !root.is_dir() {
+            return Err(errmsg!("\"{}\" is not a directory", root.display()));
+        }
+
+        let mut files = Vec::new();
+        walk_tree(root, &mut files)?;
+
+        for path in files {
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(n) => n.to_owned(),
+                None => {
+                    tt_warning!(
+                        status,
+                        "skipping \"{}\": its name is not valid Unicode",
+                        path.display()
+                    );
+                    continue;
+                }
+            };
+
+            if let Err(e) = check_name(&name) {
+                tt_warning!(status, "skipping \"{}\"", path.display(); e);
+                continue;
+            }
+
+            let data = Source::Path(path.clone()).read()?;
+            let digest = digest_of(&data);
+
+            if let Some((existing, existing_digest)) = self.files.get(&name) {
+                if *existing_digest != digest {
+                    let other = match existing {
+                        Source::Path(p) => p.display().to_string(),
+                        Source::Data(_) => "an earlier file".to_owned(),
+                    };
+                    tt_warning!(
+                        status,
+                        "ignoring \"{}\" because \"{}\" has the same name",
+                        path.display(),
+                        other
+                    );
+                }
+                continue;
+            }
+
+            self.files.insert(name, (Source::Path(path), digest));
+        }
+
+        Ok(self)
+    }
+
+    /// Add a single file with the given contents, replacing any file of the
+    /// same name.
+    pub fn add_file(&mut self, name: &str, data: Vec<u8>) -> Result<&mut Self> {
+        check_name(name)?;
+        let digest = digest_of(&data);
+        self.files
+            .insert(name.to_owned(), (Source::Data(data), digest));
+        Ok(self)
+    }
+
+    /// The number of files in the bundle, not counting its `SHA256SUM`.
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// The digest of the bundle.
+    pub fn digest(&self) -> DigestData {
+        digest::bundle_digest(self.files.iter().map(|(n, (_, d))| (&n[..], d)))
+    }
+
+    /// Iterate over the names and contents of all the files of the bundle,
+    /// including `SHA256SUM`, in order of their names.
+    fn for_each_file<F>(&self, mut f: F) -> Result<()>
+    where
+        F: FnMut(&str, &[u8]) -> Result<()>,
+    {
+        let digest_text = self.digest().to_string();
+        let mut sum_written = false;
+
+        for (name, (source, _)) in &self.files {
+            if !sum_written && &name[..] > digest::DIGEST_NAME {
+                f(digest::DIGEST_NAME, digest_text.as_bytes())?;
+                sum_written = true;
+            }
+
+            f(name, &source.read()?)?;
+        }
+
+        if !sum_written {
+            f(digest::DIGEST_NAME, digest_text.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Write the bundle as a Zip archive at `path`.
+    pub fn write_zip<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let f = ctry!(File::create(path); "couldn't open \"{}\" for writing", path.display());
+        let mut zip = ZipWriter::new(BufWriter::new(f));
+
+        self.for_each_file(|name, data| {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(data)?;
+            Ok(())
+        })?;
+
+        let mut f = ctry!(zip.finish(); "couldn't finish writing \"{}\"", path.display());
+        ctry!(f.flush(); "couldn't finish writing \"{}\"", path.display());
+        Ok(())
+    }
+
+    /// Write the bundle as an indexed tar file at `path`, along with its
+    /// index, whose path is `path` with `.index.gz` appended.
+    pub fn write_itar<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let mut index_path = path.as_os_str().to_owned();
+        index_path.push(".index.gz");
+        let index_path = PathBuf::from(index_path);
+
+        let f = ctry!(File::create(path); "couldn't open \"{}\" for writing", path.display());
+        let mut tar = BufWriter::new(f);
+        let f = ctry!(File::create(&index_path); "couldn't open \"{}\" for writing", index_path.display());
+        let mut index = GzEncoder::new(BufWriter::new(f), Compression::best());
+        let mut offset = 0u64;
+
+        self.for_each_file(|name, data| {
+            tar.write_all(&tar_header(name, data.len() as u64)?)?;
+            offset += TAR_BLOCK as u64;
+            writeln!(index, "{} {} {}", name, offset, data.len())?;
+
+            tar.write_all(data)?;
+            let padding = (TAR_BLOCK - data.len() % TAR_BLOCK) % TAR_BLOCK;
+            tar.write_all(&[0u8; TAR_BLOCK][..padding])?;
+            offset += (data.len() + padding) as u64;
+            Ok(())
+        })?;
+
+        // A tar file ends with two empty blocks.
+        tar.write_all(&[0u8; 2 * TAR_BLOCK])?;
+        ctry!(tar.flush(); "couldn't finish writing \"{}\"", path.display());
+        ctry!(index.finish().and_then(|mut w| w.flush()); "couldn't finish writing \"{}\"", index_path.display());
+        Ok(index_path)
+    }
+}
+
+fn digest_of(data: &[u8]) -> DigestData {
+    let mut dc = digest::create();
+    dc.input(data);
+    DigestData::from(dc)
+}
+
+/// Check that `name` can be used as the name of a file in a bundle.
+fn check_name(name: &str) -> Result<()> {
+    if name == digest::DIGEST_NAME {
+        return Err(errmsg!(
+            "the name \"{}\" is reserved for the bundle's digest",
+            name
+        ));
+    }
+
+    // The index is whitespace-separated, and the engines never ask for
+    // names containing path separators.
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '/' || c == '\\') {
+        return Err(errmsg!(
+            "bundle file names may not contain spaces or slashes"
+        ));
+    }
+
+    if name.len() > TAR_MAX_NAME {
+        return Err(errmsg!(
+            "bundle file names may be at most {} bytes long",
+            TAR_MAX_NAME
+        ));
+    }
+
+    Ok(())
+}
+
+/// Collect the paths of all of the non-hidden files below `dir`, in sorted
+/// order.
+fn walk_tree(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = Vec::new();
+
+    for entry in ctry!(fs::read_dir(dir); "couldn't read directory \"{}\"", dir.display()) {
+        let entry = entry?;
+
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        entries.push(entry.path());
+    }
+
+    entries.sort();
+
+    for path in entries {
+        // Follow symbolic links to files, but not to directories, which
+        // could lead us in circles.
+        if fs::symlink_metadata(&path)?.is_dir() {
+            walk_tree(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Create the header for a file in a tar archive. The header leaves out
+/// everything that would make the archive differ between systems or runs,
+/// such as timestamps and owners.
+fn tar_header(name: &str, size: u64) -> Result<[u8; TAR_BLOCK]> {
+    fn octal(field: &mut [u8], value: u64) {
+        let digits = format!("{:0width$o}", value, width = field.len() - 1);
+        field[..digits.len()].copy_from_slice(digits.as_bytes());
+    }
+
+    if size > TAR_MAX_SIZE {
+        return Err(errmsg!(
+            "the bundle file \"{}\" is too big for a tar archive ({} bytes)",
+            name,
+            size
+        ));
+    }
+
+    let mut h = [0u8; TAR_BLOCK];
+    h[..name.len()].copy_from_slice(name.as_bytes());
+    octal(&mut h[100..108], 0o644); // mode
+    octal(&mut h[108..116], 0); // uid
+    octal(&mut h[116..124], 0); // gid
+    octal(&mut h[124..136], size);
+    octal(&mut h[136..148], 0); // mtime
+    h[156] = b'0'; // regular file
+    h[257..263].copy_from_slice(b"ustar\0");
+    h[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field filled with spaces.
+    h[148..156].copy_from_slice(b"        ");
+    let sum: u64 = h.iter().map(|&b| u64::from(b)).sum();
+    octal(&mut h[148..155], sum);
+    h[154] = 0;
+    Ok(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::zipbundle::ZipBundle;
+    use crate::io::{Bundle, IoProvider, OpenResult};
+    use crate::status::NoopStatusBackend;
+    use flate2::read::GzDecoder;
+    use std::ffi::OsStr;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn make_tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path();
+        fs::create_dir_all(p.join("tex/latex/base")).unwrap();
+        fs::create_dir_all(p.join("fonts/tfm")).unwrap();
+        fs::create_dir_all(p.join("other")).unwrap();
+        fs::create_dir_all(p.join(".git")).unwrap();
+        fs::write(p.join("tex/latex/base/article.cls"), "article").unwrap();
+        fs::write(p.join("fonts/tfm/cmr10.tfm"), vec![7u8; 1000]).unwrap();
+        fs::write(p.join("other/article.cls"), "impostor").unwrap();
+        fs::write(p.join(".git/config"), "hidden").unwrap();
+        fs::write(p.join("has space.tex"), "bad name").unwrap();
+        dir
+    }
+
+    #[test]
+    fn flattens_tree() {
+        let tree = make_tree();
+        let mut status = NoopStatusBackend::new();
+        let mut b = BundleBuilder::new();
+        b.add_tree(tree.path(), &mut status).unwrap();
+
+        let names: Vec<_> = b.files.keys().cloned().collect();
+        assert_eq!(names, vec!["article.cls", "cmr10.tfm"]);
+
+        // "other" sorts after "fonts" but before "tex", so its file wins.
+        assert_eq!(b.files["article.cls"].0.read().unwrap(), b"impostor");
+    }
+
+    #[test]
+    fn zip_roundtrip() {
+        let tree = make_tree();
+        let out = TempDir::new().unwrap();
+        let mut status = NoopStatusBackend::new();
+        let mut b = BundleBuilder::new();
+        b.add_tree(tree.path(), &mut status).unwrap();
+        b.add_file("extra.sty", b"extra".to_vec()).unwrap();
+
+        let path = out.path().join("bundle.zip");
+        b.write_zip(&path).unwrap();
+
+        let mut zip = ZipBundle::open(&path).unwrap();
+        assert_eq!(zip.get_digest(&mut status).unwrap(), b.digest());
+
+        let mut buf = Vec::new();
+        match zip.input_open_name(OsStr::new("extra.sty"), &mut status) {
+            OpenResult::Ok(mut h) => h.read_to_end(&mut buf).unwrap(),
+            _ => panic!("extra.sty missing from the zip"),
+        };
+        assert_eq!(buf, b"extra");
+    }
+
+    #[test]
+    fn itar_index_matches_tar() {
+        let tree = make_tree();
+        let out = TempDir::new().unwrap();
+        let mut status = NoopStatusBackend::new();
+        let mut b = BundleBuilder::new();
+        b.add_tree(tree.path(), &mut status).unwrap();
+
+        let tar_path = out.path().join("bundle.tar");
+        let index_path = b.write_itar(&tar_path).unwrap();
+        assert_eq!(index_path, out.path().join("bundle.tar.index.gz"));
+
+        let tar = fs::read(&tar_path).unwrap();
+        assert_eq!(tar.len() % TAR_BLOCK, 0);
+
+        let mut index = String::new();
+        GzDecoder::new(File::open(&index_path).unwrap())
+            .read_to_string(&mut index)
+            .unwrap();
+
+        let mut seen = Vec::new();
+
+        for line in index.lines() {
+            let bits: Vec<_> = line.split(' ').collect();
+            let (name, offset, length) = (
+                bits[0],
+                bits[1].parse::<usize>().unwrap(),
+                bits[2].parse::<usize>().unwrap(),
+            );
+            let data = &tar[offset..offset + length];
+
+            // The header just before the data names the file.
+            let header = &tar[offset - TAR_BLOCK..offset];
+            assert_eq!(&header[..name.len()], name.as_bytes());
+            assert_eq!(header[name.len()], 0);
+
+            match name {
+                "article.cls" => assert_eq!(data, b"impostor"),
+                "cmr10.tfm" => assert_eq!(data, &[7u8; 1000][..]),
+                "SHA256SUM" => assert_eq!(data, b.digest().to_string().as_bytes()),
+                _ => panic!("unexpected file {}", name),
+            }
+
+            seen.push(name.to_owned());
+        }
+
+        assert_eq!(seen, vec!["SHA256SUM", "article.cls", "cmr10.tfm"]);
+    }
+
+    #[test]
+    fn tar_header_checksum() {
+        let h = tar_header("a.tex", 5).unwrap();
+        let stored = std::str::from_utf8(&h[148..154]).unwrap();
+        let mut copy = h;
+        copy[148..156].copy_from_slice(b"        ");
+        let sum: u64 = copy.iter().map(|&b| u64::from(b)).sum();
+        assert_eq!(u64::from_str_radix(stored, 8).unwrap(), sum);
+        assert_eq!(&h[124..135], b"00000000005");
+
+        let h = tar_header("big.tex", TAR_MAX_SIZE).unwrap();
+        assert_eq!(&h[124..135], b"77777777777");
+        assert!(tar_header("big.tex", TAR_MAX_SIZE + 1).is_err());
+    }
+}
@@ -0,0 +1,780 @@
+// src/io/cache_admin.rs -- maintenance of the local bundle cache
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Maintenance of the local cache used by [`CachedITarBundle`].
+//!
+//! [`CachedITarBundle`]: super::cached_itarbundle::CachedITarBundle
+//!
+//! The cache has the following subdirectories:
+//!
+//! - `urls`: for each bundle URL, the digest of the bundle that it served
+//!   when we last checked. A bundle with mirrors is listed under the URL of
+//!   the first one.
+//! - `redirects`, `indexes`, and `manifests`: for each bundle digest, a file
+//!   named `<digest>.txt` giving, respectively, the URL that the bundle URL
+//!   (or the mirror that last worked) redirected to, the bundle’s index, and
+//!   the files that have been downloaded from the bundle so far.
+//! - `files`: the downloaded files, named by their own SHA-256 digests. A
+//!   file is shared by every bundle that contains it.
+//! - `formats`: compiled format files, named `<bundle digest>-<name>-<serial>.fmt`.
+//!   Formats made from document preambles come with a list of the files they
+//!   were made from, with the extension `.inputs` in place of `.fmt`.
+//! - `documents`: for each document processed by the command-line program,
+//!   the names of the bundle files that it used last time, so that they can
+//!   be prefetched.
+//!
+//! Normal operation never deletes anything from the cache, so it grows each
+//! time that the default bundle is updated. The [`CacheAdmin`] type reports
+//! on the cache’s contents and prunes it.
+
+use fs2::FileExt;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::cached_itarbundle::{cache_dir, make_txt_path, parse_manifest_line};
+use crate::app_dirs;
+use crate::digest::{self, Digest, DigestData};
+use crate::errors::Result;
+use crate::status::StatusBackend;
+use crate::{ctry, tt_note, tt_warning};
+
+/// How recently a cached file must have been written for garbage collection
+/// to leave it alone. Another Tectonic process may have just downloaded it
+/// and not yet listed it in a manifest.
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+/// What the cache holds for one bundle.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BundleCacheInfo {
+    /// The bundle’s digest, as a hex string.
+    pub digest: String,
+
+    /// The number of the bundle’s files that are present in the cache.
+    pub n_files: usize,
+
+    /// The total size of those files, in bytes. Since files are shared
+    /// between bundles, the sizes of different bundles may overlap.
+    pub files_size: u64,
+
+    /// The number of compiled format files for this bundle.
+    pub n_formats: usize,
+
+    /// The total size of the format files, in bytes.
+    pub formats_size: u64,
+}
+
+/// The result of checking the cached files against their digests.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VerifyReport {
+    /// The number of distinct files that were checked.
+    pub n_checked: usize,
+
+    /// The names of files that were damaged or missing, along with the
+    /// digests of the bundles that they belong to. They have been deleted
+    /// from the cache and will be downloaded again when next needed.
+    pub bad: Vec<(String, String)>,
+}
+
+/// Reports on and prunes the local bundle cache.
+#[derive(Clone, Debug)]
+pub struct CacheAdmin {
+    urls: PathBuf,
+    redirects: PathBuf,
+    indexes: PathBuf,
+    manifests: PathBuf,
+    files: PathBuf,
+    formats: PathBuf,
+    documents: PathBuf,
+}
+
+impl CacheAdmin {
+    /// Access the cache in the per-user cache directory, or in
+    /// `custom_cache_root` if specified, as with
+    /// [`super::cached_itarbundle::CachedITarBundle::new`].
+    pub fn new(custom_cache_root: Option<&Path>) -> Result<CacheAdmin> {
+        let formats = match custom_cache_root {
+            Some(_) => cache_dir("formats", custom_cache_root)?,
+            None => app_dirs::user_cache_dir("formats")?,
+        };
+        let documents = match custom_cache_root {
+            Some(_) => cache_dir("documents", custom_cache_root)?,
+            None => app_dirs::user_cache_dir("documents")?,
+        };
+
+        Ok(CacheAdmin {
+            urls: cache_dir("urls", custom_cache_root)?,
+            redirects: cache_dir("redirects", custom_cache_root)?,
+            indexes: cache_dir("indexes", custom_cache_root)?,
+            manifests: cache_dir("manifests", custom_cache_root)?,
+            files: cache_dir("files", custom_cache_root)?,
+            formats,
+            documents,
+        })
+    }
+
+    /// Get the digest of the bundle that `url` served when it was last
+    /// used, if it has been used at all.
+    pub fn digest_for_url(&self, url: &str) -> Result<Option<String>> {
+        let path = self.urls.join(app_dirs::sanitized(url));
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let text = ctry!(fs::read_to_string(&path); "couldn't read \"{}\"", path.display());
+        Ok(Some(text.trim().to_owned()))
+    }
+
+    /// Describe the bundles that have data in the cache, sorted by digest.
+    pub fn list(&self) -> Result<Vec<BundleCacheInfo>> {
+        let mut bundles: HashMap<String, BundleCacheInfo> = HashMap::new();
+
+        for digest in self.known_digests()? {
+            bundles.insert(
+                digest.clone(),
+                BundleCacheInfo {
+                    digest,
+                    ..BundleCacheInfo::default()
+                },
+            );
+        }
+
+        for info in bundles.values_mut() {
+            for file_digest in self.manifest_entries(&info.digest)?.values() {
+                if let Ok(md) = fs::metadata(self.file_path(file_digest)) {
+                    info.n_files += 1;
+                    info.files_size += md.len();
+                }
+            }
+
+            for (path, size) in self.format_files(&info.digest)? {
+                if path.extension() == Some(OsStr::new("fmt")) {
+                    info.n_formats += 1;
+                }
+
+                info.formats_size += size;
+            }
+        }
+
+        let mut bundles: Vec<_> = bundles.into_values().collect();
+        bundles.sort_by(|a, b| a.digest.cmp(&b.digest));
+        Ok(bundles)
+    }
+
+    /// The total size of everything in the cache, in bytes.
+    pub fn total_size(&self) -> Result<u64> {
+        let mut total = 0;
+
+        for dir in self.all_dirs() {
+            for (_, size) in walk_files(dir)? {
+                total += size;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Check every cached file against the SHA-256 digest recorded for it.
+    ///
+    /// Files that are damaged or missing are deleted and removed from their
+    /// bundles’ manifests, so that they will be downloaded again the next
+    /// time that they are needed.
+    pub fn verify(&self, status: &mut dyn StatusBackend) -> Result<VerifyReport> {
+        let mut report = VerifyReport::default();
+        let mut checked: HashMap<String, bool> = HashMap::new();
+
+        for bundle in self.known_digests()? {
+            let mut bad_digests = HashSet::new();
+
+            for (name, file_digest) in self.manifest_entries(&bundle)? {
+                let ok = match checked.get(&file_digest) {
+                    Some(ok) => *ok,
+                    None => {
+                        let ok = self.check_file(&file_digest, status)?;
+                        checked.insert(file_digest.clone(), ok);
+                        ok
+                    }
+                };
+
+                if !ok {
+                    report.bad.push((name, bundle.clone()));
+                    bad_digests.insert(file_digest);
+                }
+            }
+
+            if !bad_digests.is_empty() {
+                self.drop_manifest_entries(&bundle, &bad_digests)?;
+            }
+        }
+
+        report.n_checked = checked.len();
+        report.bad.sort();
+        Ok(report)
+    }
+
+    /// Delete the data belonging to all bundles except those whose digests
+    /// are listed in `keep`, and any files that no remaining bundle uses.
+    ///
+    /// Then, if `max_size` is given and the cache is still larger than that
+    /// many bytes, delete the files that were least recently used until it
+    /// is not. Returns the number of bytes freed.
+    ///
+    /// Files written in the last minute are never deleted, so that a
+    /// concurrent run of Tectonic doesn't have files removed from under it.
+    pub fn gc(
+        &self,
+        keep: &[String],
+        max_size: Option<u64>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<u64> {
+        let cutoff = SystemTime::now() - GC_GRACE_PERIOD;
+        self.gc_files_older_than(keep, max_size, cutoff, status)
+    }
+
+    fn gc_files_older_than(
+        &self,
+        keep: &[String],
+        max_size: Option<u64>,
+        cutoff: SystemTime,
+        status: &mut dyn StatusBackend,
+    ) -> Result<u64> {
+        let mut freed = 0;
+
+        for digest in self.known_digests()? {
+            if keep.contains(&digest) {
+                continue;
+            }
+
+            tt_note!(status, "removing cached data for bundle {}", digest);
+
+            for base in &[&self.redirects, &self.indexes, &self.manifests] {
+                let path = make_txt_path(base, &digest);
+
+                if path.exists() {
+                    freed += remove_file(&path)?;
+                }
+            }
+
+            for (path, _) in self.format_files(&digest)? {
+                freed += remove_file(&path)?;
+            }
+        }
+
+        for (url_file, digest) in self.url_entries()? {
+            if !keep.contains(&digest) {
+                freed += remove_file(&self.urls.join(url_file))?;
+            }
+        }
+
+        // Now get rid of files that no bundle refers to.
+
+        let mut referenced = HashSet::new();
+
+        for digest in self.known_digests()? {
+            referenced.extend(self.manifest_entries(&digest)?.into_values());
+        }
+
+        let mut in_use = Vec::new();
+
+        for (path, size) in walk_files(&self.files)? {
+            if is_newer_than(&path, cutoff) {
+                continue;
+            }
+
+            match self.file_digest_for_path(&path) {
+                Some(ref d) if referenced.contains(d) => in_use.push((path, size)),
+                _ => freed += remove_file(&path)?,
+            }
+        }
+
+        let max_size = match max_size {
+            Some(m) => m,
+            None => return Ok(freed),
+        };
+
+        let mut total = self.total_size()?;
+
+        if total <= max_size {
+            return Ok(freed);
+        }
+
+        // Delete the least recently used files first. Access times aren't
+        // always maintained, so fall back to modification times.
+
+        let mut in_use: Vec<_> = in_use
+            .into_iter()
+            .map(|(path, size)| {
+                let when = fs::metadata(&path)
+                    .and_then(|md| md.accessed().or_else(|_| md.modified()))
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                (when, path, size)
+            })
+            .collect();
+        in_use.sort();
+
+        let mut evicted = HashSet::new();
+
+        for (_, path, size) in in_use {
+            if total <= max_size {
+                break;
+            }
+
+            remove_file(&path)?;
+            total -= size;
+            freed += size;
+
+            if let Some(d) = self.file_digest_for_path(&path) {
+                evicted.insert(d);
+            }
+        }
+
+        for digest in self.known_digests()? {
+            self.drop_manifest_entries(&digest, &evicted)?;
+        }
+
+        if total > max_size {
+            tt_warning!(
+                status,
+                "the cache is still {} bytes after removing all unneeded files",
+                total
+            );
+        }
+
+        Ok(freed)
+    }
+
+    /// Delete everything in the cache. Returns the number of bytes freed.
+    pub fn clear(&self) -> Result<u64> {
+        let mut freed = 0;
+
+        for dir in self.all_dirs() {
+            for (path, _) in walk_files(dir)? {
+                freed += remove_file(&path)?;
+            }
+
+            // Remove the subdirectories of `files`.
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+
+                if path.is_dir() {
+                    ctry!(fs::remove_dir_all(&path); "couldn't delete \"{}\"", path.display());
+                }
+            }
+        }
+
+        Ok(freed)
+    }
+
+    fn all_dirs(&self) -> [&Path; 7] {
+        [
+            &self.urls,
+            &self.redirects,
+            &self.indexes,
+            &self.manifests,
+            &self.files,
+            &self.formats,
+            &self.documents,
+        ]
+    }
+
+    /// The digests of all bundles that have any data in the cache.
+    fn known_digests(&self) -> Result<Vec<String>> {
+        let mut digests = HashSet::new();
+
+        for dir in &[&self.redirects, &self.indexes, &self.manifests] {
+            for (path, _) in walk_files(dir)? {
+                if path.extension() == Some(OsStr::new("txt")) {
+                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                        digests.insert(stem.to_owned());
+                    }
+                }
+            }
+        }
+
+        for (path, _) in walk_files(&self.formats)? {
+            if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+                if let Some(i) = name.find('-') {
+                    if is_format_file(name) {
+                        digests.insert(name[..i].to_owned());
+                    }
+                }
+            }
+        }
+
+        let mut digests: Vec<_> = digests.into_iter().collect();
+        digests.sort();
+        Ok(digests)
+    }
+
+    /// The entries in the `urls` directory, as pairs of file names and
+    /// bundle digests.
+    fn url_entries(&self) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+
+        for (path, _) in walk_files(&self.urls)? {
+            let text = ctry!(fs::read_to_string(&path); "couldn't read \"{}\"", path.display());
+
+            if let Some(name) = path.file_name().and_then(|s| s.to_str()) {
+                entries.push((name.to_owned(), text.trim().to_owned()));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// The files recorded in a bundle’s manifest, mapping their names to
+    /// their digests. Files that were found not to exist are skipped.
+    fn manifest_entries(&self, bundle: &str) -> Result<HashMap<String, String>> {
+        let path = make_txt_path(&self.manifests, bundle);
+        let mut entries = HashMap::new();
+
+        if !path.exists() {
+            return Ok(entries);
+        }
+
+        let text = ctry!(fs::read_to_string(&path); "couldn't read \"{}\"", path.display());
+
+        for line in text.lines() {
+            if let Some((name, _, digest)) = parse_manifest_line(line) {
+                if digest != "-" {
+                    entries.insert(name.to_owned(), digest.to_owned());
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Rewrite a bundle’s manifest without the entries for files with the
+    /// given digests.
+    fn drop_manifest_entries(&self, bundle: &str, digests: &HashSet<String>) -> Result<()> {
+        let path = make_txt_path(&self.manifests, bundle);
+
+        if digests.is_empty() || !path.exists() {
+            return Ok(());
+        }
+
+        let mut f = ctry!(fs::OpenOptions::new().read(true).write(true).open(&path);
+                          "couldn't open \"{}\"", path.display());
+
+        // The lock is released when the file is closed.
+        ctry!(f.lock_exclusive(); "failed to lock manifest file \"{}\" for writing", path.display());
+
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+
+        let mut kept = String::with_capacity(text.len());
+
+        for line in text.lines() {
+            let drop = match parse_manifest_line(line) {
+                Some((_, _, digest)) => digests.contains(digest),
+                None => false,
+            };
+
+            if !drop {
+                kept.push_str(line);
+                kept.push('\n');
+            }
+        }
+
+        if kept.len() != text.len() {
+            f.seek(SeekFrom::Start(0))?;
+            f.set_len(0)?;
+            f.write_all(kept.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// The compiled format files for a bundle, and the lists of inputs saved
+    /// next to formats made from preambles, with their sizes.
+    fn format_files(&self, bundle: &str) -> Result<Vec<(PathBuf, u64)>> {
+        let prefix = format!("{}-", bundle);
+
+        Ok(walk_files(&self.formats)?
+            .into_iter()
+            .filter(|(path, _)| {
+                path.file_name()
+                    .and_then(|s| s.to_str())
+                    .map(|n| n.starts_with(&prefix) && is_format_file(n))
+                    .unwrap_or(false)
+            })
+            .collect())
+    }
+
+    /// Where the file with the given digest is stored. This mirrors
+    /// [`DigestData::create_two_part_path`], without creating anything.
+    fn file_path(&self, file_digest: &str) -> PathBuf {
+        match (file_digest.get(..2), file_digest.get(2..)) {
+            (Some(dir), Some(name)) if !name.is_empty() => self.files.join(dir).join(name),
+            _ => self.files.join(file_digest),
+        }
+    }
+
+    /// The inverse of `file_path`.
+    fn file_digest_for_path(&self, path: &Path) -> Option<String> {
+        let rel = path.strip_prefix(&self.files).ok()?;
+        let mut parts = rel.iter();
+        let dir = parts.next()?.to_str()?;
+        let name = parts.next()?.to_str()?;
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(format!("{}{}", dir, name))
+    }
+
+    /// Check whether the cached file with the given digest is intact. If it
+    /// is damaged, delete it.
+    fn check_file(&self, file_digest: &str, status: &mut dyn StatusBackend) -> Result<bool> {
+        let path = self.file_path(file_digest);
+
+        let mut f = match File::open(&path) {
+            Ok(f) => f,
+            Err(_) => return Ok(false),
+        };
+
+        let mut contents = Vec::new();
+        ctry!(f.read_to_end(&mut contents); "couldn't read \"{}\"", path.display());
+        drop(f);
+
+        let mut dc = digest::create();
+        dc.input(&contents);
+        let actual = DigestData::from(dc).to_string();
+
+        if actual == file_digest {
+            return Ok(true);
+        }
+
+        tt_warning!(
+            status,
+            "cached file \"{}\" is damaged; deleting it",
+            path.display()
+        );
+        remove_file(&path)?;
+        Ok(false)
+    }
+}
+
+/// Whether `name` is a file that belongs in the `formats` directory: either
+/// a format, or the list of inputs saved next to one made from a preamble.
+fn is_format_file(name: &str) -> bool {
+    name.ends_with(".fmt") || name.ends_with(".inputs")
+}
+
+/// Whether the file at `path` was modified after `cutoff`. If we can't tell,
+/// err on the side of saying that it was.
+fn is_newer_than(path: &Path, cutoff: SystemTime) -> bool {
+    match fs::metadata(path).and_then(|md| md.modified()) {
+        Ok(t) => t > cutoff,
+        Err(_) => true,
+    }
+}
+
+/// List the files under `dir`, recursively, with their sizes.
+fn walk_files(dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    let mut found = Vec::new();
+
+    if !dir.is_dir() {
+        return Ok(found);
+    }
+
+    for entry in ctry!(fs::read_dir(dir); "couldn't read directory \"{}\"", dir.display()) {
+        let entry = entry?;
+        let ftype = entry.file_type()?;
+
+        if ftype.is_dir() {
+            found.extend(walk_files(&entry.path())?);
+        } else if ftype.is_file() {
+            found.push((entry.path(), entry.metadata()?.len()));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Delete a file, returning its size. Cached files are made read-only when
+/// they are downloaded, which on Windows prevents their deletion, so we
+/// undo that first.
+fn remove_file(path: &Path) -> Result<u64> {
+    let md = ctry!(fs::metadata(path); "couldn't examine \"{}\"", path.display());
+
+    #[cfg(windows)]
+    {
+        let mut perms = md.permissions();
+
+        if perms.readonly() {
+            perms.set_readonly(false);
+            fs::set_permissions(path, perms)?;
+        }
+    }
+
+    ctry!(fs::remove_file(path); "couldn't delete \"{}\"", path.display());
+    Ok(md.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::NoopStatusBackend;
+
+    fn digest_of(data: &[u8]) -> String {
+        let mut dc = digest::create();
+        dc.input(data);
+        DigestData::from(dc).to_string()
+    }
+
+    /// Populate a cache with two bundles, "old" and "new", which share the
+    /// file `shared.tex`.
+    fn setup() -> (tempfile::TempDir, CacheAdmin, String, String) {
+        let root = tempfile::Builder::new()
+            .prefix("tectonic_cache_test")
+            .tempdir()
+            .unwrap();
+        let admin = CacheAdmin::new(Some(root.path())).unwrap();
+        let old = "0".repeat(64);
+        let new = "1".repeat(64);
+
+        let write_file = |bundle: &str, name: &str, contents: &[u8]| {
+            let d = digest_of(contents);
+            let path = admin.file_path(&d);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+
+            let mpath = make_txt_path(&admin.manifests, bundle);
+            let mut man = fs::OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(mpath)
+                .unwrap();
+            writeln!(man, "{} {} {}", name, contents.len(), d).unwrap();
+        };
+
+        write_file(&old, "old.sty", b"old style");
+        write_file(&old, "shared.tex", b"shared file");
+        write_file(&new, "shared.tex", b"shared file");
+        write_file(&new, "new.sty", b"new style");
+
+        for d in &[&old, &new] {
+            fs::write(make_txt_path(&admin.indexes, d), "index").unwrap();
+            fs::write(make_txt_path(&admin.redirects, d), "https://example.com/").unwrap();
+        }
+
+        fs::write(admin.urls.join("old_url"), format!("{}\n", old)).unwrap();
+        fs::write(admin.urls.join("new_url"), format!("{}\n", new)).unwrap();
+        fs::write(admin.formats.join(format!("{}-latex-1.fmt", old)), "fmt").unwrap();
+        fs::write(admin.formats.join(format!("{}-pre-1.inputs", old)), "a\n").unwrap();
+
+        (root, admin, old, new)
+    }
+
+    #[test]
+    fn list() {
+        let (_root, admin, old, new) = setup();
+        let bundles = admin.list().unwrap();
+
+        assert_eq!(
+            bundles,
+            vec![
+                BundleCacheInfo {
+                    digest: old,
+                    n_files: 2,
+                    files_size: 20,
+                    n_formats: 1,
+                    formats_size: 5,
+                },
+                BundleCacheInfo {
+                    digest: new.clone(),
+                    n_files: 2,
+                    files_size: 20,
+                    n_formats: 0,
+                    formats_size: 0,
+                },
+            ]
+        );
+
+        assert_eq!(admin.digest_for_url("new_url").unwrap(), Some(new));
+    }
+
+    #[test]
+    fn verify() {
+        let (_root, admin, _old, new) = setup();
+        let mut status = NoopStatusBackend::default();
+
+        let report = admin.verify(&mut status).unwrap();
+        assert_eq!(report.n_checked, 3);
+        assert!(report.bad.is_empty());
+
+        let path = admin.file_path(&digest_of(b"new style"));
+        fs::write(&path, "tampered").unwrap();
+
+        let report = admin.verify(&mut status).unwrap();
+        assert_eq!(report.bad, vec![("new.sty".to_owned(), new.clone())]);
+        assert!(!path.exists());
+        assert_eq!(admin.manifest_entries(&new).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn gc() {
+        let (_root, admin, old, new) = setup();
+        let mut status = NoopStatusBackend::default();
+
+        // The files in the test cache are brand new, so gc() itself
+        // wouldn't touch them.
+        let now = SystemTime::now() + Duration::from_secs(1);
+
+        admin
+            .gc_files_older_than(std::slice::from_ref(&new), None, now, &mut status)
+            .unwrap();
+
+        let bundles = admin.list().unwrap();
+        assert_eq!(bundles.len(), 1);
+        assert_eq!(bundles[0].digest, new);
+        assert_eq!(bundles[0].n_files, 2);
+        assert!(!admin.file_path(&digest_of(b"old style")).exists());
+        assert!(!admin.urls.join("old_url").exists());
+        assert!(admin.format_files(&old).unwrap().is_empty());
+        assert!(!admin.formats.join(format!("{}-pre-1.inputs", old)).exists());
+
+        // Capping the size evicts files and their manifest entries.
+        let before = admin.total_size().unwrap();
+        let freed = admin
+            .gc_files_older_than(
+                std::slice::from_ref(&new),
+                Some(before - 1),
+                now,
+                &mut status,
+            )
+            .unwrap();
+        assert!(freed > 0);
+        assert_eq!(admin.manifest_entries(&new).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn gc_spares_new_files() {
+        let (_root, admin, _old, new) = setup();
+        let mut status = NoopStatusBackend::default();
+
+        admin
+            .gc(std::slice::from_ref(&new), Some(0), &mut status)
+            .unwrap();
+
+        assert!(admin.file_path(&digest_of(b"old style")).exists());
+        assert_eq!(admin.manifest_entries(&new).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn clear() {
+        let (_root, admin, _old, _new) = setup();
+        let before = admin.total_size().unwrap();
+        assert_eq!(admin.clear().unwrap(), before);
+        assert_eq!(admin.total_size().unwrap(), 0);
+        assert!(admin.list().unwrap().is_empty());
+    }
+}
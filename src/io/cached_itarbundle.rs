@@ -6,24 +6,42 @@ use error_chain::bail;
 use flate2::read::GzDecoder;
 use fs2::FileExt;
 use reqwest::{header::HeaderMap, Client, RedirectPolicy, Response, StatusCode};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 use std::fs::{self, File};
 use std::io::ErrorKind as IoErrorKind;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
 use super::{try_open_file, Bundle, InputHandle, InputOrigin, IoProvider, OpenResult};
 use crate::app_dirs;
 use crate::digest::{self, Digest, DigestData};
 use crate::errors::{Error, ErrorKind, Result, ResultExt};
 use crate::status::StatusBackend;
-use crate::{ctry, tt_note, tt_warning};
+use crate::{ctry, errmsg, tt_note, tt_warning};
 
 const MAX_HTTP_REDIRECTS_ALLOWED: usize = 10;
 const MAX_HTTP_ATTEMPTS: usize = 4;
 
+/// How long to wait before retrying a failed download. The wait doubles
+/// with each further attempt.
+const HTTP_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// How many requests to have in flight at once when prefetching files.
+const MAX_PARALLEL_FETCHES: usize = 8;
+
+/// Files that are at most this many bytes apart in the tar file are fetched
+/// with a single request, gap included. Consecutive files in a real tar file
+/// are separated by a 512-byte header and some padding.
+const MAX_COALESCE_GAP: u64 = 4096;
+
+/// The most data that a single coalesced request may cover.
+const MAX_COALESCED_LENGTH: u64 = 8 * 1024 * 1024;
+
 /// A simple way to read chunks out of a big seekable byte stream. You could
 /// implement this for io::File pretty trivially but that's not currently
 /// needed.
@@ -43,7 +61,7 @@ impl HttpRangeReader {
 }
 
 impl HttpRangeReader {
-    fn read_range(&mut self, offset: u64, length: usize) -> Result<Response> {
+    fn read_range(&self, offset: u64, length: usize) -> Result<Response> {
         let end_inclusive = offset + length as u64 - 1;
 
         let mut headers = HeaderMap::new();
@@ -52,19 +70,16 @@ impl HttpRangeReader {
 
         let res = self.client.get(&self.url).headers(headers).send()?;
 
+        // Not chained, so that `is_transient` can see what went wrong.
         if res.status() != StatusCode::PARTIAL_CONTENT {
-            return Err(Error::from(ErrorKind::UnexpectedHttpResponse(
-                self.url.clone(),
-                res.status(),
-            )))
-            .chain_err(|| format!("read range expected {}", StatusCode::PARTIAL_CONTENT));
+            return Err(ErrorKind::UnexpectedHttpResponse(self.url.clone(), res.status()).into());
         }
 
         Ok(res)
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 struct FileInfo {
     offset: u64,
     length: u64,
@@ -146,9 +161,82 @@ fn resolve_url(url: &str, status: &mut dyn StatusBackend) -> Result<String> {
     Ok(final_url)
 }
 
+/// Whether a failed request might succeed if it's tried again: the
+/// connection failed or timed out, it was cut short, or the server reported
+/// a problem of its own (a 5xx status). A 4xx status won't go away by itself.
+fn is_transient(e: &Error) -> bool {
+    match e.kind() {
+        ErrorKind::Reqwest(e) => match e.status() {
+            Some(s) => s.is_server_error(),
+            None => e.is_timeout() || e.is_http(),
+        },
+        ErrorKind::Io(_) => true,
+        ErrorKind::UnexpectedHttpResponse(_, s) => s.is_server_error(),
+        _ => false,
+    }
+}
+
+/// Download `length` bytes of the bundle, starting at `offset`.
+///
+/// When fetching a bunch of resource files (i.e., on the first invocation),
+/// servers will sometimes drop connections, so failed attempts are retried a
+/// few times, waiting a little longer each time. Only transient failures are
+/// retried; see [`is_transient`]. If a download is cut short, the next
+/// attempt picks up where it left off. This returns the data, if it was
+/// eventually obtained, along with the errors from all of the failed
+/// attempts, for the caller to report.
+fn fetch_range(
+    data: &HttpRangeReader,
+    offset: u64,
+    length: usize,
+) -> (Option<Vec<u8>>, Vec<Error>) {
+    let mut buf = Vec::with_capacity(length);
+    let mut failures = Vec::new();
+
+    for attempt in 0..MAX_HTTP_ATTEMPTS {
+        if buf.len() == length {
+            break;
+        }
+
+        if attempt > 0 {
+            thread::sleep(HTTP_RETRY_DELAY * (1 << (attempt - 1)));
+        }
+
+        let done = buf.len();
+        let remaining = length - done;
+
+        let result = data
+            .read_range(offset + done as u64, remaining)
+            .and_then(|stream| Ok(stream.take(remaining as u64).read_to_end(&mut buf)?));
+
+        match result {
+            Ok(n) if n < remaining => failures.push(errmsg!(
+                "the download stopped after {} of {} bytes",
+                done + n,
+                length
+            )),
+            Ok(_) => {}
+            Err(e) => {
+                let transient = is_transient(&e);
+                failures.push(e);
+
+                if !transient {
+                    break;
+                }
+            }
+        }
+    }
+
+    if buf.len() == length {
+        (Some(buf), failures)
+    } else {
+        (None, failures)
+    }
+}
+
 /// Attempts to download a file from the bundle.
 fn get_file(
-    data: &mut HttpRangeReader,
+    data: &HttpRangeReader,
     name: &str,
     offset: u64,
     length: usize,
@@ -161,50 +249,117 @@ fn get_file(
 
     tt_note!(status, "downloading {}", name);
 
-    // When fetching a bunch of resource files (i.e., on the first
-    // invocation), bintray will sometimes drop connections. The error
-    // manifests itself in a way that has a not-so-nice user experience.
-    // Our solution: retry the HTTP a few times in case it was a transient
-    // problem.
+    let (buf, failures) = fetch_range(data, offset, length);
+    let any_failed = !failures.is_empty();
 
-    let mut buf = Vec::with_capacity(length);
-    let mut overall_failed = true;
-    let mut any_failed = false;
+    for e in failures {
+        tt_warning!(status, "failure downloading \"{}\" from network", name; e);
+    }
 
-    for _ in 0..MAX_HTTP_ATTEMPTS {
-        let mut stream = match data.read_range(offset, length) {
-            Ok(r) => r,
-            Err(e) => {
-                tt_warning!(status, "failure requesting \"{}\" from network", name; e);
-                any_failed = true;
-                continue;
+    match buf {
+        Some(buf) => {
+            if any_failed {
+                tt_note!(status, "download succeeded after retry");
             }
-        };
 
-        if let Err(e) = stream.read_to_end(&mut buf) {
-            tt_warning!(status, "failure downloading \"{}\" from network", name; e.into());
-            any_failed = true;
-            continue;
+            Ok(buf)
         }
 
-        overall_failed = false;
-        break;
-    }
-
-    if overall_failed {
-        bail!(
+        None => bail!(
             "failed to retrieve \"{}\" from the network; \
              this most probably is not Tectonic's fault \
              -- please check your network connection.",
             name
-        );
-    } else if any_failed {
-        tt_note!(status, "download succeeded after retry");
+        ),
     }
+}
 
-    Ok(buf)
+/// A stretch of the tar file that is fetched with a single request, along
+/// with the files that lie within it.
+#[derive(Clone, Debug, PartialEq)]
+struct Span {
+    offset: u64,
+    length: u64,
+    files: Vec<(String, FileInfo)>,
 }
 
+/// Group files that lie close together in the tar file into spans, so that
+/// they can be fetched with fewer requests.
+fn coalesce_ranges(mut files: Vec<(String, FileInfo)>) -> Vec<Span> {
+    files.sort_by_key(|(_, info)| info.offset);
+
+    let mut spans: Vec<Span> = Vec::new();
+
+    for (name, info) in files {
+        let file_end = info.offset + info.length;
+
+        if let Some(span) = spans.last_mut() {
+            let span_end = span.offset + span.length;
+            let new_end = file_end.max(span_end);
+
+            if info.offset <= span_end + MAX_COALESCE_GAP
+                && new_end - span.offset <= MAX_COALESCED_LENGTH
+            {
+                span.length = new_end - span.offset;
+                span.files.push((name, info));
+                continue;
+            }
+        }
+
+        spans.push(Span {
+            offset: info.offset,
+            length: info.length,
+            files: vec![(name, info)],
+        });
+    }
+
+    spans
+}
+
+/// Download several spans at once. The results come back in no particular
+/// order, each with the outcome of [`fetch_range`].
+fn fetch_spans(
+    data: &HttpRangeReader,
+    spans: Vec<Span>,
+) -> Vec<(Span, Option<Vec<u8>>, Vec<Error>)> {
+    let n_workers = spans.len().min(MAX_PARALLEL_FETCHES);
+    let queue = Arc::new(Mutex::new(spans));
+    let (tx, rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..n_workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let tx = tx.clone();
+            let data = data.clone();
+
+            thread::spawn(move || loop {
+                let span = match queue.lock().unwrap().pop() {
+                    Some(s) => s,
+                    None => break,
+                };
+
+                let (buf, failures) = fetch_range(&data, span.offset, span.length as usize);
+
+                if tx.send((span, buf, failures)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+
+    drop(tx);
+    let results = rx.iter().collect();
+
+    for w in workers {
+        let _ = w.join();
+    }
+
+    results
+}
+
+/// Parse a line of the index of an indexed tar file, which gives the name of
+/// a file, the offset of its data in the tar file, and their length. See
+/// [`bundle_builder`](super::bundle_builder) for how the index is created.
 fn parse_index_line(line: &str) -> Result<Option<(&str, FileInfo)>> {
     let mut bits = line.split_whitespace();
 
@@ -225,10 +380,16 @@ fn parse_index_line(line: &str) -> Result<Option<(&str, FileInfo)>> {
 /// Attempts to find the redirected url, download the index and digest.
 fn get_everything(url: &str, status: &mut dyn StatusBackend) -> Result<(String, String, String)> {
     let url = resolve_url(url, status)?;
+    let (digest_text, index) = get_index_and_digest(&url, status)?;
+    Ok((digest_text, index, url))
+}
 
+/// Download the index and digest of the bundle at `url`, which should
+/// already have been resolved.
+fn get_index_and_digest(url: &str, status: &mut dyn StatusBackend) -> Result<(String, String)> {
     let index = {
         let mut index = String::new();
-        get_index(&url, status)?.read_to_string(&mut index)?;
+        get_index(url, status)?.read_to_string(&mut index)?;
         index
     };
 
@@ -247,9 +408,9 @@ fn get_everything(url: &str, status: &mut dyn StatusBackend) -> Result<(String,
             ctry!(digest_info; "backend does not provide needed {} file", digest::DIGEST_NAME)
         };
 
-        let mut range_reader = HttpRangeReader::new(&url);
+        let range_reader = HttpRangeReader::new(url);
         String::from_utf8(get_file(
-            &mut range_reader,
+            &range_reader,
             digest::DIGEST_NAME,
             digest_info.offset,
             digest_info.length as usize,
@@ -258,7 +419,29 @@ fn get_everything(url: &str, status: &mut dyn StatusBackend) -> Result<(String,
         .map_err(|e| e.utf8_error())?
     };
 
-    Ok((digest_text, index, url))
+    Ok((digest_text, index))
+}
+
+/// Like [`get_everything`], but trying each of the mirrors `urls` in turn
+/// until one of them works.
+fn get_everything_from_mirrors(
+    urls: &[String],
+    status: &mut dyn StatusBackend,
+) -> Result<(String, String, String)> {
+    let mut last_error = None;
+
+    for url in urls {
+        match get_everything(url, status) {
+            Ok(r) => return Ok(r),
+            Err(e) => {
+                tt_warning!(status, "couldn't use the bundle at {}", url; e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(ctry!(last_error; "no bundle URLs were given"))
+        .chain_err(|| "none of the bundle's mirrors could be used")
 }
 
 #[derive(Clone, Debug)]
@@ -325,14 +508,35 @@ fn load_cache_inner(
     })
 }
 
-fn make_txt_path(base: &Path, digest_text: &str) -> PathBuf {
+/// Parse a line of a manifest file, which records the name, size, and
+/// SHA-256 digest of a file that has been downloaded from the bundle. The
+/// digest is `-` for files that turned out not to exist.
+pub(crate) fn parse_manifest_line(line: &str) -> Option<(&str, u64, &str)> {
+    let mut bits = line.rsplitn(3, ' ');
+
+    match (bits.next(), bits.next(), bits.next(), bits.next()) {
+        (Some(digest), Some(length), Some(name), None) => {
+            Some((name, length.parse().ok()?, digest))
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn make_txt_path(base: &Path, digest_text: &str) -> PathBuf {
     base.join(&digest_text).with_extension("txt")
 }
 
 /// Bundle provided by an indexed tar file over http with a local cache.
+///
+/// The bundle may be available from several mirrors, which must all serve
+/// the same data. They're tried in order when the bundle is first set up,
+/// and if the one in use later stops working or serves something other
+/// than the expected bundle, the others are tried again. The mirror that
+/// was last used successfully is remembered in the cache and used first
+/// next time.
 #[derive(Clone, Debug)]
 pub struct CachedITarBundle {
-    url: String,
+    urls: Vec<String>,
     redirect_url: String,
     digest_path: PathBuf,
     cached_digest: DigestData,
@@ -354,6 +558,20 @@ impl CachedITarBundle {
         custom_cache_root: Option<&Path>,
         status: &mut dyn StatusBackend,
     ) -> Result<CachedITarBundle> {
+        Self::with_mirrors(&[url], only_cached, custom_cache_root, status)
+    }
+
+    /// Set up a bundle that is available from any of the mirrors `urls`,
+    /// which are tried in order. The bundle is cached under the first URL,
+    /// just as if it had been passed to [`Self::new`].
+    pub fn with_mirrors<S: AsRef<str>>(
+        urls: &[S],
+        only_cached: bool,
+        custom_cache_root: Option<&Path>,
+        status: &mut dyn StatusBackend,
+    ) -> Result<CachedITarBundle> {
+        let urls: Vec<String> = urls.iter().map(|u| u.as_ref().to_owned()).collect();
+        let url = ctry!(urls.first(); "at least one bundle URL must be given");
         let digest_path = cache_dir("urls", custom_cache_root)?.join(app_dirs::sanitized(url));
 
         let redirect_base = &cache_dir("redirects", custom_cache_root)?;
@@ -369,7 +587,7 @@ impl CachedITarBundle {
                 None => {
                     // At least one of the cached files does not exists. We fetch everything from
                     // scratch and save the files.
-                    let (digest_text, index, redirect_url) = get_everything(url, status)?;
+                    let (digest_text, index, redirect_url) = get_everything_from_mirrors(&urls, status)?;
                     let _ = DigestData::from_str(&digest_text)?;
                     checked_digest = true;
 
@@ -409,21 +627,14 @@ impl CachedITarBundle {
 
                 for res in f.lines() {
                     let line = res?;
-                    let mut bits = line.rsplitn(3, ' ');
 
-                    let (original_name, length, digest) =
-                        match (bits.next(), bits.next(), bits.next(), bits.next()) {
-                            (Some(s), Some(t), Some(r), None) => (r, t, s),
-                            _ => continue,
-                        };
+                    let (original_name, length, digest) = match parse_manifest_line(&line) {
+                        Some(t) => t,
+                        None => continue,
+                    };
 
                     let name = original_name.to_owned();
 
-                    let length = match length.parse::<u64>() {
-                        Ok(l) => l,
-                        Err(_) => continue,
-                    };
-
                     let digest = if digest == "-" {
                         continue;
                     } else {
@@ -453,7 +664,7 @@ impl CachedITarBundle {
         let tar_data = HttpRangeReader::new(&redirect_url);
 
         Ok(CachedITarBundle {
-            url: url.to_owned(),
+            urls,
             redirect_url,
             digest_path: digest_path.to_owned(),
             cached_digest,
@@ -497,6 +708,92 @@ impl CachedITarBundle {
         Ok(())
     }
 
+    /// Do a quick and dirty check that the bundle served by `reader` is the
+    /// one that we expect, by fetching its digest from where our index says
+    /// that it is. Errors just mean that the check fails.
+    fn serves_cached_digest(
+        &self,
+        reader: &HttpRangeReader,
+        status: &mut dyn StatusBackend,
+    ) -> bool {
+        let info = match self.index.get(digest::DIGEST_NAME) {
+            Some(info) => info,
+            None => return false,
+        };
+
+        if let Ok(d) = get_file(
+            reader,
+            digest::DIGEST_NAME,
+            info.offset,
+            info.length as usize,
+            status,
+        ) {
+            if let Ok(d) = String::from_utf8(d) {
+                if let Ok(d) = DigestData::from_str(&d) {
+                    return self.cached_digest == d;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Start using the bundle data at `redirect_url`, remembering it in the
+    /// cache so that it will be used first next time.
+    fn use_redirect_url(&mut self, redirect_url: String) -> Result<()> {
+        let redirect_path = make_txt_path(&self.redirect_base, &self.cached_digest.to_string());
+        file_create_write(&redirect_path, |f| f.write_all(redirect_url.as_bytes()))?;
+
+        self.tar_data = HttpRangeReader::new(&redirect_url);
+        self.redirect_url = redirect_url;
+        Ok(())
+    }
+
+    /// Switch to the first mirror, other than the one in use, that serves
+    /// the bundle that we expect. Returns whether there was one.
+    fn fail_over(&mut self, status: &mut dyn StatusBackend) -> Result<bool> {
+        for url in self.urls.clone() {
+            let redirect_url = match resolve_url(&url, status) {
+                Ok(u) => u,
+                Err(e) => {
+                    tt_warning!(status, "couldn't use the bundle mirror at {}", url; e);
+                    continue;
+                }
+            };
+
+            if redirect_url == self.redirect_url {
+                continue;
+            }
+
+            // The mirror might be serving a different version of the bundle,
+            // in which case our index's offsets mean nothing there. So look
+            // up the digest through the mirror's own index.
+            let digest_text = match get_index_and_digest(&redirect_url, status) {
+                Ok((d, _index)) => d,
+                Err(e) => {
+                    tt_warning!(status, "couldn't use the bundle mirror at {}", url; e);
+                    continue;
+                }
+            };
+
+            if DigestData::from_str(&digest_text).ok() != Some(self.cached_digest) {
+                tt_warning!(
+                    status,
+                    "the bundle mirror at {} doesn't serve the expected bundle",
+                    url
+                );
+                continue;
+            }
+
+            tt_note!(status, "switching to the bundle mirror at {}", url);
+            self.use_redirect_url(redirect_url)?;
+            self.checked_digest = true;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
     /// If we're going to make a request of the backend, we should check that
     /// its digest is what we expect. If not, we do a lame thing where we
     /// error out but set things up so that things should succeed if the
@@ -507,31 +804,22 @@ impl CachedITarBundle {
             return Ok(());
         }
 
-        // Do a quick and dirty check first and ignore errors.
-        if let Some(info) = self.index.get(digest::DIGEST_NAME) {
-            if let Ok(d) = get_file(
-                &mut self.tar_data,
-                digest::DIGEST_NAME,
-                info.offset,
-                info.length as usize,
-                status,
-            ) {
-                if let Ok(d) = String::from_utf8(d) {
-                    if let Ok(d) = DigestData::from_str(&d) {
-                        if self.cached_digest == d {
-                            // We managed to pull some data that match the digest.
-                            // We can be quite confident that the bundle is what we expect it to be.
-                            self.checked_digest = true;
-                            return Ok(());
-                        }
-                    }
-                }
-            }
+        if self.serves_cached_digest(&self.tar_data, status) {
+            // We managed to pull some data that match the digest.
+            // We can be quite confident that the bundle is what we expect it to be.
+            self.checked_digest = true;
+            return Ok(());
+        }
+
+        // The mirror that we were using is broken, or has moved on to a new
+        // version of the bundle. Maybe another one still has what we want.
+        if self.fail_over(status)? {
+            return Ok(());
         }
 
-        // The quick check failed. Try to pull all data to make sure that it wasn't a network
+        // The quick checks failed. Try to pull all data to make sure that it wasn't a network
         // error or that the redirect url hasn't been updated.
-        let (digest_text, _index, redirect_url) = get_everything(&self.url, status)?;
+        let (digest_text, _index, redirect_url) = get_everything_from_mirrors(&self.urls, status)?;
 
         let current_digest =
             ctry!(DigestData::from_str(&digest_text); "bad SHA256 digest from bundle");
@@ -548,10 +836,7 @@ impl CachedITarBundle {
 
         if self.redirect_url != redirect_url {
             // The redirect url has changed, let's update it.
-            let redirect_path = make_txt_path(&self.redirect_base, &digest_text);
-            file_create_write(&redirect_path, |f| f.write_all(redirect_url.as_bytes()))?;
-
-            self.redirect_url = redirect_url;
+            self.use_redirect_url(redirect_url)?;
         }
 
         // Index should've changed as the digest hasn't.
@@ -561,6 +846,28 @@ impl CachedITarBundle {
         Ok(())
     }
 
+    /// Download a file from the bundle, switching to another mirror if the
+    /// one in use doesn't work.
+    fn get_file_with_failover(
+        &mut self,
+        name: &str,
+        info: FileInfo,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<u8>> {
+        let length = info.length as usize;
+
+        match get_file(&self.tar_data, name, info.offset, length, status) {
+            Ok(c) => Ok(c),
+            Err(e) => {
+                if self.urls.len() > 1 && self.fail_over(status)? {
+                    get_file(&self.tar_data, name, info.offset, length, status)
+                } else {
+                    Err(e)
+                }
+            }
+        }
+    }
+
     /// Find the path in the local cache for the provided file. Download the file first if it is
     /// not in the local cache already.
     fn path_for_name(&mut self, name: &str, status: &mut dyn StatusBackend) -> OpenResult<PathBuf> {
@@ -596,31 +903,30 @@ impl CachedITarBundle {
         // touch nonexistent files. If we didn't maintain the negative cache,
         // we'd have to touch the network for virtually every compilation.
 
-        let content = match get_file(
-            &mut self.tar_data,
-            name,
-            info.offset,
-            info.length as usize,
-            status,
-        ) {
+        let content = match self.get_file_with_failover(name, info, status) {
             Ok(c) => c,
             Err(e) => return OpenResult::Err(e),
         };
 
-        // OK, we can stream the file to a temporary location on disk,
-        // computing its SHA256 as we go.
+        match self.cache_file(name, &content) {
+            Ok(p) => OpenResult::Ok(p),
+            Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    /// Save the freshly downloaded `content` of the file `name` in the local
+    /// cache, returning its path there.
+    fn cache_file(&mut self, name: &str, content: &[u8]) -> Result<PathBuf> {
+        // We compute the file's SHA256, which determines where it goes.
 
         let length = content.len();
 
         let mut digest_builder = digest::create();
-        digest_builder.input(&content);
+        digest_builder.input(content);
 
         let digest = DigestData::from(digest_builder);
 
-        let final_path = match digest.create_two_part_path(&self.data_base) {
-            Ok(p) => p,
-            Err(e) => return OpenResult::Err(e),
-        };
+        let final_path = digest.create_two_part_path(&self.data_base)?;
 
         // Perform a racy check for the destination existing, because this
         // matters on Windows: if the destination is already there, we'll get
@@ -629,36 +935,22 @@ impl CachedITarBundle {
         // subject to the race once.
 
         if !final_path.exists() {
-            if let Err(e) = file_create_write(&final_path, |f| f.write_all(&content)) {
-                return OpenResult::Err(e);
-            }
+            file_create_write(&final_path, |f| f.write_all(content))?;
 
             // Now we can make the file readonly. It would be nice to set the
             // permissions using the already-open file handle owned by the
             // tempfile, but mkstemp doesn't give us access.
-            let mut perms = match fs::metadata(&final_path) {
-                Ok(p) => p,
-                Err(e) => {
-                    return OpenResult::Err(e.into());
-                }
-            }
-            .permissions();
+            let mut perms = fs::metadata(&final_path)?.permissions();
             perms.set_readonly(true);
-
-            if let Err(e) = fs::set_permissions(&final_path, perms) {
-                return OpenResult::Err(e.into());
-            }
+            fs::set_permissions(&final_path, perms)?;
         }
 
         // And finally add a record of this file to our manifest. Note that
         // we're opening and closing this file every time we load a new file;
         // not so efficient, but whatever.
 
-        if let Err(e) = self.record_cache_result(name, length as u64, digest) {
-            return OpenResult::Err(e);
-        }
-
-        OpenResult::Ok(final_path)
+        self.record_cache_result(name, length as u64, digest)?;
+        Ok(final_path)
     }
 }
 
@@ -697,6 +989,91 @@ impl Bundle for CachedITarBundle {
     fn get_digest(&mut self, _status: &mut dyn StatusBackend) -> Result<DigestData> {
         Ok(self.cached_digest)
     }
+
+    /// Download all of the files that aren't cached yet, coalescing files
+    /// that are near each other in the tar file into single requests and
+    /// making several requests at once.
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<()> {
+        if self.only_cached {
+            return Ok(());
+        }
+
+        let wanted: BTreeMap<&str, FileInfo> = names
+            .iter()
+            .map(|n| n.as_str())
+            .filter(|n| !self.contents.contains_key(*n))
+            .filter_map(|n| self.index.get(n).map(|info| (n, *info)))
+            .collect();
+
+        if wanted.is_empty() {
+            return Ok(());
+        }
+
+        self.check_digest(status)?;
+
+        let n_files = wanted.len();
+        let spans = coalesce_ranges(
+            wanted
+                .into_iter()
+                .map(|(n, info)| (n.to_owned(), info))
+                .collect(),
+        );
+
+        tt_note!(
+            status,
+            "downloading {} files in {} requests",
+            n_files,
+            spans.len()
+        );
+
+        let mut spans = spans;
+        let mut tried_failover = false;
+
+        loop {
+            let mut failed = Vec::new();
+
+            for (span, buf, failures) in fetch_spans(&self.tar_data, spans) {
+                for e in failures {
+                    tt_warning!(status, "failure downloading part of the bundle from network"; e);
+                }
+
+                let buf = match buf {
+                    Some(b) => b,
+                    None => {
+                        failed.push(span);
+                        continue;
+                    }
+                };
+
+                for (name, info) in &span.files {
+                    let start = (info.offset - span.offset) as usize;
+                    self.cache_file(name, &buf[start..start + info.length as usize])?;
+                }
+            }
+
+            if failed.is_empty() {
+                return Ok(());
+            }
+
+            // Give the rest one more go from another mirror, if there is one.
+            if !tried_failover && self.urls.len() > 1 {
+                tried_failover = true;
+
+                if self.fail_over(status)? {
+                    spans = failed;
+                    continue;
+                }
+            }
+
+            let n_files: usize = failed.iter().map(|s| s.files.len()).sum();
+            tt_warning!(
+                status,
+                "couldn't download {} files ahead of time; they will be tried again when needed",
+                n_files
+            );
+            return Ok(());
+        }
+    }
 }
 
 /// A convenience method to provide a better error message when writing to a created file.
@@ -713,7 +1090,7 @@ where
     Ok(())
 }
 
-fn cache_dir(path: &str, custom_cache_root: Option<&Path>) -> Result<PathBuf> {
+pub(crate) fn cache_dir(path: &str, custom_cache_root: Option<&Path>) -> Result<PathBuf> {
     if let Some(root) = custom_cache_root {
         if !root.is_dir() {
             bail!("Custom cache path {} is not a directory", root.display());
@@ -725,3 +1102,28 @@ fn cache_dir(path: &str, custom_cache_root: Option<&Path>) -> Result<PathBuf> {
         app_dirs::user_cache_dir(path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_errors() {
+        let http = |code: u16| -> Error {
+            ErrorKind::UnexpectedHttpResponse(
+                "https://example.com/bundle.tar".to_owned(),
+                StatusCode::from_u16(code).unwrap(),
+            )
+            .into()
+        };
+
+        assert!(is_transient(&http(503)));
+        assert!(is_transient(&http(500)));
+        assert!(!is_transient(&http(404)));
+        assert!(!is_transient(&http(416)));
+        assert!(is_transient(
+            &std::io::Error::from(IoErrorKind::ConnectionReset).into()
+        ));
+        assert!(!is_transient(&errmsg!("something else")));
+    }
+}
@@ -4,14 +4,14 @@
 
 use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufReader, Seek, SeekFrom};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use super::{
     try_open_file, InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle,
 };
-use crate::errors::{ErrorKind, Result};
+use crate::errors::{Error, ErrorKind, Result};
 use crate::status::StatusBackend;
 
 /// FilesystemPrimaryInputIo is an I/O provider that provides the TeX "primary input"
@@ -44,23 +44,124 @@ impl IoProvider for FilesystemPrimaryInputIo {
             OpenResult::Err(e) => return OpenResult::Err(e),
         };
 
-        OpenResult::Ok(InputHandle::new(
-            OsStr::new(""),
-            BufReader::new(f),
-            InputOrigin::Filesystem,
-        ))
+        OpenResult::Ok(
+            InputHandle::new(OsStr::new(""), BufReader::new(f), InputOrigin::Filesystem)
+                .with_path(&self.path),
+        )
     }
 }
 
-/// FilesystemIo is an I/O provider that reads, and optionally writes, files
-/// from a given root directory. NOTE: no effort is made to contain I/O within
-/// the specified root!! We have an option to disallow absolute paths, but we
-/// don't do anything about "../../../...." paths.
+/// A directory that a [`FilesystemIo`] searches for input files, in the
+/// manner of an entry in the `TEXINPUTS` search path.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchDir {
+    path: PathBuf,
+    recursive: bool,
+}
+
+impl SearchDir {
+    /// Search the directory `path`. If `recursive` is true, its
+    /// subdirectories are searched as well.
+    pub fn new<P: AsRef<Path>>(path: P, recursive: bool) -> SearchDir {
+        SearchDir {
+            path: path.as_ref().to_owned(),
+            recursive,
+        }
+    }
+
+    /// Interpret a path as it would appear in `TEXINPUTS`: if it ends with
+    /// two slashes, as in `shared//`, the directory is searched recursively.
+    pub fn from_spec<P: AsRef<Path>>(spec: P) -> SearchDir {
+        let spec = spec.as_ref();
+
+        if let Some(s) = spec.to_str() {
+            let trimmed = s.trim_end_matches(std::path::is_separator);
+
+            if s.len() - trimmed.len() >= 2 {
+                let trimmed = if trimmed.is_empty() { "/" } else { trimmed };
+                return SearchDir::new(trimmed, true);
+            }
+        }
+
+        SearchDir::new(spec, false)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
+    pub fn is_recursive(&self) -> bool {
+        self.recursive
+    }
+}
+
+/// A search directory, along with the list of directories that it stands
+/// for once recursion has been taken into account. That list is computed
+/// when it's first needed, and pairs each directory with its canonical
+/// path, or `None` if it doesn't exist.
+struct SearchLayer {
+    dir: SearchDir,
+    expanded: Option<Vec<(PathBuf, Option<PathBuf>)>>,
+}
+
+impl SearchLayer {
+    fn dirs(&mut self) -> &[(PathBuf, Option<PathBuf>)] {
+        if self.expanded.is_none() {
+            let mut dirs = vec![self.dir.path.clone()];
+
+            if self.dir.recursive {
+                // Breadth-first, so that files closer to the top of the tree
+                // win. Symbolic links to directories are not followed, since
+                // they could lead us in circles.
+                let mut i = 0;
+
+                while i < dirs.len() {
+                    let mut subdirs: Vec<PathBuf> = match fs::read_dir(&dirs[i]) {
+                        Ok(entries) => entries
+                            .filter_map(|e| e.ok())
+                            .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+                            .filter(|e| e.file_type().map(|t| t.is_dir()).unwrap_or(false))
+                            .map(|e| e.path())
+                            .collect(),
+                        Err(_) => Vec::new(),
+                    };
+                    subdirs.sort();
+                    dirs.append(&mut subdirs);
+                    i += 1;
+                }
+            }
+
+            self.expanded = Some(
+                dirs.into_iter()
+                    .map(|d| {
+                        let real = canonical_dir(&d);
+                        (d, real)
+                    })
+                    .collect(),
+            );
+        }
+
+        self.expanded.as_ref().unwrap()
+    }
+}
+
+/// FilesystemIo is an I/O provider that reads, and optionally writes, files
+/// from a given root directory. NOTE: unless the I/O provider is
+/// [sandboxed](FilesystemIo::sandboxed), no effort is made to contain I/O
+/// within the specified root!! We have an option to disallow absolute paths,
+/// but we don't do anything about "../../../...." paths.
+///
+/// Input files that aren't found in the root directory are looked for in the
+/// search directories, in the order in which they were added. Files are only
+/// ever written to the root directory.
 pub struct FilesystemIo {
     root: PathBuf,
+    search_layers: Vec<SearchLayer>,
     writes_allowed: bool,
     absolute_allowed: bool,
+    sandboxed: bool,
+    /// The canonical path of `root`, if it exists, when sandboxed.
+    real_root: Option<PathBuf>,
     hidden_input_paths: HashSet<PathBuf>,
 }
 
@@ -73,13 +174,45 @@ impl FilesystemIo {
     ) -> FilesystemIo {
         FilesystemIo {
             root: PathBuf::from(root),
+            search_layers: Vec::new(),
             writes_allowed,
             absolute_allowed,
+            sandboxed: false,
+            real_root: None,
             hidden_input_paths,
         }
     }
 
+    /// Add a directory in which to look for input files that aren't found
+    /// in the root directory.
+    pub fn add_search_dir(&mut self, dir: SearchDir) -> &mut Self {
+        self.search_layers.push(SearchLayer {
+            dir,
+            expanded: None,
+        });
+        self
+    }
+
+    /// Confine all I/O to the root and search directories. In this mode,
+    /// absolute paths are forbidden, and so are relative paths that lead out
+    /// of the directory they're resolved against, whether through `..` or
+    /// through symbolic links. Trying to use such a path is an error of kind
+    /// [`ErrorKind::PathForbidden`].
+    pub fn sandboxed(&mut self, sandboxed: bool) -> &mut Self {
+        self.sandboxed = sandboxed;
+        self.real_root = if sandboxed {
+            canonical_dir(&self.root)
+        } else {
+            None
+        };
+        self
+    }
+
     fn construct_path(&mut self, name: &OsStr) -> Result<PathBuf> {
+        if self.sandboxed {
+            return confine(&self.root, self.real_root.as_deref(), name);
+        }
+
         let path = Path::new(name);
 
         if path.is_absolute() && !self.absolute_allowed {
@@ -127,33 +260,141 @@ impl IoProvider for FilesystemIo {
             Err(e) => return OpenResult::Err(e),
         };
 
-        if self.hidden_input_paths.contains(&path) {
-            return OpenResult::NotAvailable;
+        let r = open_candidate(name, path, &self.hidden_input_paths);
+
+        // Absolute paths are the same whichever directory we start from.
+        if !r.is_not_available() || Path::new(name).is_absolute() {
+            return r;
         }
 
-        let f = match File::open(path) {
-            Ok(f) => f,
-            Err(e) => {
-                return if e.kind() == io::ErrorKind::NotFound {
-                    OpenResult::NotAvailable
-                } else if let Some(libc::ENOTDIR) = e.raw_os_error() {
-                    // xdvipdfmx has a code path that basically tries to open a
-                    // font path assuming that it is a directory, which causes an
-                    // ENOTDIR to happen (i.e., it tries the equivalent of
-                    // open("/etc/passwd/subdir"). This circumstance is harmless.
-                    OpenResult::NotAvailable
+        for layer in &mut self.search_layers {
+            for (d, real) in layer.dirs() {
+                let path = if self.sandboxed {
+                    // Nothing can be found in a directory that doesn't
+                    // exist, so don't bother checking the path.
+                    if real.is_none() {
+                        continue;
+                    }
+
+                    match confine(d, real.as_deref(), name) {
+                        Ok(p) => p,
+                        Err(e) => return OpenResult::Err(e),
+                    }
                 } else {
-                    OpenResult::Err(e.into())
+                    d.join(name)
                 };
+                let r = open_candidate(name, path, &self.hidden_input_paths);
+
+                if !r.is_not_available() {
+                    return r;
+                }
+            }
+        }
+
+        OpenResult::NotAvailable
+    }
+}
+
+/// Resolve `name` relative to `base`, making sure that the result lies
+/// within `base`. That rules out absolute paths and paths that climb out of
+/// `base` using `..`. Symbolic links are checked by resolving the longest
+/// part of the path that exists, so that a link can't lead outside of `base`
+/// either, even if the file that it points to doesn't exist yet. If `base`
+/// itself doesn't exist, nothing can be reached through it, so only `name`
+/// is checked.
+pub fn confined_path<P: AsRef<Path>>(base: P, name: &OsStr) -> Result<PathBuf> {
+    let base = base.as_ref();
+    confine(base, canonical_dir(base).as_deref(), name)
+}
+
+/// The canonical form of the directory `dir`, or `None` if it doesn't exist.
+/// An empty path means the current directory.
+fn canonical_dir(dir: &Path) -> Option<PathBuf> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+
+    fs::canonicalize(dir).ok()
+}
+
+/// The guts of [`confined_path`], given the canonical path of `base`.
+fn confine(base: &Path, real_base: Option<&Path>, name: &OsStr) -> Result<PathBuf> {
+    let path = Path::new(name);
+    let forbidden =
+        || -> Error { ErrorKind::PathForbidden(path.to_string_lossy().into_owned()).into() };
+
+    let mut depth = 0usize;
+
+    for c in path.components() {
+        match c {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if depth == 0 {
+                    return Err(forbidden());
+                }
+                depth -= 1;
             }
+            Component::RootDir | Component::Prefix(_) => return Err(forbidden()),
+        }
+    }
+
+    let combined = base.join(path);
+
+    let real_base = match real_base {
+        Some(b) => b,
+        None => return Ok(combined),
+    };
+    let check = real_base.join(path);
+
+    for ancestor in check.ancestors() {
+        if fs::symlink_metadata(ancestor).is_err() {
+            continue;
+        }
+
+        // If this fails, we have a dangling symbolic link, which could
+        // point anywhere.
+        return match fs::canonicalize(ancestor) {
+            Ok(ref p) if p.starts_with(real_base) => Ok(combined),
+            _ => Err(forbidden()),
         };
+    }
+
+    Ok(combined)
+}
 
-        OpenResult::Ok(InputHandle::new(
-            name,
-            BufReader::new(f),
-            InputOrigin::Filesystem,
-        ))
+/// Try to open the input file `name`, which would be found at `path`.
+fn open_candidate(
+    name: &OsStr,
+    path: PathBuf,
+    hidden_input_paths: &HashSet<PathBuf>,
+) -> OpenResult<InputHandle> {
+    if hidden_input_paths.contains(&path) {
+        return OpenResult::NotAvailable;
     }
+
+    let f = match File::open(&path) {
+        Ok(f) => f,
+        Err(e) => {
+            return if e.kind() == io::ErrorKind::NotFound {
+                OpenResult::NotAvailable
+            } else if let Some(libc::ENOTDIR) = e.raw_os_error() {
+                // xdvipdfmx has a code path that basically tries to open a
+                // font path assuming that it is a directory, which causes an
+                // ENOTDIR to happen (i.e., it tries the equivalent of
+                // open("/etc/passwd/subdir"). This circumstance is harmless.
+                OpenResult::NotAvailable
+            } else {
+                OpenResult::Err(e.into())
+            };
+        }
+    };
+
+    OpenResult::Ok(
+        InputHandle::new(name, BufReader::new(f), InputOrigin::Filesystem).with_path(path),
+    )
 }
 
 impl InputFeatures for File {
@@ -175,3 +416,147 @@ impl InputFeatures for BufReader<File> {
         Ok(self.seek(pos)?)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::NoopStatusBackend;
+    use std::io::Read;
+    use tempfile::TempDir;
+
+    fn read(io: &mut FilesystemIo, name: &str) -> Option<(String, PathBuf)> {
+        let mut status = NoopStatusBackend::new();
+
+        match io.input_open_name(OsStr::new(name), &mut status) {
+            OpenResult::Ok(mut h) => {
+                let path = h.path().unwrap().to_owned();
+                let mut text = String::new();
+                h.read_to_string(&mut text).unwrap();
+                Some((text, path))
+            }
+            OpenResult::NotAvailable => None,
+            OpenResult::Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn search_dir_spec() {
+        assert_eq!(SearchDir::from_spec("a/b"), SearchDir::new("a/b", false));
+        assert_eq!(SearchDir::from_spec("a/b/"), SearchDir::new("a/b/", false));
+        assert_eq!(SearchDir::from_spec("a/b//"), SearchDir::new("a/b", true));
+        assert_eq!(SearchDir::from_spec("a/b///"), SearchDir::new("a/b", true));
+        assert_eq!(SearchDir::from_spec("//"), SearchDir::new("/", true));
+    }
+
+    #[test]
+    fn layered_lookup() {
+        let dir = TempDir::new().unwrap();
+        let p = dir.path();
+
+        for d in &["root", "flat/sub", "deep/a/b", "deep/c"] {
+            fs::create_dir_all(p.join(d)).unwrap();
+        }
+
+        fs::write(p.join("root/main.sty"), "root").unwrap();
+        fs::write(p.join("flat/main.sty"), "flat").unwrap();
+        fs::write(p.join("flat/sub/nested.sty"), "flat-nested").unwrap();
+        fs::write(p.join("flat/only.sty"), "flat-only").unwrap();
+        fs::write(p.join("deep/a/b/nested.sty"), "deep-b").unwrap();
+        fs::write(p.join("deep/c/nested.sty"), "deep-c").unwrap();
+        fs::write(p.join("deep/c/hidden.sty"), "hidden").unwrap();
+
+        let mut hidden = HashSet::new();
+        hidden.insert(p.join("deep/c/hidden.sty"));
+
+        let mut io = FilesystemIo::new(&p.join("root"), false, false, hidden);
+        io.add_search_dir(SearchDir::from_spec(p.join("flat")))
+            .add_search_dir(SearchDir::from_spec(p.join("deep//")));
+
+        // The root wins, then the search directories in order.
+        assert_eq!(
+            read(&mut io, "main.sty"),
+            Some(("root".to_owned(), p.join("root/main.sty")))
+        );
+        assert_eq!(
+            read(&mut io, "only.sty"),
+            Some(("flat-only".to_owned(), p.join("flat/only.sty")))
+        );
+
+        // "flat" isn't recursive; in "deep", shallower directories win.
+        assert_eq!(
+            read(&mut io, "nested.sty"),
+            Some(("deep-c".to_owned(), p.join("deep/c/nested.sty")))
+        );
+        assert_eq!(
+            read(&mut io, "sub/nested.sty"),
+            Some(("flat-nested".to_owned(), p.join("flat/sub/nested.sty")))
+        );
+        assert_eq!(
+            read(&mut io, "b/nested.sty"),
+            Some(("deep-b".to_owned(), p.join("deep/a/b/nested.sty")))
+        );
+
+        assert_eq!(read(&mut io, "hidden.sty"), None);
+        assert_eq!(read(&mut io, "missing.sty"), None);
+    }
+
+    fn is_forbidden(r: Result<PathBuf>) -> bool {
+        matches!(r, Err(Error(ErrorKind::PathForbidden(_), _)))
+    }
+
+    #[test]
+    fn confinement() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(dir.path().join("secret.tex"), "secret").unwrap();
+        fs::write(root.join("main.tex"), "main").unwrap();
+
+        let check = |name: &str| confined_path(&root, OsStr::new(name));
+
+        assert_eq!(check("main.tex").unwrap(), root.join("main.tex"));
+        assert_eq!(
+            check("sub/../new.tex").unwrap(),
+            root.join("sub/../new.tex")
+        );
+        assert!(check("sub/missing/file.tex").is_ok());
+        assert!(is_forbidden(check("../secret.tex")));
+        assert!(is_forbidden(check("sub/../../secret.tex")));
+        assert!(is_forbidden(check(
+            &dir.path().join("secret.tex").to_string_lossy()
+        )));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path(), root.join("escape")).unwrap();
+            std::os::unix::fs::symlink(root.join("sub"), root.join("inside")).unwrap();
+            assert!(is_forbidden(check("escape/secret.tex")));
+            assert!(is_forbidden(check("escape/new.tex")));
+            assert!(check("inside/new.tex").is_ok());
+        }
+
+        let mut io = FilesystemIo::new(&root, false, false, HashSet::new());
+        io.sandboxed(true);
+        let mut status = NoopStatusBackend::new();
+
+        assert_eq!(
+            read(&mut io, "main.tex").map(|r| r.0),
+            Some("main".to_owned())
+        );
+        assert!(matches!(
+            io.input_open_name(OsStr::new("../secret.tex"), &mut status),
+            OpenResult::Err(Error(ErrorKind::PathForbidden(_), _))
+        ));
+
+        // A search directory that doesn't exist is just skipped.
+        fs::write(root.join("sub/found.tex"), "found").unwrap();
+        io.add_search_dir(SearchDir::new(dir.path().join("missing"), false))
+            .add_search_dir(SearchDir::new(root.join("sub"), false));
+        assert_eq!(
+            read(&mut io, "found.tex").map(|r| r.0),
+            Some("found".to_owned())
+        );
+        assert_eq!(read(&mut io, "absent.tex"), None);
+        assert!(confined_path(dir.path().join("missing"), OsStr::new("x.tex")).is_ok());
+    }
+}
@@ -6,7 +6,8 @@
 //! Code for locally caching compiled format files.
 
 use std::ffi::OsStr;
-use std::io::{BufReader, Write};
+use std::fs;
+use std::io::{BufReader, ErrorKind as IoErrorKind, Write};
 use std::path::PathBuf;
 
 use super::{InputHandle, InputOrigin, IoProvider, OpenResult};
@@ -20,6 +21,7 @@ use crate::status::StatusBackend;
 /// same root cache directory as the `LocalCache` item, but is implemented
 /// separately so that there is a way to save the format files associated with
 /// backends that may not have their own LocalCache.
+#[derive(Clone)]
 pub struct FormatCache {
     bundle_digest: DigestData,
     formats_base: PathBuf,
@@ -64,6 +66,38 @@ impl FormatCache {
         ));
         Ok(p)
     }
+
+    /// Get the names of the inputs recorded for the format `name` by
+    /// [`FormatCache::save_inputs`], if there are any.
+    pub fn load_inputs(&mut self, name: &str) -> Result<Option<Vec<String>>> {
+        let path = self
+            .path_for_format(OsStr::new(name))?
+            .with_extension("inputs");
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(Some(text.lines().map(|l| l.to_owned()).collect())),
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record the names of the files, other than those in the bundle, that a
+    /// format was made from. This is used for formats made from documents'
+    /// preambles, which must be remade if any of these files change.
+    pub fn save_inputs<S: AsRef<str>>(&mut self, name: &str, inputs: &[S]) -> Result<()> {
+        let path = self
+            .path_for_format(OsStr::new(name))?
+            .with_extension("inputs");
+        let mut text = String::new();
+
+        for input in inputs {
+            text.push_str(input.as_ref());
+            text.push('\n');
+        }
+
+        fs::write(path, text)?;
+        Ok(())
+    }
 }
 
 impl IoProvider for FormatCache {
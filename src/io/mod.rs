@@ -9,7 +9,7 @@ use std::borrow::Cow;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use crate::ctry;
@@ -17,12 +17,16 @@ use crate::digest::{self, Digest, DigestData};
 use crate::errors::{Error, ErrorKind, Result};
 use crate::status::StatusBackend;
 
+pub mod bundle_builder;
+pub mod cache_admin;
 pub mod cached_itarbundle;
 pub mod dirbundle;
 pub mod filesystem;
 pub mod format_cache;
 pub mod memory;
+pub mod recording;
 pub mod setup;
+pub mod shared;
 pub mod stack;
 pub mod stdstreams;
 pub mod zipbundle;
@@ -80,6 +84,7 @@ pub struct InputHandle {
     read_only: bool,
     digest: digest::DigestComputer,
     origin: InputOrigin,
+    path: Option<PathBuf>,
     ever_read: bool,
     did_unhandled_seek: bool,
 }
@@ -96,6 +101,7 @@ impl InputHandle {
             read_only: false,
             digest: Default::default(),
             origin,
+            path: None,
             ever_read: false,
             did_unhandled_seek: false,
         }
@@ -112,6 +118,7 @@ impl InputHandle {
             read_only: true,
             digest: Default::default(),
             origin,
+            path: None,
             ever_read: false,
             did_unhandled_seek: false,
         }
@@ -125,6 +132,17 @@ impl InputHandle {
         self.origin
     }
 
+    /// Record where on the filesystem the file was found.
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> InputHandle {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// The path of the file on the filesystem, if it came from there.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
     /// Consumes the object and returns the underlying readable handle that
     /// it references.
     pub fn into_inner(self) -> Box<dyn InputFeatures> {
@@ -436,12 +454,28 @@ pub trait Bundle: IoProvider {
 
         Ok(ctry!(DigestData::from_str(&digest_text); "corrupted SHA256 digest data"))
     }
+
+    /// Let the bundle know that the files `names` are likely to be needed
+    /// soon, so that it can get hold of them in bulk rather than one at a
+    /// time.
+    ///
+    /// Names that the bundle doesn't contain are ignored. Failing to get a
+    /// file here isn't fatal, since it can always be tried again when it's
+    /// actually opened. The default implementation does nothing, which is
+    /// appropriate for bundles whose files are all available locally.
+    fn prefetch(&mut self, _names: &[String], _status: &mut dyn StatusBackend) -> Result<()> {
+        Ok(())
+    }
 }
 
 impl<B: Bundle + ?Sized> Bundle for Box<B> {
     fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
         (**self).get_digest(status)
     }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<()> {
+        (**self).prefetch(names, status)
+    }
 }
 
 // Some generically helpful InputFeatures impls
@@ -468,7 +502,7 @@ impl InputFeatures for Cursor<Vec<u8>> {
 
 // Reexports
 
-pub use self::filesystem::{FilesystemIo, FilesystemPrimaryInputIo};
+pub use self::filesystem::{FilesystemIo, FilesystemPrimaryInputIo, SearchDir};
 pub use self::memory::MemoryIo;
 pub use self::setup::{IoSetup, IoSetupBuilder};
 pub use self::stack::IoStack;
@@ -0,0 +1,152 @@
+// src/io/recording.rs -- a bundle that remembers which files were read from it
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A wrapper that keeps track of the files that are read from a bundle.
+//!
+//! This is how `tectonic fetch` finds out which parts of a bundle a document
+//! needs: the document is processed with its bundle wrapped in a
+//! [`RecordingBundle`], and afterwards the names of all the files that the
+//! engines successfully opened can be obtained from the bundle's
+//! [`BundleRecorder`].
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::rc::Rc;
+
+use super::{Bundle, InputHandle, IoProvider, OpenResult, OutputHandle};
+use crate::digest::DigestData;
+use crate::errors::Result;
+use crate::status::StatusBackend;
+
+/// A shared handle on the set of names recorded by a [`RecordingBundle`].
+/// It stays usable after the bundle itself has been handed off to a
+/// processing session.
+#[derive(Clone, Debug, Default)]
+pub struct BundleRecorder {
+    names: Rc<RefCell<BTreeSet<String>>>,
+}
+
+impl BundleRecorder {
+    /// The names of the files that have been read so far, in sorted order.
+    pub fn names(&self) -> Vec<String> {
+        self.names.borrow().iter().cloned().collect()
+    }
+}
+
+/// A bundle that passes all requests on to another one, noting the name of
+/// each file that it manages to open.
+pub struct RecordingBundle<B: Bundle> {
+    inner: B,
+    recorder: BundleRecorder,
+}
+
+impl<B: Bundle> RecordingBundle<B> {
+    pub fn new(inner: B) -> RecordingBundle<B> {
+        RecordingBundle {
+            inner,
+            recorder: BundleRecorder::default(),
+        }
+    }
+
+    /// Get a handle on the names that this bundle records.
+    pub fn recorder(&self) -> BundleRecorder {
+        self.recorder.clone()
+    }
+
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Bundle> IoProvider for RecordingBundle<B> {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        self.inner.output_open_name(name)
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        self.inner.output_open_stdout()
+    }
+
+    fn input_open_name(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        let r = self.inner.input_open_name(name, status);
+
+        if let OpenResult::Ok(_) = r {
+            // Bundles only deal in UTF8 names, so there's nothing to lose
+            // here.
+            if let Some(s) = name.to_str() {
+                self.recorder.names.borrow_mut().insert(s.to_owned());
+            }
+        }
+
+        r
+    }
+
+    fn input_open_primary(&mut self, status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        self.inner.input_open_primary(status)
+    }
+
+    fn input_open_format(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.inner.input_open_format(name, status)
+    }
+
+    fn write_format(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        status: &mut dyn StatusBackend,
+    ) -> Result<()> {
+        self.inner.write_format(name, data, status)
+    }
+}
+
+impl<B: Bundle> Bundle for RecordingBundle<B> {
+    fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        self.inner.get_digest(status)
+    }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<()> {
+        self.inner.prefetch(names, status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::zipbundle::ZipBundle;
+    use crate::status::NoopStatusBackend;
+    use std::io::{Cursor, Write};
+    use zip::write::{FileOptions, ZipWriter};
+
+    #[test]
+    fn records_only_opened_files() {
+        let mut buf = Vec::new();
+        {
+            let mut zip = ZipWriter::new(Cursor::new(&mut buf));
+            for name in &["b.sty", "a.tex"] {
+                zip.start_file(*name, FileOptions::default()).unwrap();
+                zip.write_all(b"\\relax").unwrap();
+            }
+            zip.finish().unwrap();
+        }
+
+        let mut bundle = RecordingBundle::new(ZipBundle::new(Cursor::new(buf)).unwrap());
+        let recorder = bundle.recorder();
+        let mut status = NoopStatusBackend::new();
+
+        for name in &["b.sty", "missing.cls", "a.tex", "b.sty"] {
+            let _ = bundle.input_open_name(OsStr::new(name), &mut status);
+        }
+
+        assert_eq!(recorder.names(), vec!["a.tex", "b.sty"]);
+    }
+}
@@ -7,6 +7,7 @@ use crate::io::format_cache::FormatCache;
 use crate::io::stdstreams::BufferedPrimaryIo;
 use crate::io::{
     Bundle, FilesystemIo, FilesystemPrimaryInputIo, GenuineStdoutIo, IoProvider, IoStack, MemoryIo,
+    SearchDir,
 };
 use crate::status::StatusBackend;
 
@@ -34,13 +35,29 @@ pub struct IoSetup {
 
 impl IoSetup {
     pub fn as_stack(&mut self) -> IoStack {
+        self.stack_with_primary(None)
+    }
+
+    /// Creates an `IoStack` just like [`IoSetup::as_stack`], except that
+    /// `primary` takes the place of the usual primary input.
+    pub fn as_stack_with_primary<'a>(&'a mut self, primary: &'a mut dyn IoProvider) -> IoStack<'a> {
+        self.stack_with_primary(Some(primary))
+    }
+
+    fn stack_with_primary<'a>(
+        &'a mut self,
+        primary: Option<&'a mut dyn IoProvider>,
+    ) -> IoStack<'a> {
         let mut providers: Vec<&mut dyn IoProvider> = Vec::new();
 
         if let Some(ref mut p) = self.genuine_stdout {
             providers.push(p);
         }
 
-        providers.push(&mut *self.primary_input);
+        match primary {
+            Some(p) => providers.push(p),
+            None => providers.push(&mut *self.primary_input),
+        }
         providers.push(&mut self.mem);
         providers.push(&mut self.filesystem);
 
@@ -114,8 +131,11 @@ pub struct IoSetupBuilder {
     primary_input: PrimaryInputMode,
     filesystem_root: PathBuf,
     format_cache_path: Option<PathBuf>,
+    format_cache: Option<FormatCache>,
+    search_dirs: Vec<SearchDir>,
     bundle: Option<Box<dyn Bundle>>,
     use_genuine_stdout: bool,
+    sandboxed: bool,
     hidden_input_paths: HashSet<PathBuf>,
 }
 
@@ -125,8 +145,11 @@ impl Default for IoSetupBuilder {
             primary_input: PrimaryInputMode::Undefined,
             filesystem_root: PathBuf::new(),
             format_cache_path: None,
+            format_cache: None,
+            search_dirs: Vec::new(),
             bundle: None,
             use_genuine_stdout: false,
+            sandboxed: false,
             hidden_input_paths: HashSet::new(),
         }
     }
@@ -185,6 +208,31 @@ impl IoSetupBuilder {
         self
     }
 
+    /// Uses an existing format cache.
+    ///
+    /// The cache must have been created for the bundle that is given to
+    /// `self.bundle()`. This allows several setups that use the same bundle
+    /// to share one cache without each of them computing the bundle digest,
+    /// which may require network access. If set, `format_cache_path()` is
+    /// ignored.
+    pub fn format_cache(&mut self, cache: FormatCache) -> &mut Self {
+        self.format_cache = Some(cache);
+        self
+    }
+
+    /// Adds a directory in which to look for input files that are not found
+    /// in the filesystem root.
+    ///
+    /// Directories are searched in the order in which they were added, after
+    /// the filesystem root and before the bundle. As with `TEXINPUTS`, a
+    /// path ending in two slashes, like `shared//`, means that the
+    /// directory's subdirectories are searched too. Files are never written
+    /// to these directories.
+    pub fn search_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.search_dirs.push(SearchDir::from_spec(path));
+        self
+    }
+
     /// Adds a bundle to the I/O configuration.
     pub fn bundle<T: 'static + Bundle>(&mut self, bundle: T) -> &mut Self {
         self.bundle = Some(Box::new(bundle));
@@ -200,6 +248,15 @@ impl IoSetupBuilder {
         self
     }
 
+    /// Confines filesystem I/O to the filesystem root and the search
+    /// directories.
+    ///
+    /// See [`FilesystemIo::sandboxed`] for what this entails.
+    pub fn sandboxed(&mut self, setting: bool) -> &mut Self {
+        self.sandboxed = setting;
+        self
+    }
+
     /// Marks a path as hidden, meaning that the filesystem layer will pretend it doesn't exist.
     pub fn hide_path<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
         self.hidden_input_paths.insert(path.as_ref().to_owned());
@@ -212,7 +269,9 @@ impl IoSetupBuilder {
     ///
     /// Panics if no primary input mechanism was specified.
     pub fn create(mut self, status: &mut dyn StatusBackend) -> Result<IoSetup> {
-        let format_cache = if let Some(ref mut b) = self.bundle {
+        let format_cache = if let Some(c) = self.format_cache {
+            Some(c)
+        } else if let Some(ref mut b) = self.bundle {
             let default_path = self.filesystem_root.clone(); // unwrap_or_else() causes borrowck issues
             let format_cache_path = self.format_cache_path.unwrap_or(default_path);
             Some(FormatCache::new(b.get_digest(status)?, format_cache_path))
@@ -234,15 +293,19 @@ impl IoSetupBuilder {
             }
         };
 
+        let mut filesystem =
+            FilesystemIo::new(&self.filesystem_root, false, true, self.hidden_input_paths);
+
+        filesystem.sandboxed(self.sandboxed);
+
+        for d in self.search_dirs {
+            filesystem.add_search_dir(d);
+        }
+
         Ok(IoSetup {
             primary_input: pio,
             mem: MemoryIo::new(true),
-            filesystem: FilesystemIo::new(
-                &self.filesystem_root,
-                false,
-                true,
-                self.hidden_input_paths,
-            ),
+            filesystem,
             format_cache,
             bundle: self.bundle,
             genuine_stdout: if self.use_genuine_stdout {
@@ -0,0 +1,82 @@
+// src/io/shared.rs -- a bundle that can be used by several sessions in turn
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A handle that lets several processing sessions use the same bundle.
+//!
+//! A [`crate::driver::ProcessingSession`] takes ownership of its bundle, and
+//! if setting up the session fails, the bundle goes down with it. Opening a
+//! network bundle is expensive, so `tectonic build`, which runs one session
+//! per target, gives each of them a clone of a [`SharedBundle`] instead.
+
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::rc::Rc;
+
+use super::{Bundle, InputHandle, IoProvider, OpenResult, OutputHandle};
+use crate::digest::DigestData;
+use crate::errors::Result;
+use crate::status::StatusBackend;
+
+/// A cloneable handle on a bundle. All clones refer to the same bundle.
+#[derive(Clone)]
+pub struct SharedBundle {
+    inner: Rc<RefCell<Box<dyn Bundle>>>,
+}
+
+impl SharedBundle {
+    pub fn new(inner: Box<dyn Bundle>) -> SharedBundle {
+        SharedBundle {
+            inner: Rc::new(RefCell::new(inner)),
+        }
+    }
+}
+
+impl IoProvider for SharedBundle {
+    fn output_open_name(&mut self, name: &OsStr) -> OpenResult<OutputHandle> {
+        self.inner.borrow_mut().output_open_name(name)
+    }
+
+    fn output_open_stdout(&mut self) -> OpenResult<OutputHandle> {
+        self.inner.borrow_mut().output_open_stdout()
+    }
+
+    fn input_open_name(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.inner.borrow_mut().input_open_name(name, status)
+    }
+
+    fn input_open_primary(&mut self, status: &mut dyn StatusBackend) -> OpenResult<InputHandle> {
+        self.inner.borrow_mut().input_open_primary(status)
+    }
+
+    fn input_open_format(
+        &mut self,
+        name: &OsStr,
+        status: &mut dyn StatusBackend,
+    ) -> OpenResult<InputHandle> {
+        self.inner.borrow_mut().input_open_format(name, status)
+    }
+
+    fn write_format(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        status: &mut dyn StatusBackend,
+    ) -> Result<()> {
+        self.inner.borrow_mut().write_format(name, data, status)
+    }
+}
+
+impl Bundle for SharedBundle {
+    fn get_digest(&mut self, status: &mut dyn StatusBackend) -> Result<DigestData> {
+        self.inner.borrow_mut().get_digest(status)
+    }
+
+    fn prefetch(&mut self, names: &[String], status: &mut dyn StatusBackend) -> Result<()> {
+        self.inner.borrow_mut().prefetch(names, status)
+    }
+}
@@ -4,14 +4,17 @@
 
 use std::ffi::OsStr;
 use std::fs::File;
-use std::io::{Cursor, Read, Seek};
+use std::io::{Cursor, Read, Seek, Write};
 use std::path::Path;
 use zip::result::ZipError;
+use zip::write::{FileOptions, ZipWriter};
 use zip::ZipArchive;
 
 use super::{Bundle, InputHandle, InputOrigin, IoProvider, OpenResult};
+use crate::digest::{self, Digest, DigestData};
 use crate::errors::Result;
 use crate::status::StatusBackend;
+use crate::{ctry, errmsg};
 
 pub struct ZipBundle<R: Read + Seek> {
     zip: ZipArchive<R>,
@@ -29,6 +32,64 @@ impl ZipBundle<File> {
     pub fn open<P: AsRef<Path>>(path: P) -> Result<ZipBundle<File>> {
         Self::new(File::open(path)?)
     }
+
+    /// Create a new Zip-format bundle at `path` that holds copies of the
+    /// files `names` taken from the bundle `source`, and open it.
+    ///
+    /// The new bundle gets a `SHA256SUM` file of its own, computed from its
+    /// contents with [`digest::bundle_digest`], so that formats generated
+    /// from it are never confused with those of `source`. It is an error for
+    /// any of the files to be missing from `source`.
+    pub fn create_from<P, B, S>(
+        path: P,
+        source: &mut B,
+        names: &[S],
+        status: &mut dyn StatusBackend,
+    ) -> Result<ZipBundle<File>>
+    where
+        P: AsRef<Path>,
+        B: Bundle + ?Sized,
+        S: AsRef<str>,
+    {
+        let path = path.as_ref();
+        let f = ctry!(File::create(path); "couldn't open \"{}\" for writing", path.display());
+        let mut zip = ZipWriter::new(f);
+        let mut digests = Vec::with_capacity(names.len());
+
+        for name in names {
+            let name = name.as_ref();
+
+            if name == digest::DIGEST_NAME {
+                continue;
+            }
+
+            let mut buf = Vec::new();
+
+            match source.input_open_name(OsStr::new(name), status) {
+                OpenResult::Ok(mut h) => {
+                    ctry!(h.read_to_end(&mut buf); "couldn't read \"{}\" from the bundle", name)
+                }
+                OpenResult::NotAvailable => {
+                    return Err(errmsg!("the bundle does not contain \"{}\"", name));
+                }
+                OpenResult::Err(e) => return Err(e),
+            };
+
+            let mut dc = digest::create();
+            dc.input(&buf);
+            digests.push((name, DigestData::from(dc)));
+
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(&buf)?;
+        }
+
+        let bundle_digest = digest::bundle_digest(digests.iter().map(|(n, d)| (*n, d)));
+        zip.start_file(digest::DIGEST_NAME, FileOptions::default())?;
+        zip.write_all(bundle_digest.to_string().as_bytes())?;
+        ctry!(zip.finish(); "couldn't finish writing \"{}\"", path.display());
+
+        Self::open(path)
+    }
 }
 
 impl<R: Read + Seek> IoProvider for ZipBundle<R> {
@@ -75,3 +136,85 @@ impl<R: Read + Seek> IoProvider for ZipBundle<R> {
 }
 
 impl<R: Read + Seek> Bundle for ZipBundle<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::NoopStatusBackend;
+    use std::str::FromStr;
+    use tempfile::TempDir;
+
+    fn make_zip(files: &[(&str, &[u8])]) -> ZipBundle<Cursor<Vec<u8>>> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+
+        for (name, data) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+
+        ZipBundle::new(Cursor::new(zip.finish().unwrap().into_inner())).unwrap()
+    }
+
+    fn read(bundle: &mut dyn Bundle, name: &str) -> OpenResult<Vec<u8>> {
+        let mut status = NoopStatusBackend::new();
+
+        match bundle.input_open_name(OsStr::new(name), &mut status) {
+            OpenResult::Ok(mut h) => {
+                let mut buf = Vec::new();
+                h.read_to_end(&mut buf).unwrap();
+                OpenResult::Ok(buf)
+            }
+            OpenResult::NotAvailable => OpenResult::NotAvailable,
+            OpenResult::Err(e) => OpenResult::Err(e),
+        }
+    }
+
+    #[test]
+    fn create_from_subset() {
+        let mut source = make_zip(&[
+            ("a.tex", b"aaa"),
+            ("b.sty", b"bbb"),
+            ("c.cls", b"ccc"),
+            (digest::DIGEST_NAME, b"not the digest of the subset"),
+        ]);
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("subset.zip");
+        let mut status = NoopStatusBackend::new();
+
+        let mut subset =
+            ZipBundle::create_from(&path, &mut source, &["c.cls", "a.tex"], &mut status).unwrap();
+
+        assert_eq!(read(&mut subset, "a.tex").unwrap(), b"aaa");
+        assert_eq!(read(&mut subset, "c.cls").unwrap(), b"ccc");
+        assert!(read(&mut subset, "b.sty").is_not_available());
+
+        let hash = |data: &[u8]| {
+            let mut dc = digest::create();
+            dc.input(data);
+            DigestData::from(dc)
+        };
+        let (da, dc) = (hash(b"aaa"), hash(b"ccc"));
+        let expected = digest::bundle_digest(vec![("c.cls", &dc), ("a.tex", &da)]);
+        let sum = read(&mut subset, digest::DIGEST_NAME).unwrap();
+        assert_eq!(
+            DigestData::from_str(std::str::from_utf8(&sum).unwrap()).unwrap(),
+            expected
+        );
+        assert_eq!(subset.get_digest(&mut status).unwrap(), expected);
+    }
+
+    #[test]
+    fn create_from_missing_file() {
+        let mut source = make_zip(&[("a.tex", b"aaa")]);
+        let dir = TempDir::new().unwrap();
+        let mut status = NoopStatusBackend::new();
+
+        assert!(ZipBundle::create_from(
+            dir.path().join("subset.zip"),
+            &mut source,
+            &["a.tex", "nope.sty"],
+            &mut status
+        )
+        .is_err());
+    }
+}
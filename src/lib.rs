@@ -56,7 +56,9 @@ pub mod engines;
 pub mod errors;
 pub mod io;
 pub mod status;
+pub mod synctex;
 pub mod unstable_opts;
+pub mod watch;
 
 // Note: this module is intentionally *not* gated by #[cfg(test)] -- see its
 // docstring for details.
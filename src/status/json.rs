@@ -0,0 +1,346 @@
+// src/status/json.rs -- machine-readable status reporting
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A status backend that emits JSON.
+//!
+//! Each message is printed as a JSON object on a line of its own, for the
+//! benefit of editors, CI systems, and other programs that drive Tectonic.
+//! Every object has the same keys:
+//!
+//! - `kind`: one of `"note"`, `"warning"`, `"error"`, or `"log"`. The last
+//!   is used for the raw output of an engine that failed.
+//! - `message`: the text of the message.
+//! - `engine`: the name of the engine that was running when the message was
+//!   issued (such as `"TeX"` or `"xdvipdfmx"`), or `null`.
+//! - `pass`: the number of the engine pass during which the message was
+//!   issued, starting at 1, or `null`.
+//! - `rerun_reason`: if the TeX engine was being rerun, an object whose
+//!   `kind` key is `"bibtex"`, `"file_change"` (with a `file` key naming the
+//!   file), or `"requested"`; otherwise `null`.
+//! - `file` and `line`: the TeX source location that the message refers to,
+//!   or `null` if it isn’t known.
+//! - `causes`: an array of strings giving the chain of underlying errors.
+//!
+//! TeX reports locations most reliably when errors are printed in the
+//! `file:line: message` style; see
+//! [`crate::driver::ProcessingSessionBuilder::file_line_errors`].
+
+use std::fmt::{Arguments, Write as FmtWrite};
+use std::io::{self, Write};
+
+use super::{ChatterLevel, MessageKind, StatusBackend};
+use crate::driver::RerunReason;
+use crate::errors::Error;
+
+/// What the driver was doing when a message was issued.
+#[derive(Clone, Debug, Default)]
+struct PassContext {
+    engine: Option<String>,
+    pass_number: Option<usize>,
+    rerun_reason: Option<RerunReason>,
+}
+
+/// A message parsed out of an engine’s output.
+#[derive(Debug, PartialEq)]
+struct LogDiagnostic<'a> {
+    file: Option<&'a str>,
+    line: Option<u32>,
+    message: &'a str,
+}
+
+/// A status backend that writes one JSON object per message.
+pub struct JsonStatusBackend<W: Write = io::Stdout> {
+    chatter: ChatterLevel,
+    context: PassContext,
+    out: W,
+}
+
+impl JsonStatusBackend<io::Stdout> {
+    /// Create a backend that writes to standard output.
+    pub fn new(chatter: ChatterLevel) -> Self {
+        JsonStatusBackend::with_output(chatter, io::stdout())
+    }
+}
+
+impl<W: Write> JsonStatusBackend<W> {
+    /// Create a backend that writes to the given stream.
+    pub fn with_output(chatter: ChatterLevel, out: W) -> Self {
+        JsonStatusBackend {
+            chatter,
+            context: PassContext::default(),
+            out,
+        }
+    }
+
+    /// Consume the backend, returning its output stream.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn emit(
+        &mut self,
+        kind: &str,
+        message: &str,
+        file: Option<&str>,
+        line: Option<u32>,
+        err: Option<&Error>,
+    ) {
+        let mut obj = String::new();
+
+        obj.push_str("{\"kind\":");
+        push_json_str(&mut obj, kind);
+        obj.push_str(",\"message\":");
+        push_json_str(&mut obj, message);
+
+        obj.push_str(",\"engine\":");
+        match self.context.engine {
+            Some(ref e) => push_json_str(&mut obj, e),
+            None => obj.push_str("null"),
+        }
+
+        obj.push_str(",\"pass\":");
+        match self.context.pass_number {
+            Some(n) => write!(obj, "{}", n).unwrap(),
+            None => obj.push_str("null"),
+        }
+
+        obj.push_str(",\"rerun_reason\":");
+        match self.context.rerun_reason {
+            Some(RerunReason::Bibtex) => obj.push_str("{\"kind\":\"bibtex\"}"),
+            Some(RerunReason::FileChange(ref name)) => {
+                obj.push_str("{\"kind\":\"file_change\",\"file\":");
+                push_json_str(&mut obj, name);
+                obj.push('}');
+            }
+            Some(RerunReason::Requested) => obj.push_str("{\"kind\":\"requested\"}"),
+            None => obj.push_str("null"),
+        }
+
+        obj.push_str(",\"file\":");
+        match file {
+            Some(f) => push_json_str(&mut obj, f),
+            None => obj.push_str("null"),
+        }
+
+        obj.push_str(",\"line\":");
+        match line {
+            Some(n) => write!(obj, "{}", n).unwrap(),
+            None => obj.push_str("null"),
+        }
+
+        obj.push_str(",\"causes\":[");
+        if let Some(e) = err {
+            for (i, item) in e.iter().enumerate() {
+                if i > 0 {
+                    obj.push(',');
+                }
+                push_json_str(&mut obj, &item.to_string());
+            }
+        }
+        obj.push_str("]}");
+
+        writeln!(self.out, "{}", obj).expect("write to JSON status stream failed");
+    }
+}
+
+impl<W: Write> StatusBackend for JsonStatusBackend<W> {
+    fn report(&mut self, kind: MessageKind, args: Arguments, err: Option<&Error>) {
+        if kind == MessageKind::Note && self.chatter <= ChatterLevel::Minimal {
+            return;
+        }
+
+        let kind = match kind {
+            MessageKind::Note => "note",
+            MessageKind::Warning => "warning",
+            MessageKind::Error => "error",
+        };
+
+        let text = args.to_string();
+
+        match parse_file_line(&text) {
+            Some((file, line, message)) => self.emit(kind, message, Some(file), Some(line), err),
+            None => self.emit(kind, &text, None, None, err),
+        }
+    }
+
+    fn pass_started(
+        &mut self,
+        engine: &str,
+        pass_number: usize,
+        rerun_reason: Option<&RerunReason>,
+    ) {
+        self.context = PassContext {
+            engine: Some(engine.to_owned()),
+            pass_number: Some(pass_number),
+            rerun_reason: rerun_reason.cloned(),
+        };
+    }
+
+    fn dump_error_logs(&mut self, output: &[u8]) {
+        let text = String::from_utf8_lossy(output);
+
+        for diag in parse_log(&text) {
+            self.emit("error", diag.message, diag.file, diag.line, None);
+        }
+
+        self.emit("log", &text, None, None, None);
+    }
+}
+
+/// Append `text` to `dest` as a quoted JSON string.
+pub(crate) fn push_json_str(dest: &mut String, text: &str) {
+    dest.push('"');
+
+    for c in text.chars() {
+        match c {
+            '"' => dest.push_str("\\\""),
+            '\\' => dest.push_str("\\\\"),
+            '\n' => dest.push_str("\\n"),
+            '\r' => dest.push_str("\\r"),
+            '\t' => dest.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(dest, "\\u{:04x}", c as u32).unwrap(),
+            c => dest.push(c),
+        }
+    }
+
+    dest.push('"');
+}
+
+/// Split a message of the form `file:line: text` into its parts.
+///
+/// This is how TeX prefixes its error messages when its `file:line:` error
+/// style is enabled. We insist that the file name not begin with whitespace
+/// so that we don’t mistake ordinary prose for a location.
+fn parse_file_line(text: &str) -> Option<(&str, u32, &str)> {
+    for (i, _) in text.match_indices(':') {
+        let file = &text[..i];
+
+        if file.is_empty() || file.starts_with(char::is_whitespace) || file.contains(": ") {
+            return None;
+        }
+
+        let rest = &text[i + 1..];
+        let n_digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+
+        if n_digits == 0 || !rest[n_digits..].starts_with(':') {
+            continue;
+        }
+
+        let line = match rest[..n_digits].parse() {
+            Ok(n) => n,
+            Err(_) => continue,
+        };
+
+        return Some((file, line, rest[n_digits + 1..].trim_start()));
+    }
+
+    None
+}
+
+/// Find the error messages in an engine’s terminal output.
+///
+/// We understand both TeX’s `file:line:` error style and its traditional
+/// style, in which the message starts with `!` and the line number is given
+/// later on in context lines beginning `l.`.
+fn parse_log(text: &str) -> Vec<LogDiagnostic<'_>> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut diags = Vec::new();
+
+    for (i, l) in lines.iter().enumerate() {
+        if let Some((file, line, message)) = parse_file_line(l) {
+            diags.push(LogDiagnostic {
+                file: Some(file),
+                line: Some(line),
+                message,
+            });
+        } else if let Some(message) = l.strip_prefix("! ") {
+            let line = lines[i + 1..]
+                .iter()
+                .take_while(|l| !l.starts_with("! ") && parse_file_line(l).is_none())
+                .filter_map(|l| {
+                    let digits: String = l
+                        .strip_prefix("l.")?
+                        .chars()
+                        .take_while(|c| c.is_ascii_digit())
+                        .collect();
+                    digits.parse().ok()
+                })
+                .next();
+
+            diags.push(LogDiagnostic {
+                file: None,
+                line,
+                message,
+            });
+        }
+    }
+
+    diags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tt_warning;
+
+    #[test]
+    fn file_line() {
+        assert_eq!(
+            parse_file_line("./doc.tex:12: Undefined control sequence."),
+            Some(("./doc.tex", 12, "Undefined control sequence."))
+        );
+        assert_eq!(
+            parse_file_line("C:/docs/doc.tex:3: Missing $ inserted."),
+            Some(("C:/docs/doc.tex", 3, "Missing $ inserted."))
+        );
+        assert_eq!(parse_file_line("warning: 12:30: not a location"), None);
+        assert_eq!(parse_file_line("no location here"), None);
+    }
+
+    #[test]
+    fn log() {
+        let log = "(./doc.tex\n\
+                   ./doc.tex:5: Undefined control sequence.\n\
+                   l.5 \\foo\n\
+                   \n\
+                   ! Emergency stop.\n\
+                   <*> doc.tex\n\
+                   l.7 \\end\n";
+
+        assert_eq!(
+            parse_log(log),
+            vec![
+                LogDiagnostic {
+                    file: Some("./doc.tex"),
+                    line: Some(5),
+                    message: "Undefined control sequence.",
+                },
+                LogDiagnostic {
+                    file: None,
+                    line: Some(7),
+                    message: "Emergency stop.",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn output() {
+        let mut status = JsonStatusBackend::with_output(ChatterLevel::Normal, Vec::new());
+        status.pass_started(
+            "TeX",
+            2,
+            Some(&RerunReason::FileChange("a\"b.aux".to_owned())),
+        );
+        tt_warning!(status, "doc.tex:3: something\todd");
+        let out = String::from_utf8(status.into_inner()).unwrap();
+
+        assert_eq!(
+            out,
+            "{\"kind\":\"warning\",\"message\":\"something\\todd\",\"engine\":\"TeX\",\
+             \"pass\":2,\"rerun_reason\":{\"kind\":\"file_change\",\"file\":\"a\\\"b.aux\"},\
+             \"file\":\"doc.tex\",\"line\":3,\"causes\":[]}\n"
+        );
+    }
+}
@@ -4,6 +4,7 @@
 
 //! A framework for showing status messages to the user.
 
+pub mod json;
 pub mod plain;
 pub mod termcolor;
 
@@ -12,6 +13,7 @@ use std::fmt::Arguments;
 use std::result::Result as StdResult;
 use std::str::FromStr;
 
+use crate::driver::RerunReason;
 use crate::errors::Error;
 
 #[repr(usize)]
@@ -79,6 +81,21 @@ pub trait StatusBackend {
         )
     }
 
+    /// Notify the backend that an engine pass is starting.
+    ///
+    /// `pass_number` counts the engine passes of the current processing run,
+    /// starting at 1. If the TeX engine is being rerun, `rerun_reason` says
+    /// why. Backends that produce machine-readable output can use this to
+    /// add context to subsequent messages; the default implementation does
+    /// nothing.
+    fn pass_started(
+        &mut self,
+        _engine: &str,
+        _pass_number: usize,
+        _rerun_reason: Option<&RerunReason>,
+    ) {
+    }
+
     /// This is used to print TeX engine logs after it encountered errors. This prints the log,
     /// surrounded by lines of equal signs.
     fn dump_error_logs(&mut self, output: &[u8]);
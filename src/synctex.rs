@@ -0,0 +1,604 @@
+// src/synctex.rs -- reading SyncTeX data
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Reading the SyncTeX data produced by the TeX engine.
+//!
+//! When a [`crate::driver::ProcessingSession`] is run with SyncTeX enabled,
+//! the engine writes a `.synctex.gz` file that relates positions in the
+//! output document to positions in the TeX source. This module reads that
+//! file and answers the two questions that editors ask of it:
+//!
+//! - *forward search*: where on which pages does a given source line end up?
+//! - *inverse search*: which source line produced a given point on a page?
+//!
+//! All page coordinates are in PDF “big points” (1/72 of an inch), measured
+//! rightward and downward from the top-left corner of the page, which is how
+//! the upstream `synctex` tool reports them too.
+
+use flate2::read::GzDecoder;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::errors::{Error, Result};
+use crate::{ctry, errmsg};
+
+/// The number of TeX scaled points in a PDF big point.
+const SP_PER_BP: f64 = 65536. * 72.27 / 72.;
+
+/// A rectangle on a page of the output document.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageBox {
+    /// The page number, starting at 1.
+    pub page: u32,
+
+    /// The horizontal position of the left edge of the rectangle.
+    pub x: f64,
+
+    /// The vertical position of the top edge of the rectangle.
+    pub y: f64,
+
+    /// The width of the rectangle.
+    pub width: f64,
+
+    /// The height of the rectangle, including the depth below the baseline.
+    pub height: f64,
+}
+
+/// A position in a TeX source file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SourceLocation {
+    /// The name of the file, as TeX recorded it.
+    pub file: String,
+
+    /// The line number, starting at 1.
+    pub line: u32,
+
+    /// The column number, starting at 0, if known. TeX does not currently
+    /// record columns, so this is always None in practice.
+    pub column: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum NodeKind {
+    VBox,
+    HBox,
+    VoidVBox,
+    VoidHBox,
+    Current,
+    Kern,
+    Glue,
+    Math,
+    Rule,
+}
+
+impl NodeKind {
+    fn is_hbox(self) -> bool {
+        self == NodeKind::HBox || self == NodeKind::VoidHBox
+    }
+
+    fn is_vbox(self) -> bool {
+        self == NodeKind::VBox || self == NodeKind::VoidVBox
+    }
+}
+
+/// One record in the content section of a SyncTeX file. Dimensions are in
+/// the units of the file.
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    kind: NodeKind,
+    tag: u32,
+    line: u32,
+    h: i64,
+    v: i64,
+    width: i64,
+    height: i64,
+    depth: i64,
+
+    /// The index of the innermost enclosing box on the same page, if any.
+    parent: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+struct Sheet {
+    page: u32,
+    nodes: Vec<Node>,
+}
+
+/// The contents of a SyncTeX file.
+#[derive(Clone, Debug)]
+pub struct SyncTex {
+    inputs: HashMap<u32, String>,
+    magnification: f64,
+    unit: f64,
+    x_offset: i64,
+    y_offset: i64,
+    sheets: Vec<Sheet>,
+}
+
+impl SyncTex {
+    /// Read a SyncTeX file, which may be gzipped.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SyncTex> {
+        let path = path.as_ref();
+        let f = ctry!(File::open(path); "couldn't open SyncTeX file \"{}\"", path.display());
+        let data =
+            ctry!(SyncTex::from_reader(f); "couldn't read SyncTeX file \"{}\"", path.display());
+        Ok(data)
+    }
+
+    /// Read SyncTeX data from a stream, which may be gzipped.
+    pub fn from_reader<R: Read>(mut stream: R) -> Result<SyncTex> {
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw)?;
+
+        let text = if raw.starts_with(&[0x1f, 0x8b]) {
+            let mut text = String::new();
+            GzDecoder::new(&raw[..]).read_to_string(&mut text)?;
+            text
+        } else {
+            ctry!(String::from_utf8(raw); "SyncTeX data is not valid UTF-8")
+        };
+
+        SyncTex::parse(&text)
+    }
+
+    /// Parse uncompressed SyncTeX data.
+    pub fn parse(text: &str) -> Result<SyncTex> {
+        let mut data = SyncTex {
+            inputs: HashMap::new(),
+            magnification: 1000.,
+            unit: 1.,
+            x_offset: 0,
+            y_offset: 0,
+            sheets: Vec::new(),
+        };
+
+        let mut saw_header = false;
+        let mut box_stack: Vec<usize> = Vec::new();
+
+        for (lineno, line) in text.lines().enumerate() {
+            let bad =
+                || -> Error { errmsg!("malformed SyncTeX data at line {}: {}", lineno + 1, line) };
+
+            if let Some(rest) = line.strip_prefix("SyncTeX Version:") {
+                saw_header = true;
+                let _version: u32 = rest.trim().parse().map_err(|_| bad())?;
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("Input:") {
+                let mut pieces = rest.splitn(2, ':');
+                let tag = pieces.next().and_then(|t| t.parse().ok()).ok_or_else(bad)?;
+                let name = pieces.next().ok_or_else(bad)?;
+                data.inputs.insert(tag, name.to_owned());
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("Magnification:") {
+                data.magnification = rest.trim().parse().map_err(|_| bad())?;
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("Unit:") {
+                data.unit = rest.trim().parse().map_err(|_| bad())?;
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("X Offset:") {
+                data.x_offset = rest.trim().parse().map_err(|_| bad())?;
+                continue;
+            }
+
+            if let Some(rest) = line.strip_prefix("Y Offset:") {
+                data.y_offset = rest.trim().parse().map_err(|_| bad())?;
+                continue;
+            }
+
+            let mut chars = line.chars();
+
+            let kind = match chars.next() {
+                Some('{') => {
+                    let page = chars.as_str().parse().map_err(|_| bad())?;
+                    data.sheets.push(Sheet {
+                        page,
+                        nodes: Vec::new(),
+                    });
+                    box_stack.clear();
+                    continue;
+                }
+
+                Some('}') => {
+                    box_stack.clear();
+                    continue;
+                }
+
+                Some(']') | Some(')') => {
+                    box_stack.pop();
+                    continue;
+                }
+
+                Some('[') => NodeKind::VBox,
+                Some('(') => NodeKind::HBox,
+                Some('v') => NodeKind::VoidVBox,
+                Some('h') => NodeKind::VoidHBox,
+                Some('x') => NodeKind::Current,
+                Some('k') => NodeKind::Kern,
+                Some('g') => NodeKind::Glue,
+                Some('$') => NodeKind::Math,
+                Some('r') => NodeKind::Rule,
+
+                // Anchors, the postamble, form records, and anything that
+                // we don't understand.
+                _ => continue,
+            };
+
+            let sheet = match data.sheets.last_mut() {
+                Some(s) => s,
+                None => return Err(bad()),
+            };
+
+            let mut node = parse_node(kind, chars.as_str()).ok_or_else(bad)?;
+            node.parent = box_stack.last().cloned();
+            sheet.nodes.push(node);
+
+            if kind == NodeKind::VBox || kind == NodeKind::HBox {
+                box_stack.push(sheet.nodes.len() - 1);
+            }
+        }
+
+        if !saw_header {
+            return Err(errmsg!("not a SyncTeX file"));
+        }
+
+        Ok(data)
+    }
+
+    /// Convert a dimension in the units of the file into big points.
+    fn to_bp(&self, value: i64) -> f64 {
+        value as f64 * self.unit * self.magnification / 1000. / SP_PER_BP
+    }
+
+    /// The rectangle covered by a box node, in big points.
+    fn node_box(&self, page: u32, node: &Node) -> PageBox {
+        PageBox {
+            page,
+            x: self.to_bp(node.h + self.x_offset),
+            y: self.to_bp(node.v - node.height + self.y_offset),
+            width: self.to_bp(node.width),
+            height: self.to_bp(node.height + node.depth),
+        }
+    }
+
+    /// Find the tags that TeX assigned to the source file named `file`.
+    fn tags_for(&self, file: &str) -> Vec<u32> {
+        self.inputs
+            .iter()
+            .filter(|(_, name)| same_file(name, file))
+            .map(|(tag, _)| *tag)
+            .collect()
+    }
+
+    /// Forward search: find the places in the output that correspond to a
+    /// source position.
+    ///
+    /// If nothing in the output came from line `line` of `file`, the nearest
+    /// line that did contribute is used instead, preferring later lines.
+    /// TeX does not record columns, so `column` does not currently affect
+    /// the result; it is accepted for parity with the upstream `synctex`
+    /// tool. Each result is the extent of a line of typeset material.
+    pub fn forward(&self, file: &str, line: u32, column: u32) -> Vec<PageBox> {
+        let _ = column;
+        let tags = self.tags_for(file);
+
+        // What lines of this file made it into the output?
+        let mut lines: Vec<u32> = self
+            .sheets
+            .iter()
+            .flat_map(|s| s.nodes.iter())
+            .filter(|n| !n.kind.is_vbox() && tags.contains(&n.tag))
+            .map(|n| n.line)
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+
+        let target = match lines.iter().find(|l| **l >= line) {
+            Some(l) => *l,
+            None => match lines.last() {
+                Some(l) => *l,
+                None => return Vec::new(),
+            },
+        };
+
+        let mut results = Vec::new();
+
+        for sheet in &self.sheets {
+            let matching =
+                sheet.nodes.iter().enumerate().filter(|(_, n)| {
+                    !n.kind.is_vbox() && n.line == target && tags.contains(&n.tag)
+                });
+
+            // The best information comes from the glue, kerns, etc. inside
+            // a line, since TeX tags a box with the position at which it was
+            // *finished*, which for the lines of a paragraph is its end.
+            let mut found: Vec<usize> = matching
+                .clone()
+                .filter(|(_, n)| !n.kind.is_hbox())
+                .filter_map(|(_, n)| n.parent)
+                .filter(|p| sheet.nodes[*p].kind.is_hbox())
+                .collect();
+
+            if found.is_empty() {
+                found = matching
+                    .filter(|(_, n)| n.kind.is_hbox())
+                    .map(|(i, _)| i)
+                    .collect();
+            }
+
+            found.sort_unstable();
+            found.dedup();
+
+            for idx in found {
+                results.push(self.node_box(sheet.page, &sheet.nodes[idx]));
+            }
+        }
+
+        results
+    }
+
+    /// Inverse search: find the source position that produced the point
+    /// (`x`, `y`) on page `page`.
+    ///
+    /// We look for the smallest line of typeset material containing the
+    /// point, and within it the last item that starts to the left of the
+    /// point. Returns None if the page doesn’t exist or has no SyncTeX
+    /// information, or if the point isn’t finite.
+    pub fn inverse(&self, page: u32, x: f64, y: f64) -> Option<SourceLocation> {
+        if !x.is_finite() || !y.is_finite() {
+            return None;
+        }
+
+        let sheet = self.sheets.iter().find(|s| s.page == page)?;
+
+        let contains =
+            |b: &PageBox| x >= b.x && x <= b.x + b.width && y >= b.y && y <= b.y + b.height;
+
+        let container = sheet
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.kind.is_hbox())
+            .map(|(i, n)| (i, self.node_box(page, n)))
+            .filter(|(_, b)| contains(b))
+            .min_by(|(_, a), (_, b)| {
+                (a.width * a.height)
+                    .partial_cmp(&(b.width * b.height))
+                    .unwrap_or(Ordering::Equal)
+            })
+            .map(|(i, _)| i);
+
+        let node = match container {
+            Some(idx) => {
+                let children = sheet
+                    .nodes
+                    .iter()
+                    .filter(|n| n.parent == Some(idx) && !n.kind.is_vbox() && n.line > 0);
+
+                let before = children
+                    .clone()
+                    .filter(|n| self.to_bp(n.h) <= x)
+                    .max_by_key(|n| n.h);
+
+                before
+                    .or_else(|| children.min_by_key(|n| n.h))
+                    .unwrap_or(&sheet.nodes[idx])
+            }
+
+            None => {
+                // Not inside any line: use the closest thing to the point.
+                let distance = |n: &Node| {
+                    let dx = self.to_bp(n.h) - x;
+                    let dy = self.to_bp(n.v) - y;
+                    dx * dx + dy * dy
+                };
+
+                sheet
+                    .nodes
+                    .iter()
+                    .filter(|n| !n.kind.is_vbox() && n.line > 0)
+                    .min_by(|a, b| {
+                        distance(a)
+                            .partial_cmp(&distance(b))
+                            .unwrap_or(Ordering::Equal)
+                    })?
+            }
+        };
+
+        Some(SourceLocation {
+            file: self.inputs.get(&node.tag)?.clone(),
+            line: node.line,
+            column: None,
+        })
+    }
+}
+
+/// Parse the fields of a content record, which look like
+/// `tag,line:h,v` possibly followed by `:W` or `:W,H,D`.
+fn parse_node(kind: NodeKind, text: &str) -> Option<Node> {
+    let mut sections = text.split(':');
+
+    let mut link = sections.next()?.split(',');
+    let tag = link.next()?.parse().ok()?;
+    let line = link.next()?.parse().ok()?;
+
+    let mut point = sections.next()?.split(',');
+    let h = point.next()?.parse().ok()?;
+    let v = point.next()?.parse().ok()?;
+
+    let mut node = Node {
+        kind,
+        tag,
+        line,
+        h,
+        v,
+        width: 0,
+        height: 0,
+        depth: 0,
+        parent: None,
+    };
+
+    if let Some(size) = sections.next() {
+        let mut size = size.split(',');
+        node.width = size.next()?.parse().ok()?;
+
+        if let Some(height) = size.next() {
+            node.height = height.parse().ok()?;
+            node.depth = size.next()?.parse().ok()?;
+        }
+    }
+
+    Some(node)
+}
+
+/// Decide whether the input file name recorded by TeX refers to the file
+/// that the user asked about. TeX records names as it opened them, e.g.
+/// `./chapter.tex`, while editors tend to use absolute paths.
+fn same_file(recorded: &str, query: &str) -> bool {
+    fn normalize(mut name: &str) -> &str {
+        while let Some(rest) = name.strip_prefix("./") {
+            name = rest;
+        }
+        name
+    }
+
+    let recorded = normalize(recorded);
+    let query = normalize(query);
+
+    if recorded == query {
+        return true;
+    }
+
+    let is_suffix = |long: &str, short: &str| {
+        long.len() > short.len()
+            && long.ends_with(short)
+            && long[..long.len() - short.len()].ends_with('/')
+    };
+
+    is_suffix(query, recorded) || is_suffix(recorded, query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    // One page with two lines of a paragraph from doc.tex, the first of
+    // which contains material from line 3 and the second from line 4.
+    // Dimensions are in sp, with TeX's one-inch offsets already included.
+    const SAMPLE: &str = "SyncTeX Version:1
+Input:1:./doc.tex
+Input:2:./chapter.tex
+Output:pdf
+Magnification:1000
+Unit:1
+X Offset:0
+Y Offset:0
+Content:
+!100
+{1
+[1,5:4736286,4736286:30000000,40000000,0
+(1,4:4736286,6000000:20000000,500000,100000
+g1,3:5000000,6000000
+k1,3:8000000,6000000:10000
+)
+(1,4:4736286,7000000:20000000,500000,100000
+g1,4:5000000,7000000
+x2,9:9000000,7000000
+)
+]
+!50
+}1
+Input:3:./appendix.tex
+!10
+{2
+[3,1:4736286,4736286:30000000,40000000,0
+(3,1:4736286,6000000:20000000,500000,100000
+g3,1:5000000,6000000
+)
+]
+}2
+Postamble:
+Count:12
+!
+Post scriptum:
+";
+
+    fn bp(sp: i64) -> f64 {
+        sp as f64 / SP_PER_BP
+    }
+
+    #[test]
+    fn forward() {
+        let data = SyncTex::parse(SAMPLE).unwrap();
+
+        let boxes = data.forward("doc.tex", 3, 0);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].page, 1);
+        assert!((boxes[0].x - bp(4736286)).abs() < 1e-6);
+        assert!((boxes[0].y - bp(6000000 - 500000)).abs() < 1e-6);
+        assert!((boxes[0].height - bp(600000)).abs() < 1e-6);
+
+        // Absolute paths match, and missing lines fall through to the next
+        // line that contributed something.
+        let boxes = data.forward("/home/me/thesis/doc.tex", 2, 0);
+        assert_eq!(boxes.len(), 1);
+        assert!((boxes[0].y - bp(6000000 - 500000)).abs() < 1e-6);
+
+        let boxes = data.forward("appendix.tex", 1, 0);
+        assert_eq!(boxes.len(), 1);
+        assert_eq!(boxes[0].page, 2);
+
+        assert!(data.forward("missing.tex", 1, 0).is_empty());
+    }
+
+    #[test]
+    fn inverse() {
+        let data = SyncTex::parse(SAMPLE).unwrap();
+
+        // Inside the first line, after its glue.
+        let loc = data.inverse(1, bp(6000000), bp(5800000)).unwrap();
+        assert_eq!(loc.file, "./doc.tex");
+        assert_eq!(loc.line, 3);
+
+        // Inside the second line, after the inserted material.
+        let loc = data.inverse(1, bp(9500000), bp(6900000)).unwrap();
+        assert_eq!(loc.file, "./chapter.tex");
+        assert_eq!(loc.line, 9);
+
+        assert_eq!(data.inverse(3, 100., 100.), None);
+        assert_eq!(data.inverse(1, f64::NAN, 100.), None);
+        assert_eq!(data.inverse(1, 100., f64::INFINITY), None);
+    }
+
+    #[test]
+    fn gzipped() {
+        let mut enc = GzEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(SAMPLE.as_bytes()).unwrap();
+        let gz = enc.finish().unwrap();
+
+        let data = SyncTex::from_reader(&gz[..]).unwrap();
+        assert_eq!(data.forward("doc.tex", 4, 0).len(), 1);
+    }
+
+    #[test]
+    fn file_names() {
+        assert!(same_file("./doc.tex", "doc.tex"));
+        assert!(same_file("./sub/doc.tex", "/abs/sub/doc.tex"));
+        assert!(!same_file("./doc.tex", "/abs/mydoc.tex"));
+        assert!(SyncTex::parse("not synctex").is_err());
+    }
+}
@@ -8,6 +8,7 @@
 //! to be reliable or very polished. In particular, many of these prevent the build from being
 //! reproducible.
 
+use crate::engines::shell_escape::parse_allowed_commands;
 use crate::errors::{Error, Result};
 use std::default::Default;
 use std::str::FromStr;
@@ -19,11 +20,11 @@ const HELPMSG: &str = r#"Available unstable options:
     -Z min-crossrefs=<num>      Equivalent to bibtex's -min-crossrefs flag - "include after <num>
                                     crossrefs" [default: 2]
     -Z paper-size=<spec>        Change the default paper size [default: letter]
+    -Z shell-escape             Enable \write18
+    -Z shell-escape-commands=<cmd,...>
+                                Enable \write18, but only for the listed programs
 "#;
 
-// Re-add this when \write18 gets implemented
-//    -Z shell-escape             Enable \write18
-
 // Each entry of this should correspond to a field of UnstableOptions.
 #[derive(Debug)]
 pub enum UnstableArg {
@@ -32,6 +33,7 @@ pub enum UnstableArg {
     MinCrossrefs(i32),
     PaperSize(String),
     ShellEscapeEnabled,
+    ShellEscapeCommands(Vec<String>),
 }
 
 impl FromStr for UnstableArg {
@@ -66,18 +68,27 @@ impl FromStr for UnstableArg {
                 })
                 .map(|s| UnstableArg::PaperSize(s.to_string())),
 
-            // Re-add this when \write18 gets implemetned
-            // "shell-escape" => Ok(UnstableArg::ShellEscapeEnabled),
+            "shell-escape" => Ok(UnstableArg::ShellEscapeEnabled),
+
+            "shell-escape-commands" => value
+                .ok_or_else(|| {
+                    "'-Z shell-escape-commands <list>' requires a value but none was supplied"
+                        .into()
+                })
+                .and_then(parse_allowed_commands)
+                .map(UnstableArg::ShellEscapeCommands),
             _ => Err(format!("Unknown unstable option '{}'", arg).into()),
         }
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default)]
 pub struct UnstableOptions {
     pub continue_on_errors: bool,
     pub paper_size: Option<String>,
     pub shell_escape: bool,
+    /// If set, shell escape is restricted to these programs.
+    pub shell_escape_commands: Option<Vec<String>>,
     pub min_crossrefs: Option<i32>,
 }
 
@@ -99,6 +110,10 @@ impl UnstableOptions {
                 MinCrossrefs(num) => opts.min_crossrefs = Some(num),
                 PaperSize(size) => opts.paper_size = Some(size),
                 ShellEscapeEnabled => opts.shell_escape = true,
+                ShellEscapeCommands(cmds) => {
+                    opts.shell_escape = true;
+                    opts.shell_escape_commands = Some(cmds);
+                }
             }
         }
 
@@ -0,0 +1,407 @@
+// src/watch.rs -- waiting for input files to change
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Waiting for input files to change.
+//!
+//! This module powers the command-line client’s `--watch` mode. After a
+//! processing session has run, the [`crate::driver::ProcessingSession`]
+//! knows exactly which files it read from the filesystem; a [`FileWatcher`]
+//! blocks until one of them is modified.
+//!
+//! On Linux, changes are detected with inotify. We watch the *directories*
+//! containing the files rather than the files themselves, because many
+//! editors save by writing a new file and renaming it over the old one,
+//! which would silently invalidate a watch on the original inode. Events for
+//! files that we weren’t asked about are ignored. On other platforms, we fall
+//! back to polling modification times.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::errors::Result;
+
+/// Blocks until one of a set of files changes.
+pub struct FileWatcher {
+    inner: imp::Watcher,
+}
+
+impl FileWatcher {
+    /// Start watching the specified files.
+    ///
+    /// Files that do not exist yet may be watched too; creating them counts
+    /// as a change.
+    pub fn new<I, P>(paths: I) -> Result<FileWatcher>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let paths: HashSet<PathBuf> = paths.into_iter().map(|p| p.as_ref().to_owned()).collect();
+
+        Ok(FileWatcher {
+            inner: imp::Watcher::new(paths)?,
+        })
+    }
+
+    /// Wait until at least one of the watched files changes.
+    ///
+    /// Saving a document often touches the file several times in quick
+    /// succession, and a user might save several files at once. So, after
+    /// the first change, we keep collecting changes until none have arrived
+    /// for `debounce`. Returns the paths of the files that changed, sorted
+    /// and without duplicates.
+    pub fn wait(&mut self, debounce: Duration) -> Result<Vec<PathBuf>> {
+        let mut changed = HashSet::new();
+
+        while changed.is_empty() {
+            self.inner.poll(None, &mut changed)?;
+        }
+
+        while self.inner.poll(Some(debounce), &mut changed)? {}
+
+        let mut changed: Vec<_> = changed.into_iter().collect();
+        changed.sort();
+        Ok(changed)
+    }
+
+    /// Return the files that have changed since the watcher was created or
+    /// last reported a change, without waiting. The result is sorted and
+    /// without duplicates, and empty if nothing has changed.
+    pub fn take_changes(&mut self) -> Result<Vec<PathBuf>> {
+        let mut changed = HashSet::new();
+        while self
+            .inner
+            .poll(Some(Duration::from_millis(0)), &mut changed)?
+        {}
+
+        let mut changed: Vec<_> = changed.into_iter().collect();
+        changed.sort();
+        Ok(changed)
+    }
+}
+
+/// The directory to watch for changes to `path`, and the name of the file
+/// within it.
+fn split_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let name = path.file_name()?;
+
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_owned(),
+        _ => PathBuf::from("."),
+    };
+
+    Some((dir, PathBuf::from(name)))
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::collections::{HashMap, HashSet};
+    use std::ffi::{CString, OsStr};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use super::split_path;
+    use crate::ctry;
+    use crate::errors::{Result, ResultExt};
+
+    /// The inotify events that mean that a file in a directory has new
+    /// contents.
+    const EVENT_MASK: u32 = libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_TO
+        | libc::IN_CREATE
+        | libc::IN_DELETE
+        | libc::IN_MODIFY;
+
+    pub struct Watcher {
+        fd: libc::c_int,
+
+        /// Maps watch descriptors to the directories they watch, and the
+        /// names of the files in each directory that we care about. Keying
+        /// on the descriptor rather than the path means that two different
+        /// spellings of the same directory are handled correctly: inotify
+        /// hands back the same descriptor for both.
+        watches: HashMap<libc::c_int, (PathBuf, HashSet<PathBuf>)>,
+    }
+
+    impl Watcher {
+        pub fn new(paths: HashSet<PathBuf>) -> Result<Watcher> {
+            let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+
+            if fd < 0 {
+                return Err(io::Error::last_os_error()).chain_err(|| "couldn't initialize inotify");
+            }
+
+            let mut watcher = Watcher {
+                fd,
+                watches: HashMap::new(),
+            };
+
+            for path in paths {
+                let (dir, name) = match split_path(&path) {
+                    Some(t) => t,
+                    None => continue,
+                };
+
+                let c_dir = ctry!(CString::new(dir.as_os_str().as_bytes());
+                                  "invalid directory path \"{}\"", dir.display());
+                let wd = unsafe { libc::inotify_add_watch(fd, c_dir.as_ptr(), EVENT_MASK) };
+
+                if wd < 0 {
+                    return Err(io::Error::last_os_error())
+                        .chain_err(|| format!("couldn't watch directory \"{}\"", dir.display()));
+                }
+
+                watcher
+                    .watches
+                    .entry(wd)
+                    .or_insert_with(|| (dir, HashSet::new()))
+                    .1
+                    .insert(name);
+            }
+
+            Ok(watcher)
+        }
+
+        /// Wait up to `timeout` (or forever, if None) for events, adding the
+        /// paths of any changed files to `changed`. Returns false if the
+        /// timeout expired without any events arriving.
+        pub fn poll(
+            &mut self,
+            timeout: Option<Duration>,
+            changed: &mut HashSet<PathBuf>,
+        ) -> Result<bool> {
+            let timeout_ms = match timeout {
+                Some(d) => d.as_millis().min(libc::c_int::MAX as u128) as libc::c_int,
+                None => -1,
+            };
+
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            let n = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+
+            if n < 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(true);
+                }
+
+                return Err(err).chain_err(|| "error waiting for file changes");
+            }
+
+            if n == 0 {
+                return Ok(false);
+            }
+
+            let mut buf = [0u8; 4096];
+
+            loop {
+                let len = unsafe {
+                    libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+                };
+
+                if len < 0 {
+                    let err = io::Error::last_os_error();
+
+                    if err.kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    }
+
+                    return Err(err).chain_err(|| "error reading file change events");
+                }
+
+                if len == 0 {
+                    break;
+                }
+
+                self.parse_events(&buf[..len as usize], changed);
+            }
+
+            Ok(true)
+        }
+
+        fn parse_events(&self, mut buf: &[u8], changed: &mut HashSet<PathBuf>) {
+            const HEADER_SIZE: usize = std::mem::size_of::<libc::inotify_event>();
+
+            while buf.len() >= HEADER_SIZE {
+                // The kernel doesn't guarantee that the events are aligned
+                // in our buffer, so don't just cast the pointer.
+                let event: libc::inotify_event =
+                    unsafe { std::ptr::read_unaligned(buf.as_ptr() as *const _) };
+                let end = HEADER_SIZE + event.len as usize;
+
+                if buf.len() < end {
+                    break;
+                }
+
+                // The name is padded with NULs.
+                let name = &buf[HEADER_SIZE..end];
+                let name = match name.iter().position(|b| *b == 0) {
+                    Some(i) => &name[..i],
+                    None => name,
+                };
+                let name = PathBuf::from(OsStr::from_bytes(name));
+
+                if let Some((dir, names)) = self.watches.get(&event.wd) {
+                    if names.contains(&name) {
+                        changed.insert(dir.join(name));
+                    }
+                }
+
+                buf = &buf[end..];
+            }
+        }
+    }
+
+    impl Drop for Watcher {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod imp {
+    use std::collections::{HashMap, HashSet};
+    use std::fs;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    use super::split_path;
+    use crate::errors::Result;
+
+    /// How often to check for modifications.
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    pub struct Watcher {
+        mtimes: HashMap<PathBuf, Option<SystemTime>>,
+    }
+
+    impl Watcher {
+        pub fn new(paths: HashSet<PathBuf>) -> Result<Watcher> {
+            let mtimes = paths
+                .into_iter()
+                .filter(|p| split_path(p).is_some())
+                .map(|p| {
+                    let t = mtime(&p);
+                    (p, t)
+                })
+                .collect();
+
+            Ok(Watcher { mtimes })
+        }
+
+        /// Wait up to `timeout` (or forever, if None) for changes, adding
+        /// the paths of any changed files to `changed`. Returns false if the
+        /// timeout expired without any changes.
+        pub fn poll(
+            &mut self,
+            timeout: Option<Duration>,
+            changed: &mut HashSet<PathBuf>,
+        ) -> Result<bool> {
+            let start = Instant::now();
+
+            loop {
+                let mut any = false;
+
+                for (path, last) in &mut self.mtimes {
+                    let now = mtime(path);
+
+                    if now != *last {
+                        *last = now;
+                        changed.insert(path.clone());
+                        any = true;
+                    }
+                }
+
+                if any {
+                    return Ok(true);
+                }
+
+                if let Some(t) = timeout {
+                    if start.elapsed() >= t {
+                        return Ok(false);
+                    }
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    fn mtime(path: &PathBuf) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|md| md.modified()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+
+    #[test]
+    fn split() {
+        assert_eq!(
+            split_path(Path::new("doc.tex")),
+            Some((PathBuf::from("."), PathBuf::from("doc.tex")))
+        );
+        assert_eq!(
+            split_path(Path::new("sub/doc.tex")),
+            Some((PathBuf::from("sub"), PathBuf::from("doc.tex")))
+        );
+        assert_eq!(split_path(Path::new("/")), None);
+    }
+
+    #[test]
+    fn detects_modification() {
+        let dir = tempfile::Builder::new()
+            .prefix("tectonic_watch_test")
+            .tempdir()
+            .unwrap();
+        let watched = dir.path().join("watched.tex");
+        let other = dir.path().join("other.tex");
+        fs::write(&watched, "old").unwrap();
+
+        let mut watcher = FileWatcher::new([&watched]).unwrap();
+
+        let writer = {
+            let watched = watched.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(300));
+                fs::write(&other, "ignored").unwrap();
+                fs::write(&watched, "new").unwrap();
+            })
+        };
+
+        let changed = watcher.wait(Duration::from_millis(100)).unwrap();
+        writer.join().unwrap();
+        assert_eq!(changed, vec![watched]);
+    }
+
+    #[test]
+    fn takes_changes_without_waiting() {
+        let dir = tempfile::Builder::new()
+            .prefix("tectonic_watch_test")
+            .tempdir()
+            .unwrap();
+        let watched = dir.path().join("watched.tex");
+
+        let mut watcher = FileWatcher::new([&watched]).unwrap();
+        assert!(watcher.take_changes().unwrap().is_empty());
+
+        fs::write(&watched, "new").unwrap();
+        assert_eq!(watcher.take_changes().unwrap(), vec![watched]);
+        assert!(watcher.take_changes().unwrap().is_empty());
+    }
+}
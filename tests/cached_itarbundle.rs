@@ -16,7 +16,7 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use tectonic::config::PersistentConfig;
 use tectonic::driver::ProcessingSessionBuilder;
-use tectonic::io::OpenResult;
+use tectonic::io::{Bundle, OpenResult};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
 use tokio::runtime::current_thread;
@@ -94,12 +94,22 @@ enum TectonicRequest {
     Head(String),
     Index,
     File(String),
+    /// A request for a file from the mirror at `/mirror.tar`.
+    MirrorFile(String),
+    /// A request covering several files, listed in the order of the tar file.
+    Files(Vec<String>),
+    /// A request that the service was told to fail.
+    Failed,
 }
 
 struct TarIndexService {
     tar_index: Mutex<TarIndex>,
+    /// The bundle served by the mirror at `/mirror.tar`. If this is `None`,
+    /// the mirror serves the same bundle as `/bundle.tar`.
+    mirror_tar_index: Mutex<Option<TarIndex>>,
     requests: Mutex<Vec<TectonicRequest>>,
     local_addr: Mutex<Option<SocketAddr>>,
+    failures_left: Mutex<usize>,
 }
 
 type ResponseFuture = Box<dyn Future<Item = Response<Body>, Error = io::Error> + Send>;
@@ -108,11 +118,18 @@ impl TarIndexService {
     fn new(tar_index: TarIndex) -> TarIndexService {
         TarIndexService {
             tar_index: Mutex::new(tar_index),
+            mirror_tar_index: Mutex::new(None),
             requests: Mutex::new(Vec::new()),
             local_addr: Mutex::new(None),
+            failures_left: Mutex::new(0),
         }
     }
 
+    /// Make the next `n` requests for file data fail.
+    fn fail_next(&self, n: usize) {
+        *self.failures_left.lock().unwrap() = n;
+    }
+
     fn set_local_addr(&self, local_addr: SocketAddr) {
         *self.local_addr.lock().unwrap() = Some(local_addr);
     }
@@ -121,6 +138,10 @@ impl TarIndexService {
         *self.tar_index.lock().unwrap() = tar_index;
     }
 
+    fn set_mirror_tar_index(&self, tar_index: TarIndex) {
+        *self.mirror_tar_index.lock().unwrap() = Some(tar_index);
+    }
+
     fn response(&self, req: Request<Body>) -> ResponseFuture {
         match (
             req.method(),
@@ -141,18 +162,53 @@ impl TarIndexService {
                 );
                 Box::new(future::ok(resp.body(Body::empty()).unwrap()))
             }
-            (&Method::HEAD, "/bundle.tar", None) => {
+            (&Method::HEAD, "/bundle.tar", None) | (&Method::HEAD, "/mirror.tar", None) => {
                 self.log_request(TectonicRequest::Head(req.uri().path().to_owned()));
                 Box::new(future::ok(Response::new(Body::empty())))
             }
-            (&Method::GET, "/bundle.tar", Some(range)) => {
+            (&Method::GET, path @ "/bundle.tar", Some(range))
+            | (&Method::GET, path @ "/mirror.tar", Some(range)) => {
+                let mirror = path == "/mirror.tar";
+
                 if let Some((Bound::Included(l), Bound::Included(h))) = range.iter().next() {
-                    let tar_index = self.tar_index.lock().unwrap();
-                    let name = tar_index
-                        .map
-                        .get(&(l, h - l + 1))
-                        .expect("unknown file data requested");
-                    self.log_request(TectonicRequest::File(name.to_owned()));
+                    {
+                        let mut failures_left = self.failures_left.lock().unwrap();
+
+                        if *failures_left > 0 {
+                            *failures_left -= 1;
+                            self.log_request(TectonicRequest::Failed);
+                            return Box::new(future::ok(
+                                Response::builder()
+                                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            ));
+                        }
+                    }
+
+                    let main_tar_index = self.tar_index.lock().unwrap();
+                    let mirror_tar_index = self.mirror_tar_index.lock().unwrap();
+                    let tar_index = match *mirror_tar_index {
+                        Some(ref t) if mirror => t,
+                        _ => &*main_tar_index,
+                    };
+                    let request = match tar_index.map.get(&(l, h - l + 1)) {
+                        Some(name) if mirror => TectonicRequest::MirrorFile(name.to_owned()),
+                        Some(name) => TectonicRequest::File(name.to_owned()),
+                        None => {
+                            let mut files: Vec<_> = tar_index
+                                .map
+                                .iter()
+                                .filter(|((o, n), _)| *o >= l && o + n <= h + 1)
+                                .collect();
+                            assert!(files.len() > 1, "unknown file data requested");
+                            files.sort();
+                            TectonicRequest::Files(
+                                files.into_iter().map(|(_, name)| name.clone()).collect(),
+                            )
+                        }
+                    };
+                    self.log_request(request);
                     let mut resp = Response::builder();
                     resp.status(StatusCode::PARTIAL_CONTENT);
                     resp.headers_mut()
@@ -172,6 +228,14 @@ impl TarIndexService {
                     self.tar_index.lock().unwrap().index.to_vec().into(),
                 )))
             }
+            (&Method::GET, "/mirror.tar.index.gz", None) => {
+                self.log_request(TectonicRequest::Index);
+                let index = match *self.mirror_tar_index.lock().unwrap() {
+                    Some(ref t) => t.index.to_vec(),
+                    None => self.tar_index.lock().unwrap().index.to_vec(),
+                };
+                Box::new(future::ok(Response::new(index.into())))
+            }
             _ => Box::new(future::ok(
                 Response::builder()
                     .status(StatusCode::NOT_FOUND)
@@ -186,9 +250,14 @@ impl TarIndexService {
     }
 
     fn url(&self) -> String {
+        self.url_for("/tectonic-default")
+    }
+
+    fn url_for(&self, path: &str) -> String {
         format!(
-            "http://{}/tectonic-default",
-            self.local_addr.lock().unwrap().unwrap()
+            "http://{}{}",
+            self.local_addr.lock().unwrap().unwrap(),
+            path
         )
     }
 }
@@ -460,3 +529,270 @@ fn test_bundle_update() {
         }
     });
 }
+
+fn prefetch_test_index() -> TarIndex {
+    let mut builder = TarIndexBuilder::new();
+    builder
+        .push("a.tex", b"a")
+        .push("b.tex", b"b")
+        .push("far-apart.tex", &[b'%'; 8192])
+        .push("c.tex", b"c")
+        .push(
+            tectonic::digest::DIGEST_NAME,
+            b"0000000000000000000000000000000000000000000000000000000000000000",
+        );
+    builder.finish()
+}
+
+#[test]
+fn test_prefetch() {
+    let requests = run_test(Some(prefetch_test_index()), |_, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+        let config = PersistentConfig::default();
+        let mut cache = config
+            .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        let names: Vec<String> = vec!["c.tex", "a.tex", "b.tex", "not-in-bundle.tex"]
+            .into_iter()
+            .map(|s| s.to_owned())
+            .collect();
+        cache.prefetch(&names, &mut status).unwrap();
+
+        // Everything is cached now.
+        for name in &["a.tex", "b.tex", "c.tex"] {
+            match cache.input_open_name(OsStr::new(name), &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open {}", name),
+            }
+        }
+
+        // So there's nothing more to do.
+        cache.prefetch(&names, &mut status).unwrap();
+    });
+
+    // Nearby files are fetched together; far-apart ones aren't.
+    check_req_count(
+        &requests,
+        TectonicRequest::Files(vec!["a.tex".into(), "b.tex".into()]),
+        1,
+    );
+    check_req_count(&requests, TectonicRequest::File("c.tex".into()), 1);
+    check_req_count(&requests, TectonicRequest::File("far-apart.tex".into()), 0);
+    check_req_count(
+        &requests,
+        TectonicRequest::File(tectonic::digest::DIGEST_NAME.into()),
+        1,
+    );
+}
+
+#[test]
+fn test_retry() {
+    let requests = run_test(Some(prefetch_test_index()), |service, url| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+        let config = PersistentConfig::default();
+        let mut cache = config
+            .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        // Transient failures are retried.
+        service.fail_next(2);
+        match cache.input_open_name(OsStr::new("a.tex"), &mut status) {
+            OpenResult::Ok(_) => {}
+            _ => panic!("Failed to open a.tex"),
+        }
+
+        // But not forever.
+        service.fail_next(100);
+        match cache.input_open_name(OsStr::new("b.tex"), &mut status) {
+            OpenResult::Err(_) => {}
+            _ => panic!("b.tex was opened despite the network failing"),
+        }
+        service.fail_next(0);
+    });
+
+    check_req_count(&requests, TectonicRequest::File("a.tex".into()), 1);
+    check_req_count(&requests, TectonicRequest::File("b.tex".into()), 0);
+    check_req_count(&requests, TectonicRequest::Failed, 6);
+}
+
+#[test]
+fn test_session_prefetch_list() {
+    let list_dir = tempfile::tempdir().unwrap();
+    let list_path = list_dir.path().join("the_letter_a.txt");
+
+    let requests = run_test(None, |_, url| {
+        let config = PersistentConfig::default();
+
+        // Each run uses a fresh cache, so everything must be downloaded again.
+        let run = || {
+            let tempdir = tempfile::tempdir().unwrap();
+            let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+            let mut sess_builder = ProcessingSessionBuilder::default();
+            sess_builder.bundle(
+                config
+                    .make_cached_url_provider(url, false, Some(tempdir.path()), &mut status)
+                    .unwrap(),
+            );
+            let input_path = Path::new("tests/tex-outputs/the_letter_a.tex");
+            sess_builder.primary_input_path(input_path);
+            sess_builder.tex_input_name("the_letter_a.tex");
+            sess_builder.output_dir(tempdir.path());
+            sess_builder.format_name("plain");
+            sess_builder.format_cache_path(tempdir.path());
+            sess_builder.prefetch_list_path(&list_path);
+
+            let mut sess = sess_builder.create(&mut status).unwrap();
+            sess.run(&mut status).expect("failed to run session");
+        };
+
+        run();
+        let list = fs::read_to_string(&list_path).unwrap();
+        assert!(list.lines().any(|l| l == "plain.tex"));
+
+        run();
+    });
+
+    // The first run fetches files one by one. The second gets them all up
+    // front, with the test bundle's files being right next to each other.
+    check_req_count(&requests, TectonicRequest::File("plain.tex".into()), 1);
+    assert_eq!(
+        requests
+            .iter()
+            .filter(|r| match r {
+                TectonicRequest::Files(names) => names.iter().any(|n| n == "plain.tex"),
+                _ => false,
+            })
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn test_mirror_failover() {
+    // The same files as in `prefetch_test_index()`, but a different digest.
+    let digest_changed = {
+        let mut builder = TarIndexBuilder::new();
+        builder
+            .push("a.tex", b"a")
+            .push("b.tex", b"b")
+            .push("far-apart.tex", &[b'%'; 8192])
+            .push("c.tex", b"c")
+            .push(
+                tectonic::digest::DIGEST_NAME,
+                b"ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+            );
+        builder.finish()
+    };
+
+    let requests = run_test(Some(prefetch_test_index()), |service, _| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let config = PersistentConfig::default();
+        let urls = vec![
+            service.url_for("/unreachable"),
+            service.url(),
+            service.url_for("/mirror.tar"),
+        ];
+
+        let open =
+            |cache: &mut Box<dyn Bundle>, name: &str, status: &mut TermcolorStatusBackend| {
+                match cache.input_open_name(OsStr::new(name), status) {
+                    OpenResult::Ok(_) => {}
+                    _ => panic!("Failed to open {}", name),
+                }
+            };
+
+        {
+            // The first mirror doesn't work, so the second one is used.
+            let mut cache = config
+                .make_cached_mirrors_provider(&urls, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+            open(&mut cache, "a.tex", &mut status);
+
+            // Then the second one stops working, so the third is used.
+            service.fail_next(4);
+            open(&mut cache, "b.tex", &mut status);
+        }
+
+        {
+            // The third mirror is remembered, and now the second one
+            // serves something else entirely.
+            service.set_mirror_tar_index(prefetch_test_index());
+            service.set_tar_index(digest_changed.clone());
+
+            let mut cache = config
+                .make_cached_mirrors_provider(&urls, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+            open(&mut cache, "c.tex", &mut status);
+        }
+
+        {
+            // Starting afresh, the second mirror is used until its digest
+            // turns out to be wrong.
+            let tempdir = tempfile::tempdir().unwrap();
+            service.set_tar_index(prefetch_test_index());
+
+            let mut cache = config
+                .make_cached_mirrors_provider(&urls, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+            open(&mut cache, "a.tex", &mut status);
+
+            service.set_tar_index(digest_changed.clone());
+
+            let mut cache = config
+                .make_cached_mirrors_provider(&urls, false, Some(tempdir.path()), &mut status)
+                .unwrap();
+            open(&mut cache, "b.tex", &mut status);
+        }
+    });
+
+    check_req_count(&requests, TectonicRequest::File("a.tex".into()), 2);
+    check_req_count(&requests, TectonicRequest::Failed, 4);
+    check_req_count(&requests, TectonicRequest::MirrorFile("b.tex".into()), 2);
+    check_req_count(&requests, TectonicRequest::MirrorFile("c.tex".into()), 1);
+    check_req_count(&requests, TectonicRequest::File("b.tex".into()), 0);
+    check_req_count(&requests, TectonicRequest::File("c.tex".into()), 0);
+}
+
+#[test]
+fn test_prefetch_failover() {
+    let requests = run_test(Some(prefetch_test_index()), |service, _| {
+        let tempdir = tempfile::tempdir().unwrap();
+        let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+        let config = PersistentConfig::default();
+        let urls = vec![service.url(), service.url_for("/mirror.tar")];
+
+        let mut cache = config
+            .make_cached_mirrors_provider(&urls, false, Some(tempdir.path()), &mut status)
+            .unwrap();
+
+        // Every attempt at both spans fails, so the mirror is used instead.
+        service.fail_next(8);
+        let names: Vec<String> = vec!["a.tex", "b.tex", "c.tex"]
+            .into_iter()
+            .map(|s| s.to_owned())
+            .collect();
+        cache.prefetch(&names, &mut status).unwrap();
+
+        for name in &names {
+            match cache.input_open_name(OsStr::new(name), &mut status) {
+                OpenResult::Ok(_) => {}
+                _ => panic!("Failed to open {}", name),
+            }
+        }
+    });
+
+    check_req_count(&requests, TectonicRequest::Failed, 8);
+    check_req_count(&requests, TectonicRequest::MirrorFile("c.tex".into()), 1);
+    check_req_count(
+        &requests,
+        TectonicRequest::MirrorFile(tectonic::digest::DIGEST_NAME.into()),
+        1,
+    );
+    check_req_count(&requests, TectonicRequest::File("c.tex".into()), 0);
+}
@@ -1,6 +1,7 @@
 // Copyright 2016-2018 the Tectonic Project
 // Licensed under the MIT License.
 
+use flate2::read::ZlibDecoder;
 use lazy_static::lazy_static;
 
 use std::env;
@@ -9,6 +10,8 @@ use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Output, Stdio};
 use std::str;
+use std::thread;
+use std::time::Duration;
 use tempfile::TempDir;
 
 #[path = "util/mod.rs"]
@@ -141,6 +144,87 @@ fn check_file(tempdir: &TempDir, rest: &str) {
     }
 }
 
+/// Read a PDF with its Flate-compressed streams, among them the page
+/// contents and the object streams, inflated in place.
+fn read_inflated_pdf(path: &Path) -> Vec<u8> {
+    let pdf = fs::read(path).unwrap();
+    let mut out = Vec::new();
+    let mut in_stream = false;
+    let mut stream = Vec::new();
+
+    for line in pdf.split_inclusive(|&b| b == b'\n') {
+        if !in_stream {
+            out.extend_from_slice(line);
+            in_stream = line == b"stream\n";
+        } else if line.starts_with(b"endstream") {
+            // The newline before `endstream` is not part of the data.
+            stream.pop();
+            let mut inflated = Vec::new();
+            match ZlibDecoder::new(&stream[..]).read_to_end(&mut inflated) {
+                Ok(_) => out.extend(inflated),
+                Err(_) => out.extend(&stream),
+            }
+            out.extend_from_slice(line);
+            stream.clear();
+            in_stream = false;
+        } else {
+            stream.extend_from_slice(line);
+        }
+    }
+
+    out
+}
+
+/// Typeset the picture file `name` from the test bundle with
+/// `\XeTeXpicfile`. Returns its width and height in points, and the
+/// inflated PDF.
+fn typeset_picture(name: &str) -> ((f64, f64), Vec<u8>) {
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        format!(
+            "\\setbox0\\hbox{{\\XeTeXpicfile {} }}\n\
+             \\message{{[size \\the\\wd0 \\the\\ht0]}}\n\
+             \\box0\n\
+             \\bye\n",
+            name
+        ),
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--print", "main.tex"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+
+    let size = stdout
+        .split("[size ")
+        .nth(1)
+        .and_then(|rest| rest.split(']').next())
+        .expect("the size of the picture wasn't printed");
+    let dims: Vec<f64> = size
+        .split("pt")
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse().unwrap())
+        .collect();
+    assert_eq!(dims.len(), 2, "unexpected size {:?}", size);
+
+    let pdf = read_inflated_pdf(&tempdir.path().join("main.pdf"));
+    ((dims[0], dims[1]), pdf)
+}
+
+/// Check that a picture size in points is `bp` big points, give or take
+/// rounding.
+fn assert_size_bp(actual: (f64, f64), bp: (f64, f64)) {
+    let pt = (bp.0 * 72.27 / 72., bp.1 * 72.27 / 72.);
+    assert!(
+        (actual.0 - pt.0).abs() < 0.01 && (actual.1 - pt.1).abs() < 0.01,
+        "picture is {:?}pt, expected {:?}pt",
+        actual,
+        pt
+    );
+}
+
 /* Keep tests alphabetized */
 
 #[test]
@@ -230,46 +314,252 @@ fn stdin_content() {
     success_or_panic(output);
 }
 
-// Regression #36
 #[test]
-fn test_space() {
+#[should_panic]
+fn test_bad_outdir() {
     if env::var("RUNNING_COVERAGE").is_ok() {
-        return;
+        panic!()
     }
 
     let fmt_arg = get_plain_format_arg();
-    let tempdir = setup_and_copy_files(&["test space.tex"]);
+    let tempdir = setup_and_copy_files(&["subdirectory/content/1.tex"]);
 
-    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "test space.tex"]);
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "subdirectory/content/1.tex",
+            "--outdir=subdirectory/non_existent",
+        ],
+    );
     success_or_panic(output);
 }
 
 #[test]
-fn test_outdir() {
+fn test_bitmap_images() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // GIF files have no resolution, so they are 72 dpi; the others are 144
+    // dpi.
+    for &(name, bp) in &[
+        ("redbox.gif", (12., 8.)),
+        ("redbox.tif", (10., 6.)),
+        ("redbox.bmp", (8., 4.)),
+    ] {
+        let (size, pdf) = typeset_picture(name);
+        assert_size_bp(size, bp);
+        assert!(pdf.windows(14).any(|w| w == b"/Subtype/Image"));
+    }
+}
+
+#[test]
+fn test_build() {
     if env::var("RUNNING_COVERAGE").is_ok() {
         return;
     }
 
     let fmt_arg = get_plain_format_arg();
     let tempdir = setup_and_copy_files(&["subdirectory/content/1.tex"]);
+    fs::create_dir(tempdir.path().join("shared")).unwrap();
+    fs::write(
+        tempdir.path().join("shared/greeting.tex"),
+        "Hello from a shared file.\n",
+    )
+    .unwrap();
+    fs::write(tempdir.path().join("main.tex"), "\\input greeting\n\\bye\n").unwrap();
+    fs::write(
+        tempdir.path().join("Tectonic.toml"),
+        "[[target]]\nname = \"one\"\ninput = \"subdirectory/content/1.tex\"\n\n\
+         [[target]]\nname = \"main\"\ninput = \"main.tex\"\nsearch_paths = [\"shared\"]\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "build"]);
+    success_or_panic(output);
+    check_file(&tempdir, "subdirectory/content/1.pdf");
+    check_file(&tempdir, "main.pdf");
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "build", "nonexistent"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn test_build_after_failed_setup() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("build.tex"),
+        "Named like a subcommand.\n\\bye\n",
+    )
+    .unwrap();
+    fs::write(
+        tempdir.path().join("Tectonic.toml"),
+        "[[target]]\nname = \"broken\"\ninput = \"build.tex\"\noutdir = \"missing\"\n\n\
+         [[target]]\nname = \"ok\"\ninput = \"build.tex\"\n",
+    )
+    .unwrap();
+
+    // The second target still gets the bundle after the first one fails.
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "build"]);
+    error_or_panic(output);
+    check_file(&tempdir, "build.pdf");
+    fs::remove_file(tempdir.path().join("build.pdf")).unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "./build.tex"]);
+    success_or_panic(output);
+    check_file(&tempdir, "build.pdf");
+}
+
+#[test]
+fn test_bundle_create() {
+    let tempdir = setup_and_copy_files(&[]);
+    fs::create_dir_all(tempdir.path().join("tree/tex/plain")).unwrap();
+    fs::write(tempdir.path().join("tree/tex/plain/greeting.tex"), "Hi.\n").unwrap();
+
+    let output = run_tectonic(tempdir.path(), &["bundle", "create", "-o", "out", "tree"]);
+    success_or_panic(output);
+    check_file(&tempdir, "out.zip");
+    check_file(&tempdir, "out.tar");
+    check_file(&tempdir, "out.tar.index.gz");
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["bundle", "create", "-o", "out", "nowhere"],
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn test_dependency_reports() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("greeting.tex"), "Hello.\n").unwrap();
+    fs::write(tempdir.path().join("main.tex"), "\\input greeting\n\\bye\n").unwrap();
 
     let output = run_tectonic(
         tempdir.path(),
         &[
             &fmt_arg,
-            "subdirectory/content/1.tex",
-            "--outdir=subdirectory",
+            "--keep-logs",
+            "--makefile-rules",
+            "main.d",
+            "--depfile-format",
+            "ninja",
+            "--deps-json",
+            "main.deps.json",
+            "main.tex",
         ],
     );
     success_or_panic(output);
-    check_file(&tempdir, "subdirectory/1.pdf");
+
+    // Ninja only wants to hear about the main output.
+    let rules = fs::read_to_string(tempdir.path().join("main.d")).unwrap();
+    assert!(rules.starts_with("main.pdf : main.tex"));
+    assert!(rules.contains("greeting.tex"));
+    assert!(!rules.contains("main.log"));
+
+    let report = fs::read_to_string(tempdir.path().join("main.deps.json")).unwrap();
+    assert!(report.contains("\"primary_input\": \"main.tex\""));
+    assert!(report.contains("{\"name\": \"greeting.tex\""));
+    assert!(report.contains("{\"name\": \"main.pdf\", \"path\": \"main.pdf\""));
+    assert!(report.contains("{\"name\": \"main.log\""));
+    assert!(report.contains("\"bundle_digest\": "));
 }
 
 #[test]
-#[should_panic]
-fn test_bad_outdir() {
+fn test_diagnostics_on_error() {
     if env::var("RUNNING_COVERAGE").is_ok() {
-        panic!()
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "\\hbox to 1pt{Much too wide}\n\\undefinedcontrolsequence\n\\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "main.tex"]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    error_or_panic(output);
+
+    // The log is dumped on failure too, so look for the warning itself.
+    assert!(stderr
+        .lines()
+        .any(|l| l.starts_with("warning:") && l.contains("Overfull \\hbox")));
+}
+
+#[test]
+fn test_encrypt() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    // Asking for PDF 1.4 must not quietly turn AES-128 into RC4.
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "\\special{pdf:minorversion 4}Secret.\n\\bye\n",
+    )
+    .unwrap();
+
+    // The encryption dictionary itself is never encrypted or compressed.
+    let has = |pdf: &[u8], needle: &[u8]| pdf.windows(needle.len()).any(|w| w == needle);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--encrypt=aes128",
+            "--user-password=open",
+            "--permissions=print",
+            "main.tex",
+        ],
+    );
+    success_or_panic(output);
+    let pdf = fs::read(tempdir.path().join("main.pdf")).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.5"));
+    assert!(has(&pdf, b"/Filter/Standard/V 4/Length 128"));
+    assert!(has(&pdf, b"/CFM/AESV2"));
+    assert!(has(&pdf, b"/R 4"));
+    assert!(has(&pdf, b"/P 196"));
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--encrypt=aes256", "main.tex"]);
+    success_or_panic(output);
+    let pdf = fs::read(tempdir.path().join("main.pdf")).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.7"));
+    assert!(has(&pdf, b"/Filter/Standard/V 5/Length 256"));
+    assert!(has(&pdf, b"/CFM/AESV3"));
+    assert!(has(&pdf, b"/R 6"));
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--encrypt=aes128",
+            "--permissions=fly",
+            "main.tex",
+        ],
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn test_fetch() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
     }
 
     let fmt_arg = get_plain_format_arg();
@@ -279,32 +569,101 @@ fn test_bad_outdir() {
         tempdir.path(),
         &[
             &fmt_arg,
+            "fetch",
+            "--export-zip",
+            "subset.zip",
+            "subdirectory/content/1.tex",
+        ],
+    );
+    success_or_panic(output);
+    check_file(&tempdir, "subset.zip");
+    assert!(!tempdir.path().join("subdirectory/content/1.pdf").exists());
+
+    // The exported bundle is enough to process the document on its own.
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--bundle",
+            "subset.zip",
             "subdirectory/content/1.tex",
-            "--outdir=subdirectory/non_existent",
         ],
     );
     success_or_panic(output);
+    check_file(&tempdir, "subdirectory/content/1.pdf");
 }
 
 #[test]
-#[should_panic]
-fn test_outdir_is_file() {
+fn test_include_dir() {
     if env::var("RUNNING_COVERAGE").is_ok() {
-        panic!()
+        return;
     }
 
     let fmt_arg = get_plain_format_arg();
-    let tempdir = setup_and_copy_files(&["test space.tex", "subdirectory/content/1.tex"]);
+    let tempdir = setup_and_copy_files(&[]);
+    fs::create_dir_all(tempdir.path().join("shared/deep")).unwrap();
+    fs::write(
+        tempdir.path().join("shared/deep/greeting.tex"),
+        "Hello from a shared file.\n",
+    )
+    .unwrap();
+    fs::write(tempdir.path().join("main.tex"), "\\input greeting\n\\bye\n").unwrap();
+
+    // Without recursion, the file isn't found.
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "-I", "shared", "main.tex"]);
+    error_or_panic(output);
 
     let output = run_tectonic(
         tempdir.path(),
         &[
             &fmt_arg,
-            "subdirectory/content/1.tex",
-            "--outdir=test space.tex",
+            "-I",
+            "shared//",
+            "--makefile-rules",
+            "main.d",
+            "main.tex",
         ],
     );
     success_or_panic(output);
+    check_file(&tempdir, "main.pdf");
+
+    // The dependency is listed where it was found.
+    let rules = fs::read_to_string(tempdir.path().join("main.d")).unwrap();
+    assert!(rules.contains(&Path::new("shared/deep/greeting.tex").display().to_string()));
+}
+
+#[test]
+fn test_jp2_image() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // A 20x10 greyscale image without a resolution box, so 72 dpi.
+    let (size, pdf) = typeset_picture("graybox.jp2");
+    assert_size_bp(size, (20., 10.));
+    assert!(pdf.windows(10).any(|w| w == b"/JPXDecode"));
+}
+
+#[test]
+fn test_json_messages_exclude_print() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("main.tex"), "Hi.\n\\bye\n").unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "--message-format=json", "--print", "main.tex"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    error_or_panic(output);
+
+    // Even the error is reported as JSON.
+    assert!(stdout.starts_with('{'));
+    assert!(stdout.contains("cannot be used with --message-format=json"));
 }
 
 #[test]
@@ -361,3 +720,375 @@ fn test_no_color() {
     error_or_panic(output_nocolor);
     error_or_panic(output_autocolor);
 }
+
+#[test]
+fn test_outdir() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&["subdirectory/content/1.tex"]);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "subdirectory/content/1.tex",
+            "--outdir=subdirectory",
+        ],
+    );
+    success_or_panic(output);
+    check_file(&tempdir, "subdirectory/1.pdf");
+}
+
+#[test]
+#[should_panic]
+fn test_outdir_is_file() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        panic!()
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&["test space.tex", "subdirectory/content/1.tex"]);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "subdirectory/content/1.tex",
+            "--outdir=test space.tex",
+        ],
+    );
+    success_or_panic(output);
+}
+
+#[test]
+fn test_page_ranges() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "One.\\vfill\\eject Two.\\vfill\\eject Three.\\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--pages=2-3", "main.tex"]);
+    success_or_panic(output);
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--pages=50-60", "main.tex"]);
+    error_or_panic(output);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "--pages=1", "--outfmt=xdv", "main.tex"],
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn test_pdfa() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("main.tex"), "Archived.\n\\bye\n").unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--pdfa=3b", "main.tex"]);
+    success_or_panic(output);
+
+    // The XMP metadata stream is never compressed.
+    let pdf = fs::read(tempdir.path().join("main.pdf")).unwrap();
+    let xmp = b"<pdfaid:part>3</pdfaid:part>";
+    assert!(pdf.windows(xmp.len()).any(|w| w == xmp));
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "--pdfa=2b", "--encrypt=aes256", "main.tex"],
+    );
+    error_or_panic(output);
+
+    // Violations are reported as errors.
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "\\special{pdf:ann width 10pt height 10pt <</Subtype/FileAttachment>>}Attached.\n\\bye\n",
+    )
+    .unwrap();
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--pdfa=2b", "main.tex"]);
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    error_or_panic(output);
+    assert!(stderr.contains("does not conform to PDF/A-2b"));
+}
+
+#[test]
+fn test_precompile_preamble() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+
+    // In test mode the format cache is shared by all runs of the test
+    // suite, so make the preamble unique to this one.
+    fs::write(
+        tempdir.path().join("main.tex"),
+        format!(
+            "% {}\n\\def\\greeting{{Hello}}\n%%endpreamble\n\\greeting.\n\\bye\n",
+            tempdir.path().display()
+        ),
+    )
+    .unwrap();
+
+    for (i, expected) in [true, false].iter().enumerate() {
+        let output = run_tectonic(
+            tempdir.path(),
+            &[&fmt_arg, "--precompile-preamble", "main.tex"],
+        );
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        success_or_panic(output);
+        assert_eq!(
+            stdout.contains("Precompiling the preamble"),
+            *expected,
+            "unexpected output from run {}",
+            i + 1
+        );
+        assert!(tempdir.path().join("main.pdf").exists());
+    }
+}
+
+#[test]
+fn test_print_config() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("Tectonic.toml"),
+        "[defaults]\nsynctex = true\nreruns = 2\nformat = \"plain\"\n",
+    )
+    .unwrap();
+
+    // The command line takes priority over the project file.
+    let output = run_tectonic(tempdir.path(), &["--print-config", "--reruns=0", "doc.tex"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+
+    assert!(stdout.contains("format = \"plain\""));
+    assert!(stdout.contains("synctex = true"));
+    assert!(stdout.contains("reruns = 0"));
+    assert!(stdout.contains("keep_logs = false"));
+
+    // A negated flag can turn off a setting from a configuration file.
+    let output = run_tectonic(
+        tempdir.path(),
+        &["--print-config", "--no-synctex", "doc.tex"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+
+    assert!(stdout.contains("synctex = false"));
+    assert!(stdout.contains("reruns = 2"));
+}
+
+#[test]
+fn test_reproducible() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "The year is \\the\\year.\n\\bye\n",
+    )
+    .unwrap();
+
+    let mut outputs = Vec::new();
+
+    for _ in 0..2 {
+        let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--reproducible", "main.tex"]);
+        success_or_panic(output);
+        outputs.push(fs::read(tempdir.path().join("main.pdf")).unwrap());
+        thread::sleep(Duration::from_millis(1100));
+    }
+
+    assert_eq!(outputs[0], outputs[1]);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "--reproducible", "-Z", "shell-escape", "main.tex"],
+    );
+    error_or_panic(output);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shell_escape() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "\\immediate\\openout1=gone.txt \\immediate\\write1{x}\\immediate\\closeout1\n\
+         \\immediate\\write18{echo shell-said-hi >said.txt && rm gone.txt}\n\
+         \\newread\\r\n\
+         \\openin\\r=said.txt \\read\\r to\\said \\closein\\r\n\
+         \\message{[\\said]}\n\
+         \\openin\\r=gone.txt \\ifeof\\r\\message{[gone]}\\else\\message{[kept]}\\fi\n\
+         Done.\n\
+         \\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[&fmt_arg, "--print", "-Z", "shell-escape", "main.tex"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("[shell-said-hi"));
+    assert!(stdout.contains("[gone]"));
+}
+
+// Regression #36
+#[test]
+fn test_space() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&["test space.tex"]);
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "test space.tex"]);
+    success_or_panic(output);
+}
+
+#[test]
+fn test_svg_image() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // SVG images become form XObjects, sized by their viewport.
+    let (size, pdf) = typeset_picture("redbox.svg");
+    assert_size_bp(size, (24., 12.));
+    assert!(pdf.windows(13).any(|w| w == b"/Subtype/Form"));
+    // The rectangle is filled and stroked.
+    assert!(pdf.windows(5).any(|w| w == b" re B") || pdf.windows(4).any(|w| w == b" h B"));
+
+    // Nested <use> references that would make a billion elements are cut
+    // short.
+    let (size, _) = typeset_picture("uselaughs.svg");
+    assert_size_bp(size, (30., 15.));
+}
+
+#[test]
+fn test_synctex_edit() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("doc.synctex"),
+        "SyncTeX Version:1\nInput:1:./doc.tex\nContent:\n{1\n\
+         (1,7:4736286,6000000:20000000,500000,100000\ng1,7:5000000,6000000\n)\n}1\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["synctex", "edit", "-o", "1:80:88:doc.pdf"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+
+    assert!(stdout.contains("Input:./doc.tex\n"));
+    assert!(stdout.contains("Line:7\n"));
+
+    // No SyncTeX data for this one.
+    let output = run_tectonic(
+        tempdir.path(),
+        &["synctex", "view", "-i", "7:0:doc.tex", "-o", "other.pdf"],
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn test_tagged_pdf() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(
+        tempdir.path().join("main.tex"),
+        "\\special{pdf:rolemap <</Para /P>>}\n\
+         \\special{pdf:bstruct /Sect}\n\
+         \\special{pdf:bstruct /Para <</Lang (en)>>}Tagged.\\special{pdf:estruct}\n\
+         \\special{pdf:estruct}\n\
+         \\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "main.tex"]);
+    success_or_panic(output);
+
+    let pdf = read_inflated_pdf(&tempdir.path().join("main.pdf"));
+    let contains = |s: &[u8]| pdf.windows(s.len()).any(|w| w == s);
+    assert!(contains(b"/Para <</MCID 1>> BDC"));
+    assert!(contains(b"EMC"));
+    assert!(contains(b"/StructTreeRoot"));
+    assert!(contains(b"/ParentTree"));
+    assert!(contains(b"/RoleMap"));
+    assert!(contains(b"/StructParents"));
+}
+
+#[test]
+fn test_untrusted() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::create_dir_all(tempdir.path().join("doc")).unwrap();
+    fs::write(tempdir.path().join("secret.tex"), "Secret.\n").unwrap();
+    fs::write(
+        tempdir.path().join("doc/main.tex"),
+        "\\input ../secret\n\\bye\n",
+    )
+    .unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "doc/main.tex"]);
+    success_or_panic(output);
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "--untrusted", "doc/main.tex"]);
+    error_or_panic(output);
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--untrusted",
+            "-Z",
+            "shell-escape",
+            "doc/main.tex",
+        ],
+    );
+    error_or_panic(output);
+}
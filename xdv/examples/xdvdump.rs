@@ -101,6 +101,58 @@ impl tectonic_xdv::XdvEvents for Stats {
         Ok(())
     }
 
+    fn handle_define_font(
+        &mut self,
+        font_num: tectonic_xdv::FontNum,
+        area: &str,
+        name: &str,
+        scale_factor: u32,
+        _design_size: u32,
+    ) -> Result<(), Self::Error> {
+        println!("font {}: {}{} at {}sp", font_num, area, name, scale_factor);
+        Ok(())
+    }
+
+    fn handle_define_native_font(
+        &mut self,
+        font_num: tectonic_xdv::FontNum,
+        name: &str,
+        size: i32,
+        face_index: u32,
+        _color_rgba: Option<u32>,
+        _extend: Option<u32>,
+        _slant: Option<u32>,
+        _embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        println!(
+            "native font {}: {} (face {}) at {}sp",
+            font_num, name, face_index, size
+        );
+        Ok(())
+    }
+
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: Option<tectonic_xdv::FontNum>,
+        h: i32,
+        v: i32,
+        text: &str,
+        _width: i32,
+        glyphs: &[u16],
+        _x: &[i32],
+        _y: &[i32],
+    ) -> Result<(), Self::Error> {
+        println!(
+            "text at ({}, {}) in font {:?}: {:?} ({} glyphs)",
+            h,
+            v,
+            font_num,
+            text,
+            glyphs.len()
+        );
+        Ok(())
+    }
+
     fn handle_char_run(&mut self, chars: &[i32]) -> Result<(), Self::Error> {
         let all_ascii_printable = chars.iter().all(|c| *c > 0x20 && *c < 0x7F);
         println!(
@@ -131,8 +131,110 @@ pub trait XdvEvents {
     fn handle_char_run(&mut self, chars: &[i32]) -> Result<(), Self::Error> {
         Ok(())
     }
+
+    /// Handle a sequence of characters without intervening commands, along
+    /// with the font they are set in and the position of the first
+    /// character.
+    ///
+    /// The parser does not know the widths of characters in traditional
+    /// fonts, so the positions of subsequent characters cannot be reported.
+    /// The default implementation calls `handle_char_run`.
+    #[allow(unused)]
+    fn handle_positioned_char_run(
+        &mut self,
+        font_num: Option<FontNum>,
+        h: i32,
+        v: i32,
+        chars: &[i32],
+    ) -> Result<(), Self::Error> {
+        self.handle_char_run(chars)
+    }
+
+    /// Handle the definition of a traditional (TFM) font.
+    ///
+    /// Font definitions appear both in the body of the file and in the
+    /// postamble, so this may be called more than once for the same font.
+    /// The scale factor and design size are in DVI units.
+    #[allow(unused)]
+    fn handle_define_font(
+        &mut self,
+        font_num: FontNum,
+        area: &str,
+        name: &str,
+        scale_factor: u32,
+        design_size: u32,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle the definition of a native (OpenType/TrueType) font.
+    ///
+    /// As with `handle_define_font`, this may be called more than once for
+    /// the same font. The size, extend, slant and embolden values are 16.16
+    /// fixed-point numbers; the size is in DVI units.
+    #[allow(unused, clippy::too_many_arguments)]
+    fn handle_define_native_font(
+        &mut self,
+        font_num: FontNum,
+        name: &str,
+        size: i32,
+        face_index: u32,
+        color_rgba: Option<u32>,
+        extend: Option<u32>,
+        slant: Option<u32>,
+        embolden: Option<u32>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a run of glyphs set in a native font.
+    ///
+    /// The glyph offsets `x` and `y` are relative to the starting position
+    /// (`h`, `v`). The run has a total advance of `width`.
+    #[allow(unused, clippy::too_many_arguments)]
+    fn handle_glyph_run(
+        &mut self,
+        font_num: Option<FontNum>,
+        h: i32,
+        v: i32,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a run of glyphs set in a native font that is accompanied by
+    /// the Unicode text that it represents.
+    ///
+    /// The arguments are the same as for `handle_glyph_run`, plus the text.
+    #[allow(unused, clippy::too_many_arguments)]
+    fn handle_text_and_glyphs(
+        &mut self,
+        font_num: Option<FontNum>,
+        h: i32,
+        v: i32,
+        text: &str,
+        width: i32,
+        glyphs: &[u16],
+        x: &[i32],
+        y: &[i32],
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Handle a relative horizontal movement, such as the space between two
+    /// words.
+    #[allow(unused)]
+    fn handle_horizontal_move(&mut self, delta: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
 }
 
+/// The number used to identify a font within an XDV file.
+pub type FontNum = i32;
+
 /// State for parsing an XDV file.
 #[derive(Debug)]
 pub struct XdvParser<T: XdvEvents> {
@@ -143,6 +245,7 @@ pub struct XdvParser<T: XdvEvents> {
     cur_font_num: Option<i32>,
     offset: u64,
     cur_char_run: Vec<i32>,
+    cur_char_run_pos: (i32, i32),
 }
 
 /// Which type of file is being parsed.
@@ -201,6 +304,7 @@ impl<T: XdvEvents> XdvParser<T> {
             cur_font_num: None,
             offset: 0,
             cur_char_run: Vec::new(),
+            cur_char_run_pos: (0, 0),
         }
     }
 
@@ -284,23 +388,32 @@ impl<T: XdvEvents> XdvParser<T> {
             }
 
             let opcode = cursor.get_u8().unwrap();
-            let mut char_run_ended = true; // most commands end runs of characters
+
+            // Most commands end runs of characters. Flush the run before
+            // executing the command, so that the run is reported with the
+            // font that it was set in and ahead of any events that the
+            // command generates.
+            if !continues_char_run(opcode) && !self.cur_char_run.is_empty() {
+                let (h, v) = self.cur_char_run_pos;
+                self.events.handle_positioned_char_run(
+                    self.cur_font_num,
+                    h,
+                    v,
+                    &self.cur_char_run,
+                )?;
+                self.cur_char_run.clear();
+            }
 
             let rv = match opcode {
                 // This is the least ugly way I've found to map the u8 to the
                 // symbolic enum values.
-                oc if oc == Opcode::Noop as u8 => {
-                    char_run_ended = false;
-                    Ok(())
-                }
+                oc if oc == Opcode::Noop as u8 => Ok(()),
 
                 oc if oc >= Opcode::DefineFont1 as u8 && oc <= Opcode::DefineFont4 as u8 => {
-                    char_run_ended = false;
                     self.do_define_font(oc, &mut cursor)
                 }
 
                 oc if oc == Opcode::DefineNativeFont as u8 => {
-                    char_run_ended = false;
                     self.do_define_native_font(oc, &mut cursor)
                 }
 
@@ -310,10 +423,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
                 oc if oc == Opcode::EndOfPage as u8 => self.do_end_of_page(oc, &mut cursor),
 
-                oc if oc == Opcode::PushStack as u8 => {
-                    char_run_ended = false;
-                    self.do_push_stack(oc, &mut cursor)
-                }
+                oc if oc == Opcode::PushStack as u8 => self.do_push_stack(oc, &mut cursor),
 
                 oc if oc == Opcode::PopStack as u8 => self.do_pop_stack(oc, &mut cursor),
 
@@ -360,12 +470,10 @@ impl<T: XdvEvents> XdvParser<T> {
                 oc if oc >= Opcode::SetCharNumber0 as u8
                     && oc <= Opcode::SetCharNumber127 as u8 =>
                 {
-                    char_run_ended = false;
                     self.do_set_char_number(oc, &mut cursor)
                 }
 
                 oc if oc >= Opcode::SetChar1 as u8 && oc <= Opcode::SetChar4 as u8 => {
-                    char_run_ended = false;
                     self.do_set_char(oc, &mut cursor)
                 }
 
@@ -403,11 +511,6 @@ impl<T: XdvEvents> XdvParser<T> {
                     return Err(e);
                 }
             }
-
-            if char_run_ended && !self.cur_char_run.is_empty() {
-                self.events.handle_char_run(&self.cur_char_run)?;
-                self.cur_char_run.clear();
-            }
         }
 
         self.offset += cursor.checkpoint as u64;
@@ -447,20 +550,21 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
+        let font_num = cursor.get_compact_i32_smpos(opcode - Opcode::DefineFont1 as u8)?;
         let _checksum = cursor.get_u32()?;
-        let _scale_factor = cursor.get_u32()?;
-        let _design_size = cursor.get_u32()?;
+        let scale_factor = cursor.get_u32()?;
+        let design_size = cursor.get_u32()?;
         let area_len = cursor.get_u8()?;
         let name_len = cursor.get_u8()?;
-        // XXX TEMP
-        use std::str::from_utf8;
-        let _area_str = from_utf8(cursor.get_slice(area_len as usize)?)
-            .unwrap()
-            .to_owned();
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
-            .to_owned();
+        let area_str = String::from_utf8_lossy(cursor.get_slice(area_len as usize)?).into_owned();
+        let name_str = String::from_utf8_lossy(cursor.get_slice(name_len as usize)?).into_owned();
+        self.events.handle_define_font(
+            font_num,
+            &area_str,
+            &name_str,
+            scale_factor,
+            design_size,
+        )?;
         Ok(())
     }
 
@@ -473,41 +577,40 @@ impl<T: XdvEvents> XdvParser<T> {
             return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
         }
 
-        let _font_num = cursor.get_i32()?;
-        let _size = cursor.get_i32()?; // fixed-point
+        let font_num = cursor.get_i32()?;
+        let size = cursor.get_i32()?; // fixed-point
         let flags = cursor.get_u16()?;
         let name_len = cursor.get_u8()?;
-        // XXX TEMP
-        use std::str::from_utf8;
-        let _name_str = from_utf8(cursor.get_slice(name_len as usize)?)
-            .unwrap()
-            .to_owned();
-        let _face_index = cursor.get_u32()?;
-
-        let _color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
+        let name_str = String::from_utf8_lossy(cursor.get_slice(name_len as usize)?).into_owned();
+        let face_index = cursor.get_u32()?;
+
+        let color_rgba = if flags & NativeFontFlags::Colored as u16 != 0 {
             Some(cursor.get_u32()?)
         } else {
             None
         };
 
-        let _extend = if flags & NativeFontFlags::Extend as u16 != 0 {
+        let extend = if flags & NativeFontFlags::Extend as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
-        let _slant = if flags & NativeFontFlags::Slant as u16 != 0 {
+        let slant = if flags & NativeFontFlags::Slant as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
-        let _embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
+        let embolden = if flags & NativeFontFlags::Embolden as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
             None
         };
 
+        self.events.handle_define_native_font(
+            font_num, &name_str, size, face_index, color_rgba, extend, slant, embolden,
+        )?;
         Ok(())
     }
 
@@ -587,6 +690,7 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let n = cursor.get_compact_i32_smneg(opcode - Opcode::Right1 as u8)?;
         self.stack.last_mut().unwrap().h += n;
+        self.events.handle_horizontal_move(n)?;
         Ok(())
     }
 
@@ -601,6 +705,8 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.h += state.w;
+        let delta = state.w;
+        self.events.handle_horizontal_move(delta)?;
         Ok(())
     }
 
@@ -613,6 +719,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.w = n;
         state.h += n;
+        self.events.handle_horizontal_move(n)?;
         Ok(())
     }
 
@@ -627,6 +734,8 @@ impl<T: XdvEvents> XdvParser<T> {
 
         let state = self.stack.last_mut().unwrap();
         state.h += state.x;
+        let delta = state.x;
+        self.events.handle_horizontal_move(delta)?;
         Ok(())
     }
 
@@ -639,6 +748,7 @@ impl<T: XdvEvents> XdvParser<T> {
         let state = self.stack.last_mut().unwrap();
         state.x = n;
         state.h += n;
+        self.events.handle_horizontal_move(n)?;
         Ok(())
     }
 
@@ -731,7 +841,7 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let char_num = opcode - Opcode::SetCharNumber0 as u8;
-        self.cur_char_run.push(i32::from(char_num));
+        self.push_char(i32::from(char_num));
         Ok(())
     }
 
@@ -742,31 +852,60 @@ impl<T: XdvEvents> XdvParser<T> {
         }
 
         let char_num = cursor.get_compact_i32_smpos(opcode - Opcode::SetChar1 as u8)?;
-        self.cur_char_run.push(char_num as i32);
+        self.push_char(char_num as i32);
         Ok(())
     }
 
-    fn do_set_glyphs(
-        &mut self,
-        opcode: u8,
-        cursor: &mut Cursor<T>,
-    ) -> InternalResult<(), T::Error> {
-        if self.state != ParserState::InPage {
-            return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
+    /// Add a character to the current run, noting the position of the run
+    /// if this is its first character.
+    fn push_char(&mut self, c: i32) {
+        if self.cur_char_run.is_empty() {
+            let state = self.stack.last().unwrap();
+            self.cur_char_run_pos = (state.h, state.v);
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
+        self.cur_char_run.push(c);
+    }
+
+    /// Read the positioned glyphs shared by the `SetGlyphs` and
+    /// `SetTextAndGlyphs` opcodes.
+    fn get_glyphs(
+        &mut self,
+        cursor: &mut Cursor<T>,
+    ) -> InternalResult<(i32, Vec<u16>, Vec<i32>, Vec<i32>), T::Error> {
+        let width = cursor.get_i32()?;
+        let n_glyphs = cursor.get_u16()? as usize;
+        let mut x = Vec::with_capacity(n_glyphs);
+        let mut y = Vec::with_capacity(n_glyphs);
+        let mut glyphs = Vec::with_capacity(n_glyphs);
 
         for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
+            x.push(cursor.get_i32()?);
+            y.push(cursor.get_i32()?);
         }
 
         for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
+            glyphs.push(cursor.get_u16()?);
         }
 
+        Ok((width, glyphs, x, y))
+    }
+
+    fn do_set_glyphs(
+        &mut self,
+        opcode: u8,
+        cursor: &mut Cursor<T>,
+    ) -> InternalResult<(), T::Error> {
+        if self.state != ParserState::InPage {
+            return Err(XdvError::IllegalOpcode(opcode, cursor.global_offset()).into_internal());
+        }
+
+        let (width, glyphs, x, y) = self.get_glyphs(cursor)?;
+        let state = self.stack.last_mut().unwrap();
+        let (h, v) = (state.h, state.v);
+        state.h += width;
+        self.events
+            .handle_glyph_run(self.cur_font_num, h, v, width, &glyphs, &x, &y)?;
         Ok(())
     }
 
@@ -786,18 +925,21 @@ impl<T: XdvEvents> XdvParser<T> {
             chars.push(cursor.get_u16()?);
         }
 
-        let _width = cursor.get_i32()?;
-        let n_glyphs = cursor.get_u16()?;
-
-        for _ in 0..n_glyphs {
-            let _x = cursor.get_u32()?;
-            let _y = cursor.get_u32()?;
-        }
-
-        for _ in 0..n_glyphs {
-            let _glyph_id = cursor.get_i16()?;
-        }
-
+        let (width, glyphs, x, y) = self.get_glyphs(cursor)?;
+        let text = String::from_utf16_lossy(&chars);
+        let state = self.stack.last_mut().unwrap();
+        let (h, v) = (state.h, state.v);
+        state.h += width;
+        self.events.handle_text_and_glyphs(
+            self.cur_font_num,
+            h,
+            v,
+            &text,
+            width,
+            &glyphs,
+            &x,
+            &y,
+        )?;
         Ok(())
     }
 
@@ -866,6 +1008,17 @@ impl<T: XdvEvents> XdvParser<T> {
     }
 }
 
+/// Returns true if the opcode may appear within a run of characters without
+/// ending it.
+fn continues_char_run(opcode: u8) -> bool {
+    opcode == Opcode::Noop as u8
+        || (opcode >= Opcode::DefineFont1 as u8 && opcode <= Opcode::DefineFont4 as u8)
+        || opcode == Opcode::DefineNativeFont as u8
+        || opcode == Opcode::PushStack as u8
+        || (opcode >= Opcode::SetCharNumber0 as u8 && opcode <= Opcode::SetCharNumber127 as u8)
+        || (opcode >= Opcode::SetChar1 as u8 && opcode <= Opcode::SetChar4 as u8)
+}
+
 /// The states that may be stacked while processing the DVI.
 #[derive(Clone, Debug, Eq, PartialEq)]
 struct State {